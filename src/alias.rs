@@ -0,0 +1,158 @@
+use clap::ValueEnum;
+
+use crate::config::{Config, find_project_file};
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq)]
+pub enum AliasShell {
+	Bash,
+	Zsh,
+	Fish,
+}
+
+/// A `ts` function that asks `tempsystem --print-effective-args` for the extra flags, so the config is only ever parsed by the binary.
+pub fn generate(shell: AliasShell) -> &'static str {
+	return match shell {
+		AliasShell::Bash => {
+			r#"ts() {
+	local out
+	out=$(command tempsystem --print-effective-args) || return
+	local -a extra=()
+	[[ -n $out ]] && mapfile -t extra <<< "$out"
+	command tempsystem "${extra[@]}" "$@"
+}
+"#
+		}
+		AliasShell::Zsh => {
+			r#"ts() {
+	local -a extra
+	extra=(${(f)"$(command tempsystem --print-effective-args)"}) || return
+	command tempsystem "${extra[@]}" "$@"
+}
+"#
+		}
+		AliasShell::Fish => {
+			r#"function ts --wraps tempsystem
+	set -l extra (command tempsystem --print-effective-args); or return
+	command tempsystem $extra $argv
+end
+"#
+		}
+	};
+}
+
+/// Flags the `ts` alias passes before the user's own: the configured `default-args`,
+/// plus `--reuse` when inside a project with a `tempsystem.toml`.
+pub fn effective_args(config: &Config) -> Vec<String> {
+	let mut args = config.default_args.clone();
+	if std::env::current_dir()
+		.ok()
+		.and_then(|cwd| find_project_file(&cwd))
+		.is_some()
+		&& !args.iter().any(|a| a == "--reuse")
+	{
+		args.push("--reuse".into());
+	}
+
+	return args;
+}
+
+/// A direnv `use tempsystem` function: entering the directory makes sure its `--reuse` system exists,
+/// creating it in the background, and puts wrappers on PATH that run `commands` inside it through `tempsystem exec`.
+/// direnv only exports variables, not functions, so the proxies are small scripts in `.direnv`.
+pub fn direnv_hook(commands: &[String]) -> String {
+	let mut hook = String::from(
+		r#"# add to .envrc, or put the function in ~/.config/direnv/direnvrc and keep only the last line
+use_tempsystem() {
+	local root=$PWD
+	local bin=$root/.direnv/tempsystem/bin
+	mkdir -p "$bin"
+	local cmd
+	for cmd in "$@"; do
+		printf '#!/usr/bin/env bash\ncd %q && exec tempsystem exec -- %q "$@"\n' "$root" "$cmd" > "$bin/$cmd"
+		chmod +x "$bin/$cmd"
+	done
+	PATH_add "$bin"
+
+	local lock=$root/.direnv/tempsystem/creating
+	if mkdir "$lock" 2>/dev/null; then
+		(
+			if tempsystem exec -- true; then
+				command -v notify-send && notify-send tempsystem "system for $root is ready"
+			fi
+			rmdir "$lock"
+		) </dev/null >/dev/null 2>&1 &
+	fi
+}
+use tempsystem"#,
+	);
+	for command in commands {
+		hook.push(' ');
+		hook.push_str(&crate::build::shell_quote(command));
+	}
+	hook.push('\n');
+
+	return hook;
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	/// Checks `script` parses with `shell` and `flags`, when that shell is installed.
+	fn check_syntax(shell: &str, flags: &[&str], script: &str) {
+		use std::io::Write;
+
+		let child = std::process::Command::new(shell)
+			.args(flags)
+			.stdin(std::process::Stdio::piped())
+			.stderr(std::process::Stdio::piped())
+			.spawn();
+		let mut child = match child {
+			Ok(child) => child,
+			Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+				eprintln!("{shell} is not installed, not checking the syntax");
+				return;
+			}
+			Err(e) => panic!("could not run {shell}: {e}"),
+		};
+		child
+			.stdin
+			.take()
+			.unwrap()
+			.write_all(script.as_bytes())
+			.unwrap();
+		let output = child.wait_with_output().unwrap();
+		assert!(output.status.success(), "{shell} rejected the script: {}", String::from_utf8_lossy(&output.stderr));
+	}
+
+	#[test]
+	fn alias_matches_golden_files() {
+		assert_eq!(generate(AliasShell::Bash), include_str!("../testdata/alias/ts.bash"));
+		assert_eq!(generate(AliasShell::Zsh), include_str!("../testdata/alias/ts.zsh"));
+		assert_eq!(generate(AliasShell::Fish), include_str!("../testdata/alias/ts.fish"));
+	}
+
+	#[test]
+	fn alias_is_valid_in_its_shell() {
+		check_syntax("bash", &["-n"], generate(AliasShell::Bash));
+		check_syntax("zsh", &["-n"], generate(AliasShell::Zsh));
+		check_syntax("fish", &["--no-execute"], generate(AliasShell::Fish));
+	}
+
+	#[test]
+	fn direnv_hook_matches_golden_files() {
+		assert_eq!(direnv_hook(&["make".into(), "cargo".into()]), include_str!("../testdata/alias/direnv-hook.bash"));
+		// names that need quoting are quoted once for .envrc, and again by printf for the proxies
+		assert_eq!(
+			direnv_hook(&["npm run".into(), "it's".into(), "./gradlew".into()]),
+			include_str!("../testdata/alias/direnv-hook-quoted.bash")
+		);
+	}
+
+	#[test]
+	fn direnv_hook_is_valid_bash() {
+		// direnv evaluates .envrc with bash
+		check_syntax("bash", &["-n"], &direnv_hook(&[]));
+		check_syntax("bash", &["-n"], &direnv_hook(&["make".into(), "it's".into()]));
+	}
+}
@@ -0,0 +1,631 @@
+use std::{
+	os::unix::process::ExitStatusExt,
+	path::PathBuf,
+	process::Stdio,
+	sync::{Arc, Mutex},
+};
+
+use clap::ValueEnum;
+use tokio::process::Command;
+
+use crate::{
+	Args, PullPolicy, UpdateSystem, ZshHistorySync,
+	classify::Phase,
+	color,
+	config::Config,
+	docker,
+	engine::Endpoint,
+	env::{EnvSource, ExecEnv},
+	error::Error,
+	events::{Event, Events},
+	features::{self, ImageConfig, ImageFeatures, Privilege},
+	names, print_error, proxy,
+	redact::redact,
+	rng::Rng,
+	session::{self, SessionOptions},
+	setup::{self, ImageSource, Target},
+	termcheck,
+};
+
+#[derive(ValueEnum, Debug, Clone, Copy, Default, PartialEq)]
+pub enum BackendKind {
+	/// the API socket of docker or podman, or the CLI when no socket can be reached and `docker` or `podman` is installed
+	#[default]
+	Auto,
+
+	/// the API socket of docker or podman, whichever answers first
+	Api,
+
+	/// only docker's API socket, or `$DOCKER_HOST`
+	Docker,
+
+	/// only podman's API socket, the rootless one first
+	Podman,
+
+	/// the `docker` or `podman` binary, for hosts that only allow the CLI
+	Cli,
+}
+
+/// What the core create, install, enter, and delete flow needs from a container engine.
+pub trait ContainerBackend: ImageSource {
+	/// Whether the daemon runs on another machine, which cannot see the host paths we would mount.
+	fn is_remote(&self) -> bool;
+
+	async fn image_present(&self, image: &str) -> bool;
+
+	async fn pull(&self, image: &str) -> Result<(), Error>;
+
+	/// Creates the system under `name`, which is also its hostname, with the variables in `env`.
+	async fn create(&self, options: &SessionOptions, name: &str, env: &ExecEnv) -> Result<String, Error>;
+
+	async fn start(&self, id: &str) -> Result<(), Error>;
+
+	/// Runs `cmd` without a terminal, returning its exit code and output.
+	async fn exec(&self, id: &str, user: &str, cmd: &[String], env: &ExecEnv) -> Result<(i64, String), Error>;
+
+	/// Runs `cmd` on our stdin and stdout, in a tty when `tty`, returning its exit code.
+	async fn exec_interactive(&self, id: &str, user: &str, cmd: &[String], env: &ExecEnv, tty: bool) -> Result<i64, Error>;
+
+	async fn remove(&self, id: &str) -> Result<(), Error>;
+}
+
+/// Shells out to `docker` or `podman`, for hosts where only the CLI may reach the daemon (e.g. through a setuid wrapper).
+pub struct CliBackend {
+	program: PathBuf,
+	/// `--host`, passed on through the variables docker and podman read it from
+	host: Option<String>,
+}
+
+impl CliBackend {
+	/// The first of `docker` and `podman` in PATH.
+	pub fn find() -> Option<Self> {
+		let path = std::env::var_os("PATH")?;
+		return ["docker", "podman"]
+			.iter()
+			.find_map(|name| {
+				std::env::split_paths(&path)
+					.map(|dir| dir.join(name))
+					.find(|program| program.is_file())
+			})
+			.map(|program| Self { program, host: None });
+	}
+
+	pub fn with_host(mut self, host: Option<&str>) -> Self {
+		self.host = host.map(String::from);
+		return self;
+	}
+
+	pub fn program(&self) -> String {
+		return self.program.display().to_string();
+	}
+
+	/// A command running the binary with `args`. Exec environment is passed by name only, with the values
+	/// set on the command itself, so they do not show up in the process list.
+	fn command(&self, args: &[String], env: &ExecEnv) -> Command {
+		let mut command = Command::new(&self.program);
+		command.kill_on_drop(true);
+		command.args(args);
+		if let Some(host) = &self.host {
+			command.env("DOCKER_HOST", host).env("CONTAINER_HOST", host);
+		}
+		command.envs(env.iter());
+		return command;
+	}
+
+	/// Runs `args`, returning stdout, or an error carrying stderr when it fails.
+	async fn run(&self, args: &[String]) -> Result<String, Error> {
+		return self.run_with_env(args, &ExecEnv::default()).await;
+	}
+
+	async fn run_with_env(&self, args: &[String], env: &ExecEnv) -> Result<String, Error> {
+		let output = self
+			.command(args, env)
+			.stdin(Stdio::null())
+			.output()
+			.await
+			.map_err(|e| Error::CliRun(self.program(), e))?;
+		if !output.status.success() {
+			return Err(Error::CliFailed(
+				format!("{} {}", self.program(), args.first().map(String::as_str).unwrap_or_default()),
+				exit_code(output.status),
+				String::from_utf8_lossy(&output.stderr).trim().to_string(),
+			));
+		}
+
+		return Ok(String::from_utf8_lossy(&output.stdout).trim().to_string());
+	}
+
+	fn exec_args(id: &str, user: &str, cmd: &[String], env: &ExecEnv, flags: &[&str]) -> Vec<String> {
+		let mut args: Vec<String> = vec!["exec".into()];
+		args.extend(flags.iter().map(|f| f.to_string()));
+		args.extend(["--user".into(), user.into()]);
+		for (key, _) in env.iter() {
+			args.extend(["--env".into(), key.into()]);
+		}
+		args.push(id.into());
+		args.extend(cmd.iter().cloned());
+		return args;
+	}
+}
+
+/// The exit code of a CLI process, or `128 + signal` like a shell reports it when it was killed.
+pub fn exit_code(status: std::process::ExitStatus) -> i64 {
+	return status
+		.code()
+		.or(status.signal().map(|s| 128 + s))
+		.unwrap_or(-1) as i64;
+}
+
+impl ContainerBackend for CliBackend {
+	fn is_remote(&self) -> bool {
+		return self
+			.host
+			.clone()
+			.or(std::env::var("DOCKER_HOST").ok())
+			.filter(|h| !h.is_empty())
+			.is_some_and(|host| Endpoint::Host(host).is_remote());
+	}
+
+	async fn image_present(&self, image: &str) -> bool {
+		return self
+			.run(&["image".into(), "inspect".into(), "--format".into(), "{{.Id}}".into(), image.into()])
+			.await
+			.is_ok();
+	}
+
+	async fn pull(&self, image: &str) -> Result<(), Error> {
+		self.run(&["pull".into(), "--quiet".into(), image.into()])
+			.await
+			.map_err(|e| match e {
+				Error::CliFailed(_, _, stderr) => Error::ImageCreate(image.into(), stderr),
+				e => e,
+			})?;
+		if let Some(digest) = session::image_digest(image) {
+			let digests: Vec<String> = self
+				.run(&[
+					"image".into(),
+					"inspect".into(),
+					"--format".into(),
+					"{{join .RepoDigests \"\\n\"}}".into(),
+					image.into(),
+				])
+				.await?
+				.lines()
+				.map(String::from)
+				.collect();
+			if !session::digest_matches(&digest, &digests) {
+				return Err(Error::ImageDigestMismatch(digest, docker::describe_digests(&digests)));
+			}
+		}
+		return Ok(());
+	}
+
+	async fn create(&self, options: &SessionOptions, name: &str, env: &ExecEnv) -> Result<String, Error> {
+		let mut args: Vec<String> = vec!["create".into(), "--tty".into(), "--name".into(), name.into(), "--hostname".into(), name.into()];
+		for (key, value) in docker::container_labels(options, None) {
+			args.extend(["--label".into(), format!("{key}={value}")]);
+		}
+		for dns in ["1.1.1.1", "1.0.0.1"] {
+			args.extend(["--dns".into(), dns.into()]);
+		}
+		if !options.network {
+			args.extend(["--network".into(), "none".into()]);
+		}
+		if proxy::needs_host_alias(env) {
+			args.extend(["--add-host".into(), proxy::HOST_ALIAS_ENTRY.into()]);
+		}
+		if options.privileged {
+			args.push("--privileged".into());
+		}
+		if options.privileged_full {
+			for rule in docker::PRIVILEGED_FULL_DEVICE_RULES {
+				args.extend(["--device-cgroup-rule".into(), rule.to_string()]);
+			}
+		}
+		for opt in docker::container_security_opts(options) {
+			args.extend(["--security-opt".into(), opt]);
+		}
+		for cap in docker::container_cap_add(options) {
+			args.extend(["--cap-add".into(), cap]);
+		}
+		if options.ro_cwd_overlay {
+			args.extend(["--tmpfs".into(), docker::overlay_tmpfs(&options.home)]);
+		}
+		if options.ro_root {
+			args.push("--read-only".into());
+		}
+		if options.mount_cwd || options.ro_cwd_overlay {
+			args.extend(["--workdir".into(), docker::work_dir(&options.home)]);
+		}
+		for bind in docker::container_binds(options)? {
+			args.extend(["--volume".into(), bind]);
+		}
+		if let Some(cpus) = options.restrict_cpu {
+			args.extend(["--cpuset-cpus".into(), format!("0-{}", cpus - 1)]);
+		}
+		if let Some(cpuset) = &options.cpuset_cpus {
+			args.extend(["--cpuset-cpus".into(), cpuset.clone()]);
+		}
+		if let Some(nano_cpus) = options.nano_cpus {
+			args.extend(["--cpus".into(), format!("{}", nano_cpus as f64 / 1e9)]);
+		}
+		if let Some(pids) = options.pids_limit {
+			args.extend(["--pids-limit".into(), pids.to_string()]);
+		}
+		for ulimit in &options.ulimits {
+			args.extend(["--ulimit".into(), ulimit.to_string()]);
+		}
+		if let Some(memory) = options.restrict_memory {
+			args.extend(["--memory".into(), format!("{memory}m"), "--memory-swap".into(), format!("{memory}m")]);
+		}
+		if let Some(memory) = options.memory {
+			args.extend(["--memory".into(), memory.to_string()]);
+		}
+		if let Some(swap) = options.memory_swap {
+			args.extend(["--memory-swap".into(), swap.to_string()]);
+		}
+		if let Some(parent) = &options.cgroup_parent {
+			args.extend(["--cgroup-parent".into(), parent.clone()]);
+		}
+		if let Some(size) = options.shm_size {
+			args.extend(["--shm-size".into(), size.to_string()]);
+		}
+		if let Some(size) = options.storage_size {
+			args.extend(["--storage-opt".into(), format!("size={size}")]);
+		}
+		// by name only, so values stay out of the process list, like for execs
+		for (key, _) in env.iter() {
+			args.extend(["--env".into(), key.into()]);
+		}
+		args.push(options.image.clone());
+
+		return self.run_with_env(&args, env).await.map_err(|e| match e {
+			Error::CliFailed(_, _, stderr) if options.storage_size.is_some() && docker::storage_quota_unsupported(&stderr) => {
+				Error::StorageQuotaUnsupported(stderr)
+			}
+			Error::CliFailed(_, _, stderr) if options.cgroup_parent.is_some() && docker::cgroup_parent_refused(&stderr) => {
+				Error::CgroupParent(docker::cgroup_parent_hint(options.cgroup_parent.as_deref().unwrap_or_default(), None), stderr)
+			}
+			e => e,
+		});
+	}
+
+	async fn start(&self, id: &str) -> Result<(), Error> {
+		self.run(&["start".into(), id.into()]).await?;
+		return Ok(());
+	}
+
+	async fn exec(&self, id: &str, user: &str, cmd: &[String], env: &ExecEnv) -> Result<(i64, String), Error> {
+		let output = self
+			.command(&Self::exec_args(id, user, cmd, env, &[]), env)
+			.stdin(Stdio::null())
+			.output()
+			.await
+			.map_err(|e| Error::CliRun(self.program(), e))?;
+		let mut text = String::from_utf8_lossy(&output.stdout).to_string();
+		text.push_str(&String::from_utf8_lossy(&output.stderr));
+
+		return Ok((exit_code(output.status), text));
+	}
+
+	async fn exec_interactive(&self, id: &str, user: &str, cmd: &[String], env: &ExecEnv, tty: bool) -> Result<i64, Error> {
+		let flags: &[&str] = if tty { &["--interactive", "--tty"] } else { &["--interactive"] };
+		let status = self
+			.command(&Self::exec_args(id, user, cmd, env, flags), env)
+			.status()
+			.await
+			.map_err(|e| Error::CliRun(self.program(), e))?;
+
+		return Ok(exit_code(status));
+	}
+
+	async fn remove(&self, id: &str) -> Result<(), Error> {
+		self.run(&["rm".into(), "--force".into(), id.into()])
+			.await?;
+		return Ok(());
+	}
+}
+
+impl ImageSource for CliBackend {
+	async fn image_config(&self, image: &str) -> Result<ImageConfig, Error> {
+		let output = self
+			.run(&["image".into(), "inspect".into(), "--format".into(), "{{json .Config}}".into(), image.into()])
+			.await?;
+		let config: serde_json::Value = serde_json::from_str(&output).unwrap_or_default();
+		return Ok(ImageConfig {
+			labels: serde_json::from_value(config["Labels"].clone()).unwrap_or_default(),
+			user: config["User"]
+				.as_str()
+				.filter(|u| !u.is_empty())
+				.map(String::from),
+			env: serde_json::from_value(config["Env"].clone()).unwrap_or_default(),
+		});
+	}
+
+	async fn probe_home(&self, image: &str, user: &str) -> Result<Option<String>, Error> {
+		let output = self
+			.run(&[
+				"run".into(),
+				"--rm".into(),
+				"--network".into(),
+				"none".into(),
+				"--user".into(),
+				"root".into(),
+				"--entrypoint".into(),
+				"/bin/sh".into(),
+				image.into(),
+				"-c".into(),
+				features::HOME_PROBE_SCRIPT.into(),
+				"sh".into(),
+				user.into(),
+			])
+			.await?;
+		return Ok(features::probed_home(&output));
+	}
+}
+
+/// A system created on some backend, with what its image provides and the environment its execs get.
+struct System<'a, B: ContainerBackend> {
+	backend: &'a B,
+	events: &'a Events,
+	id: String,
+	features: ImageFeatures,
+	privilege: Privilege,
+	env: ExecEnv,
+}
+
+impl<B: ContainerBackend> System<'_, B> {
+	async fn probe_features(&mut self) -> Result<(), Error> {
+		let (status, output) = self
+			.run_as_root(&features::probe_script(self.features.user()))
+			.await?;
+		if status != 0 {
+			return Err(Error::FeatureProbe(status));
+		}
+		self.features.apply_probe(&output);
+
+		return Ok(());
+	}
+}
+
+impl<B: ContainerBackend> Target for System<'_, B> {
+	fn features(&self) -> &ImageFeatures {
+		return &self.features;
+	}
+
+	fn phase(&self, cur: usize, total: usize, msg: String) {
+		self.events.phase(cur, total, msg);
+	}
+
+	async fn run_as_root(&self, script: &str) -> Result<(i64, String), Error> {
+		return self
+			.backend
+			.exec(&self.id, "root", &["/bin/sh".into(), "-c".into(), script.into()], &ExecEnv::default())
+			.await;
+	}
+
+	async fn run_privileged(&self, operation: &'static str, command: &str) -> Result<(i64, String), Error> {
+		// sudo leaves out the environment, so pacman would not find the proxy
+		let command = match self.privilege {
+			Privilege::Sudo => format!("{}{command}", proxy::exports(&self.env)),
+			_ => command.into(),
+		};
+		let (user, cmd) = self
+			.privilege
+			.exec(self.features.user(), operation, &command)?;
+		return self.backend.exec(&self.id, &user, &cmd, &self.env).await;
+	}
+
+	async fn run_as_user(&self, command: &str) -> Result<(i64, String), Error> {
+		return self
+			.backend
+			.exec(&self.id, self.features.user(), &[self.features.shell().into(), "-c".into(), command.into()], &self.env)
+			.await;
+	}
+
+	/// Without a streamed exec there is no progress to report, so this is a plain exec.
+	async fn run_pacman(&self, command: String, privileged: Option<&'static str>) -> Result<(i64, String), Error> {
+		return match privileged {
+			Some(operation) => self.run_privileged(operation, &command).await,
+			None => self.run_as_user(&command).await,
+		};
+	}
+}
+
+/// The create, install, enter, and delete flow of `Context::perform_all_enter` on any backend.
+/// Options that need more than `ContainerBackend` offers are refused up front.
+pub async fn enter(
+	backend: &impl ContainerBackend,
+	args: &Args,
+	config: &Config,
+	events: &Events,
+	live_container: Arc<Mutex<Option<String>>>,
+) -> Result<i64, Error> {
+	let unsupported = [
+		("--reuse", args.reuse || args.reuse_reset),
+		("--resume", args.resume.is_some()),
+		("--keep-on-error", args.keep_on_error),
+		("--build", !args.build.is_empty()),
+		("--containerfile", args.containerfile.is_some()),
+		("--verify-exit", args.verify_exit.is_some()),
+		("--sync-zsh-history copy", args.sync_zsh_history == ZshHistorySync::Copy),
+		("--storage-warn", !args.storage_warn.is_empty()),
+		("--suggest", args.suggest),
+		("--why-slow", args.why_slow),
+		("--host-job-control", args.host_job_control),
+		("--wipe-on-exit", args.wipe_on_exit),
+		("--chown-work-on-exit", args.chown_work_on_exit),
+		("--pause-on-detach", args.pause_on_detach),
+		("--guard", args.guard),
+		("--allow-host", !args.allow_host.is_empty()),
+		("--copy-cwd", args.copy_cwd),
+		("--tls-ca", args.tls_ca.is_some()),
+		("--registry-user", args.registry_user.is_some()),
+		("--platform", args.platform.is_some()),
+		("--bug-report", args.bug_report),
+		("--capture-env-on-failure", args.capture_env_on_failure),
+		("--autosnapshot", args.autosnapshot.is_some()),
+		("--update-system=background", args.update_system == Some(UpdateSystem::Background)),
+	];
+	if let Some((option, _)) = unsupported.iter().find(|(_, used)| *used) {
+		return Err(Error::BackendUnsupported(option, "cli"));
+	}
+
+	let mut options = SessionOptions::from_args(args, config)?;
+	options.check_offline(args)?;
+	if backend.is_remote() {
+		docker::warn_dropped_mounts(&options.drop_host_mounts());
+	}
+	if args.verbose {
+		println!("image: {}", options.image);
+		if let Some(memory) = options.memory {
+			println!("memory limit: {memory} bytes ({})", indicatif::HumanBytes(memory));
+		}
+		if let Some(size) = options.storage_size {
+			println!("storage limit: {size} bytes ({})", indicatif::HumanBytes(size));
+		}
+		if let Some(size) = options.shm_size {
+			println!("/dev/shm size: {size} bytes ({})", indicatif::HumanBytes(size));
+		}
+		println!("{}", options.describe_packages());
+		if options.privileged_full {
+			println!("{}", docker::describe_privileged_full());
+		}
+	}
+	options.check_package_count()?;
+	for note in &options.notes {
+		eprintln!("{}", color::yellow(note));
+	}
+	let env = docker::session_env(args, config)?;
+	let tty = docker::session_tty(args)?;
+
+	let pull = match args.pull {
+		_ if args.offline && backend.image_present(&options.image).await => false,
+		_ if args.offline => return Err(Error::OfflineImageMissing(options.image)),
+		PullPolicy::Always => true,
+		PullPolicy::Missing => !backend.image_present(&options.image).await,
+		PullPolicy::Never if backend.image_present(&options.image).await => false,
+		PullPolicy::Never => return Err(Error::ImageMissing(options.image)),
+	};
+	let total = 3
+		+ pull as usize
+		+ options.install_steps(args.install_chunk_size as usize)
+		+ args.update_system.is_some() as usize
+		+ args.update_pkgfile as usize
+		+ !options.toolchain_setup.is_empty() as usize
+		+ args.landware as usize
+		+ args.chaotic_aur as usize
+		+ !args.keep as usize;
+	let mut cur = 1;
+	if pull {
+		events.phase(cur, total, format!("Downloading image ({})", session::image_version(&options.image)));
+		backend.pull(&options.image).await?;
+		cur += 1;
+	}
+	options.home = setup::resolve_home(backend, &options.image, args.verbose).await;
+	events.phase(cur, total, "Creating system");
+	cur += 1;
+	let (options_ref, container_env) = (&options, &env.only(&[EnvSource::Passthrough, EnvSource::Explicit]));
+	let (id, name) =
+		names::create_with_retry(
+			args.name.as_deref(),
+			&mut Rng::new(args.seed),
+			|name| async move { backend.create(options_ref, &name, container_env).await },
+		)
+		.await?;
+	*live_container.lock().unwrap() = Some(id.clone());
+	events.emit(Event::SystemNamed {
+		id: id.clone(),
+		name: name.clone(),
+	});
+	events.phase(cur, total, "Starting system");
+	cur += 1;
+	backend.start(&id).await?;
+
+	let image_config = backend.image_config(&options.image).await?;
+	let mut system = System {
+		backend,
+		events,
+		id,
+		features: ImageFeatures::from_config(&image_config),
+		privilege: Privilege::default(),
+		env,
+	};
+	if !args.no_healthcheck
+		&& let Some(script) = image_config.healthcheck_script(session::is_own_image(&options.image))
+	{
+		let (status, output) = system.run_as_root(&script).await?;
+		if status != 0 {
+			return Err(Error::ImageUnhealthy(options.image, output.trim().to_string()));
+		}
+	}
+	if !system.features.fully_declared() {
+		system.probe_features().await?;
+	}
+	if !args.no_bootstrap_user && !args.ro_root && system.features.missing_user() {
+		let (status, output) = system.run_as_root(features::BOOTSTRAP_USER_SCRIPT).await?;
+		if args.verbose {
+			println!("{}", redact(&output));
+		}
+		if status != 0 {
+			return Err(docker::classified_error(Phase::Packages, &output, || {
+				Error::BootstrapUser(status, docker::get_error_from_pacman(&output))
+			}));
+		}
+		system.features.forget_missing();
+		system.probe_features().await?;
+	}
+	if args.verbose {
+		println!("image features: {}", system.features);
+	}
+	let root_exec = !system.features.full_sudo() && matches!(system.run_as_root("id -u").await, Ok((0, output)) if output.trim() == "0");
+	system.privilege = system.features.privilege(root_exec);
+	if args.verbose && system.privilege == Privilege::Root {
+		println!("`{}` has no passwordless sudo for everything, running setup as root", system.features.user());
+	}
+	if options.ro_cwd_overlay {
+		let (status, output) = system
+			.run_as_root(&docker::overlay_mount_script(&options.home, system.features.user()))
+			.await?;
+		if status != 0 {
+			return Err(Error::OverlayMount(status, output.trim().to_string()));
+		}
+	}
+	docker::check_features(&system.features, &system.privilege, args, &options)?;
+	let cur = setup::run(&system, args, &options, cur, total).await?;
+
+	events.phase(cur, total, "Executing");
+	let mut term_notice = None;
+	if !args.no_term_check {
+		let (_, output) = system.run_as_user(termcheck::TERM_CHECK_SCRIPT).await?;
+		term_notice = termcheck::apply(&output, &mut system.env);
+	}
+	events.hand_off_terminal().await;
+	if let Some(notice) = term_notice {
+		eprintln!("{}", color::yellow(notice));
+	}
+	let default_command = docker::default_command(args, config, &name, system.features.user(), &options.home)?;
+	let (cmd, env) = docker::session_command(args, system.features.shell(), &system.env, default_command);
+	let exit_code = backend
+		.exec_interactive(&system.id, system.features.user(), &cmd, &env, tty)
+		.await?;
+	if let Some(note) = docker::oom_note(exit_code, &options) {
+		print_error!(note);
+	}
+	if docker::keep_system(Some(exit_code), args.keep, args.keep_on_failure, args.keep_on_error) {
+		live_container.lock().unwrap().take();
+		if args.keep {
+			println!("kept system `{name}`");
+		} else {
+			println!("{}", docker::kept_on_failure(exit_code, &system.id, system.features.shell()));
+		}
+		events.emit(Event::Finished { exit_code });
+		return Ok(exit_code);
+	}
+
+	events.phase(total, total, "Deleting system");
+	backend.remove(&system.id).await?;
+	live_container.lock().unwrap().take();
+	events.emit(Event::Finished { exit_code });
+	return Ok(exit_code);
+}
@@ -0,0 +1,39 @@
+use std::{
+	path::PathBuf,
+	time::{SystemTime, UNIX_EPOCH},
+};
+
+use flate2::{Compression, write::GzEncoder};
+use tar::{Builder, Header};
+
+use crate::{config, redact::redact};
+
+/// `$XDG_STATE_HOME/tempsystem/bug-reports`, where `--bug-report` leaves its bundles.
+pub fn dir() -> Option<PathBuf> {
+	return config::state_dir().map(|d| d.join("bug-reports"));
+}
+
+/// Writes `files`, each name with its contents, to a timestamped tar.gz under `dir()` and returns its path.
+/// Every file goes through `redact` first; the bundle stays on this machine unless someone sends it.
+pub fn write(files: &[(&str, String)]) -> std::io::Result<PathBuf> {
+	let dir = dir().ok_or_else(|| std::io::Error::new(std::io::ErrorKind::NotFound, "no home directory for the state directory"))?;
+	std::fs::create_dir_all(&dir)?;
+	let now = SystemTime::now()
+		.duration_since(UNIX_EPOCH)
+		.unwrap_or_default();
+	let path = dir.join(format!("tempsystem-{}.tar.gz", now.as_secs()));
+
+	let mut builder = Builder::new(GzEncoder::new(std::fs::File::create(&path)?, Compression::default()));
+	for (name, contents) in files {
+		let contents = redact(contents);
+		let mut header = Header::new_gnu();
+		header.set_size(contents.len() as u64);
+		header.set_mode(0o644);
+		header.set_mtime(now.as_secs());
+		header.set_cksum();
+		builder.append_data(&mut header, name, contents.as_bytes())?;
+	}
+	builder.into_inner()?.finish()?;
+
+	return Ok(path);
+}
@@ -0,0 +1,239 @@
+use std::path::Path;
+
+use crate::{
+	Args,
+	docker::{CHAOTIC_AUR_SETUP, LANDWARE_SETUP},
+	error::Error,
+	features::DEFAULT_USER,
+	imageref::ImageRef,
+	print_error,
+	session::{self, SessionOptions},
+};
+
+/// Set on derived images, holding the base image they were built from.
+pub const LABEL_DERIVED: &str = "dev.tempsystem.derived";
+
+/// Repository derived images are tagged under; the tag is a hash of the Containerfile.
+pub const DERIVED_REPOSITORY: &str = "tempsystem-derived";
+
+/// Extra image setup from `--containerfile` and `--build`, applied on top of the base image.
+#[derive(Debug, Clone)]
+pub struct Recipe {
+	pub base: String,
+	/// the `FROM` line, which is the Containerfile's own when it has one
+	pub from: String,
+	instructions: Vec<String>,
+}
+
+impl Recipe {
+	pub fn from_args(args: &Args, base: &str) -> Result<Option<Self>, Error> {
+		let mut base = base.to_string();
+		let mut from = format!("FROM {base}");
+		let mut instructions = vec![];
+		if let Some(path) = &args.containerfile {
+			let mut file = std::fs::read_to_string(path).map_err(|e| Error::ContainerfileRead(path.display().to_string(), e))?;
+			if let Some((line, image)) = own_from(&file) {
+				base = image;
+				from = line.trim().to_string();
+				file = file.replacen(&line, "", 1);
+			}
+			instructions.push(file);
+		}
+		instructions.extend(args.build.iter().cloned());
+		if instructions.is_empty() {
+			return Ok(None);
+		}
+
+		return Ok(Some(Self { base, from, instructions }));
+	}
+
+	pub fn containerfile(&self) -> String {
+		let mut file = format!("{}\n", self.from);
+		for instruction in &self.instructions {
+			file.push_str(instruction.trim_end());
+			file.push('\n');
+		}
+
+		return file;
+	}
+
+	/// Same instructions on the same base give the same tag, so an existing image is a cache hit. The base counts
+	/// normalized, so `alpine` and `docker.io/library/alpine:latest` share their builds.
+	pub fn tag(&self) -> String {
+		let normalized = ImageRef::split(&self.base).normalized();
+		let key = self
+			.containerfile()
+			.replacen(&self.from, &self.from.replacen(&self.base, &normalized, 1), 1);
+		return format!("{DERIVED_REPOSITORY}:{:016x}", session::fnv1a(key.as_bytes()));
+	}
+
+	/// A build context holding nothing but the Containerfile.
+	pub fn context(&self) -> Result<Vec<u8>, Error> {
+		let containerfile = self.containerfile();
+		let mut header = tar::Header::new_gnu();
+		header.set_size(containerfile.len() as u64);
+		header.set_mode(0o644);
+		header.set_cksum();
+		let mut builder = tar::Builder::new(vec![]);
+		builder
+			.append_data(&mut header, Path::new("Containerfile"), containerfile.as_bytes())
+			.map_err(Error::BuildContext)?;
+
+		return builder.into_inner().map_err(Error::BuildContext);
+	}
+}
+
+/// The line starting `containerfile` when that is a `FROM`, with the image it names.
+fn own_from(containerfile: &str) -> Option<(String, String)> {
+	let line = containerfile
+		.lines()
+		.find(|l| !l.trim().is_empty() && !l.trim_start().starts_with('#'))?;
+	let mut words = line.split_whitespace();
+	if !words.next()?.eq_ignore_ascii_case("FROM") {
+		return None;
+	}
+	let image = words.find(|w| !w.starts_with("--"))?;
+
+	return Some((line.to_string(), image.to_string()));
+}
+
+/// Quotes `s` for `/bin/sh` unless it is made of characters that never need it.
+pub fn shell_quote(s: &str) -> String {
+	if !s.is_empty()
+		&& s.chars()
+			.all(|c| c.is_ascii_alphanumeric() || "-_.+@=/:,".contains(c))
+	{
+		return s.into();
+	}
+
+	return format!("'{}'", s.replace('\'', r"'\''"));
+}
+
+/// Joins a multi-line setup script into a single `RUN` line.
+fn one_line(script: &str) -> String {
+	return script
+		.lines()
+		.map(str::trim)
+		.filter(|l| !l.is_empty())
+		.collect::<Vec<&str>>()
+		.join(" ");
+}
+
+/// A Containerfile reproducing the system `options` describes, without talking to the daemon.
+/// Instructions that change least often come first so rebuilds can reuse the most layers.
+pub fn emit_containerfile(args: &Args, options: &SessionOptions, recipe: Option<&Recipe>, env: &[(String, String)]) -> String {
+	let mut lines = vec![
+		"# generated by tempsystem".to_string(),
+		recipe.map_or(format!("FROM {}", options.image), |r| r.from.clone()),
+	];
+	// the repositories are set up as root; everything after uses sudo like in the system
+	if options.chaotic_aur {
+		lines.push(format!("RUN {}", one_line(CHAOTIC_AUR_SETUP)));
+	}
+	if options.landware {
+		lines.push(format!("RUN {}", one_line(LANDWARE_SETUP)));
+	}
+	lines.push(format!("USER {DEFAULT_USER}"));
+	if args.update_system.is_some() {
+		lines.push("RUN sudo pacman -Syu --noconfirm".into());
+	}
+	for (command, packages) in [
+		("sudo pacman -S --needed --noconfirm", &options.packages),
+		("yay --sync --needed --noconfirm --noprogressbar", &options.aur_packages),
+	] {
+		let mut packages: Vec<String> = packages.iter().map(|p| shell_quote(p)).collect();
+		packages.sort();
+		packages.dedup();
+		if !packages.is_empty() {
+			lines.push(format!("RUN {command} {}", packages.join(" ")));
+		}
+	}
+	lines.extend(
+		options
+			.toolchain_setup
+			.iter()
+			.map(|command| format!("RUN {command}")),
+	);
+	if args.update_pkgfile {
+		lines.push("RUN sudo pkgfile -u".into());
+	}
+	if let Some(recipe) = recipe {
+		lines.extend(recipe.instructions.iter().map(|i| i.trim_end().to_string()));
+	}
+	for (name, value) in env {
+		if value.contains('\n') {
+			print_error!(format!("not emitting `{name}` since its value spans multiple lines"));
+			continue;
+		}
+		let value = value
+			.replace('\\', r"\\")
+			.replace('"', r#"\""#)
+			.replace('$', r"\$");
+		lines.push(format!("ENV {name}=\"{value}\""));
+	}
+
+	return lines.join("\n") + "\n";
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::config::Config;
+
+	fn emit(flags: &[&str], env: &[(&str, &str)]) -> String {
+		let args = Args::from_flags(flags);
+		let options = SessionOptions::from_args(&args, &Config::default()).unwrap();
+		let recipe = Recipe::from_args(&args, &options.image).unwrap();
+		let env: Vec<(String, String)> = env
+			.iter()
+			.map(|(name, value)| (name.to_string(), value.to_string()))
+			.collect();
+		return emit_containerfile(&args, &options, recipe.as_ref(), &env);
+	}
+
+	#[test]
+	fn emits_the_bare_image() {
+		assert_eq!(emit(&[], &[]), include_str!("../testdata/containerfile/default.containerfile"));
+	}
+
+	#[test]
+	fn emits_repositories_and_packages_in_cache_order() {
+		let flags = [
+			"--image",
+			"archlinux:base",
+			"-p",
+			"vim git vim",
+			"-a",
+			"yay-bin",
+			"--chaotic-aur",
+			"--landware",
+			"--update-system",
+			"--update-pkgfile",
+		];
+		assert_eq!(emit(&flags, &[]), include_str!("../testdata/containerfile/packages.containerfile"));
+	}
+
+	#[test]
+	fn emits_toolchain_setup_after_its_packages() {
+		let flags = ["--toolchain", "rust@nightly", "-p", "c++-tools"];
+		assert_eq!(emit(&flags, &[]), include_str!("../testdata/containerfile/toolchain.containerfile"));
+	}
+
+	#[test]
+	fn emits_build_instructions_and_escaped_env() {
+		let flags = ["--build", "RUN echo hi", "--build", "WORKDIR /srv\n"];
+		// a value spanning lines cannot be an ENV line, so it is left out
+		let env = [("PLAIN", "value"), ("QUOTED", r#"say "hi" to $USER\n"#), ("MULTI", "a\nb")];
+		assert_eq!(emit(&flags, &env), include_str!("../testdata/containerfile/build-env.containerfile"));
+	}
+
+	#[test]
+	fn quotes_only_what_needs_it() {
+		assert_eq!(shell_quote("c++-tools"), "c++-tools");
+		assert_eq!(shell_quote("user@host:/path,x=y"), "user@host:/path,x=y");
+		assert_eq!(shell_quote(""), "''");
+		assert_eq!(shell_quote("two words"), "'two words'");
+		assert_eq!(shell_quote("it's"), r"'it'\''s'");
+		assert_eq!(shell_quote("$HOME"), "'$HOME'");
+	}
+}
@@ -0,0 +1,80 @@
+use std::sync::atomic::{AtomicU8, Ordering};
+
+/// An optional daemon endpoint; minimal runtimes behind the Docker API and some Podman versions lack them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Capability {
+	ExecResize,
+	Pause,
+	Stats,
+}
+
+impl Capability {
+	pub const ALL: [Capability; 3] = [Self::ExecResize, Self::Pause, Self::Stats];
+
+	pub fn name(self) -> &'static str {
+		return match self {
+			Self::ExecResize => "exec resize",
+			Self::Pause => "pause",
+			Self::Stats => "stats",
+		};
+	}
+
+	/// What the session does without it, for the warning when it turns out to be missing.
+	pub fn degraded(self) -> &'static str {
+		return match self {
+			Self::ExecResize => "the session keeps the size it started with",
+			Self::Pause => "systems cannot be paused",
+			Self::Stats => "--guard cannot clamp memory",
+		};
+	}
+}
+
+// each starts out as zero, for not used yet
+const SUPPORTED: u8 = 1;
+const UNSUPPORTED: u8 = 2;
+
+/// What the daemon was found to support, learned the first time each endpoint is used.
+#[derive(Debug, Default)]
+pub struct Capabilities([AtomicU8; Capability::ALL.len()]);
+
+impl Capabilities {
+	/// Whether `capability` is worth trying, which it is until it failed as unsupported.
+	pub fn usable(&self, capability: Capability) -> bool {
+		return self.0[capability as usize].load(Ordering::SeqCst) != UNSUPPORTED;
+	}
+
+	/// Records how using `capability` went. Returns true when it just turned out to be unsupported, so the
+	/// caller warns once and leaves the error behind.
+	pub fn record<T>(&self, capability: Capability, result: &Result<T, bollard::errors::Error>) -> bool {
+		let state = match result {
+			Err(e) if unsupported(e) => UNSUPPORTED,
+			Err(_) => return false,
+			Ok(_) => SUPPORTED,
+		};
+		return self.0[capability as usize].swap(state, Ordering::SeqCst) != UNSUPPORTED && state == UNSUPPORTED;
+	}
+
+	/// One line per capability, for `--verbose`.
+	pub fn map(&self) -> String {
+		return Capability::ALL
+			.iter()
+			.map(|&c| {
+				let state = match self.0[c as usize].load(Ordering::SeqCst) {
+					SUPPORTED => "supported",
+					UNSUPPORTED => "unsupported",
+					_ => "not used",
+				};
+				format!("  {}: {state}\n", c.name())
+			})
+			.collect();
+	}
+}
+
+/// Whether the daemon does not have the endpoint at all, rather than not finding what it was asked about.
+pub fn unsupported(e: &bollard::errors::Error) -> bool {
+	return match e {
+		bollard::errors::Error::DockerResponseServerError { status_code: 404, message } => !message.to_ascii_lowercase().contains("no such"),
+		bollard::errors::Error::DockerResponseServerError { status_code, .. } => matches!(status_code, 405 | 501),
+		_ => false,
+	};
+}
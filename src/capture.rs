@@ -0,0 +1,123 @@
+use std::{collections::BTreeMap, io::Read, path::Path};
+
+use flate2::read::GzDecoder;
+
+use crate::error::Error;
+
+/// Run as the session user with the session's environment and working directory once the command failed, for
+/// `--capture-env-on-failure`; every section starts with a `== name ==` line.
+pub const CAPTURE_SCRIPT: &str = r#"
+echo '== env =='
+env | sort
+echo '== cwd =='
+pwd
+ls -la 2>&1
+echo '== packages =='
+pacman -Q 2>&1
+"#;
+
+/// The sections of a capture, with the name each is stored under in the bug report bundle.
+pub const SECTIONS: &[(&str, &str)] = &[("env", "capture/env.txt"), ("cwd", "capture/cwd.txt"), ("packages", "capture/packages.txt")];
+
+/// Splits the output of `CAPTURE_SCRIPT` into the files of the bundle.
+pub fn files(output: &str) -> Vec<(&'static str, String)> {
+	let mut files: Vec<(&'static str, String)> = vec![];
+	for line in output.lines() {
+		let section = line
+			.strip_prefix("== ")
+			.and_then(|l| l.strip_suffix(" =="))
+			.and_then(|name| SECTIONS.iter().find(|(n, _)| *n == name));
+		match (section, files.last_mut()) {
+			(Some((_, file)), _) => files.push((file, String::new())),
+			(None, Some((_, contents))) => {
+				contents.push_str(line);
+				contents.push('\n');
+			}
+			(None, None) => {}
+		}
+	}
+
+	return files;
+}
+
+/// Whether the system could still run a capture after `e`; not when the daemon itself is out of reach.
+pub fn exec_possible(e: &Error) -> bool {
+	return !matches!(
+		e,
+		Error::Connection(_) | Error::HostScheme(_) | Error::HostUnreachable(..) | Error::SshTunnel(..) | Error::NoEngine(_) | Error::NotConnected
+	);
+}
+
+/// The capture sections in the bundle at `path`, by name.
+fn read(path: &Path) -> Result<BTreeMap<String, String>, Error> {
+	let invalid = |e: std::io::Error| Error::CaptureRead(path.display().to_string(), e);
+	let file = std::fs::File::open(path).map_err(invalid)?;
+	let mut archive = tar::Archive::new(GzDecoder::new(file));
+	let mut sections = BTreeMap::new();
+	for entry in archive.entries().map_err(invalid)? {
+		let mut entry = entry.map_err(invalid)?;
+		let name = entry
+			.path()
+			.map_err(invalid)?
+			.to_string_lossy()
+			.into_owned();
+		let Some((section, _)) = SECTIONS.iter().find(|(_, file)| *file == name) else {
+			continue;
+		};
+		let mut contents = String::new();
+		entry.read_to_string(&mut contents).map_err(invalid)?;
+		sections.insert(section.to_string(), contents);
+	}
+	if sections.is_empty() {
+		return Err(Error::NoCapture(path.display().to_string()));
+	}
+
+	return Ok(sections);
+}
+
+/// Lines of a section by what identifies them: the variable of `NAME=value`, the package of `name version`, and the
+/// whole line otherwise.
+fn keyed(section: &str, contents: &str) -> BTreeMap<String, String> {
+	return contents
+		.lines()
+		.map(|line| {
+			let key = match section {
+				"env" => line.split_once('=').map(|(k, _)| k),
+				"packages" => line.split_once(' ').map(|(k, _)| k),
+				_ => None,
+			};
+			return (key.unwrap_or(line).to_string(), line.to_string());
+		})
+		.collect();
+}
+
+/// A readable diff of the captures in the bundles `a` and `b`: per section, `-` for lines only `a` has, `+` for lines
+/// only `b` has, and both for a variable or package that differs.
+pub fn diff(a: &Path, b: &Path) -> Result<String, Error> {
+	let (a, b) = (read(a)?, read(b)?);
+	let mut out = String::new();
+	for (section, _) in SECTIONS {
+		let old = keyed(section, a.get(*section).map_or("", String::as_str));
+		let new = keyed(section, b.get(*section).map_or("", String::as_str));
+		let mut keys: Vec<&String> = old.keys().chain(new.keys()).collect();
+		keys.sort();
+		keys.dedup();
+		let mut lines = vec![];
+		for key in keys {
+			match (old.get(key), new.get(key)) {
+				(Some(was), Some(now)) if was == now => {}
+				(was, now) => {
+					lines.extend(was.map(|l| format!("  - {l}")));
+					lines.extend(now.map(|l| format!("  + {l}")));
+				}
+			}
+		}
+		if lines.is_empty() {
+			out.push_str(&format!("{section}: same\n"));
+		} else {
+			out.push_str(&format!("{section}:\n{}\n", lines.join("\n")));
+		}
+	}
+
+	return Ok(out);
+}
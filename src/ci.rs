@@ -0,0 +1,91 @@
+use std::path::Path;
+
+use serde::Serialize;
+
+use crate::{Args, color::ColorWhen, error::Error, progress, redact::redact};
+
+/// What `--ci` exits with when the system could not be set up, the same as `docker run` when it fails before the
+/// command starts, so a broken environment is not mistaken for a failing command.
+pub const EXIT_SETUP_FAILED: u8 = 125;
+/// What `--ci` exits with when the command does not exist in the system, like a shell.
+pub const EXIT_COMMAND_NOT_FOUND: u8 = 127;
+
+/// Variables CI services set, so a run without `--ci` can point it out.
+const CI_VARIABLES: &[&str] = &["CI", "GITHUB_ACTIONS", "GITLAB_CI", "BUILDKITE", "JENKINS_URL"];
+
+pub fn detected() -> bool {
+	return CI_VARIABLES
+		.iter()
+		.any(|var| std::env::var(var).is_ok_and(|v| !v.is_empty() && v != "false" && v != "0"));
+}
+
+/// Turns on everything `--ci` implies that was not set the other way on the command line.
+pub fn apply_preset(args: &mut Args) {
+	if !args.tty {
+		args.no_tty = true;
+	}
+	if !args.suggest {
+		args.no_suggest = true;
+	}
+	if !args.verbose {
+		args.quiet = true;
+	}
+	args.color.get_or_insert(ColorWhen::Never);
+	args.progress.get_or_insert(progress::Output::Plain);
+}
+
+/// The exit code for a setup that failed with `e` under `--ci`.
+pub fn exit_code(e: &Error) -> u8 {
+	return match e {
+		Error::CommandNotFound(_) => EXIT_COMMAND_NOT_FOUND,
+		_ => EXIT_SETUP_FAILED,
+	};
+}
+
+/// The exit code for a setup that failed with `e`, on any backend: `exit_code` under `--ci`, otherwise 1.
+pub fn setup_failed_exit_code(e: &Error, ci: bool) -> u8 {
+	return if ci { exit_code(e) } else { 1 };
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Outcome {
+	/// the command ran; its exit code is the report's
+	Completed,
+	SetupFailed,
+	Cancelled,
+}
+
+#[derive(Serialize, Debug)]
+pub struct ReportedError {
+	pub code: &'static str,
+	pub message: String,
+}
+
+impl From<&Error> for ReportedError {
+	fn from(e: &Error) -> Self {
+		return Self {
+			code: e.code(),
+			message: redact(&e.to_string()).into_owned(),
+		};
+	}
+}
+
+/// The summary `--ci-report` writes.
+#[derive(Serialize, Debug)]
+pub struct Report {
+	pub exit_code: u8,
+	pub outcome: Outcome,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub error: Option<ReportedError>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub system: Option<String>,
+	pub duration_secs: f64,
+}
+
+pub fn write_report(path: &Path, report: &Report) -> Result<(), Error> {
+	let write = || -> std::io::Result<()> {
+		return std::fs::write(path, serde_json::to_string_pretty(report).map_err(std::io::Error::other)? + "\n");
+	};
+	return write().map_err(|e| Error::StateWrite(path.display().to_string(), e));
+}
@@ -0,0 +1,341 @@
+/// Where the output being classified came from, since package manager wording means nothing in other output.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Phase {
+	/// pacman or an AUR helper running a transaction: updates, installs, adding repositories
+	Packages,
+
+	/// any other setup script
+	Setup,
+}
+
+/// What a failed command's output says went wrong. `line` is the line that gave it away.
+#[derive(Debug, Clone, PartialEq)]
+pub enum FailureKind {
+	/// a package or database signature did not verify, usually from an outdated keyring
+	Signature {
+		line: String,
+	},
+
+	/// a mirror could not be reached or did not have a file
+	Mirror {
+		line: String,
+	},
+
+	DiskFull {
+		line: String,
+	},
+
+	/// something was refused for its timestamps
+	ClockSkew {
+		line: String,
+	},
+
+	/// a binary for another architecture was run; `path` is the binary, when the output names it
+	ArchMismatch {
+		path: Option<String>,
+	},
+
+	/// packages conflict with each other or with files already on the system
+	DependencyConflict {
+		line: String,
+	},
+
+	Unknown,
+}
+
+/// What pacman, gpg, and curl say when a timestamp is off, lowercased.
+const CLOCK_SKEW_PATTERNS: &[&str] = &[
+	"time warp or clock problem",
+	"signature made in the future",
+	"is newer than the current time",
+	"certificate is not yet valid",
+	"not yet activated",
+	"clock skew detected",
+];
+
+/// Messages the kernel's ENOEXEC shows up as, from shells and from programs reporting a failed exec.
+const EXEC_FORMAT_PATTERNS: &[&str] = &["exec format error", "cannot execute binary file"];
+
+const DISK_FULL_PATTERNS: &[&str] = &["no space left on device", "not enough free disk space", "disk quota exceeded"];
+
+const SIGNATURE_PATTERNS: &[&str] = &[
+	"invalid or corrupted package (pgp signature)",
+	"invalid or corrupted database (pgp signature)",
+	"is unknown trust",
+	"is marginal trust",
+	"required key missing from keyring",
+	"could not be looked up remotely",
+	"signature is invalid",
+];
+
+const DEPENDENCY_CONFLICT_PATTERNS: &[&str] = &[
+	"unresolvable package conflicts detected",
+	"conflicting files",
+	"exists in filesystem",
+	"could not satisfy dependencies",
+	"unable to satisfy dependency",
+	"breaks dependency",
+	"conflicting dependencies",
+];
+
+const MIRROR_PATTERNS: &[&str] = &[
+	"failed retrieving file",
+	"failed to retrieve some files",
+	"failed to synchronize all databases",
+	"could not resolve host",
+	"operation too slow",
+	"connection timed out",
+	"download library error",
+	"the requested url returned error",
+];
+
+/// The first line of `output` containing one of `patterns`, which are lowercase.
+fn find_line<'a>(output: &'a str, patterns: &[&str]) -> Option<&'a str> {
+	return output.lines().map(str::trim).find(|line| {
+		let line = line.to_lowercase();
+		return patterns.iter().any(|p| line.contains(p));
+	});
+}
+
+/// Works out what went wrong from a failed command's `output`. Earlier checks win, since a wrong clock also breaks
+/// signatures and a full disk also fails downloads.
+pub fn classify(phase: Phase, output: &str) -> FailureKind {
+	if let Some(line) = find_line(output, CLOCK_SKEW_PATTERNS) {
+		return FailureKind::ClockSkew { line: line.into() };
+	}
+	if let Some(line) = find_line(output, EXEC_FORMAT_PATTERNS) {
+		return FailureKind::ArchMismatch { path: exec_format_path(line) };
+	}
+	if let Some(line) = find_line(output, DISK_FULL_PATTERNS) {
+		return FailureKind::DiskFull { line: line.into() };
+	}
+	if phase != Phase::Packages {
+		return FailureKind::Unknown;
+	}
+	if let Some(line) = find_line(output, SIGNATURE_PATTERNS) {
+		return FailureKind::Signature { line: line.into() };
+	}
+	if let Some(line) = find_line(output, DEPENDENCY_CONFLICT_PATTERNS) {
+		return FailureKind::DependencyConflict { line: line.into() };
+	}
+	if let Some(line) = find_line(output, MIRROR_PATTERNS) {
+		return FailureKind::Mirror { line: line.into() };
+	}
+
+	return FailureKind::Unknown;
+}
+
+impl FailureKind {
+	/// A script to run as root before trying a transaction that failed like this once more, for failures that
+	/// usually go away with it.
+	pub fn retry_script(&self) -> Option<&'static str> {
+		return match self {
+			Self::Signature { .. } => Some("pacman -Sy --needed --noconfirm archlinux-keyring"),
+			Self::Mirror { .. } => Some("pacman -Syy"),
+			_ => None,
+		};
+	}
+}
+
+/// The binary in lines like `bash: ./tool: cannot execute binary file: Exec format error`,
+/// `exec /usr/bin/tool: exec format error`, or zsh's `zsh: exec format error: ./tool`.
+fn exec_format_path(line: &str) -> Option<String> {
+	let lower = line.to_lowercase();
+	let (start, end) = EXEC_FORMAT_PATTERNS
+		.iter()
+		.filter_map(|p| lower.find(p).map(|start| (start, start + p.len())))
+		.min()?;
+	let before = line[..start].trim_end().trim_end_matches(':');
+	let after = line[end..].trim_start_matches(':').trim_start();
+	let path = before
+		.rsplit([':', ' '])
+		.find(|word| !word.is_empty())
+		.filter(|word| word.contains('/'))
+		.or_else(|| after.split_whitespace().next().filter(|word| word.contains('/')))?;
+
+	return Some(path.to_string());
+}
+
+/// Prints the system's architecture, then what `file` or `readelf` say about `$1`.
+pub const ARCH_PROBE_SCRIPT: &str = r#"uname -m
+[ -n "$1" ] || exit 0
+path="$(command -v "$1" || printf %s "$1")"
+if command -v file >/dev/null; then
+	file -bL "$path"
+elif command -v readelf >/dev/null; then
+	readelf -h "$path" | sed -n 's/^ *Machine: *//p'
+fi
+"#;
+
+/// Machine names as `file` and `readelf` print them, by the `uname -m` they run on.
+const MACHINES: &[(&str, &[&str])] = &[
+	("x86_64", &["x86-64", "Advanced Micro Devices X86-64"]),
+	("aarch64", &["ARM aarch64", "AArch64"]),
+	("i686", &["Intel 80386", "Intel i386"]),
+	("armv7l", &["ARM, EABI5", "ARM"]),
+	("riscv64", &["RISC-V"]),
+];
+
+/// A hint for an exec format error, from `ARCH_PROBE_SCRIPT`'s output.
+pub fn arch_hint(path: Option<&str>, probe: &str) -> String {
+	let mut lines = probe.lines();
+	let system = lines.next().unwrap_or_default().trim();
+	let description = lines.next().unwrap_or_default().trim();
+	let binary = MACHINES
+		.iter()
+		.find(|(_, names)| names.iter().any(|name| description.contains(name)))
+		.map(|(arch, _)| *arch);
+	let what = path.map_or("a binary".to_string(), |p| format!("`{p}`"));
+	let fix = "use an image built for that architecture (or its variant for it with --platform), or install qemu-user-static with binfmt support on the host to emulate it";
+	return match binary {
+		Some(binary) if binary != system => format!("{what} is built for {binary}, but the system runs {system}; {fix}"),
+		_ if system.is_empty() => format!("{what} could not be executed, probably because it is built for another architecture; {fix}"),
+		_ => format!("{what} could not be executed on {system}, probably because it is built for another architecture; {fix}"),
+	};
+}
+
+#[cfg(test)]
+mod tests {
+	use std::path::Path;
+
+	use super::*;
+
+	fn kind_name(kind: &FailureKind) -> &'static str {
+		return match kind {
+			FailureKind::Signature { .. } => "signature",
+			FailureKind::Mirror { .. } => "mirror",
+			FailureKind::DiskFull { .. } => "disk-full",
+			FailureKind::ClockSkew { .. } => "clock-skew",
+			FailureKind::ArchMismatch { .. } => "arch-mismatch",
+			FailureKind::DependencyConflict { .. } => "dependency-conflict",
+			FailureKind::Unknown => "unknown",
+		};
+	}
+
+	/// Every captured output under `testdata/classify/<phase>/<kind>/` is classified as that kind, with the line that
+	/// gave it away taken from the output. A new pattern comes with its output dropped in there.
+	#[test]
+	fn classifies_the_corpus() {
+		let corpus = Path::new(env!("CARGO_MANIFEST_DIR")).join("testdata/classify");
+		let mut checked = 0;
+		for (phase_dir, phase) in [("packages", Phase::Packages), ("setup", Phase::Setup)] {
+			for kind_dir in std::fs::read_dir(corpus.join(phase_dir)).unwrap() {
+				let kind_dir = kind_dir.unwrap();
+				let expected = kind_dir.file_name().into_string().unwrap();
+				for fixture in std::fs::read_dir(kind_dir.path()).unwrap() {
+					let path = fixture.unwrap().path();
+					let output = std::fs::read_to_string(&path).unwrap();
+					let kind = classify(phase, &output);
+					assert_eq!(kind_name(&kind), expected, "{}: {kind:?}", path.display());
+					match &kind {
+						FailureKind::Signature { line }
+						| FailureKind::Mirror { line }
+						| FailureKind::DiskFull { line }
+						| FailureKind::ClockSkew { line }
+						| FailureKind::DependencyConflict { line } => {
+							assert!(output.lines().any(|l| l.trim() == line), "{}: `{line}` is not a line of it", path.display());
+						}
+						FailureKind::ArchMismatch { path: Some(binary) } => {
+							assert!(output.contains(binary.as_str()), "{}: `{binary}` is not in it", path.display());
+						}
+						FailureKind::ArchMismatch { path: None } | FailureKind::Unknown => {}
+					}
+					checked += 1;
+				}
+			}
+		}
+		assert!(checked > 0, "no fixtures in {}", corpus.display());
+	}
+
+	#[test]
+	fn clock_skew_wins_over_what_it_breaks() {
+		let output = "gpg: key 4AA4767BBC9C4B1D was created 2 days in the future (time warp or clock problem)\n\
+		              error: archlinux-keyring: signature from \"Pierre Schmitz\" is unknown trust\n";
+		assert!(matches!(classify(Phase::Packages, output), FailureKind::ClockSkew { .. }));
+	}
+
+	#[test]
+	fn disk_full_wins_over_failed_downloads() {
+		let output = "error: failed retrieving file 'core.db' from mirror : Failed writing body\n\
+		              error: could not write: No space left on device\n";
+		assert_eq!(
+			classify(Phase::Packages, output),
+			FailureKind::DiskFull {
+				line: "error: could not write: No space left on device".into()
+			}
+		);
+	}
+
+	#[test]
+	fn package_wording_only_counts_in_transactions() {
+		let output = "error: failed retrieving file 'core.db' from mirror : Could not resolve host: mirror\n";
+		assert!(matches!(classify(Phase::Packages, output), FailureKind::Mirror { .. }));
+		assert_eq!(classify(Phase::Setup, output), FailureKind::Unknown);
+	}
+
+	#[test]
+	fn finds_the_binary_of_an_exec_format_error() {
+		assert_eq!(exec_format_path("bash: ./tool: cannot execute binary file: Exec format error"), Some("./tool".into()));
+		assert_eq!(exec_format_path("exec /usr/bin/node: exec format error"), Some("/usr/bin/node".into()));
+		assert_eq!(exec_format_path("zsh: exec format error: ./build/tool"), Some("./build/tool".into()));
+		assert_eq!(exec_format_path("/usr/bin/env: 'node': Exec format error"), None);
+		assert_eq!(exec_format_path("exec format error"), None);
+		assert_eq!(exec_format_path("qemu: tool: exec format error"), None);
+		assert_eq!(exec_format_path("zsh: exec format error: tool"), None);
+	}
+
+	#[test]
+	fn only_fixable_failures_are_retried() {
+		let line = String::new;
+		assert!(FailureKind::Signature { line: line() }.retry_script().is_some());
+		assert!(FailureKind::Mirror { line: line() }.retry_script().is_some());
+		assert!(FailureKind::DiskFull { line: line() }.retry_script().is_none());
+		assert!(FailureKind::ClockSkew { line: line() }.retry_script().is_none());
+		assert!(FailureKind::DependencyConflict { line: line() }.retry_script().is_none());
+		assert!(FailureKind::ArchMismatch { path: None }.retry_script().is_none());
+		assert!(FailureKind::Unknown.retry_script().is_none());
+	}
+
+	#[test]
+	fn hints_at_the_architectures() {
+		let hint = arch_hint(Some("/usr/bin/tool"), "x86_64\nELF 64-bit LSB executable, ARM aarch64, version 1 (SYSV)\n");
+		assert!(hint.starts_with("`/usr/bin/tool` is built for aarch64, but the system runs x86_64;"), "{hint}");
+		let hint = arch_hint(None, "x86_64\n");
+		assert!(hint.starts_with("a binary could not be executed on x86_64,"), "{hint}");
+		let hint = arch_hint(None, "");
+		assert!(hint.starts_with("a binary could not be executed, probably"), "{hint}");
+	}
+
+	#[test]
+	fn names_the_binary_architecture_from_file_or_readelf() {
+		for (description, binary) in [
+			("ELF 64-bit LSB pie executable, x86-64, version 1 (SYSV), dynamically linked", "x86_64"),
+			("Advanced Micro Devices X86-64", "x86_64"),
+			("ELF 64-bit LSB executable, ARM aarch64, version 1 (SYSV), statically linked", "aarch64"),
+			("AArch64", "aarch64"),
+			("ELF 32-bit LSB executable, Intel 80386, version 1 (SYSV)", "i686"),
+			("ELF 32-bit LSB executable, ARM, EABI5 version 1 (SYSV)", "armv7l"),
+			("ELF 64-bit LSB executable, UCB RISC-V, RVC, double-float ABI", "riscv64"),
+		] {
+			let hint = arch_hint(Some("./tool"), &format!("ppc64le\n{description}\n"));
+			assert!(hint.starts_with(&format!("`./tool` is built for {binary}, but the system runs ppc64le;")), "{description}: {hint}");
+		}
+	}
+
+	#[test]
+	fn does_not_blame_a_binary_for_the_right_architecture() {
+		// a script with a bad interpreter line fails the same way, but the probe shows nothing foreign
+		for probe in ["x86_64\nELF 64-bit LSB pie executable, x86-64, version 1 (SYSV)\n", "x86_64\nPOSIX shell script, ASCII text executable\n"] {
+			let hint = arch_hint(Some("/usr/bin/tool"), probe);
+			assert!(hint.starts_with("`/usr/bin/tool` could not be executed on x86_64, probably"), "{hint}");
+		}
+	}
+
+	#[test]
+	fn every_hint_suggests_a_way_out() {
+		for (path, probe) in [(Some("./tool"), "x86_64\nARM aarch64\n"), (None, "aarch64\n"), (None, "")] {
+			let hint = arch_hint(path, probe);
+			assert!(hint.contains("--platform") && hint.contains("binfmt"), "{hint}");
+		}
+	}
+}
@@ -0,0 +1,78 @@
+use std::{
+	process::Stdio,
+	sync::{Arc, Mutex},
+};
+
+use crate::{backend, engine::Endpoint, error::Error, print_error};
+
+/// The exit code after a panic, same as Rust's default.
+pub const EXIT_PANIC: i32 = 101;
+
+/// Makes every panic fatal, deleting the system `live_container` holds before the process exits, unless `keep`.
+/// With `cli`, the system is deleted through that program, otherwise through a fresh connection to `endpoint`,
+/// since the runtime the panic came from may be the one that would have to drive the delete.
+pub fn install_panic_hook(live_container: Arc<Mutex<Option<String>>>, cli: Option<String>, endpoint: Option<Endpoint>, keep: bool) {
+	let previous = std::panic::take_hook();
+	std::panic::set_hook(Box::new(move |info| {
+		previous(info);
+		// the panic may have happened while the lock was held
+		let id = live_container
+			.lock()
+			.unwrap_or_else(|e| e.into_inner())
+			.take();
+		if let Some(id) = id {
+			if keep {
+				print_error!(format!("note: kept system `{id}` after a crash"));
+			} else if let Err(e) = remove(&id, cli.as_deref(), endpoint.as_ref()) {
+				print_error!(format!("could not delete system `{id}` after a crash, remove it with `docker rm -f {id}`"), e);
+			}
+		}
+		std::process::exit(EXIT_PANIC);
+	}));
+}
+
+fn remove(id: &str, cli: Option<&str>, endpoint: Option<&Endpoint>) -> Result<(), Error> {
+	if let Some(program) = cli {
+		let output = std::process::Command::new(program)
+			.args(["rm", "--force", id])
+			.stdin(Stdio::null())
+			.output()
+			.map_err(|e| Error::CliRun(program.into(), e))?;
+		if !output.status.success() {
+			return Err(Error::CliFailed(
+				format!("{program} rm"),
+				backend::exit_code(output.status),
+				String::from_utf8_lossy(&output.stderr).trim().to_string(),
+			));
+		}
+		return Ok(());
+	}
+
+	let Some(endpoint) = endpoint.cloned() else {
+		return Err(Error::NotConnected);
+	};
+	let id = id.to_string();
+	return std::thread::spawn(move || {
+		let runtime = tokio::runtime::Builder::new_current_thread()
+			.enable_all()
+			.build()
+			.map_err(Error::Runtime)?;
+		return runtime.block_on(async {
+			let (docker, _tunnel) = endpoint.connect().await?;
+			return docker
+				.remove_container(
+					&id,
+					Some(
+						bollard::query_parameters::RemoveContainerOptionsBuilder::default()
+							.force(true)
+							.build(),
+					),
+				)
+				.await
+				.map_err(Error::ContainerDelete);
+		});
+	})
+	.join()
+	// a panic in there went through this hook as well, which exits before this could be reached
+	.unwrap_or(Ok(()));
+}
@@ -0,0 +1,44 @@
+use std::{
+	io::IsTerminal,
+	sync::atomic::{AtomicBool, Ordering},
+};
+
+use clap::ValueEnum;
+use colorize::AnsiColor;
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq)]
+pub enum ColorWhen {
+	/// when stdout is a terminal and `NO_COLOR` is not set
+	Auto,
+	Always,
+	Never,
+}
+
+static ENABLED: AtomicBool = AtomicBool::new(true);
+
+/// Decides once, before anything is printed, whether messages are colored.
+pub fn init(when: ColorWhen) {
+	let enabled = match when {
+		ColorWhen::Auto => std::env::var_os("NO_COLOR").is_none_or(|v| v.is_empty()) && std::io::stdout().is_terminal(),
+		ColorWhen::Always => true,
+		ColorWhen::Never => false,
+	};
+	ENABLED.store(enabled, Ordering::Relaxed);
+}
+
+fn paint(s: impl Into<String>, color: fn(String) -> String) -> String {
+	let s = s.into();
+	return if ENABLED.load(Ordering::Relaxed) { color(s) } else { s };
+}
+
+pub fn red(s: impl Into<String>) -> String {
+	return paint(s, AnsiColor::red);
+}
+
+pub fn yellow(s: impl Into<String>) -> String {
+	return paint(s, AnsiColor::yellow);
+}
+
+pub fn green(s: impl Into<String>) -> String {
+	return paint(s, AnsiColor::green);
+}
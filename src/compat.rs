@@ -0,0 +1,119 @@
+use serde::Serialize;
+
+use crate::features::{DEFAULT_USER, ImageFeatures};
+
+/// How well one part of tempsystem works with an image.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "snake_case", tag = "status", content = "reason")]
+pub enum Support {
+	Works,
+	/// works, but not like with our images
+	Degraded(String),
+	Fails(String),
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct Row {
+	pub feature: &'static str,
+	#[serde(flatten)]
+	pub support: Support,
+	/// whether the system cannot be entered at all when this fails
+	pub required: bool,
+}
+
+/// What `tempsystem check-image` prints with --json.
+#[derive(Serialize, Debug)]
+pub struct Report<'a> {
+	pub image: &'a str,
+	pub compatible: bool,
+	pub features: &'a [Row],
+}
+
+/// What works with an image that has `features`, probed like `features::probe_script` does before every session.
+pub fn matrix(features: &ImageFeatures) -> Vec<Row> {
+	let pacman = features.pacman != Some(false);
+	let user = features.user();
+	let entering = match &features.user {
+		Some(Some(_)) | None => Support::Works,
+		Some(None) if pacman => {
+			Support::Degraded(format!("no `{DEFAULT_USER}` user; it is created on start, unless --no-bootstrap-user or --ro-root is given"))
+		}
+		Some(None) => Support::Fails(format!("no `{DEFAULT_USER}` user, and no pacman to create it with")),
+	};
+	let shell = match features.shell.as_ref().map(|s| s.as_deref()) {
+		Some(Some(shell)) if !shell.ends_with("zsh") => Support::Degraded(format!("no zsh; sessions run {shell}")),
+		Some(None) => Support::Fails("no shell found".into()),
+		_ => Support::Works,
+	};
+	let installing = if !pacman {
+		Support::Fails("no pacman".into())
+	} else if features.sudo == Some(false) && features.missing_user() {
+		Support::Degraded("no sudo; it is installed along with the user".into())
+	} else if !features.full_sudo() {
+		Support::Degraded(format!("`{user}` has no passwordless sudo for everything; these run in execs as root instead"))
+	} else {
+		Support::Works
+	};
+	let aur = if features.aur_helper().is_none() {
+		Support::Fails("no AUR helper (yay or paru)".into())
+	} else if !pacman {
+		installing.clone()
+	} else if !features.sudo_pacman() {
+		Support::Fails(format!("`{user}` cannot run pacman through sudo without a password, which AUR helpers need"))
+	} else {
+		Support::Works
+	};
+	let pkgfile = if features.pkgfile == Some(false) {
+		Support::Fails("no pkgfile".into())
+	} else {
+		installing.clone()
+	};
+
+	return vec![
+		Row {
+			feature: "entering the system",
+			support: entering,
+			required: true,
+		},
+		Row {
+			feature: "shell",
+			support: shell,
+			required: true,
+		},
+		Row {
+			feature: "--extra-packages, --update-system, --chaotic-aur, --landware",
+			support: installing,
+			required: false,
+		},
+		Row {
+			feature: "--extra-aur-packages",
+			support: aur,
+			required: false,
+		},
+		Row {
+			feature: "--update-pkgfile",
+			support: pkgfile,
+			required: false,
+		},
+	];
+}
+
+/// Whether the system can be entered at all.
+pub fn compatible(rows: &[Row]) -> bool {
+	return !rows
+		.iter()
+		.any(|r| r.required && matches!(r.support, Support::Fails(_)));
+}
+
+/// One line per row, `works`, `degraded`, or `fails` first, with the reason after the feature.
+pub fn describe(rows: &[Row]) -> String {
+	return rows
+		.iter()
+		.map(|row| match &row.support {
+			Support::Works => format!("works     {}", row.feature),
+			Support::Degraded(reason) => format!("degraded  {}: {reason}", row.feature),
+			Support::Fails(reason) => format!("fails     {}: {reason}", row.feature),
+		})
+		.collect::<Vec<String>>()
+		.join("\n");
+}
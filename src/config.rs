@@ -0,0 +1,374 @@
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+
+use crate::{error::Error, size, statefile, suggest, template};
+
+/// Defaults read from `$XDG_CONFIG_HOME/tempsystem/config.toml`; command line flags add to or override them.
+/// Unknown keys are refused, so a typo does not go unnoticed.
+#[derive(Deserialize, Debug, Default)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Config {
+	/// host environment variables copied into every exec, same as `--inherit-env`
+	pub inherit_env: Vec<String>,
+
+	/// flags the `ts` alias from `tempsystem generate-alias` always passes, e.g. `["--chaotic-aur"]`
+	pub default_args: Vec<String>,
+
+	/// packages installed into every system, same as `--extra-packages`
+	pub packages: Vec<String>,
+
+	/// AUR packages installed into every system, same as `--extra-aur-packages`
+	pub aur_packages: Vec<String>,
+
+	/// image flavor, same as `--flavor`
+	pub flavor: Option<String>,
+
+	/// extra environment variable names, as globs, whose values are redacted from everything tempsystem prints,
+	/// on top of `*_TOKEN`, `*_SECRET`, and `*PASSWORD*`
+	pub secret_patterns: Vec<String>,
+
+	/// writable layer sizes in GiB at which to warn during a session, same as `--storage-warn`
+	pub storage_warnings: Option<Vec<u64>>,
+
+	/// cgroup every system is created under, same as `--cgroup-parent`
+	pub cgroup_parent: Option<String>,
+
+	/// size of /dev/shm in every system, like `1g`, same as `--shm-size`
+	#[serde(deserialize_with = "size::deserialize")]
+	pub shm_size: Option<u64>,
+
+	/// command line the session runs when no command is given, instead of a shell; a template, like
+	/// `tmux new -A -s {project}`, with the placeholders `tempsystem placeholders` lists
+	pub default_command: Option<String>,
+
+	/// the `tempsystem.toml` of the project the current directory is in, if any
+	#[serde(skip)]
+	pub project: Option<(PathBuf, ProjectConfig)>,
+}
+
+/// Settings from a project's `tempsystem.toml`, shared by everyone working on it.
+#[derive(Deserialize, Debug, Default)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct ProjectConfig {
+	pub packages: Vec<String>,
+	pub aur_packages: Vec<String>,
+	/// the digest, like `sha256:<hex>`, every run from the project uses the image at; see `tempsystem pin-image`
+	#[serde(alias = "image_digest")]
+	pub image_digest: Option<String>,
+}
+
+/// Marks a project directory; the `ts` alias enters a kept system with `--reuse` anywhere below one.
+pub const PROJECT_FILE: &str = "tempsystem.toml";
+
+/// The nearest `tempsystem.toml` in `start` or one of its parents.
+pub fn find_project_file(start: &Path) -> Option<PathBuf> {
+	return start
+		.ancestors()
+		.map(|dir| dir.join(PROJECT_FILE))
+		.find(|file| file.is_file());
+}
+
+pub fn config_dir() -> Option<PathBuf> {
+	return std::env::var_os("XDG_CONFIG_HOME")
+		.map(PathBuf::from)
+		.filter(|p| p.is_absolute())
+		.or_else(|| std::env::home_dir().map(|h| h.join(".config")))
+		.map(|p| p.join("tempsystem"));
+}
+
+/// Where tempsystem keeps what it learns about its use, like setup durations and remembered flags.
+pub fn state_dir() -> Option<PathBuf> {
+	return std::env::var_os("XDG_STATE_HOME")
+		.map(PathBuf::from)
+		.filter(|p| p.is_absolute())
+		.or_else(|| std::env::home_dir().map(|h| h.join(".local/state")))
+		.map(|p| p.join("tempsystem"));
+}
+
+/// Sets `image-digest` in the project file at `path` to `digest`, creating the file when there is none. Only that value
+/// changes, so comments, formatting, and line endings around it stay as they were.
+pub fn write_image_digest(path: &Path, digest: &str) -> Result<(), Error> {
+	let contents = match std::fs::read_to_string(path) {
+		Ok(contents) => contents,
+		Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+		Err(e) => return Err(Error::ConfigRead(path.display().to_string(), e)),
+	};
+	let mut document: toml_edit::DocumentMut = contents
+		.parse()
+		.map_err(|e: toml_edit::TomlError| Error::ConfigParse(location(path, &contents, e.span()), e.message().trim().to_string()))?;
+	// the pin may be under its alias, which is kept rather than adding the key a second time
+	let key = ["image-digest", "image_digest"]
+		.into_iter()
+		.find(|key| document.contains_key(key))
+		.unwrap_or("image-digest");
+	match document.get_mut(key).and_then(toml_edit::Item::as_value_mut) {
+		Some(value) => {
+			let decor = value.decor().clone();
+			*value = digest.into();
+			*value.decor_mut() = decor;
+		}
+		None => document[key] = toml_edit::value(digest),
+	}
+	let mut written = document.to_string();
+	// toml_edit ends every line it writes with `\n`
+	if contents.contains("\r\n") {
+		written = written.replace("\r\n", "\n").replace('\n', "\r\n");
+	}
+
+	return statefile::write_atomic(path, written.as_bytes()).map_err(|e| Error::StateWrite(path.display().to_string(), e));
+}
+
+/// Parses the toml file at `path`, or `None` when it does not exist.
+fn read_toml<T: serde::de::DeserializeOwned>(path: &Path) -> Result<Option<T>, Error> {
+	let contents = match std::fs::read_to_string(path) {
+		Ok(contents) => contents,
+		Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+		Err(e) => return Err(Error::ConfigRead(path.display().to_string(), e)),
+	};
+
+	return toml::from_str(&contents)
+		.map(Some)
+		.map_err(|e| Error::ConfigParse(location(path, &contents, e.span()), diagnose(&contents, &e)));
+}
+
+/// `path:line:column` of where `span` starts in `contents`, or just `path` without a span.
+fn location(path: &Path, contents: &str, span: Option<std::ops::Range<usize>>) -> String {
+	let Some(start) = span.map(|s| s.start.min(contents.len())) else {
+		return path.display().to_string();
+	};
+	let before = &contents[..start];
+	let line = before.matches('\n').count() + 1;
+	let column = before
+		.rsplit('\n')
+		.next()
+		.unwrap_or_default()
+		.chars()
+		.count()
+		+ 1;
+	return format!("{}:{line}:{column}", path.display());
+}
+
+/// What is wrong in `contents`: the parser's message, the key it is about, and for an unknown key, the known one it
+/// was probably meant to be.
+fn diagnose(contents: &str, e: &toml::de::Error) -> String {
+	let message = e
+		.message()
+		.lines()
+		.next()
+		.unwrap_or_default()
+		.trim()
+		.to_string();
+	// serde words unknown keys as "unknown field `key`, expected one of `a`, `b`"
+	if let Some(rest) = message.strip_prefix("unknown field `")
+		&& let Some((key, expected)) = rest.split_once('`')
+	{
+		let known: Vec<&str> = expected.split('`').skip(1).step_by(2).collect();
+		return match suggest::closest(key, known.iter().copied(), 1).first() {
+			Some(suggestion) => format!("unknown key `{key}`; did you mean `{suggestion}`?"),
+			None => format!("unknown key `{key}`; known keys are {}", known.join(", ")),
+		};
+	}
+	let key = e
+		.span()
+		.and_then(|span| contents.get(..span.start.min(contents.len())))
+		.and_then(|before| {
+			let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+			let line = contents[line_start..].lines().next()?;
+			return Some(line.split_once('=')?.0.trim().to_string());
+		})
+		.filter(|key| !key.is_empty());
+
+	return match key {
+		Some(key) => format!("`{key}`: {message}"),
+		None => message,
+	};
+}
+
+/// Checks the config file at `path` without loading anything else: a `tempsystem.toml` as a project file, anything
+/// else as `config.toml`.
+pub fn validate(path: &Path) -> Result<(), Error> {
+	if path.file_name().is_some_and(|name| name == PROJECT_FILE) {
+		read_toml::<ProjectConfig>(path)?.ok_or_else(|| missing(path))?;
+		return Ok(());
+	}
+	read_toml::<Config>(path)?
+		.ok_or_else(|| missing(path))?
+		.check()?;
+
+	return Ok(());
+}
+
+fn missing(path: &Path) -> Error {
+	return Error::ConfigRead(path.display().to_string(), std::io::ErrorKind::NotFound.into());
+}
+
+impl Config {
+	/// Loads the config file, or the defaults when there is none, along with the current project's `tempsystem.toml`.
+	pub fn load() -> Result<Self, Error> {
+		let mut config: Self = match config_dir().map(|d| d.join("config.toml")) {
+			Some(path) => read_toml(&path)?.unwrap_or_default(),
+			None => Self::default(),
+		};
+		if let Some(path) = std::env::current_dir()
+			.ok()
+			.and_then(|cwd| find_project_file(&cwd))
+			&& let Some(project) = read_toml(&path)?
+		{
+			config.project = Some((path, project));
+		}
+		config.check()?;
+
+		return Ok(config);
+	}
+
+	/// Checks what parsing cannot, like that the default command is a valid template.
+	fn check(&self) -> Result<(), Error> {
+		if let Some(command) = &self.default_command {
+			template::validate(command).map_err(|reason| Error::Template(command.clone(), reason))?;
+		}
+		return Ok(());
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn fixture(name: &str) -> PathBuf {
+		return Path::new(env!("CARGO_MANIFEST_DIR")).join("testdata/config").join(name);
+	}
+
+	/// What `validate` says about the fixture `name`, with the fixture directory cut from the front.
+	fn diagnostic(name: &str) -> String {
+		let Err(Error::ConfigParse(location, reason)) = validate(&fixture(name)) else {
+			panic!("{name} should not parse");
+		};
+		let dir = fixture("");
+		let location = location.strip_prefix(dir.to_str().unwrap()).unwrap_or(&location);
+		return format!("{location}: {reason}");
+	}
+
+	#[test]
+	fn diagnoses_the_corpus() {
+		for (name, expected) in [
+			(
+				"unknown-key.toml",
+				"unknown-key.toml:4:1: unknown key `colour`; known keys are inherit-env, default-args, packages, aur-packages, flavor, \
+				 secret-patterns, storage-warnings, cgroup-parent, shm-size, default-command",
+			),
+			("did-you-mean.toml", "did-you-mean.toml:2:1: unknown key `aur-pakages`; did you mean `aur-packages`?"),
+			("snake-case.toml", "snake-case.toml:2:5: unknown key `default_args`; did you mean `default-args`?"),
+			("project/tempsystem.toml", "project/tempsystem.toml:3:1: unknown key `image-digests`; did you mean `image-digest`?"),
+			("wrong-type.toml", "wrong-type.toml:3:12: `packages`: invalid type: string \"git vim\", expected a sequence"),
+			("bad-size.toml", "bad-size.toml:1:12: `shm-size`: `lots` is not a size like 512m or 4g"),
+			("syntax.toml", "syntax.toml:2:8: `flavor`: unexpected `=` in array, expected value, `]`"),
+		] {
+			assert_eq!(diagnostic(name), expected);
+		}
+	}
+
+	#[test]
+	fn counts_columns_in_characters() {
+		let contents = "# ünïcödé\nflavor = 1\n  ä = 2";
+		let path = Path::new("config.toml");
+		let at = |needle: char| {
+			let start = contents.find(needle).unwrap();
+			return Some(start..start + needle.len_utf8());
+		};
+		assert_eq!(location(path, contents, at('1')), "config.toml:2:10");
+		assert_eq!(location(path, contents, at('ä')), "config.toml:3:3");
+		assert_eq!(location(path, contents, at('2')), "config.toml:3:7");
+		// past the end, like an unterminated value
+		assert_eq!(location(path, contents, Some(1000..1001)), "config.toml:3:8");
+		assert_eq!(location(path, contents, None), "config.toml");
+	}
+
+	/// A project file path in a fresh directory of its own.
+	fn scratch_project(name: &str) -> PathBuf {
+		let dir = std::env::temp_dir().join(format!("tempsystem-pin-{}-{name}", std::process::id()));
+		let _ = std::fs::remove_dir_all(&dir);
+		std::fs::create_dir_all(&dir).unwrap();
+		return dir.join(PROJECT_FILE);
+	}
+
+	/// What `write_image_digest` makes of a project file with `contents`, or of none.
+	fn pinned(name: &str, contents: Option<&str>) -> String {
+		let path = scratch_project(name);
+		if let Some(contents) = contents {
+			std::fs::write(&path, contents).unwrap();
+		}
+		write_image_digest(&path, "sha256:1234").unwrap();
+		let written = std::fs::read_to_string(&path).unwrap();
+		std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+		return written;
+	}
+
+	#[test]
+	fn pins_into_a_new_file() {
+		assert_eq!(pinned("new", None), "image-digest = \"sha256:1234\"\n");
+		assert_eq!(pinned("empty", Some("")), "image-digest = \"sha256:1234\"\n");
+	}
+
+	#[test]
+	fn pins_without_touching_the_rest() {
+		let contents = "# shared by the team\npackages = [\"git\"]  # always\nimage-digest = \"sha256:0000\"  # pinned for the release\n\n# more later\n";
+		assert_eq!(
+			pinned("replace", Some(contents)),
+			"# shared by the team\npackages = [\"git\"]  # always\nimage-digest = \"sha256:1234\"  # pinned for the release\n\n# more later\n"
+		);
+		assert_eq!(pinned("add", Some("packages = [\"git\"]\n")), "packages = [\"git\"]\nimage-digest = \"sha256:1234\"\n");
+	}
+
+	#[test]
+	fn pins_the_key_however_it_is_written() {
+		for (name, contents, expected) in [
+			("quoted", "\"image-digest\" = \"sha256:0000\"\n", "\"image-digest\" = \"sha256:1234\"\n"),
+			("alias", "image_digest = 'sha256:0000'\n", "image_digest = \"sha256:1234\"\n"),
+		] {
+			let written = pinned(name, Some(contents));
+			assert_eq!(written, expected, "{name}");
+			let project: ProjectConfig = toml::from_str(&written).unwrap();
+			assert_eq!(project.image_digest.as_deref(), Some("sha256:1234"), "{name}");
+		}
+	}
+
+	#[test]
+	fn pins_above_a_bracket_in_a_multiline_string() {
+		// the `[` starting a line is not a table, so the pin is still a top-level key
+		let contents = "packages = [\"git\"]\nnotes = \"\"\"\n[not a table]\n\"\"\"\n";
+		let mut written: toml::Table = toml::from_str(&pinned("multiline", Some(contents))).unwrap();
+		assert_eq!(written.remove("notes").unwrap().as_str(), Some("[not a table]\n"));
+		assert_eq!(written.remove("image-digest").unwrap().as_str(), Some("sha256:1234"));
+	}
+
+	#[test]
+	fn pins_keeping_crlf_line_endings() {
+		assert_eq!(
+			pinned("crlf", Some("# windows\r\npackages = [\"git\"]\r\nimage-digest = \"sha256:0000\"\r\n")),
+			"# windows\r\npackages = [\"git\"]\r\nimage-digest = \"sha256:1234\"\r\n"
+		);
+	}
+
+	#[test]
+	fn refuses_to_pin_into_a_broken_file() {
+		let path = scratch_project("broken");
+		std::fs::write(&path, "packages = [\"git\"\n").unwrap();
+		let result = write_image_digest(&path, "sha256:1234");
+		assert_eq!(std::fs::read_to_string(&path).unwrap(), "packages = [\"git\"\n");
+		std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+		let Err(Error::ConfigParse(location, reason)) = result else {
+			panic!("expected a parse error");
+		};
+		assert!(location.ends_with("/tempsystem.toml:1:18"), "{location}");
+		assert_eq!(reason, "unclosed array, expected `]`");
+	}
+
+	#[test]
+	fn accepts_valid_files() {
+		for contents in ["", "packages = [\"git\"]\nshm-size = \"1g\"\ndefault-command = \"tmux new -A -s {project}\"\n"] {
+			let config: Config = toml::from_str(contents).unwrap();
+			assert!(config.check().is_ok(), "{contents}");
+		}
+	}
+}
@@ -1,113 +1,1037 @@
 use std::{
 	collections::HashMap,
 	fs::File,
-	io::{Read, Write},
-	time::Duration,
+	io::{IsTerminal, Read, Stdout, Write},
+	sync::{
+		Arc, Mutex,
+		atomic::{AtomicBool, Ordering},
+	},
+	time::{Duration, Instant},
 };
 
-use bollard::{Docker, query_parameters::UploadToContainerOptions};
+use bollard::{Docker, exec::StartExecResults, query_parameters::UploadToContainerOptions};
 use futures_util::StreamExt;
-use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
-use tar::Builder;
-use termion::{async_stdin, raw::IntoRawMode, terminal_size};
-use thiserror::Error;
-use tokio::io::AsyncWriteExt;
+use indicatif::{HumanBytes, HumanDuration};
+use tar::{Builder, Header};
+use termion::{
+	async_stdin,
+	raw::{IntoRawMode, RawTerminal},
+	terminal_size,
+};
+use tokio::{
+	io::AsyncWriteExt,
+	signal::unix::{SignalKind, signal},
+	sync::mpsc::UnboundedReceiver,
+};
+use tokio_util::{sync::CancellationToken, task::TaskTracker};
+
+pub use crate::error::Error;
+use crate::{
+	Args, PullPolicy, UpdateSystem, ZshHistorySync, alias,
+	backend::BackendKind,
+	bugreport,
+	build::{self, Recipe},
+	capability::{Capabilities, Capability},
+	capture,
+	classify::{self, FailureKind, Phase},
+	color,
+	config::Config,
+	engine::{self, Endpoint, Engine, TlsFiles, Tunnel},
+	env::{self, EnvSource, ExecEnv},
+	escape::{self, EscapeDetector, Input},
+	events::{Event, Events},
+	features::{self, ImageConfig, ImageFeatures, Privilege},
+	firewall, guard,
+	helper::{self, Helper},
+	imageref::ImageRef,
+	names,
+	pacman::DownloadProgress,
+	paused,
+	policy::{self, Policy},
+	print_error, proxy,
+	redact::redact,
+	registry,
+	rng::Rng,
+	session::{self, Flavor, SessionOptions},
+	setup::{self, ImageSource, Target},
+	snapshot, stats,
+	streams::LabeledStream,
+	suggest, template, termcheck,
+	timing::ExecTimings,
+	utf8::{self, Utf8Holdback},
+};
 
-use crate::{Args, ZshHistorySync, print_error};
+pub struct Context {
+	docker: Option<Docker>,
+	engine: Engine,
+	endpoint: Option<Endpoint>,
+	/// forwards the socket of an `ssh://` host for as long as `docker` is used
+	_tunnel: Option<Tunnel>,
+	container_id: String,
+	lifecycle: Mutex<Lifecycle>,
+	/// whether deleting the system deletes its anonymous volumes too, for `--wipe-on-exit`
+	remove_volumes: bool,
+	/// the `--cgroup-parent` the system is created under, to explain the daemon refusing it
+	cgroup_parent: Option<String>,
+	/// the name the system was created under, empty when this run did not create it
+	name: String,
+	reused: bool,
+	env: ExecEnv,
+	features: ImageFeatures,
+	/// credentials for pulls from --registry-user, instead of the docker CLI's
+	registry_login: Option<registry::Login>,
+	/// the platform from --platform, for pulls, builds, and the system
+	platform: Option<String>,
+	/// how setup runs what needs root, picked once the features are known
+	privilege: Privilege,
+	live_container: Arc<Mutex<Option<String>>>,
+	shutdown_token: CancellationToken,
+	token: CancellationToken,
+	tasks: TaskTracker,
+	stdout_closed: AtomicBool,
+	/// whether the session exec gets a tty, see `session_tty`
+	tty: bool,
+	timings: Mutex<ExecTimings>,
+	/// every non-interactive exec's command and output, kept for `--bug-report`
+	transcript: Mutex<Option<Vec<String>>>,
+	events: Events,
+	/// the in-container helper, when it could be started; setup commands fall back to plain execs without it
+	helper: tokio::sync::Mutex<Option<Helper>>,
+	/// the key that starts escape sequences in the session, when they are enabled
+	escape: Option<u8>,
+	/// whether Ctrl-Z in the session stops us rather than the program in the system
+	host_job_control: bool,
+	/// whether a terminal without a readable size or raw mode degrades the session instead of ending it
+	degraded_tty: bool,
+	/// set once the terminal's size could not be read and `DEGRADED_TTY_SIZE` was assumed
+	size_unknown: AtomicBool,
+	/// the optional endpoints the daemon turned out to have, shared with background tasks like `--guard`
+	capabilities: Arc<Capabilities>,
+}
 
-#[derive(Error, Debug)]
-pub enum Error {
-	#[error("could not connect to docker: {0}")]
-	Connection(bollard::errors::Error),
+/// Where the stdin bridge sends input instead of the session's exec, while a root shell is open.
+type OverlayInput = Arc<tokio::sync::Mutex<Option<std::pin::Pin<Box<dyn tokio::io::AsyncWrite + Send>>>>>;
+
+/// A system or image `tempsystem prune` removes.
+#[derive(Debug, Clone)]
+pub enum Leftover {
+	/// `paused_for` is how long ago `--pause-on-detach` paused it
+	System {
+		id: String,
+		name: String,
+		size: u64,
+		paused_for: Option<Duration>,
+	},
+	Image {
+		id: String,
+		repo: String,
+		size: u64,
+	},
+}
 
-	#[error("inner error: docker not connected")]
-	NotConnected,
+impl Leftover {
+	/// The disk space removing it gives back: a system's writable layer, or an image's size.
+	pub fn size(&self) -> u64 {
+		return match self {
+			Self::System { size, .. } | Self::Image { size, .. } => *size,
+		};
+	}
+}
 
-	#[error("could not create image: {0}")]
-	ImageCreate(bollard::errors::Error),
+impl std::fmt::Display for Leftover {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		return match self {
+			Self::System { id, name, paused_for, .. } => {
+				write!(f, "system `{name}` ({})", &id[..id.len().min(12)])?;
+				if let Some(paused_for) = paused_for {
+					write!(f, ", paused {} ago", HumanDuration(*paused_for))?;
+				}
+				return Ok(());
+			}
+			Self::Image { id, repo, .. } => {
+				let id = id.trim_start_matches("sha256:");
+				write!(f, "image {repo} ({})", &id[..id.len().min(12)])
+			}
+		};
+	}
+}
 
-	#[error("could not create container: {0}")]
-	ContainerCreate(bollard::errors::Error),
+/// A system tempsystem created that still exists, as `tempsystem list` shows it.
+#[derive(Debug, Clone)]
+pub struct ListedSystem {
+	pub id: String,
+	pub name: String,
+	/// what the daemon says it is doing, like `running` or `exited`
+	pub state: String,
+	/// how long ago `--pause-on-detach` paused it, if it did
+	pub paused_for: Option<Duration>,
+}
 
-	#[error("could not set container memory limit: {0}")]
-	MemoryLimitSet(bollard::errors::Error),
+impl ListedSystem {
+	pub fn paused(&self) -> bool {
+		return self.state == "paused";
+	}
+}
 
-	#[error("could not start container: {0}")]
-	ContainerStart(bollard::errors::Error),
+impl std::fmt::Display for ListedSystem {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		write!(f, "{} ({}) ", self.name, &self.id[..self.id.len().min(12)])?;
+		if !self.paused() {
+			return write!(f, "{}", self.state);
+		}
+		if let Some(paused_for) = self.paused_for {
+			write!(f, "paused {} ago", HumanDuration(paused_for))?;
+		} else {
+			write!(f, "paused")?;
+		}
+		return write!(f, ", `tempsystem attach {}` continues it", self.name);
+	}
+}
 
-	#[error("could not create exec: {0}")]
-	ExecCreate(bollard::errors::Error),
+/// Where `Context`'s system is in its life, so cleanup only undoes what was done.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum Lifecycle {
+	#[default]
+	NotCreated,
+	Created,
+	Started,
+	Deleted,
+}
 
-	#[error("could not start exec: {0}")]
-	ExecStart(bollard::errors::Error),
+/// Runs as root with `--wipe-on-exit`, overwriting and deleting every file in the home and temporary directories.
+/// `-xdev` keeps it out of mounts, like ~/work with `--ro-cwd-overlay`.
+pub const WIPE_SCRIPT: &str = r#"
+for dir in /home /root /tmp /var/tmp; do
+	[ -d "$dir" ] || continue
+	if command -v shred >/dev/null; then
+		find "$dir" -xdev -type f -exec shred --force --remove --zero --iterations=1 {} + 2>/dev/null
+	fi
+	find "$dir" -xdev -mindepth 1 -delete 2>/dev/null
+done
+sync
+true
+"#;
+
+/// How many lines of a failed build step's output the error shows.
+const BUILD_LOG_TAIL: usize = 30;
+
+/// How long `WIPE_SCRIPT` gets before the system is deleted anyway.
+const WIPE_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Whether removing a container failed only because someone else deleted it already.
+fn already_deleted(e: &bollard::errors::Error) -> bool {
+	return matches!(e, bollard::errors::Error::DockerResponseServerError { status_code: 404, .. });
+}
 
-	#[error("exec was expected to be attached")]
-	ExpectedAttached,
+/// Adds the Chaotic-AUR to pacman.conf unless it is already there. Needs root, like `LANDWARE_SETUP`.
+pub const CHAOTIC_AUR_SETUP: &str = r#"
+grep -q '^\[chaotic-aur\]' /etc/pacman.conf ||
+(pacman-key --init &&
+pacman-key --populate &&
+pacman-key --recv-key 3056513887B78AEB --keyserver keyserver.ubuntu.com &&
+pacman-key --lsign-key 3056513887B78AEB &&
+pacman -U --needed --noconfirm 'https://cdn-mirror.chaotic.cx/chaotic-aur/chaotic-keyring.pkg.tar.zst' &&
+yes | pacman -U --needed --noconfirm 'https://cdn-mirror.chaotic.cx/chaotic-aur/chaotic-mirrorlist.pkg.tar.zst' &&
+printf '\n\n# Added by tempsystem\n[chaotic-aur]\nInclude = /etc/pacman.d/chaotic-mirrorlist' >> /etc/pacman.conf &&
+pacman -Sy --noconfirm)"#;
+
+/// Adds the landware repository to pacman.conf unless it is already there.
+pub const LANDWARE_SETUP: &str = r#"
+grep -q '^\[landware\]' /etc/pacman.conf ||
+(printf '\n\n# Added by tempsystem\n[landware]\nServer = https://repo.kage.sj.strangled.net/landware/x86_64\nSigLevel = DatabaseNever PackageNever TrustedOnly' >> /etc/pacman.conf &&
+pacman -Sy --noconfirm)"#;
+
+/// The size a degraded tty gets when the terminal's size cannot be read, what terminals start out as.
+const DEGRADED_TTY_SIZE: (u16, u16) = (80, 24);
+
+/// How often the size of the system's writable layer is checked during a session.
+const STORAGE_SAMPLE_INTERVAL: Duration = Duration::from_secs(180);
+
+/// How often `--guard` looks at the host.
+const GUARD_SAMPLE_INTERVAL: Duration = Duration::from_secs(3);
+
+/// Writable layer sizes, in GiB, at which a warning is printed when neither the config nor `--storage-warn` set any.
+pub const DEFAULT_STORAGE_WARNINGS: &[u64] = &[5, 10, 20];
+
+/// How long `Context::shutdown` waits for background tasks to stop.
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// How long `tempsystem kill --graceful` gives the system's processes to exit before deleting it.
+const GRACEFUL_KILL_WAIT: Duration = Duration::from_secs(3);
+
+/// Linux's number for the stop signal a terminal sends on Ctrl-Z.
+const SIGTSTP: i32 = 20;
+
+/// Exit code of a process killed by SIGPIPE, used when our stdout is closed mid-session.
+pub const EXIT_BROKEN_PIPE: i64 = 128 + 13;
+
+/// Runs as root for `--update-system=background`. While it runs, a `pacman` in /usr/local/bin waits for it to finish,
+/// so packages installed meanwhile do not trip over the update's database lock.
+pub const BACKGROUND_UPDATE_SCRIPT: &str = r#"
+lock=/tmp/tempsystem-update.lock
+printf '#!/bin/sh\nexec flock %s /usr/bin/pacman "$@"\n' "$lock" > /usr/local/bin/pacman && chmod 755 /usr/local/bin/pacman
+trap 'rm -f /usr/local/bin/pacman' EXIT
+flock "$lock" /usr/bin/pacman -Syu --noconfirm
+"#;
+
+/// How far `--update-system=background` got by the time the session ended.
+#[derive(Debug, Clone, PartialEq)]
+pub enum BackgroundUpdate {
+	Running,
+	Finished,
+	Failed(String),
+}
 
-	// #[error("exec was expected to be detached")]
-	// ExpectedDetached,
-	#[error("could not recv terminal size: {0}")]
-	TerminalSize(std::io::Error),
+impl BackgroundUpdate {
+	/// What the exit summary should say about the update, if anything.
+	pub fn problem(&self) -> Option<String> {
+		return match self {
+			Self::Running => Some("the background system update did not finish before the session ended".into()),
+			Self::Finished => None,
+			Self::Failed(e) => Some(format!("the background system update failed: {e}")),
+		};
+	}
+}
 
-	#[error("could not resize exec: {0}")]
-	ExecResize(bollard::errors::Error),
+/// Images with `label`, which is either just a key or `key=value`.
+async fn list_labeled_images(docker: &Docker, label: &str) -> Result<Vec<bollard::models::ImageSummary>, bollard::errors::Error> {
+	return docker
+		.list_images(Some(
+			bollard::query_parameters::ListImagesOptionsBuilder::default()
+				.filters(&HashMap::from([("label", vec![label])]))
+				.build(),
+		))
+		.await;
+}
 
-	#[error("could not inspect exec: {0}")]
-	ExecInspect(bollard::errors::Error),
+/// Commits the running system `id` to a new snapshot of `system`, then removes all but its `keep` newest snapshots.
+async fn take_snapshot(docker: &Docker, id: &str, system: &str, keep: usize, sequence: Option<u64>) -> Result<String, bollard::errors::Error> {
+	let tag = snapshot::tag(system, sequence);
+	docker
+		.commit_container(
+			bollard::query_parameters::CommitContainerOptionsBuilder::default()
+				.container(id)
+				.repo(snapshot::REPOSITORY)
+				.tag(&tag)
+				.comment("tempsystem --autosnapshot")
+				// the session goes on while the snapshot is taken
+				.pause(false)
+				.build(),
+			bollard::models::ContainerConfig {
+				labels: Some(HashMap::from([(snapshot::LABEL_SNAPSHOT.to_string(), system.to_string())])),
+				..Default::default()
+			},
+		)
+		.await?;
+	let mut snapshots = list_labeled_images(docker, &format!("{}={system}", snapshot::LABEL_SNAPSHOT)).await?;
+	snapshots.sort_by_key(|image| std::cmp::Reverse(image.created));
+	for old in snapshots.into_iter().skip(keep) {
+		docker
+			.remove_image(&old.id, None::<bollard::query_parameters::RemoveImageOptions>, None)
+			.await?;
+	}
 
-	#[error("could not set raw mode: {0}")]
-	Rawmode(std::io::Error),
+	return Ok(format!("{}:{tag}", snapshot::REPOSITORY));
+}
 
-	#[error("could not write to stdout: {0}")]
-	StdoutWrite(std::io::Error),
+/// Runs `BACKGROUND_UPDATE_SCRIPT`, sending its output out as the `update` stream, and printing it labeled when `print`.
+async fn run_background_update(docker: &Docker, id: &str, events: &Events, print: bool) -> Result<(), String> {
+	let exec_id = docker
+		.create_exec(
+			id,
+			bollard::models::ExecConfig {
+				attach_stdout: Some(true),
+				attach_stderr: Some(true),
+				user: Some("root".into()),
+				cmd: Some(vec!["/bin/sh".into(), "-c".into(), BACKGROUND_UPDATE_SCRIPT.into()]),
+				..Default::default()
+			},
+		)
+		.await
+		.map_err(|e| e.to_string())?
+		.id;
+	let StartExecResults::Attached { mut output, .. } = docker
+		.start_exec(&exec_id, None)
+		.await
+		.map_err(|e| e.to_string())?
+	else {
+		return Err(Error::ExpectedAttached.to_string());
+	};
+	let mut collected = String::new();
+	let mut stream = LabeledStream::new("update");
+	while let Some(Ok(chunk)) = output.next().await {
+		let chunk = chunk.to_string();
+		events.emit(Event::ExecOutputChunk {
+			exec_id: exec_id.clone(),
+			stream: stream.name().into(),
+			output: chunk.clone(),
+		});
+		if print {
+			eprint!("{}", redact(&stream.feed(&chunk)));
+		}
+		collected.push_str(&chunk);
+	}
+	if print {
+		eprint!("{}", redact(&stream.flush()));
+	}
+	let status = docker
+		.inspect_exec(&exec_id)
+		.await
+		.map_err(|e| e.to_string())?
+		.exit_code
+		.unwrap_or(0);
+	if status != 0 {
+		return Err(classified_error(Phase::Packages, &collected, || Error::SystemUpdate(status, get_error_from_pacman(&collected))).to_string());
+	}
 
-	#[error("could not format stdout to buffer: {0}")]
-	StdoutFmtWrite(std::fmt::Error),
+	return Ok(());
+}
 
-	#[error("could flush stdout: {0}")]
-	StdoutFlush(std::io::Error),
+/// Size of the container's writable layer in bytes.
+async fn container_size(docker: &Docker, id: &str) -> Result<u64, Error> {
+	let inspect = docker
+		.inspect_container(
+			id,
+			Some(
+				bollard::query_parameters::InspectContainerOptionsBuilder::default()
+					.size(true)
+					.build(),
+			),
+		)
+		.await
+		.map_err(Error::ContainerInspect)?;
 
-	#[error("could not delete container: {0}")]
-	ContainerDelete(bollard::errors::Error),
+	return Ok(inspect.size_rw.unwrap_or(0).max(0) as u64);
+}
 
-	#[error("could not get cwd: {0}")]
-	GetCWD(std::io::Error),
+/// The pid `host_pid` has inside its own pid namespace, from the last `NSpid` field of its status.
+fn namespaced_pid(host_pid: i64) -> Option<String> {
+	let status = std::fs::read_to_string(format!("/proc/{host_pid}/status")).ok()?;
+	return status
+		.lines()
+		.find_map(|l| l.strip_prefix("NSpid:"))?
+		.split_whitespace()
+		.last()
+		.map(String::from);
+}
 
-	#[error("package `{0}` does not exist")]
-	PackageDNE(String),
+/// Device cgroup rules, security options, and binds `--privileged-full` adds to `--privileged`.
+pub const PRIVILEGED_FULL_DEVICE_RULES: &[&str] = &["a *:* rwm"];
+pub const PRIVILEGED_FULL_SECURITY_OPTS: &[&str] = &["apparmor=unconfined", "seccomp=unconfined"];
+pub const PRIVILEGED_FULL_BINDS: &[&str] = &["/sys/fs/cgroup:/sys/fs/cgroup:rw"];
+
+/// Every setting `--privileged-full` applies, one per line, for `--verbose`.
+pub fn describe_privileged_full() -> String {
+	let mut lines = vec!["privileged".to_string()];
+	lines.extend(
+		PRIVILEGED_FULL_DEVICE_RULES
+			.iter()
+			.map(|r| format!("device cgroup rule `{r}`")),
+	);
+	lines.extend(
+		PRIVILEGED_FULL_SECURITY_OPTS
+			.iter()
+			.map(|o| format!("security option `{o}`")),
+	);
+	lines.extend(
+		PRIVILEGED_FULL_BINDS
+			.iter()
+			.map(|b| format!("bind mount `{b}`")),
+	);
+	return format!("--privileged-full applies:\n  {}", lines.join("\n  "));
+}
 
-	#[error("failed to install package: {0}; {1}")]
-	PackageInstall(i64, String),
+/// Where the current directory is mounted under the user's `home`.
+pub fn work_dir(home: &str) -> String {
+	return format!("{home}/work");
+}
 
-	#[error("failed to update system: {0}; {1}")]
-	SystemUpdate(i64, String),
+/// Where `--ro-cwd-overlay` mounts the current directory, and the tmpfs holding the overlay's writable layer.
+pub fn overlay_lower(home: &str) -> String {
+	return format!("{home}/.work-lower");
+}
 
-	#[error("failed to add the Chaotic-AUR: {0}; {1}")]
-	ChaoticAUR(i64, String),
+pub fn overlay_tmpfs(home: &str) -> String {
+	return format!("{home}/.work-overlay");
+}
 
-	#[error("failed to add landware: {0}")]
-	Landware(i64),
+/// Runs as root, mounting the `--ro-cwd-overlay` overlay at ~/work unless it already is, like after a kept system restarted.
+pub fn overlay_mount_script(home: &str, user: &str) -> String {
+	let overlay = overlay_tmpfs(home);
+	let (target, upper, work) = (work_dir(home), format!("{overlay}/upper"), format!("{overlay}/work"));
+	let options = format!("lowerdir={home}/.work-lower,upperdir={upper},workdir={work}");
+	return format!(
+		r#"
+set -e
+mountpoint -q {target} && exit 0
+mkdir -p {upper} {work} {target}
+mount -t overlay overlay -o {options} {target}
+chown {owner} {target}"#,
+		target = build::shell_quote(&target),
+		upper = build::shell_quote(&upper),
+		work = build::shell_quote(&work),
+		options = build::shell_quote(&options),
+		owner = build::shell_quote(&format!("{user}:")),
+	);
+}
 
-	#[error("failed to update pkgfile database: {0}")]
-	Pkgfile(i64),
+/// Runs as root, giving what in ~/work is not owned by the owner of ~/work itself, which is the host user however uids
+/// are mapped, back to them. `find` neither follows symlinks nor leaves the mount, and `chown -h` changes a symlink
+/// rather than what it points to. Prints `fixed=<count>`.
+pub fn chown_work_script(home: &str) -> String {
+	let work = build::shell_quote(&work_dir(home));
+	return format!(
+		r#"
+owner=$(stat -c %u:%g {work}) || exit 1
+fixed=$(find {work} -xdev \( ! -uid "${{owner%:*}}" -o ! -gid "${{owner#*:}}" \) -exec chown -h "$owner" {{}} + -print | wc -l)
+echo "fixed=$fixed""#
+	);
+}
 
-	#[error("could not find user's home directory")]
-	HomeDir,
+/// Security options for a new system; mounting the overlay needs the mount syscall, which apparmor's default profile denies.
+pub fn container_security_opts(options: &SessionOptions) -> Vec<String> {
+	let mut opts: Vec<String> = vec![];
+	if options.privileged_full {
+		opts.extend(PRIVILEGED_FULL_SECURITY_OPTS.iter().map(|o| o.to_string()));
+	} else if options.ro_cwd_overlay {
+		opts.push("apparmor=unconfined".into());
+	}
 
-	#[error("could not open ~/.zsh_history: {0}")]
-	OpenHistory(std::io::Error),
+	return opts;
+}
 
-	#[error("could not add ~/.zsh_history to tar archive: {0}")]
-	Tar(std::io::Error),
+/// Capabilities added to a new system.
+pub fn container_cap_add(options: &SessionOptions) -> Vec<String> {
+	let mut caps = vec![];
+	if options.privileged {
+		return caps;
+	}
+	if options.ro_cwd_overlay {
+		caps.push("SYS_ADMIN".into());
+	}
+	// for the `--allow-host` rules in the system's network namespace
+	if !options.allow_host.is_empty() {
+		caps.push("NET_ADMIN".into());
+	}
 
-	#[error("could not upload archive to container: {0}")]
-	ContainerUpload(bollard::errors::Error),
+	return caps;
 }
 
-#[derive(Default)]
-pub struct Context {
-	docker: Option<Docker>,
-	container_id: String,
+/// Host paths mounted into a new system, in `host:guest[:ro]` form.
+pub fn container_binds(options: &SessionOptions) -> Result<Vec<String>, Error> {
+	let mut binds = vec![];
+	if options.ro_cwd_overlay {
+		binds.push(format!("{}:{}:ro", std::env::current_dir().map_err(Error::GetCWD)?.display(), overlay_lower(&options.home)));
+	} else if options.mount_cwd {
+		binds.push(format!(
+			"{}:{}{}",
+			std::env::current_dir().map_err(Error::GetCWD)?.display(),
+			work_dir(&options.home),
+			if options.ro_cwd { ":ro" } else { "" }
+		));
+	}
+	if options.mount_history {
+		binds.push(format!(
+			"{}/.zsh_history:{}/.zsh_history",
+			std::env::home_dir()
+				.ok_or(Error::HomeDir)?
+				.canonicalize()
+				.map_err(|_| Error::HomeDir)?
+				.display(),
+			options.home
+		));
+	}
+	if options.privileged_full {
+		binds.extend(PRIVILEGED_FULL_BINDS.iter().map(|b| b.to_string()));
+	}
+
+	return Ok(binds);
+}
+
+/// Warns that the mounts in `dropped` were left out because the daemon is on another machine.
+pub fn warn_dropped_mounts(dropped: &[&str]) {
+	if !dropped.is_empty() {
+		eprintln!("{}", color::yellow(format!("the daemon is on another machine, so not mounting {}", dropped.join(" or "))));
+	}
+}
+
+/// The host mounts that the system can write to, described for `--wipe-on-exit`'s refusal.
+pub fn writable_host_mounts(options: &SessionOptions) -> Vec<&'static str> {
+	let mut mounts = vec![];
+	if options.mount_cwd && !options.ro_cwd && !options.ro_cwd_overlay {
+		mounts.push("the current directory at ~/work (use --ro-cwd, --ro-cwd-overlay, or --disable-cwd-mount)");
+	}
+	if options.mount_history {
+		mounts.push("~/.zsh_history (from --sync-zsh-history mount)");
+	}
+
+	return mounts;
+}
+
+/// Labels marking a new system as ours, with the options it was created with.
+pub fn container_labels(options: &SessionOptions, reuse_key: Option<&str>) -> HashMap<String, String> {
+	let mut labels = HashMap::from([
+		(session::LABEL_MANAGED.to_string(), "true".to_string()),
+		(session::LABEL_OPTIONS.to_string(), options.to_label()),
+		(session::LABEL_OPTIONS_HASH.to_string(), options.hash()),
+		(session::LABEL_FLAVOR.to_string(), options.flavor.to_string()),
+	]);
+	if let Some(key) = reuse_key {
+		labels.insert(session::LABEL_REUSE_KEY.into(), key.into());
+	}
+
+	return labels;
+}
+
+/// The environment every exec gets: what `--env-passthrough` names and `--inherit-env` and the config match on the host,
+/// then `--env` and `--env-file`.
+pub fn session_env(args: &Args, config: &Config) -> Result<ExecEnv, Error> {
+	let mut session_env = ExecEnv::default();
+	let defaults: &[&str] = if args.no_default_env { &[] } else { env::DEFAULT_PASSTHROUGH };
+	let names: Vec<String> = defaults
+		.iter()
+		.map(|name| name.to_string())
+		.chain(args.env_passthrough.iter().cloned())
+		.collect();
+	let (passed, mut refused) = env::inherit_from_host(&names, args.inherit_env_unsafe);
+	for (name, value) in passed {
+		session_env.set(EnvSource::Passthrough, name, value);
+	}
+	if !args.no_proxy_passthrough {
+		let proxies = proxy::from_host();
+		if args.verbose && !proxies.is_empty() {
+			println!(
+				"passing proxy settings: {}",
+				proxies
+					.iter()
+					.map(|(name, _)| name.as_str())
+					.collect::<Vec<&str>>()
+					.join(", ")
+			);
+		}
+		for (name, value) in proxies {
+			session_env.set(EnvSource::Passthrough, name, value);
+		}
+	}
+	let patterns: Vec<String> = config
+		.inherit_env
+		.iter()
+		.chain(&args.inherit_env)
+		.cloned()
+		.collect();
+	let (inherited, refused_inherited) = env::inherit_from_host(&patterns, args.inherit_env_unsafe);
+	refused.extend(refused_inherited);
+	refused.sort();
+	refused.dedup();
+	for name in refused {
+		print_error!(format!("not inheriting `{name}` since it looks like a secret; pass --inherit-env-unsafe to allow it"));
+	}
+	if args.verbose && !inherited.is_empty() {
+		println!(
+			"inheriting from host: {}",
+			inherited
+				.iter()
+				.map(|(name, _)| name.as_str())
+				.collect::<Vec<&str>>()
+				.join(", ")
+		);
+	}
+	for (name, value) in inherited {
+		session_env.set(EnvSource::Inherited, name, value);
+	}
+	let explicit = env::explicit(&args.env_file, &args.env)?;
+	for name in explicit.missing {
+		eprintln!("{}", color::yellow(format!("not setting `{name}`, it is not set here to copy")));
+	}
+	for (name, value) in explicit.vars {
+		session_env.set(EnvSource::Explicit, name, value);
+	}
+
+	return Ok(session_env);
+}
+
+/// Whether the session exec gets a tty: `--tty` and `--no-tty` decide, otherwise it does when both stdin and stdout are terminals.
+pub fn session_tty(args: &Args) -> Result<bool, Error> {
+	return pick_tty(
+		args,
+		std::fs::metadata("/dev/stdin").is_ok(),
+		std::io::stdin().is_terminal(),
+		std::io::stdout().is_terminal(),
+	);
+}
+
+/// `session_tty` for a stdin that is `readable` at all, and for whether stdin and stdout are terminals.
+fn pick_tty(args: &Args, readable: bool, stdin_terminal: bool, stdout_terminal: bool) -> Result<bool, Error> {
+	if args.tty {
+		// the tty's input is our stdin, so there has to be one to read from
+		if !readable {
+			return Err(Error::TtyWithoutStdin);
+		}
+		return Ok(true);
+	}
+	if args.no_tty {
+		return Ok(false);
+	}
+	return Ok(stdin_terminal && stdout_terminal);
+}
+
+/// The exec of `cmd` as `user`. Only an `attach`ed exec, the session, gets our stdin, and a tty when `tty`.
+fn exec_config(user: &str, cmd: Vec<String>, attach: bool, tty: bool, env: &ExecEnv) -> bollard::models::ExecConfig {
+	return bollard::models::ExecConfig {
+		attach_stdout: Some(true),
+		attach_stderr: Some(true),
+		attach_stdin: Some(attach),
+		user: Some(user.into()),
+		tty: Some(attach && tty),
+		env: Some(env.to_vec()),
+		cmd: Some(cmd),
+		..Default::default()
+	};
+}
+
+/// Whether no command was given, so the session is an interactive shell.
+pub fn is_default_command(args: &Args) -> bool {
+	return args.command.is_empty();
+}
+
+pub fn uses_login_shell(args: &Args) -> bool {
+	return args.login || (is_default_command(args) && !args.no_login);
+}
+
+/// Lowers the memory limit of the system `id` to what it uses plus half of what the host has left, and its CPU quota to
+/// half the host's CPUs, never raising the `limits` it was created with. Returns what it clamped to.
+/// Without the stats endpoint what the system uses is unknown, so only its CPU quota is lowered.
+async fn clamp(
+	docker: &Docker,
+	id: &str,
+	host: &guard::HostSample,
+	limits: (Option<u64>, Option<i64>),
+	capabilities: &Capabilities,
+) -> Result<String, bollard::errors::Error> {
+	let mut usage = None;
+	if capabilities.usable(Capability::Stats) {
+		let stats = docker
+			.stats(
+				id,
+				Some(
+					bollard::query_parameters::StatsOptionsBuilder::default()
+						.stream(false)
+						.one_shot(true)
+						.build(),
+				),
+			)
+			.next()
+			.await
+			.transpose();
+		if capabilities.record(Capability::Stats, &stats) {
+			warn_unsupported(Capability::Stats);
+		} else {
+			usage = Some(
+				stats?
+					.and_then(|stats| stats.memory_stats)
+					.and_then(|memory| memory.usage)
+					.unwrap_or_default(),
+			);
+		}
+	}
+	let memory = usage.map(|usage| (usage + host.available / 2).min(limits.0.unwrap_or(u64::MAX)));
+	let nano_cpus = ((host.cpus / 2).max(1) as i64 * 1_000_000_000).min(limits.1.unwrap_or(i64::MAX));
+	docker
+		.update_container(
+			id,
+			bollard::models::ContainerUpdateBody {
+				memory: memory.map(|m| m as i64),
+				memory_swap: memory.map(|m| m as i64),
+				nano_cpus: Some(nano_cpus),
+				..Default::default()
+			},
+		)
+		.await?;
+
+	return Ok(match memory {
+		Some(memory) => format!("{} of memory and {} CPUs", HumanBytes(memory), nano_cpus as f64 / 1e9),
+		None => format!("{} CPUs", nano_cpus as f64 / 1e9),
+	});
+}
+
+/// Prints `message` on a line of its own while a session runs. The session's terminal is in raw mode, so lines need an
+/// explicit carriage return.
+fn print_over_session(message: impl std::fmt::Display) {
+	eprint!("\r\n{message}\r\n");
+}
+
+/// Says once that the daemon lacks `capability`, over the session's terminal.
+fn warn_unsupported(capability: Capability) {
+	print_over_session(color::yellow(format!("the daemon does not support {}, so {}", capability.name(), capability.degraded())));
+}
+
+/// Whether the daemon refused a container over its cgroup parent.
+pub fn cgroup_parent_refused(message: &str) -> bool {
+	let message = message.to_ascii_lowercase();
+	return message.contains("cgroup-parent") || message.contains("cgroup parent") || message.contains("cgroupspath") || message.contains(".slice");
+}
+
+/// Why `parent` does not fit the cgroup `driver`, `systemd` or `cgroupfs`, when it is known.
+pub fn cgroup_parent_hint(parent: &str, driver: Option<&str>) -> String {
+	let slice = parent.ends_with(".slice");
+	return match driver {
+		Some("systemd") if !slice => format!("the daemon uses the systemd cgroup driver, which needs a slice like `myproject.slice`, not `{parent}`"),
+		Some("systemd") => {
+			format!("the daemon uses the systemd cgroup driver, but could not use the slice `{parent}`; check it exists with `systemctl status {parent}`")
+		}
+		Some("cgroupfs") if slice => {
+			format!("`{parent}` is a systemd slice, but the daemon uses the cgroupfs cgroup driver, which needs a path like `/myproject`")
+		}
+		Some(driver) => format!("the daemon uses the {driver} cgroup driver, which could not use `{parent}`"),
+		None => format!(
+			"`{parent}` does not fit the daemon's cgroup driver: the systemd driver needs a slice like `myproject.slice`, cgroupfs a path like `/myproject`"
+		),
+	};
+}
+
+/// Whether the daemon refused a container because its storage driver cannot limit the writable layer, which docker
+/// words as "--storage-opt is supported only for overlay over xfs with 'pquota' mount option" and podman similarly.
+pub fn storage_quota_unsupported(message: &str) -> bool {
+	let message = message.to_ascii_lowercase();
+	return message.contains("storage-opt") || message.contains("storage opt") || message.contains("pquota");
+}
+
+/// Whether the system stays once the session ends. After the command exited with `Some(exit_code)`: always with
+/// `--keep`, and with `--keep-on-failure` when the command failed. After setup failed (`None`): only with `--keep-on-error`.
+pub fn keep_system(exit_code: Option<i64>, keep: bool, keep_on_failure: bool, keep_on_error: bool) -> bool {
+	return match exit_code {
+		Some(exit_code) => keep || (keep_on_failure && exit_code != 0),
+		None => keep_on_error,
+	};
+}
+
+/// Where the system `--keep-on-failure` kept is, and how to get into it.
+pub fn kept_on_failure(exit_code: i64, id: &str, shell: &str) -> String {
+	return format!(
+		"the command exited with {exit_code}, so the system `{id}` was kept; look into it with `docker exec -it {id} {shell}`, \
+		 and remove it with `docker rm -f {id}`"
+	);
+}
+
+/// What exit code 137, a SIGKILL, most likely means when the system has a memory limit.
+pub fn oom_note(exit_code: i64, options: &SessionOptions) -> Option<String> {
+	let limit = options
+		.memory
+		.or(options.restrict_memory.map(|m| m as u64 * 1024 * 1024))?;
+	return (exit_code == 137)
+		.then(|| format!("note: the command was killed (exit code 137), likely OOM-killed for going over the {} memory limit", HumanBytes(limit)));
+}
+
+/// config.toml's `default-command`, expanded for this session, when no command was given.
+pub fn default_command(args: &Args, config: &Config, hostname: &str, user: &str, home: &str) -> Result<Option<String>, Error> {
+	let Some(command) = config
+		.default_command
+		.as_deref()
+		.filter(|_| is_default_command(args))
+	else {
+		return Ok(None);
+	};
+	let values = template::Values {
+		project: template::project_name(),
+		hostname,
+		user,
+		home,
+	};
+	return template::expand(command, &values)
+		.map(Some)
+		.map_err(|reason| Error::Template(command.into(), reason));
+}
+
+/// The command and environment of the session exec, run through `shell`; `default_command` replaces the shell
+/// when no command was given.
+pub fn session_command(args: &Args, shell: &str, session_env: &ExecEnv, default_command: Option<String>) -> (Vec<String>, ExecEnv) {
+	let login = uses_login_shell(args);
+	if is_default_command(args) {
+		let session_env = session_env
+			.clone()
+			.with(EnvSource::Session, "SHOW_WELCOME", "true");
+		if let Some(command) = default_command {
+			return (vec![shell.into(), if login { "-lc" } else { "-c" }.into(), command], session_env);
+		}
+		if login {
+			return (vec![shell.into(), "-l".into()], session_env);
+		}
+		return (vec![shell.into(), "-c".into(), shell.into()], session_env);
+	}
+
+	let command = match (&args.script, args.command.as_slice()) {
+		(Some(script), _) => script.clone(),
+		// one argument is a command line, like `tempsystem 'make | tee log'`, unless it came after `--`
+		(None, [line]) if !args.command_after_separator => line.clone(),
+		(None, argv) => argv
+			.iter()
+			.map(|arg| build::shell_quote(arg))
+			.collect::<Vec<String>>()
+			.join(" "),
+	};
+	return (vec![shell.into(), if login { "-lc" } else { "-c" }.into(), command], session_env.clone());
+}
+
+/// Fails early when a requested option relies on something the image does not have.
+pub fn check_features(features: &ImageFeatures, privilege: &Privilege, args: &Args, options: &SessionOptions) -> Result<(), Error> {
+	if features.user.as_ref().is_some_and(|u| u.is_none()) {
+		return Err(Error::MissingFeature("a `tempsystem` user", "entering the system"));
+	}
+	if !options.aur_packages.is_empty() && features.aur_helper().is_none() {
+		if options.flavor == Flavor::Minimal {
+			return Err(Error::MinimalFlavor("an AUR helper", "--extra-aur-packages"));
+		}
+		return Err(Error::MissingFeature("an AUR helper (yay or paru)", "--extra-aur-packages"));
+	}
+	if !options.aur_packages.is_empty() && !features.sudo_pacman() {
+		return Err(Error::MissingFeature("passwordless sudo for pacman", "--extra-aur-packages"));
+	}
+	let needs_root = [
+		("--extra-packages", !options.packages.is_empty()),
+		("--update-system", args.update_system.is_some()),
+		("--update-pkgfile", args.update_pkgfile),
+		("--chaotic-aur", args.chaotic_aur),
+		("--landware", args.landware),
+	];
+	if let Some((flag, _)) = needs_root.iter().find(|(_, used)| *used) {
+		if features.pacman == Some(false) {
+			return Err(Error::MissingFeature("pacman", flag));
+		}
+		if let Privilege::Unavailable(missing) = privilege {
+			return Err(Error::Unprivileged(flag, missing.clone()));
+		}
+	}
+	if args.update_pkgfile && features.pkgfile == Some(false) {
+		if options.flavor == Flavor::Minimal {
+			return Err(Error::MinimalFlavor("pkgfile", "--update-pkgfile"));
+		}
+		return Err(Error::MissingFeature("pkgfile", "--update-pkgfile"));
+	}
+
+	return Ok(());
+}
+
+impl Default for Context {
+	fn default() -> Self {
+		let shutdown_token = CancellationToken::new();
+		return Self {
+			docker: None,
+			engine: Engine::Docker,
+			endpoint: None,
+			_tunnel: None,
+			container_id: String::new(),
+			lifecycle: Default::default(),
+			remove_volumes: false,
+			cgroup_parent: None,
+			name: String::new(),
+			reused: false,
+			env: ExecEnv::default(),
+			features: ImageFeatures::default(),
+			registry_login: None,
+			platform: None,
+			privilege: Privilege::default(),
+			live_container: Default::default(),
+			token: shutdown_token.child_token(),
+			shutdown_token,
+			tasks: TaskTracker::new(),
+			stdout_closed: AtomicBool::new(false),
+			tty: false,
+			timings: Default::default(),
+			transcript: Default::default(),
+			events: Events::default(),
+			helper: Default::default(),
+			escape: None,
+			host_job_control: false,
+			degraded_tty: false,
+			size_unknown: AtomicBool::new(false),
+			capabilities: Arc::default(),
+		};
+	}
+}
+
+/// `packages` quoted for the shell and joined with spaces.
+pub fn quote_all(packages: &[String]) -> String {
+	return packages
+		.iter()
+		.map(|p| build::shell_quote(p))
+		.collect::<Vec<String>>()
+		.join(" ");
+}
+
+pub fn chunk_message(message: &str, chunk: usize, chunks: usize) -> String {
+	if chunks > 1 {
+		return format!("{message} (chunk {}/{chunks})", chunk + 1);
+	}
+
+	return message.into();
+}
+
+/// The digests an image was pulled by, for `ImageDigestMismatch`.
+pub fn describe_digests(digests: &[String]) -> String {
+	if digests.is_empty() {
+		return "no digest".into();
+	}
+	return digests.join(", ");
+}
+
+/// The last `BUILD_LOG_TAIL` lines of a failed build step's output.
+fn log_tail(output: &str) -> String {
+	let lines: Vec<&str> = output.lines().collect();
+	return lines[lines.len().saturating_sub(BUILD_LOG_TAIL)..].join("\n");
+}
+
+/// The platform a local image is for.
+fn image_platform(inspect: &bollard::models::ImageInspect) -> String {
+	return registry::platform_name(inspect.os.as_deref(), inspect.architecture.as_deref(), inspect.variant.as_deref());
+}
+
+/// Why a pull failed, without the wrapping bollard puts around the daemon's message.
+fn pull_failure(e: bollard::errors::Error) -> String {
+	return match e {
+		bollard::errors::Error::DockerResponseServerError { status_code: 404, message } => {
+			format!("{message}; check the name and tag, and log in first if the registry is private")
+		}
+		bollard::errors::Error::DockerResponseServerError { message, .. } => message,
+		e => e.to_string(),
+	};
+}
+
+/// `RegistryAuth` for a pull of `image` the registry refused, pointing at what to fix depending on whether credentials were sent.
+fn auth_failure(image: &str, logged_in: bool, e: bollard::errors::Error) -> Error {
+	let registry = registry::registry_of(image);
+	let hint = if logged_in {
+		"check the user and password".to_string()
+	} else {
+		format!("log in with `docker login {registry}` or pass --registry-user")
+	};
+	return Error::RegistryAuth(registry, format!("{}; {hint}", pull_failure(e)));
+}
+
+/// The error for what `output` says went wrong, which a generic failure would hide, otherwise `otherwise()`.
+/// Exec format errors get a hint without probing the system.
+pub fn classified_error(phase: Phase, output: &str, otherwise: impl FnOnce() -> Error) -> Error {
+	return match classify::classify(phase, output) {
+		FailureKind::Signature { line } => Error::Signature(line),
+		FailureKind::Mirror { line } => Error::Mirror(line),
+		FailureKind::DiskFull { line } => Error::DiskFull(line),
+		FailureKind::ClockSkew { line } => Error::ClockSkew(line),
+		FailureKind::ArchMismatch { path } => Error::ArchMismatch(classify::arch_hint(path.as_deref(), "")),
+		FailureKind::DependencyConflict { line } => Error::DependencyConflict(line),
+		FailureKind::Unknown => otherwise(),
+	};
+}
+
+/// The host's timezone, like `Europe/Berlin`, from where `/etc/localtime` points.
+pub fn host_timezone() -> Option<String> {
+	let target = std::fs::read_link("/etc/localtime").ok()?;
+	let (_, zone) = target.to_str()?.split_once("zoneinfo/")?;
+	return Some(zone.to_string());
+}
+
+/// Runs as root, pointing `/etc/localtime` at `zone` when the image has it and does not already.
+pub fn timezone_script(zone: &str) -> String {
+	let path = build::shell_quote(&format!("/usr/share/zoneinfo/{zone}"));
+	return format!(r#"[ -e {path} ] && [ "$(readlink /etc/localtime)" != {path} ] && ln -sf {path} /etc/localtime; true"#);
 }
 
 fn get_error_from_pacman_key(s: &str) -> String {
@@ -119,7 +1043,7 @@ fn get_error_from_pacman_key(s: &str) -> String {
 		.to_string();
 }
 
-fn get_error_from_pacman(s: &str) -> String {
+pub fn get_error_from_pacman(s: &str) -> String {
 	return s
 		.split("\n")
 		.filter_map(|s| s.strip_prefix("error: "))
@@ -128,7 +1052,7 @@ fn get_error_from_pacman(s: &str) -> String {
 		.to_string();
 }
 
-fn get_error_from_either(s: &str) -> String {
+pub fn get_error_from_either(s: &str) -> String {
 	let ret = get_error_from_pacman(s);
 	if !ret.is_empty() {
 		return ret;
@@ -138,86 +1062,167 @@ fn get_error_from_either(s: &str) -> String {
 }
 
 impl Context {
-	pub fn connect(&mut self) -> Result<(), Error> {
-		self.docker = Some(Docker::connect_with_defaults().map_err(Error::Connection)?);
-		return Ok(());
+	/// Connects to the first endpoint `kind` allows whose daemon answers, and finds out which engine it is.
+	/// A `host` given is the only endpoint tried, and why it failed is returned as is.
+	pub async fn connect(&mut self, kind: BackendKind, host: Option<&str>, tls: Option<&TlsFiles>) -> Result<(), Error> {
+		let mut tried = Vec::new();
+		for endpoint in engine::candidates(kind, host, tls) {
+			if let Endpoint::Socket(path) = &endpoint
+				&& !path.exists()
+			{
+				tried.push(format!("{endpoint}: does not exist"));
+				continue;
+			}
+			// connecting alone does not touch the socket
+			let (docker, tunnel) = match endpoint.connect().await {
+				Ok(connection) => connection,
+				Err(e) if host.is_some() => return Err(e),
+				Err(e) => {
+					tried.push(format!("{endpoint}: {e}"));
+					continue;
+				}
+			};
+			if let Err(e) = docker.ping().await {
+				match endpoint.failure(&e) {
+					Some(failure) if host.is_some() => return Err(failure),
+					Some(failure) => tried.push(format!("{endpoint}: {failure}")),
+					None => tried.push(format!("{endpoint}: {e}")),
+				}
+				continue;
+			}
+			self.engine = Engine::detect(&docker).await;
+			self.endpoint = Some(endpoint);
+			self.docker = Some(docker);
+			self._tunnel = tunnel;
+			return Ok(());
+		}
+
+		return Err(Error::NoEngine(tried.join("\n  ")));
+	}
+
+	pub fn engine(&self) -> Engine {
+		return self.engine;
+	}
+
+	/// The endpoint `connect` picked.
+	pub fn endpoint(&self) -> Option<&Endpoint> {
+		return self.endpoint.as_ref();
 	}
 
 	fn get_docker(&self) -> Result<&Docker, Error> {
 		return self.docker.as_ref().ok_or(Error::NotConnected);
 	}
 
-	async fn install_packages(&self, verbose: bool, spinner: &ProgressBar, current_task: usize, total_tasks: usize, packages: &str) -> Result<(), Error> {
-		for (i, pkg) in packages.split_whitespace().enumerate() {
-			spinner.set_message(format!("Installing {pkg}"));
-			spinner.set_prefix(format!("[{}/{total_tasks}]", i + current_task));
-			let exec_id = self
-				.create_exec(format!("/bin/pacman -Ssq \"^{pkg}$\""), false)
-				.await?;
-			let (status, output) = self.start_exec(&exec_id, false).await?;
-			if verbose {
-				println!("{}", output.unwrap());
-			}
-			if status != 0 {
-				return Err(Error::PackageDNE(pkg.to_string()));
-			}
-			let exec_id = self
-				.create_exec(format!("/bin/sudo /bin/pacman -S --needed --noconfirm {pkg}"), false)
-				.await?;
-			let (status, output) = self.start_exec(&exec_id, false).await?;
-			if verbose {
-				println!("{}", output.as_ref().unwrap());
-			}
-			if status != 0 {
-				return Err(Error::PackageInstall(status, get_error_from_pacman(&output.unwrap_or_default())));
-			}
-		}
+	pub fn container_id(&self) -> &str {
+		return &self.container_id;
+	}
 
-		return Ok(());
+	/// The system this run created and has not deleted yet, readable from outside the running future.
+	pub fn live_container(&self) -> Arc<Mutex<Option<String>>> {
+		return self.live_container.clone();
 	}
 
-	async fn install_aur_packages(&self, verbose: bool, spinner: &ProgressBar, current_task: usize, total_tasks: usize, packages: &str) -> Result<(), Error> {
-		for (i, pkg) in packages.split_whitespace().enumerate() {
-			spinner.set_message(format!("Installing {pkg} from AUR"));
-			spinner.set_prefix(format!("[{}/{total_tasks}]", i + current_task));
-			let exec_id = self
-				.create_exec(format!("/bin/yay --aur -Ssq \"^{pkg}$\""), false)
-				.await?;
-			let (status, output) = self.start_exec(&exec_id, false).await?;
-			if verbose {
-				println!("{}", output.unwrap());
-			}
-			if status != 0 {
-				return Err(Error::PackageDNE(pkg.to_string()));
-			}
-			let exec_id = self
-				.create_exec(format!("/bin/yay --sync --needed --noconfirm --noprogressbar {pkg}"), false)
-				.await?;
-			let (status, output) = self.start_exec(&exec_id, false).await?;
-			if verbose {
-				println!("{}", output.as_ref().unwrap());
-			}
-			if status != 0 {
-				return Err(Error::PackageInstall(status, get_error_from_pacman(&output.unwrap_or_default())));
-			}
+	/// The system's name when this run created it, its ID otherwise.
+	pub fn display_name(&self) -> &str {
+		if self.name.is_empty() {
+			return &self.container_id;
 		}
+		return &self.name;
+	}
 
-		return Ok(());
+	fn set_container(&mut self, id: String) {
+		*self.live_container.lock().unwrap() = Some(id.clone());
+		self.container_id = id;
+		self.set_lifecycle(Lifecycle::Created);
 	}
 
-	async fn update_system(&self, verbose: bool) -> Result<(), Error> {
-		let exec_id = self
-			.create_exec("/bin/sudo /bin/pacman -Syu --noconfirm".into(), false)
-			.await?;
-		let (status, output) = self.start_exec(&exec_id, false).await?;
-		if verbose {
-			println!("{}", output.as_ref().unwrap());
-		}
-		if status != 0 {
-			return Err(Error::SystemUpdate(status, get_error_from_pacman(&output.unwrap_or_default())));
+	fn set_lifecycle(&self, lifecycle: Lifecycle) {
+		*self.lifecycle.lock().unwrap() = lifecycle;
+	}
+
+	/// Cancelled when the user aborts or the process shuts down; every background task stops on it.
+	pub fn token(&self) -> CancellationToken {
+		return self.token.clone();
+	}
+
+	/// Only cancelled by `shutdown`, for tasks that must keep running while a cancelled run cleans up.
+	pub fn shutdown_token(&self) -> CancellationToken {
+		return self.shutdown_token.clone();
+	}
+
+	pub fn tasks(&self) -> &TaskTracker {
+		return &self.tasks;
+	}
+
+	/// Stops all background tasks, giving them a bounded amount of time to finish.
+	pub async fn shutdown(&self) {
+		self.shutdown_token.cancel();
+		self.tasks.close();
+		if tokio::time::timeout(SHUTDOWN_TIMEOUT, self.tasks.wait())
+			.await
+			.is_err()
+		{
+			print_error!(format!("{} background task(s) did not stop in time", self.tasks.len()));
 		}
+	}
 
-		return Ok(());
+	/// Starts sending events to the returned receiver, replacing any earlier subscriber.
+	pub fn subscribe(&mut self) -> UnboundedReceiver<Event> {
+		let (events, receiver) = Events::channel();
+		self.events = events;
+		return receiver;
+	}
+
+	pub fn set_registry_login(&mut self, login: Option<registry::Login>) {
+		self.registry_login = login;
+	}
+
+	pub fn set_platform(&mut self, platform: Option<String>) {
+		self.platform = platform;
+	}
+
+	pub fn events(&self) -> Events {
+		return self.events.clone();
+	}
+
+	/// Starts the next phase, attributing the execs that follow to it.
+	fn step(&self, cur: usize, total: usize, msg: impl Into<String>) {
+		let msg = msg.into();
+		self.timings.lock().unwrap().set_phase(&msg);
+		self.events.phase(cur, total, msg);
+	}
+
+	/// Whether the current system existed before this run (`--reuse`) and so must outlive it.
+	pub fn reused(&self) -> bool {
+		return self.reused;
+	}
+
+	/// Compares the architecture of the binary at `path` with the system's, for an exec format error.
+	async fn arch_hint(&self, path: Option<&str>) -> String {
+		let probe = self
+			.create_exec_raw(
+				self.features.user(),
+				vec![
+					"/bin/sh".into(),
+					"-c".into(),
+					classify::ARCH_PROBE_SCRIPT.into(),
+					"sh".into(),
+					path.unwrap_or_default().into(),
+				],
+				false,
+				&self.env,
+			)
+			.await;
+		let output = match probe {
+			Ok(exec_id) => self
+				.start_exec(&exec_id, false)
+				.await
+				.ok()
+				.and_then(|(_, output)| output)
+				.unwrap_or_default(),
+			Err(_) => String::new(),
+		};
+		return classify::arch_hint(path, &output);
 	}
 
 	async fn copy_file(&self, host_src: &str, guest_dest: &str) -> Result<(), Error> {
@@ -243,134 +1248,267 @@ impl Context {
 		return Ok(());
 	}
 
-	pub async fn perform_all_enter(&mut self, args: &Args) -> Result<i64, Error> {
-		let m = MultiProgress::new();
-		let total = 5
-			+ args
-				.extra_packages
-				.as_ref()
-				.unwrap_or(&"".to_string())
-				.split_whitespace()
-				.count() + args
-			.extra_aur_packages
-			.as_ref()
-			.unwrap_or(&"".to_string())
-			.split_whitespace()
-			.count() + args.update_system as usize
-			+ args.update_pkgfile as usize
-			+ args.landware as usize
-			+ args.chaotic_aur as usize;
-		let mut cur = 1;
-		let spinner = m.add(ProgressBar::new_spinner().with_style(ProgressStyle::with_template("{prefix:.bold.dim} {spinner:.blue} {msg}...").unwrap()));
-		{
-			spinner.set_message("Downloading image");
-			spinner.set_prefix(format!("[{cur}/{total}]"));
-			spinner.enable_steady_tick(Duration::from_millis(50));
-			self.pull_image(&m).await?;
-			cur += 1;
+	/// Uploads the current directory to ~/work for `--copy-cwd`, owned by the user.
+	async fn copy_cwd(&self, home: &str) -> Result<(), Error> {
+		let docker = self.get_docker()?;
+		let cwd = std::env::current_dir().map_err(Error::GetCWD)?;
+		let mut v = vec![];
+		let mut builder = Builder::new(&mut v);
+		builder.follow_symlinks(false);
+		builder.append_dir_all("work", &cwd).map_err(Error::Tar)?;
+		builder.finish().map_err(Error::Tar)?;
+		drop(builder);
+		docker
+			.upload_to_container(
+				&self.container_id,
+				Some(UploadToContainerOptions {
+					path: home.into(),
+					..Default::default()
+				}),
+				bollard::body_full(v.into()),
+			)
+			.await
+			.map_err(Error::ContainerUpload)?;
+		let user = self.features.user();
+		self.run_as_root(&format!("chown -R {user}: {}", build::shell_quote(&work_dir(home))))
+			.await?;
+
+		return Ok(());
+	}
+
+	/// How the session exec talks to the terminal: whether it gets a tty, the escape key, and job control.
+	fn set_session_terminal(&mut self, args: &Args) -> Result<(), Error> {
+		self.tty = session_tty(args)?;
+		self.escape = (self.tty && std::io::stdin().is_terminal() && !args.no_escape).then_some(args.escape_key);
+		self.host_job_control = args.host_job_control;
+		// with a pipe on either end there is no interactive program to break anyway
+		self.degraded_tty = args.degraded_tty || !std::io::stdin().is_terminal() || !std::io::stdout().is_terminal();
+
+		return Ok(());
+	}
+
+	pub async fn perform_all_enter(&mut self, args: &Args, config: &Config) -> Result<i64, Error> {
+		self.set_session_terminal(args)?;
+		if args.bug_report {
+			*self.transcript.lock().unwrap() = Some(vec![]);
 		}
-		self.container_id = {
-			spinner.set_message("Creating system");
-			spinner.set_prefix(format!("[{cur}/{total}]"));
-			cur += 1;
-			self.create_container(
-				args.no_network,
-				args.privileged,
-				args.ro_root,
-				args.ro_cwd,
-				!args.disable_cwd_mount,
-				args.sync_zsh_history == ZshHistorySync::Mount,
-				args.restrict_cpu,
-				args.restrict_memory,
+		self.remove_volumes = args.wipe_on_exit;
+		let mut options = SessionOptions::from_args(args, config)?;
+		self.cgroup_parent = options.cgroup_parent.clone();
+		options.check_offline(args)?;
+		if self.endpoint.as_ref().is_some_and(Endpoint::is_remote) {
+			warn_dropped_mounts(&options.drop_host_mounts());
+		}
+		let policy = args.verify_exit.as_deref().map(Policy::load).transpose()?;
+		self.warn_pin_drift(args, config).await?;
+		if args.verbose {
+			println!("image: {}", options.image);
+			if let Some(memory) = options.memory {
+				println!("memory limit: {memory} bytes ({})", HumanBytes(memory));
+			}
+			if let Some(size) = options.storage_size {
+				println!("storage limit: {size} bytes ({})", HumanBytes(size));
+			}
+			if let Some(size) = options.shm_size {
+				println!("/dev/shm size: {size} bytes ({})", HumanBytes(size));
+			}
+			println!("{}", options.describe_packages());
+			if options.privileged_full {
+				println!("{}", describe_privileged_full());
+			}
+		}
+		if args.wipe_on_exit {
+			let mounts = writable_host_mounts(&options);
+			if !mounts.is_empty() {
+				return Err(Error::WipeWithHostMounts(mounts.join(", ")));
+			}
+		}
+		options.check_package_count()?;
+		for note in &options.notes {
+			eprintln!("{}", color::yellow(note));
+		}
+		let recipe = Recipe::from_args(args, &options.image)?;
+		if let Some(recipe) = &recipe {
+			options.image = recipe.tag();
+		}
+		let reuse_key = if args.reuse || args.reuse_reset {
+			Some(
+				options.reuse_key(
+					&std::env::current_dir()
+						.map_err(Error::GetCWD)?
+						.canonicalize()
+						.map_err(Error::GetCWD)?,
+				),
 			)
-			.await?
+		} else {
+			None
 		};
+		let keep = args.keep || reuse_key.is_some();
+		if let Some(key) = &reuse_key {
+			let existing = self.find_reusable(key).await?;
+			if args.reuse_reset {
+				for id in existing {
+					self.set_container(id);
+					self.delete_container().await?;
+				}
+				self.container_id.clear();
+				self.set_lifecycle(Lifecycle::NotCreated);
+			} else if let Some(id) = existing.into_iter().next() {
+				self.container_id = id;
+				self.set_lifecycle(Lifecycle::Created);
+				self.reused = true;
+			}
+		}
+
+		self.env = session_env(args, config)?;
+
+		let fresh = !self.reused && args.resume.is_none();
+		let image = recipe.as_ref().map_or(&options.image, |r| &r.base).clone();
+		let present = fresh && self.image_present(&image).await;
+		let pull = fresh
+			&& match args.pull {
+				_ if args.offline && present => false,
+				_ if args.offline => return Err(Error::OfflineImageMissing(image)),
+				PullPolicy::Always => true,
+				PullPolicy::Missing => !present,
+				PullPolicy::Never if present => false,
+				PullPolicy::Never => return Err(Error::ImageMissing(image)),
+			};
+		let total = if self.reused {
+			1
+		} else {
+			(if args.resume.is_some() {
+				1
+			} else {
+				2 + pull as usize + recipe.is_some() as usize
+			}) + options.install_steps(args.install_chunk_size as usize)
+				+ (args.update_system == Some(UpdateSystem::Foreground)) as usize
+				+ args.update_pkgfile as usize
+				+ !options.toolchain_setup.is_empty() as usize
+				+ args.landware as usize
+				+ args.chaotic_aur as usize
+		} + 1 + !keep as usize
+			+ args.wipe_on_exit as usize;
+		let pulled = fresh && !present;
+		let setup_started = Instant::now();
+		if fresh
+			&& !args.quiet
+			&& !args.json
+			&& std::io::stdout().is_terminal()
+			&& let Some((duration, runs)) = stats::estimate(&stats::load(), pulled, options.packages.len(), options.aur_packages.len())
 		{
-			spinner.set_message("Starting system");
-			spinner.set_prefix(format!("[{cur}/{total}]"));
-			self.start_container().await?;
-			cur += 1;
+			println!("{}", stats::describe_estimate(duration, runs));
 		}
-		if args.chaotic_aur {
-			spinner.set_message("Adding Chaotic-AUR");
-			spinner.set_prefix(format!("[{cur}/{total}]"));
-			let exec_id = self
-				.create_exec(
-					r#"
-					sudo pacman-key --init &&
-					sudo pacman-key --populate &&
-					sudo pacman-key --recv-key 3056513887B78AEB --keyserver keyserver.ubuntu.com &&
-					sudo pacman-key --lsign-key 3056513887B78AEB &&
-					sudo pacman -U --needed --noconfirm 'https://cdn-mirror.chaotic.cx/chaotic-aur/chaotic-keyring.pkg.tar.zst' &&
-					yes | sudo pacman -U --needed --noconfirm 'https://cdn-mirror.chaotic.cx/chaotic-aur/chaotic-mirrorlist.pkg.tar.zst' &&
-					printf '\n\n# Added by tempsystem\n[chaotic-aur]\nInclude = /etc/pacman.d/chaotic-mirrorlist' | sudo tee -a /etc/pacman.conf && 
-					sudo pacman -Sy --noconfirm"#
-						.into(),
-					false,
-				)
+		let mut cur = 1;
+		if !fresh {
+			options.home = setup::resolve_home(self, &options.image, args.verbose).await;
+		}
+		if self.reused {
+			self.step(cur, total, "Entering existing system");
+			self.ensure_running().await?;
+			cur += 1;
+		} else if let Some(id) = &args.resume {
+			self.step(cur, total, "Resuming system");
+			self.resume_container(id, &options).await?;
+			cur += 1;
+		} else {
+			if pull {
+				self.step(cur, total, format!("Downloading image ({})", session::image_version(&image)));
+				self.pull_image(&image).await?;
+				cur += 1;
+			}
+			if let Some(recipe) = &recipe {
+				self.step(cur, total, "Building image");
+				self.build_image(recipe, args.verbose, args.build_always)
+					.await?;
+				cur += 1;
+			}
+			if args.verbose
+				&& let Some(platform) = self.local_platform(&options.image).await
+			{
+				println!("platform: {platform}");
+			}
+			options.home = setup::resolve_home(self, &options.image, args.verbose).await;
+			{
+				self.step(cur, total, "Creating system");
+				let (this, options, reuse_key) = (&*self, &options, reuse_key.as_deref());
+				let (id, name) = names::create_with_retry(args.name.as_deref(), &mut Rng::new(args.seed), |name| async move {
+					this.create_container(options, reuse_key, &name).await
+				})
 				.await?;
-			let (status, output) = self.start_exec(&exec_id, false).await?;
-			if args.verbose {
-				println!("{}", output.as_ref().unwrap());
+				self.events.emit(Event::SystemNamed {
+					id: id.clone(),
+					name: name.clone(),
+				});
+				self.name = name;
+				self.set_container(id);
+				cur += 1;
 			}
-			if status != 0 {
-				return Err(Error::ChaoticAUR(status, get_error_from_either(&output.unwrap_or_default())));
+			{
+				self.step(cur, total, "Starting system");
+				self.start_container().await?;
+				if !args.no_healthcheck {
+					self.healthcheck(&options.image).await?;
+				}
+				cur += 1;
 			}
-			cur += 1;
 		}
-		if args.landware {
-			spinner.set_message("Adding landware");
-			spinner.set_prefix(format!("[{cur}/{total}]"));
-			let exec_id = self
-				.create_exec(
-					r#"
-					printf '\n\n# Added by tempsystem\n[landware]\nServer = https://repo.kage.sj.strangled.net/landware/x86_64\nSigLevel = DatabaseNever PackageNever TrustedOnly' | sudo tee -a /etc/pacman.conf &&
-					sudo pacman -Sy --noconfirm"#
-						.into(),
-					false,
-				)
+		self.detect_features(&options.image, args.verbose, !self.reused && !args.no_bootstrap_user && !args.ro_root)
+			.await?;
+		if options.ro_cwd_overlay {
+			let (status, output) = self
+				.run_as_root(&overlay_mount_script(&options.home, self.features.user()))
 				.await?;
-			let (status, output) = self.start_exec(&exec_id, false).await?;
-			if args.verbose {
-				println!("{}", output.unwrap());
-			}
 			if status != 0 {
-				return Err(Error::Landware(status));
+				return Err(Error::OverlayMount(status, output.trim().to_string()));
 			}
-			cur += 1;
 		}
-		if args.update_system {
-			spinner.set_message("Updating system");
-			spinner.set_prefix(format!("[{cur}/{total}]"));
-			self.update_system(args.verbose).await?;
-			cur += 1;
+		if options.copy_cwd && fresh {
+			self.copy_cwd(&options.home).await?;
+		}
+		if !self.reused {
+			self.start_helper(args.verbose).await;
+			check_features(&self.features, &self.privilege, args, &options)?;
+			cur = setup::run(self, args, &options, cur, total).await?;
+		}
+		if fresh {
+			let record = stats::SetupRecord {
+				pulled,
+				packages: options.packages.len(),
+				aur_packages: options.aur_packages.len(),
+				seconds: setup_started.elapsed().as_secs_f64(),
+			};
+			if let Err(e) = stats::record(record)
+				&& args.verbose
+			{
+				print_error!("could not record how long setup took", e);
+			}
 		}
-		if args.update_pkgfile {
-			spinner.set_message("Updating pkgfile database");
-			spinner.set_prefix(format!("[{cur}/{total}]"));
-			let exec_id = self.create_exec("sudo pkgfile -u".into(), false).await?;
-			let (status, output) = self.start_exec(&exec_id, false).await?;
+		if !options.allow_host.is_empty() {
+			let allowed = firewall::resolve(&options.allow_host)?;
 			if args.verbose {
-				println!("{}", output.unwrap());
+				println!(
+					"allowing connections to {}",
+					allowed
+						.v4
+						.iter()
+						.chain(&allowed.v6)
+						.cloned()
+						.collect::<Vec<String>>()
+						.join(", ")
+				);
 			}
+			let installs = !options.packages.is_empty() || args.update_system.is_some();
+			let (status, output) = self
+				.run_as_root(&firewall::script(&allowed, installs, !options.aur_packages.is_empty()))
+				.await?;
 			if status != 0 {
-				return Err(Error::Pkgfile(status));
+				return Err(classified_error(Phase::Setup, &output, || Error::Firewall(status, get_error_from_pacman(&output))));
 			}
-			cur += 1;
-		}
-		if let Some(pkgs) = &args.extra_packages {
-			self.install_packages(args.verbose, &spinner, cur, total, pkgs)
-				.await?;
-			cur += pkgs.split_whitespace().count();
-		}
-		if let Some(pkgs) = &args.extra_aur_packages {
-			self.install_aur_packages(args.verbose, &spinner, cur, total, pkgs)
-				.await?;
-			cur += pkgs.split_whitespace().count();
 		}
+		let mut term_notice = None;
 		let exec_id = {
-			spinner.set_message("Executing");
-			spinner.set_prefix(format!("[{cur}/{total}]"));
+			self.step(cur, total, "Executing");
 			if args.sync_zsh_history == ZshHistorySync::Copy {
 				self.copy_file(
 					&format!(
@@ -381,266 +1519,1184 @@ impl Context {
 							.map_err(|_| Error::HomeDir)?
 							.display()
 					),
-					"/home/tempsystem",
+					&options.home,
 				)
 				.await?;
 			}
-			if args.command.len() == 1 && args.command[0] == "/usr/bin/zsh" {
-				self.create_exec("SHOW_WELCOME=true /usr/bin/zsh".into(), true)
-					.await?
-			} else {
-				self.create_exec(
-					args.command
-						.iter()
-						.map(|s| s.escape_default().to_string())
-						.collect::<Vec<String>>()
-						.join(" "),
-					true,
-				)
-				.await?
+			if !args.no_term_check {
+				let (_, output) = self.run_command(termcheck::TERM_CHECK_SCRIPT).await?;
+				term_notice = termcheck::apply(&output, &mut self.env);
 			}
+			let login = uses_login_shell(args);
+			if !is_default_command(args) && args.script.is_none() && !self.command_exists(&args.command[0], login).await? {
+				self.handle_missing_command(args, cur, total, login).await?;
+			}
+			let default_command = default_command(args, config, self.display_name(), self.features.user(), &options.home)?;
+			let (cmd, env) = session_command(args, self.features.shell(), &self.env, default_command);
+			self.create_exec_raw(self.features.user(), cmd, true, &env)
+				.await?
 		};
-		spinner.finish_and_clear();
-		m.remove(&spinner);
-		let (exit_code, _) = self.start_exec(&exec_id, true).await?;
-
-		let spinner = m.add(ProgressBar::new_spinner().with_style(ProgressStyle::with_template("{prefix:.bold.dim} {spinner:.blue} {msg}...").unwrap()));
+		self.events.hand_off_terminal().await;
+		if let Some(notice) = term_notice {
+			eprintln!("{}", color::yellow(notice));
+		}
+		let mut thresholds: Vec<u64> = if !args.storage_warn.is_empty() {
+			args.storage_warn.clone()
+		} else {
+			config
+				.storage_warnings
+				.clone()
+				.unwrap_or(DEFAULT_STORAGE_WARNINGS.to_vec())
+		};
+		thresholds.sort();
+		let stop_sampling = self.token.child_token();
+		let crossed = self.spawn_storage_sampler(thresholds.iter().map(|t| t << 30).collect(), stop_sampling.clone());
+		if args.guard {
+			let thresholds = guard::Thresholds {
+				memory_percent: args.guard_memory,
+				load_per_cpu: args.guard_load,
+			};
+			self.spawn_guard(thresholds, args.guard_action, &options, stop_sampling.clone());
+		}
+		let stop_update = self.token.child_token();
+		let update =
+			(args.update_system == Some(UpdateSystem::Background)).then(|| self.spawn_background_update(args.verbose && !args.json, stop_update.clone()));
+		let stop_snapshots = self.token.child_token();
+		let snapshots = args
+			.autosnapshot
+			.map(|interval| self.spawn_autosnapshot(interval, args.autosnapshot_keep as usize, args.verbose, args.seed.is_some(), stop_snapshots.clone()));
+		let (mut exit_code, _) = self.start_exec(&exec_id, true).await?;
+		stop_sampling.cancel();
+		if exit_code != 0
+			&& args.capture_env_on_failure
+			&& let Some(captured) = self.capture_env().await
+		{
+			let failure = serde_json::json!({ "exit_code": exit_code }).to_string();
+			match self.bug_report(args, config, failure, Some(captured)).await {
+				Ok(path) => print_error!(format!("note: the command failed; wrote the captured environment to {}", path.display())),
+				Err(e) => {
+					print_error!("could not write the captured environment", e);
+				}
+			}
+		}
+		if let Some(note) = oom_note(exit_code, &options)
+			&& !self.stdout_closed.load(Ordering::SeqCst)
+		{
+			print_error!(note);
+		}
+		// what shells exit with when the command was found but could not be executed
+		if exit_code == 126 && !is_default_command(args) && args.script.is_none() && !self.stdout_closed.load(Ordering::SeqCst) {
+			let hint = self.arch_hint(Some(&args.command[0])).await;
+			if !hint.contains("probably") {
+				print_error!(format!("note: {hint}"));
+			}
+		}
+		stop_update.cancel();
+		stop_snapshots.cancel();
+		if let Some(snapshots) = snapshots
+			&& let Some(image) = snapshots.lock().unwrap().take()
+			&& !self.stdout_closed.load(Ordering::SeqCst)
+		{
+			println!("latest snapshot: {image}; start over from it with `tempsystem restore-snapshot {}`", self.display_name());
+		}
+		if let Some(update) = update
+			&& let Some(problem) = update.lock().unwrap().problem()
+		{
+			print_error!(problem);
+		}
+		if (args.why_slow || args.verbose)
+			&& !self.stdout_closed.load(Ordering::SeqCst)
+			&& let Some(report) = self.timings.lock().unwrap().report()
+		{
+			print!("{report}");
+		}
+		if args.verbose && !self.stdout_closed.load(Ordering::SeqCst) {
+			print!("daemon capabilities:\n{}", self.capabilities.map());
+		}
+		if args.log_denied
+			&& let Ok((0, output)) = self.run_as_root(firewall::DENIED_SCRIPT).await
+			&& let Some(packets) = firewall::denied_packets(&output)
+			&& !self.stdout_closed.load(Ordering::SeqCst)
+		{
+			println!("--allow-host dropped {packets} outgoing packet(s)");
+		}
+		if let Some(policy) = &policy
+			&& self.verify_policy(policy, args.json).await?
+		{
+			exit_code = policy::EXIT_POLICY_VIOLATION;
+		}
+		if args.chown_work_on_exit {
+			self.chown_work(&options).await;
+		}
+		if (args.verbose || crossed.load(Ordering::SeqCst))
+			&& !self.stdout_closed.load(Ordering::SeqCst)
+			&& let Ok(size) = container_size(self.get_docker()?, &self.container_id).await
 		{
-			spinner.set_message("Deleting system");
-			spinner.set_prefix(format!("[{total}/{total}]"));
-			spinner.enable_steady_tick(Duration::from_millis(50));
-			tokio::time::sleep(Duration::from_millis(250)).await;
-			self.delete_container().await?;
-		}
-		spinner.finish_and_clear();
-		m.remove(&spinner);
+			println!("temporary system used {} of ephemeral storage", HumanBytes(size));
+		}
+		if keep {
+			if !self.stdout_closed.load(Ordering::SeqCst) {
+				println!("kept system `{}`", self.display_name());
+			}
+			self.events.emit(Event::Finished { exit_code });
+			return Ok(exit_code);
+		}
+		if keep_system(Some(exit_code), false, args.keep_on_failure, args.keep_on_error) {
+			if !self.stdout_closed.load(Ordering::SeqCst) {
+				println!("{}", kept_on_failure(exit_code, &self.container_id, self.features.shell()));
+			}
+			self.events.emit(Event::Finished { exit_code });
+			return Ok(exit_code);
+		}
+
+		if args.wipe_on_exit {
+			self.step(total - 1, total, "Wiping system");
+			match tokio::time::timeout(WIPE_TIMEOUT, self.run_as_root(WIPE_SCRIPT)).await {
+				Ok(Ok(_)) => {}
+				Ok(Err(e)) => {
+					print_error!("could not wipe the system, deleting it anyway", e);
+				}
+				Err(_) => print_error!(format!("wiping the system took over {}s, deleting it anyway", WIPE_TIMEOUT.as_secs())),
+			}
+		}
+		self.step(total, total, "Deleting system");
+		tokio::time::sleep(Duration::from_millis(250)).await;
+		self.delete_container().await?;
+		self.events.emit(Event::Finished { exit_code });
+		if args.wipe_on_exit && !self.stdout_closed.load(Ordering::SeqCst) {
+			println!("wiped and deleted the system; whether its overwritten blocks are gone from disk depends on the storage driver");
+		}
 		return Ok(exit_code);
 	}
 
+	/// Gives the files in ~/work owned by someone else back to the host user, for --chown-work-on-exit.
+	async fn chown_work(&self, options: &SessionOptions) {
+		// a daemon on another machine dropped the mount
+		if !options.mount_cwd {
+			print_error!("--chown-work-on-exit did nothing, the current directory is not mounted at ~/work");
+			return;
+		}
+		match self.run_as_root(&chown_work_script(&options.home)).await {
+			Ok((0, output)) => {
+				let fixed = output
+					.lines()
+					.find_map(|l| l.strip_prefix("fixed="))
+					.unwrap_or("0")
+					.trim();
+				if !self.stdout_closed.load(Ordering::SeqCst) {
+					println!("--chown-work-on-exit fixed the owner of {fixed} file(s) in ~/work");
+				}
+			}
+			Ok((status, output)) => print_error!(format!("--chown-work-on-exit failed with {status}: {}", output.trim())),
+			Err(e) => {
+				print_error!("--chown-work-on-exit failed", e);
+			}
+		}
+	}
+
+	/// Freezes the system for `--pause-on-detach` once the terminal hung up, keeping it for `tempsystem attach` to pick up.
+	pub async fn pause_detached(&self) -> Result<(), Error> {
+		if *self.lifecycle.lock().unwrap() != Lifecycle::Started {
+			return Err(Error::NotStarted);
+		}
+		let docker = self.get_docker()?;
+		let paused = docker.pause_container(&self.container_id).await;
+		self.capabilities.record(Capability::Pause, &paused);
+		paused.map_err(Error::ContainerPause)?;
+		self.live_container.lock().unwrap().take();
+		return paused::record(&self.container_id);
+	}
+
+	/// Deletes the system, doing nothing when there is none, like after a cancel during the image pull.
 	pub async fn delete_container(&self) -> Result<(), Error> {
+		if matches!(*self.lifecycle.lock().unwrap(), Lifecycle::NotCreated | Lifecycle::Deleted) {
+			return Ok(());
+		}
 		let docker = self.get_docker()?;
-		docker
+		let removed = docker
 			.remove_container(
 				&self.container_id,
 				Some(
 					bollard::query_parameters::RemoveContainerOptionsBuilder::default()
 						.force(true)
+						.v(self.remove_volumes)
 						.build(),
 				),
 			)
-			.await
-			.map_err(Error::ContainerDelete)?;
+			.await;
+		match removed {
+			Err(e) if already_deleted(&e) => {}
+			removed => removed.map_err(Error::ContainerDelete)?,
+		}
+		self.set_lifecycle(Lifecycle::Deleted);
+		self.live_container.lock().unwrap().take();
 
 		return Ok(());
 	}
 
 	async fn create_exec(&self, command: String, attach: bool) -> Result<String, Error> {
+		return self.create_exec_with_env(command, attach, &self.env).await;
+	}
+
+	async fn create_exec_with_env(&self, command: String, attach: bool, env: &ExecEnv) -> Result<String, Error> {
+		return self
+			.create_exec_raw(self.features.user(), vec![self.features.shell().into(), "-c".into(), command], attach, env)
+			.await;
+	}
+
+	async fn create_exec_raw(&self, user: &str, cmd: Vec<String>, attach: bool, env: &ExecEnv) -> Result<String, Error> {
 		let docker = self.get_docker()?;
 		let exec = docker
-			.create_exec(
-				&self.container_id,
-				bollard::models::ExecConfig {
-					attach_stdout: Some(true),
-					attach_stderr: Some(true),
-					attach_stdin: Some(attach),
-					user: Some("tempsystem".into()),
-					tty: Some(attach),
-					cmd: Some(vec!["/usr/bin/zsh".into(), "-c".into(), format!("{command}")]),
-					..Default::default()
-				},
-			)
+			.create_exec(&self.container_id, exec_config(user, cmd, attach, self.tty, env))
 			.await
 			.map_err(Error::ExecCreate)?
 			.id;
+		if !attach {
+			self.timings.lock().unwrap().created(&exec);
+		}
 		return Ok(exec);
 	}
 
 	async fn start_exec(&self, exec_id: &str, attach: bool) -> Result<(i64, Option<String>), Error> {
 		let docker = self.get_docker()?;
-		let output = if attach {
-			let (mut output, mut input) = if let bollard::exec::StartExecResults::Attached { output, input } = docker
-				.start_exec(exec_id, None)
-				.await
-				.map_err(Error::ExecStart)?
-			{
-				(output, input)
-			} else {
-				return Err(Error::ExpectedAttached);
-			};
-			tokio::task::spawn(async move {
-				#[allow(clippy::unbuffered_bytes)]
-				let mut stdin = async_stdin().bytes();
-				loop {
-					if let Some(Ok(byte)) = stdin.next()
-						&& let Err(e) = input.write_all(&[byte]).await
-					{
-						print_error!("failed to write to exec's stdin", e);
-						break;
-					} else {
-						tokio::time::sleep(Duration::from_nanos(10)).await;
-					}
-				}
-			});
-
-			let tty_size = terminal_size().map_err(Error::TerminalSize)?;
-			docker
-				.resize_exec(
-					exec_id,
-					bollard::query_parameters::ResizeExecOptionsBuilder::default()
-						.h(tty_size.1 as i32)
-						.w(tty_size.0 as i32)
-						.build(),
-				)
-				.await
-				.map_err(Error::ExecResize)?;
-
-			let stdout = std::io::stdout();
-			let mut stdout = stdout.lock().into_raw_mode().map_err(Error::Rawmode)?;
-
-			while let Some(Ok(output)) = output.next().await {
-				stdout
-					.write_all(output.into_bytes().as_ref())
-					.map_err(Error::StdoutWrite)?;
-				stdout.flush().map_err(Error::StdoutFlush)?;
-			}
+		if !attach {
+			let (status, output) = self.start_exec_streamed(exec_id, |_| {}).await?;
+			return Ok((status, Some(output)));
+		}
 
-			None
-		} else if let bollard::exec::StartExecResults::Attached { mut output, .. } = docker
+		let (mut output, mut input) = if let bollard::exec::StartExecResults::Attached { output, input } = docker
 			.start_exec(exec_id, None)
 			.await
 			.map_err(Error::ExecStart)?
 		{
-			use std::fmt::Write;
-
-			let mut stdout = String::new();
-			while let Some(Ok(output)) = output.next().await {
-				stdout
-					.write_fmt(format_args!("{output}"))
-					.map_err(Error::StdoutFmtWrite)?;
-			}
-
-			Some(stdout)
+			(output, input)
 		} else {
 			return Err(Error::ExpectedAttached);
 		};
+		let stop = self.token.child_token();
+		let _stop_on_return = stop.clone().drop_guard();
+		// while a root shell is open, stdin goes to it instead
+		let overlay: OverlayInput = Default::default();
+		let overlay_clone = overlay.clone();
+		let (root_sender, mut root_requests) = tokio::sync::mpsc::unbounded_channel();
+		let (suspend_sender, mut suspend_requests) = tokio::sync::mpsc::unbounded_channel();
+		let host_job_control = self.host_job_control;
+		let mut detector = self.escape.map(EscapeDetector::new);
+		self.tasks.spawn(async move {
+			#[allow(clippy::unbuffered_bytes)]
+			let mut stdin = async_stdin().bytes();
+			while !stop.is_cancelled() {
+				let Some(Ok(byte)) = stdin.next() else {
+					tokio::time::sleep(Duration::from_nanos(10)).await;
+					continue;
+				};
+				let bytes = match detector.as_mut().map(|d| d.feed(byte)) {
+					Some(Input::Root) => {
+						let _ = root_sender.send(());
+						continue;
+					}
+					Some(Input::Forward(bytes)) => bytes,
+					None => vec![byte],
+				};
+				let mut overlay = overlay_clone.lock().await;
+				if host_job_control && overlay.is_none() && bytes.contains(&escape::SUSPEND) {
+					let _ = suspend_sender.send(());
+					continue;
+				}
+				let written = match overlay.as_mut() {
+					Some(root) => root.write_all(&bytes).await,
+					None => input.write_all(&bytes).await,
+				};
+				if let Err(e) = written {
+					if overlay.is_some() {
+						// the root shell is on its way out, the primary exec gets stdin back once it is gone
+						continue;
+					}
+					print_error!("failed to write to exec's stdin", e);
+					break;
+				}
+			}
+		});
+
+		// with --tty and stdout piped, there is no terminal of ours to size the tty after or to put in raw mode
+		let raw = if self.tty && std::io::stdout().is_terminal() {
+			self.resize_exec(exec_id).await?;
+			match std::io::stdout().into_raw_mode() {
+				Ok(raw) => Some(raw),
+				Err(e) if self.degraded_tty => {
+					eprintln!(
+						"{}",
+						color::yellow(format!("could not set raw mode ({e}); input is passed line by line and interactive programs will not work"))
+					);
+					None
+				}
+				Err(e) => return Err(Error::Rawmode(e)),
+			}
+		} else {
+			None
+		};
+		let mut stdout: Box<dyn Write> = Box::new(std::io::stdout().lock());
+		// in raw mode Ctrl-Z reaches the exec as a byte, but without a tty the terminal turns it into SIGTSTP,
+		// which would stop us instead of the program it was meant for; listening for it keeps it from doing that
+		let _ignore_stop = if self.host_job_control {
+			None
+		} else {
+			signal(SignalKind::from_raw(SIGTSTP)).ok()
+		};
+
+		let mut held_back = Utf8Holdback::default();
+		loop {
+			let next = tokio::select! {
+				next = async {
+					if held_back.is_empty() {
+						Some(output.next().await)
+					} else {
+						tokio::time::timeout(utf8::HOLDBACK_TIMEOUT, output.next())
+							.await
+							.ok()
+					}
+				} => next,
+				Some(()) = suspend_requests.recv() => {
+					self.suspend(raw.as_ref()).await?;
+					self.resize_exec(exec_id).await?;
+					continue;
+				}
+				Some(()) = root_requests.recv() => {
+					self.root_overlay(&mut stdout, &overlay).await?;
+					// escapes typed in the root shell were meant for it
+					while root_requests.try_recv().is_ok() {}
+					self.resize_exec(exec_id).await?;
+					continue;
+				}
+			};
+			let (bytes, done) = match next {
+				// the rest of a split character did not come in time
+				None => (held_back.flush(), false),
+				Some(Some(Ok(output))) => {
+					// without a tty the streams stay apart, so stderr can go where it belongs
+					if let bollard::container::LogOutput::StdErr { message } = &output {
+						let _ = std::io::stderr().write_all(message);
+						continue;
+					}
+					(held_back.feed(output.into_bytes().as_ref()), false)
+				}
+				Some(_) => (held_back.flush(), true),
+			};
+			let written = stdout
+				.write_all(&bytes)
+				.map_err(Error::StdoutWrite)
+				.and_then(|_| stdout.flush().map_err(Error::StdoutFlush));
+			match written {
+				Err(Error::StdoutWrite(e) | Error::StdoutFlush(e)) if e.kind() == std::io::ErrorKind::BrokenPipe => {
+					// whatever was reading our output is gone, so end the session like a shell pipeline would
+					drop(stdout);
+					self.stdout_closed.store(true, Ordering::SeqCst);
+					self.signal_exec(exec_id, "PIPE").await;
+					return Ok((EXIT_BROKEN_PIPE, None));
+				}
+				Err(e) => return Err(e),
+				Ok(()) => {}
+			}
+			if done {
+				break;
+			}
+		}
 
 		let inspect = docker
 			.inspect_exec(exec_id)
 			.await
 			.map_err(Error::ExecInspect)?;
-		return Ok((inspect.exit_code.unwrap_or(0), output));
+		return Ok((inspect.exit_code.unwrap_or(0), None));
 	}
 
-	async fn pull_image(&self, m: &MultiProgress) -> Result<(), Error> {
-		let docker = self.get_docker()?;
-		let mut stream = docker.create_image(
-			Some(
-				bollard::query_parameters::CreateImageOptionsBuilder::default()
-					.from_image("landsj/tempsystem:latest")
-					.build(),
-			),
-			None,
-			None,
-		);
-		let sty = ProgressStyle::with_template("[{elapsed_precise}] {bar:40.cyan/blue} {bytes:>15}/{total_bytes:15} {msg}")
-			.unwrap()
-			.progress_chars("##-");
-		let mut bars: HashMap<String, ProgressBar> = HashMap::new();
-		while let Some(update) = stream.next().await {
-			let update = update.map_err(Error::ImageCreate)?;
-			if let Some(id) = update.id
-				&& id != "latest"
-			{
-				if let Some(progress) = update.progress_detail
-					&& let Some(cur) = progress.current
-					&& let Some(total) = progress.total
-				{
-					let pb = bars
-						.entry(id.clone())
-						.or_insert(m.add(ProgressBar::new(total as u64).with_style(sty.clone())));
-					pb.set_position(cur as u64);
-					pb.set_length(total as u64);
-				}
-				let pb = bars
-					.entry(id.clone())
-					.or_insert(m.add(ProgressBar::no_length().with_style(sty.clone())));
-				if let Some(msg) = update.status {
-					if msg == "Pull complete" {
-						pb.finish_and_clear();
-						m.remove(pb);
-						bars.remove(&id);
-					} else if msg.ends_with(" complete")
-						&& let Some(max) = pb.length()
-					{
-						pb.set_position(max);
-					} else {
-						pb.set_message(msg);
-					}
+	/// Stops this process like Ctrl-Z would outside of raw mode, handing the terminal back in cooked mode until it is continued.
+	async fn suspend(&self, raw: Option<&RawTerminal<Stdout>>) -> Result<(), Error> {
+		if let Some(raw) = raw {
+			raw.suspend_raw_mode().map_err(Error::Rawmode)?;
+		}
+		// returns once the shell continues us with `fg`
+		tokio::process::Command::new("kill")
+			.args(["-STOP", &std::process::id().to_string()])
+			.status()
+			.await
+			.map_err(Error::Suspend)?;
+		if let Some(raw) = raw {
+			raw.activate_raw_mode().map_err(Error::Rawmode)?;
+		}
+
+		return Ok(());
+	}
+
+	/// Resizes `exec_id`'s tty to the terminal's size, or to `DEGRADED_TTY_SIZE` with a degraded tty that has none.
+	async fn resize_exec(&self, exec_id: &str) -> Result<(), Error> {
+		let tty_size = match terminal_size() {
+			Ok(size) => size,
+			Err(e) if self.degraded_tty => {
+				if !self.size_unknown.swap(true, Ordering::SeqCst) {
+					eprintln!(
+						"{}",
+						color::yellow(format!("could not read the terminal size ({e}), assuming {}x{}", DEGRADED_TTY_SIZE.0, DEGRADED_TTY_SIZE.1))
+					);
 				}
+				DEGRADED_TTY_SIZE
 			}
+			Err(e) => return Err(Error::TerminalSize(e)),
+		};
+		if !self.capabilities.usable(Capability::ExecResize) {
+			return Ok(());
 		}
-		for (_, pb) in bars {
-			pb.finish_and_clear();
+		let resized = self
+			.get_docker()?
+			.resize_exec(
+				exec_id,
+				bollard::query_parameters::ResizeExecOptionsBuilder::default()
+					.h(tty_size.1 as i32)
+					.w(tty_size.0 as i32)
+					.build(),
+			)
+			.await;
+		if self.capabilities.record(Capability::ExecResize, &resized) {
+			warn_unsupported(Capability::ExecResize);
+			return Ok(());
 		}
+		resized.map_err(Error::ExecResize)?;
 
 		return Ok(());
 	}
 
-	#[allow(clippy::too_many_arguments)]
-	async fn create_container(
-		&self,
-		network_disabled: bool,
-		privileged: bool,
-		ro_root: bool,
-		ro_cwd: bool,
-		mount_cwd: bool,
-		mount_history: bool,
-		cpus: Option<u8>,
-		memory: Option<usize>,
-	) -> Result<String, Error> {
+	/// Runs a root shell over the session until it exits, with stdin routed to it through `overlay`.
+	/// The primary exec's output waits until the shell is gone.
+	async fn root_overlay(&self, stdout: &mut Box<dyn Write>, overlay: &OverlayInput) -> Result<(), Error> {
+		let exec_id = self
+			.create_exec_raw("root", vec![self.features.shell().into()], true, &self.env)
+			.await?;
+		let StartExecResults::Attached { mut output, input } = self
+			.get_docker()?
+			.start_exec(&exec_id, None)
+			.await
+			.map_err(Error::ExecStart)?
+		else {
+			return Err(Error::ExpectedAttached);
+		};
+		// on stdout, where the root shell's output goes, but in raw mode like `print_over_session`
+		write!(stdout, "\r\n[root shell, exit it to return to the session]\r\n").map_err(Error::StdoutWrite)?;
+		stdout.flush().map_err(Error::StdoutFlush)?;
+		*overlay.lock().await = Some(input);
+		self.resize_exec(&exec_id).await?;
+
+		let mut held_back = Utf8Holdback::default();
+		let pumped = async {
+			while let Some(Ok(chunk)) = output.next().await {
+				stdout
+					.write_all(&held_back.feed(chunk.into_bytes().as_ref()))
+					.map_err(Error::StdoutWrite)?;
+				stdout.flush().map_err(Error::StdoutFlush)?;
+			}
+			stdout
+				.write_all(&held_back.flush())
+				.map_err(Error::StdoutWrite)?;
+			write!(stdout, "\r\n[back to the session]\r\n").map_err(Error::StdoutWrite)?;
+			stdout.flush().map_err(Error::StdoutFlush)?;
+			return Ok(());
+		}
+		.await;
+		overlay.lock().await.take();
+
+		return pumped;
+	}
+
+	/// Starts a non-interactive exec, handing each chunk of its output to `on_output` as it arrives.
+	async fn start_exec_streamed(&self, exec_id: &str, mut on_output: impl FnMut(&str)) -> Result<(i64, String), Error> {
+		use std::fmt::Write;
+
 		let docker = self.get_docker()?;
-		let mut binds = vec![];
-		if mount_cwd {
-			binds.push(format!(
-				"{}:/home/tempsystem/work{}",
-				std::env::current_dir().map_err(Error::GetCWD)?.display(),
-				if ro_cwd { ":ro" } else { "" }
-			));
-		}
-		if mount_history {
-			binds.push(format!(
-				"{}/.zsh_history:/home/tempsystem/.zsh_history",
-				std::env::home_dir()
-					.ok_or(Error::HomeDir)?
-					.canonicalize()
-					.map_err(|_| Error::HomeDir)?
-					.display()
-			));
+		let bollard::exec::StartExecResults::Attached { mut output, .. } = docker
+			.start_exec(exec_id, None)
+			.await
+			.map_err(Error::ExecStart)?
+		else {
+			return Err(Error::ExpectedAttached);
+		};
+		let mut stdout = String::new();
+		while let Some(Ok(output)) = output.next().await {
+			let start = stdout.len();
+			stdout
+				.write_fmt(format_args!("{output}"))
+				.map_err(Error::StdoutFmtWrite)?;
+			on_output(&stdout[start..]);
+			self.events.emit(Event::ExecOutputChunk {
+				exec_id: exec_id.into(),
+				stream: "setup".into(),
+				output: stdout[start..].into(),
+			});
 		}
-		let id = docker
+		self.timings.lock().unwrap().finished(exec_id);
+
+		let inspect = docker
+			.inspect_exec(exec_id)
+			.await
+			.map_err(Error::ExecInspect)?;
+		let status = inspect.exit_code.unwrap_or(0);
+		let command = inspect
+			.process_config
+			.map(|p| {
+				std::iter::once(p.entrypoint.unwrap_or_default())
+					.chain(p.arguments.unwrap_or_default())
+					.collect::<Vec<String>>()
+					.join(" ")
+			})
+			.unwrap_or_default();
+		self.record(&command, status, &stdout);
+		return Ok((status, stdout));
+	}
+
+	/// Adds an exec to the transcript, when `--bug-report` keeps one.
+	fn record(&self, command: &str, status: i64, output: &str) {
+		if let Some(transcript) = self.transcript.lock().unwrap().as_mut() {
+			transcript.push(format!("$ {command}\n{output}\n[exit status {status}]\n"));
+		}
+	}
+
+	/// Writes what went into the failure `error` to a bundle with `bugreport::write`, returning where it went.
+	pub async fn bug_report(
+		&self,
+		args: &Args,
+		config: &Config,
+		failure: String,
+		captured: Option<Vec<(&'static str, String)>>,
+	) -> Result<std::path::PathBuf, Error> {
+		let mut options = alias::effective_args(config).join(" ");
+		if let Ok(session) = SessionOptions::from_args(args, config) {
+			options += &format!("\n{}\n", serde_json::to_string_pretty(&session).unwrap_or_default());
+		}
+		let mut files = vec![
+			("options.txt", options),
+			("failure.json", failure),
+			("timings.txt", self.timings.lock().unwrap().report().unwrap_or_default()),
+			(
+				"execs.txt",
+				self.transcript
+					.lock()
+					.unwrap()
+					.as_deref()
+					.unwrap_or_default()
+					.join("\n"),
+			),
+		];
+		if let Ok(docker) = self.get_docker() {
+			let version = docker
+				.version()
+				.await
+				.map(|v| serde_json::to_string_pretty(&v).unwrap_or_default());
+			let info = docker
+				.info()
+				.await
+				.map(|i| serde_json::to_string_pretty(&i).unwrap_or_default());
+			files.push(("daemon.json", format!("{}\n{}", version.unwrap_or_else(|e| e.to_string()), info.unwrap_or_else(|e| e.to_string()))));
+			if !self.container_id.is_empty()
+				&& let Ok(inspect) = docker
+					.inspect_container(&self.container_id, None::<bollard::query_parameters::InspectContainerOptions>)
+					.await
+			{
+				files.push(("container.json", serde_json::to_string_pretty(&inspect).unwrap_or_default()));
+			}
+		}
+		files.extend(captured.unwrap_or_default());
+
+		return bugreport::write(&files).map_err(Error::BugReport);
+	}
+
+	/// `Error::CgroupParent` for the daemon refusing `--cgroup-parent` with `message`, explained for its cgroup driver.
+	async fn cgroup_parent_error(&self, message: String) -> Error {
+		let driver = match self.get_docker() {
+			Ok(docker) => docker
+				.info()
+				.await
+				.ok()
+				.and_then(|info| info.cgroup_driver)
+				.map(|driver| driver.to_string()),
+			Err(_) => None,
+		};
+		let parent = self.cgroup_parent.clone().unwrap_or_default();
+		return Error::CgroupParent(cgroup_parent_hint(&parent, driver.as_deref()), message);
+	}
+
+	/// Runs `capture::CAPTURE_SCRIPT` in the system for `--capture-env-on-failure`, or `None` when it is gone or not
+	/// running anymore.
+	pub async fn capture_env(&self) -> Option<Vec<(&'static str, String)>> {
+		let docker = self.get_docker().ok()?;
+		if self.container_id.is_empty() {
+			return None;
+		}
+		let running = docker
+			.inspect_container(&self.container_id, None::<bollard::query_parameters::InspectContainerOptions>)
+			.await
+			.ok()?
+			.state
+			.and_then(|s| s.running)
+			.unwrap_or(false);
+		if !running {
+			return None;
+		}
+		let exec_id = self
+			.create_exec(capture::CAPTURE_SCRIPT.into(), false)
+			.await
+			.ok()?;
+		let (_, output) = self.start_exec(&exec_id, false).await.ok()?;
+
+		return Some(capture::files(&output.unwrap_or_default()));
+	}
+
+	/// Best effort: sends `signal` to the exec's process from inside the container.
+	/// Only works when the daemon shares our /proc, which is the usual local setup.
+	async fn signal_exec(&self, exec_id: &str, signal: &str) {
+		let Ok(docker) = self.get_docker() else {
+			return;
+		};
+		let Some(pid) = docker
+			.inspect_exec(exec_id)
+			.await
+			.ok()
+			.and_then(|inspect| inspect.pid)
+			.and_then(namespaced_pid)
+		else {
+			return;
+		};
+		if let Ok(exec_id) = self
+			.create_exec_raw("root", vec!["kill".into(), format!("-{signal}"), pid], false, &ExecEnv::default())
+			.await
+		{
+			let _ = docker
+				.start_exec(
+					&exec_id,
+					Some(bollard::exec::StartExecOptions {
+						detach: true,
+						..Default::default()
+					}),
+				)
+				.await;
+		}
+	}
+
+	/// Builds `recipe` unless an image of it exists already, or with `always` anyway, without the build cache.
+	async fn build_image(&self, recipe: &Recipe, verbose: bool, always: bool) -> Result<(), Error> {
+		let docker = self.get_docker()?;
+		let tag = recipe.tag();
+		if !always && docker.inspect_image(&tag).await.is_ok() {
+			return Ok(());
+		}
+
+		let labels = HashMap::from([(build::LABEL_DERIVED, recipe.base.as_str())]);
+		let mut stream = docker.build_image(
+			bollard::query_parameters::BuildImageOptionsBuilder::default()
+				.dockerfile("Containerfile")
+				.t(&tag)
+				.labels(&labels)
+				.rm(true)
+				.forcerm(true)
+				.nocache(always)
+				.platform(self.platform.as_deref().unwrap_or_default())
+				.build(),
+			None,
+			Some(bollard::body_full(recipe.context()?.into())),
+		);
+		// output of the step currently running, shown if it fails
+		let mut step_output = String::new();
+		while let Some(info) = stream.next().await {
+			let info = match info {
+				Ok(info) => info,
+				Err(e) => return Err(Error::ImageBuild(e.to_string(), log_tail(&step_output))),
+			};
+			if let Some(error) = info.error_detail.and_then(|d| d.message).or(info.error) {
+				return Err(Error::ImageBuild(error, log_tail(&step_output)));
+			}
+			if let Some(line) = info.stream {
+				if verbose {
+					print!("{}", redact(&line));
+				}
+				if line.starts_with("Step ") {
+					step_output.clear();
+					self.events
+						.emit(Event::BuildStep { step: line.trim().to_string() });
+				}
+				step_output.push_str(&line);
+			}
+		}
+
+		return Ok(());
+	}
+
+	/// Images from `--build`, `--containerfile`, and `--autosnapshot`, by tag, with their sizes.
+	pub async fn derived_images(&self) -> Result<Vec<(String, i64)>, Error> {
+		let docker = self.get_docker()?;
+		let mut images = list_labeled_images(docker, build::LABEL_DERIVED)
+			.await
+			.map_err(Error::ImageList)?;
+		images.extend(
+			list_labeled_images(docker, snapshot::LABEL_SNAPSHOT)
+				.await
+				.map_err(Error::ImageList)?,
+		);
+
+		return Ok(images
+			.into_iter()
+			.flat_map(|image| {
+				image
+					.repo_tags
+					.into_iter()
+					.map(move |tag| (tag, image.size))
+			})
+			.collect());
+	}
+
+	/// What `tempsystem prune` would remove: systems tempsystem created, skipping running and paused ones (returned by
+	/// name) unless `running`, or for paused ones, unless paused longer than `paused_longer`, and with `images`, versions
+	/// of our image no tag points at anymore.
+	pub async fn leftovers(&self, running: bool, paused_longer: Option<Duration>, images: bool) -> Result<(Vec<Leftover>, Vec<String>), Error> {
+		let docker = self.get_docker()?;
+		let containers = docker
+			.list_containers(Some(
+				bollard::query_parameters::ListContainersOptionsBuilder::default()
+					.all(true)
+					.size(true)
+					.filters(&HashMap::from([("label", vec![format!("{}=true", session::LABEL_MANAGED)])]))
+					.build(),
+			))
+			.await
+			.map_err(Error::ContainerList)?;
+		let paused = paused::load();
+		let mut leftovers = vec![];
+		let mut skipped = vec![];
+		for container in containers {
+			let Some(id) = container.id else {
+				continue;
+			};
+			let name = container
+				.names
+				.unwrap_or_default()
+				.first()
+				.map(|n| n.trim_start_matches('/').to_string())
+				.unwrap_or_else(|| id[..id.len().min(12)].to_string());
+			let paused_for = paused.get(&id).copied();
+			let keep = match container.state {
+				Some(bollard::models::ContainerSummaryStateEnum::RUNNING) => !running,
+				Some(bollard::models::ContainerSummaryStateEnum::PAUSED) => {
+					let expired = paused_for
+						.zip(paused_longer)
+						.is_some_and(|(paused_for, longer)| paused_for > longer);
+					!running && !expired
+				}
+				_ => false,
+			};
+			if keep {
+				skipped.push(match paused_for {
+					Some(paused_for) => format!("{name} (paused {} ago)", HumanDuration(paused_for)),
+					None => name,
+				});
+				continue;
+			}
+			leftovers.push(Leftover::System {
+				id,
+				name,
+				size: container.size_rw.unwrap_or_default().max(0) as u64,
+				paused_for,
+			});
+		}
+		if images {
+			let all = docker
+				.list_images(None::<bollard::query_parameters::ListImagesOptions>)
+				.await
+				.map_err(Error::ImageList)?;
+			for image in all {
+				let untagged = image.repo_tags.iter().all(|t| t == "<none>:<none>");
+				let Some(repo) = image
+					.repo_digests
+					.iter()
+					.map(|d| ImageRef::split(d).name())
+					.find(|repo| session::is_own_image(repo))
+				else {
+					continue;
+				};
+				if untagged {
+					leftovers.push(Leftover::Image {
+						repo,
+						id: image.id,
+						size: image.size.max(0) as u64,
+					});
+				}
+			}
+		}
+
+		return Ok((leftovers, skipped));
+	}
+
+	/// Removes what `leftovers` found, with its anonymous volumes.
+	pub async fn remove_leftover(&self, leftover: &Leftover) -> Result<(), Error> {
+		let docker = self.get_docker()?;
+		match leftover {
+			Leftover::System { id, .. } => docker
+				.remove_container(
+					id,
+					Some(
+						bollard::query_parameters::RemoveContainerOptionsBuilder::default()
+							.force(true)
+							.v(true)
+							.build(),
+					),
+				)
+				.await
+				.map_err(Error::ContainerDelete)
+				.map(|_| {
+					let _ = paused::forget(id);
+				})?,
+			Leftover::Image { id, .. } => {
+				docker
+					.remove_image(id, None::<bollard::query_parameters::RemoveImageOptions>, None)
+					.await
+					.map_err(Error::ImageRemove)?;
+			}
+		}
+
+		return Ok(());
+	}
+
+	/// Finds the system `target` names, by exact ID or name first, then by a unique prefix of either, as `(id, name)`.
+	/// Containers tempsystem did not create are refused unless `force`.
+	pub async fn resolve_container(&self, target: &str, force: bool) -> Result<(String, String), Error> {
+		let docker = self.get_docker()?;
+		let containers = docker
+			.list_containers(Some(
+				bollard::query_parameters::ListContainersOptionsBuilder::default()
+					.all(true)
+					.build(),
+			))
+			.await
+			.map_err(Error::ContainerList)?;
+		let candidates: Vec<(String, Vec<String>, bool)> = containers
+			.into_iter()
+			.filter_map(|c| {
+				let id = c.id?;
+				let names = c
+					.names
+					.unwrap_or_default()
+					.into_iter()
+					.map(|n| n.trim_start_matches('/').to_string())
+					.collect();
+				let managed = c
+					.labels
+					.is_some_and(|l| l.get(session::LABEL_MANAGED).map(String::as_str) == Some("true"));
+				return Some((id, names, managed));
+			})
+			.collect();
+
+		let exact: Vec<_> = candidates
+			.iter()
+			.filter(|(id, names, _)| id == target || names.iter().any(|n| n == target))
+			.collect();
+		let matches = if !exact.is_empty() {
+			exact
+		} else {
+			candidates
+				.iter()
+				.filter(|(id, names, _)| id.starts_with(target) || names.iter().any(|n| n.starts_with(target)))
+				.collect()
+		};
+		let (id, names, managed) = match matches.as_slice() {
+			[] => return Err(Error::NoSuchSystem(target.into())),
+			[found] => found,
+			_ => {
+				let found = matches
+					.iter()
+					.map(|(id, names, _)| {
+						names
+							.first()
+							.cloned()
+							.unwrap_or_else(|| id[..12.min(id.len())].to_string())
+					})
+					.collect::<Vec<String>>();
+				return Err(Error::AmbiguousSystem(target.into(), found.join(", ")));
+			}
+		};
+		let name = names.first().cloned().unwrap_or_else(|| id.clone());
+		if !managed && !force {
+			return Err(Error::KillUnmanaged(name));
+		}
+
+		return Ok((id.clone(), name));
+	}
+
+	/// Deletes the system `id`. With `graceful`, its processes get SIGTERM and `GRACEFUL_KILL_WAIT` to exit first.
+	/// A tempsystem attached to it sees its session end, restores the terminal, and exits.
+	pub async fn kill_container(&mut self, id: &str, graceful: bool) -> Result<(), Error> {
+		self.container_id = id.into();
+		self.set_lifecycle(Lifecycle::Created);
+		// a stopped system has no processes to ask
+		if graceful && self.run_as_root("kill -TERM -1").await.is_ok() {
+			tokio::time::sleep(GRACEFUL_KILL_WAIT).await;
+		}
+		return self.delete_container().await;
+	}
+
+	/// The systems tempsystem created that still exist, kept, paused, or in use by another run.
+	pub async fn systems(&self) -> Result<Vec<ListedSystem>, Error> {
+		let docker = self.get_docker()?;
+		let containers = docker
+			.list_containers(Some(
+				bollard::query_parameters::ListContainersOptionsBuilder::default()
+					.all(true)
+					.filters(&HashMap::from([("label", vec![format!("{}=true", session::LABEL_MANAGED)])]))
+					.build(),
+			))
+			.await
+			.map_err(Error::ContainerList)?;
+		let paused = paused::load();
+
+		return Ok(containers
+			.into_iter()
+			.filter_map(|container| {
+				let id = container.id?;
+				let name = container
+					.names
+					.unwrap_or_default()
+					.first()
+					.map(|n| n.trim_start_matches('/').to_string())
+					.unwrap_or_else(|| id[..id.len().min(12)].to_string());
+				let state = container
+					.state
+					.map(|s| s.to_string())
+					.unwrap_or_else(|| "unknown".into());
+				let paused_for = paused.get(&id).copied().filter(|_| state == "paused");
+				return Some(ListedSystem { id, name, state, paused_for });
+			})
+			.collect());
+	}
+
+	/// Opens a session in the system `target` that `--keep`, `--keep-on-error`, or `--pause-on-detach` left behind,
+	/// unpausing or starting it first. Unlike `--resume` it takes the system as it is, so no options have to match, and
+	/// the system outlives the session.
+	pub async fn attach(&mut self, target: &str, args: &Args, config: &Config) -> Result<i64, Error> {
+		let (id, name) = self.resolve_container(target, true).await?;
+		let inspect = self
+			.get_docker()?
+			.inspect_container(&id, None::<bollard::query_parameters::InspectContainerOptions>)
+			.await
+			.map_err(Error::ContainerInspect)?;
+		let container_config = inspect.config.unwrap_or_default();
+		let labels = container_config.labels.unwrap_or_default();
+		if labels.get(session::LABEL_MANAGED).map(String::as_str) != Some("true") {
+			return Err(Error::ResumeUnmanaged(name));
+		}
+		self.set_session_terminal(args)?;
+		self.env = session_env(args, config)?;
+		self.container_id = id;
+		self.name = name;
+		self.reused = true;
+		self.ensure_running().await?;
+		self.detect_features(&container_config.image.unwrap_or_default(), args.verbose, false)
+			.await?;
+		let (cmd, env) = session_command(args, self.features.shell(), &self.env, None);
+		let exec_id = self
+			.create_exec_raw(self.features.user(), cmd, true, &env)
+			.await?;
+		self.events.hand_off_terminal().await;
+		let (exit_code, _) = self.start_exec(&exec_id, true).await?;
+		if !self.stdout_closed.load(Ordering::SeqCst) {
+			println!("kept system `{}`", self.display_name());
+		}
+
+		return Ok(exit_code);
+	}
+
+	async fn image_present(&self, image: &str) -> bool {
+		let Ok(docker) = self.get_docker() else {
+			return false;
+		};
+		let Ok(inspect) = docker.inspect_image(image).await else {
+			return false;
+		};
+		// a local image for another platform has to be pulled again
+		return self
+			.platform
+			.as_deref()
+			.is_none_or(|platform| registry::platform_matches(platform, &image_platform(&inspect)));
+	}
+
+	/// The platform of the local `image`.
+	async fn local_platform(&self, image: &str) -> Option<String> {
+		let docker = self.get_docker().ok()?;
+		return Some(image_platform(&docker.inspect_image(image).await.ok()?));
+	}
+
+	pub async fn remove_image(&self, image: &str) -> Result<(), Error> {
+		let docker = self.get_docker()?;
+		docker
+			.remove_image(image, None::<bollard::query_parameters::RemoveImageOptions>, None)
+			.await
+			.map_err(Error::ImageRemove)?;
+
+		return Ok(());
+	}
+
+	async fn pull_image(&self, image: &str) -> Result<(), Error> {
+		let docker = self.get_docker()?;
+		let credentials = registry::credentials(image, self.registry_login.as_ref()).await;
+		let logged_in = credentials.is_some();
+		if let Some(platform) = &self.platform
+			&& let Ok(inspect) = docker
+				.inspect_registry_image(image, credentials.clone())
+				.await
+		{
+			let available: Vec<String> = inspect
+				.platforms
+				.iter()
+				.map(|p| registry::platform_name(p.os.as_deref(), p.architecture.as_deref(), p.variant.as_deref()))
+				// attestation manifests
+				.filter(|p| p != "unknown/unknown")
+				.collect();
+			if !available.is_empty()
+				&& !available
+					.iter()
+					.any(|a| registry::platform_matches(platform, a))
+			{
+				return Err(Error::PlatformUnavailable(image.into(), platform.clone(), available.join(", ")));
+			}
+		}
+		let mut stream = docker.create_image(
+			Some(
+				bollard::query_parameters::CreateImageOptionsBuilder::default()
+					.from_image(image)
+					.platform(self.platform.as_deref().unwrap_or_default())
+					.build(),
+			),
+			None,
+			credentials,
+		);
+		while let Some(update) = stream.next().await {
+			let update = update.map_err(|e| {
+				if registry::is_auth_failure(&e) {
+					return auth_failure(image, logged_in, e);
+				}
+				return Error::ImageCreate(image.into(), pull_failure(e));
+			})?;
+			if let Some(layer) = update.id
+				&& layer != "latest"
+			{
+				let progress = update.progress_detail.unwrap_or_default();
+				self.events.emit(Event::PullProgress {
+					layer,
+					current: progress.current.map(|c| c as u64),
+					total: progress.total.map(|t| t as u64),
+					status: update.status,
+				});
+			}
+		}
+
+		if let Some(digest) = session::image_digest(image) {
+			let digests = docker
+				.inspect_image(image)
+				.await
+				.map_err(|e| Error::ImageCreate(image.into(), pull_failure(e)))?
+				.repo_digests
+				.unwrap_or_default();
+			if !session::digest_matches(&digest, &digests) {
+				return Err(Error::ImageDigestMismatch(digest, describe_digests(&digests)));
+			}
+		}
+
+		return Ok(());
+	}
+
+	/// Creates the system under `name`, which is also its hostname.
+	async fn create_container(&self, options: &SessionOptions, reuse_key: Option<&str>, name: &str) -> Result<String, Error> {
+		let docker = self.get_docker()?;
+		let binds = container_binds(options)?;
+		let labels = container_labels(options, reuse_key);
+		let rootless_podman = self.engine == Engine::Podman { rootless: true };
+		// rootless podman maps the host user to root, so ~/work would look owned by root; keep-id maps it to itself
+		let keep_id = rootless_podman && options.mount_cwd;
+		let created = docker
 			.create_container(
-				None::<bollard::query_parameters::CreateContainerOptions>,
+				Some(
+					bollard::query_parameters::CreateContainerOptionsBuilder::default()
+						.name(name)
+						.platform(self.platform.as_deref().unwrap_or_default())
+						.build(),
+				),
 				bollard::models::ContainerCreateBody {
-					image: Some("landsj/tempsystem:latest".to_string()),
+					image: Some(options.image.clone()),
+					env: Some(
+						self.env
+							.only(&[EnvSource::Passthrough, EnvSource::Explicit])
+							.to_vec(),
+					),
+					// with keep-id, the main process would otherwise run as the host user, unlike on docker
+					user: keep_id.then(|| "root".into()),
 					tty: Some(true),
-					hostname: Some("tempsystem".into()),
-					network_disabled: Some(network_disabled),
+					hostname: Some(name.into()),
+					working_dir: (options.mount_cwd || options.ro_cwd_overlay || options.copy_cwd).then(|| work_dir(&options.home)),
+					network_disabled: Some(!options.network),
+					labels: Some(labels),
 					host_config: Some(bollard::secret::HostConfig {
 						dns: Some(vec!["1.1.1.1".into(), "1.0.0.1".into()]),
-						privileged: Some(privileged),
-						readonly_rootfs: Some(ro_root),
+						extra_hosts: proxy::needs_host_alias(&self.env).then(|| vec![proxy::HOST_ALIAS_ENTRY.into()]),
+						privileged: Some(options.privileged),
+						userns_mode: keep_id.then(|| "keep-id".into()),
+						// a rootless engine cannot hand out device access, and podman refuses to try
+						device_cgroup_rules: (options.privileged_full && !rootless_podman).then(|| {
+							PRIVILEGED_FULL_DEVICE_RULES
+								.iter()
+								.map(|r| r.to_string())
+								.collect()
+						}),
+						security_opt: Some(container_security_opts(options)),
+						cap_add: Some(container_cap_add(options)),
+						tmpfs: options
+							.ro_cwd_overlay
+							.then(|| HashMap::from([(overlay_tmpfs(&options.home), String::new())])),
+						readonly_rootfs: Some(options.ro_root),
 						binds: Some(binds),
-						cpuset_cpus: cpus.map(|x| format!("0-{}", x - 1)),
+						cpuset_cpus: options
+							.cpuset_cpus
+							.clone()
+							.or(options.restrict_cpu.map(|x| format!("0-{}", x - 1))),
+						nano_cpus: options.nano_cpus,
+						pids_limit: options.pids_limit,
+						ulimits: (!options.ulimits.is_empty()).then(|| {
+							options
+								.ulimits
+								.iter()
+								.map(|u| bollard::models::ResourcesUlimits {
+									name: Some(u.name.clone()),
+									soft: Some(u.soft),
+									hard: Some(u.hard),
+								})
+								.collect()
+						}),
+						memory: options.memory.map(|m| m as i64),
+						memory_swap: options.memory_swap.map(|m| m as i64),
+						shm_size: options.shm_size.map(|s| s as i64),
+						cgroup_parent: options.cgroup_parent.clone(),
+						storage_opt: options
+							.storage_size
+							.map(|size| HashMap::from([("size".into(), size.to_string())])),
 						..Default::default()
 					}),
 					..Default::default()
 				},
 			)
-			.await
-			.map_err(Error::ContainerCreate)?
-			.id;
+			.await;
+		let id = match created {
+			Ok(response) => response.id,
+			Err(bollard::errors::Error::DockerResponseServerError { message, .. }) if options.storage_size.is_some() && storage_quota_unsupported(&message) => {
+				return Err(Error::StorageQuotaUnsupported(message));
+			}
+			Err(bollard::errors::Error::DockerResponseServerError { message, .. }) if options.cgroup_parent.is_some() && cgroup_parent_refused(&message) => {
+				return Err(self.cgroup_parent_error(message).await);
+			}
+			Err(e) => return Err(Error::ContainerCreate(e)),
+		};
 
-		if let Some(memory) = memory {
+		if let Some(memory) = options.restrict_memory {
 			docker
 				.update_container(
 					&id,
@@ -657,13 +2713,1009 @@ impl Context {
 		return Ok(id);
 	}
 
-	async fn start_container(&self) -> Result<(), Error> {
+	/// Picks up a system left behind by `--keep-on-error`, refusing it if it was created with other options.
+	async fn resume_container(&mut self, id: &str, options: &SessionOptions) -> Result<(), Error> {
 		let docker = self.get_docker()?;
+		let inspect = docker
+			.inspect_container(id, None::<bollard::query_parameters::InspectContainerOptions>)
+			.await
+			.map_err(Error::ContainerInspect)?;
+		let labels = inspect.config.and_then(|c| c.labels).unwrap_or_default();
+		if labels.get(session::LABEL_MANAGED).map(String::as_str) != Some("true") {
+			return Err(Error::ResumeUnmanaged(id.into()));
+		}
+		if labels.get(session::LABEL_OPTIONS_HASH) != Some(&options.hash()) {
+			let differences = labels
+				.get(session::LABEL_OPTIONS)
+				.and_then(|l| SessionOptions::from_label(l))
+				.map(|original| original.differences(options))
+				.unwrap_or_else(|| vec!["options label is missing or unreadable".into()]);
+			return Err(Error::ResumeMismatch(id.into(), differences.join("\n  ")));
+		}
+
+		self.set_container(inspect.id.unwrap_or(id.into()));
+		return self.ensure_running().await;
+	}
+
+	/// Fails with `ImageUnhealthy` when the freshly started system misses what `ImageConfig::healthcheck_script` expects,
+	/// before setup would trip over it.
+	async fn healthcheck(&self, image: &str) -> Result<(), Error> {
+		let Some(script) = self
+			.image_config(image)
+			.await?
+			.healthcheck_script(session::is_own_image(image))
+		else {
+			return Ok(());
+		};
+		let (status, output) = self.run_as_root(&script).await?;
+		if status != 0 {
+			return Err(Error::ImageUnhealthy(image.into(), output.trim().to_string()));
+		}
+
+		return Ok(());
+	}
+
+	/// Reads what the image declares about itself, probing the running system for anything it leaves out.
+	/// With `bootstrap`, an image without a `tempsystem` user gets one from `features::BOOTSTRAP_USER_SCRIPT`.
+	async fn detect_features(&mut self, image: &str, verbose: bool, bootstrap: bool) -> Result<(), Error> {
+		self.features = ImageFeatures::from_config(&self.image_config(image).await?);
+		if !self.features.fully_declared() {
+			self.probe_features().await?;
+		}
+		if bootstrap && self.features.missing_user() {
+			if verbose {
+				println!("image has no `{}` user, creating it", features::DEFAULT_USER);
+			}
+			let (status, output) = self.run_as_root(features::BOOTSTRAP_USER_SCRIPT).await?;
+			if verbose {
+				println!("{}", redact(&output));
+			}
+			if status != 0 {
+				return Err(classified_error(Phase::Packages, &output, || Error::BootstrapUser(status, get_error_from_pacman(&output))));
+			}
+			self.features.forget_missing();
+			self.probe_features().await?;
+		}
+		if verbose {
+			println!("image features: {}", self.features);
+		}
+		let root_exec = !self.features.full_sudo() && matches!(self.run_as_root("id -u").await, Ok((0, output)) if output.trim() == "0");
+		self.privilege = self.features.privilege(root_exec);
+		if verbose && self.privilege == Privilege::Root {
+			println!("`{}` has no passwordless sudo for everything, running setup as root", self.features.user());
+		}
+
+		return Ok(());
+	}
+
+	/// Uploads `helper::HELPER_SCRIPT` and starts it in a long-lived exec, leaving `self.helper` empty when either fails.
+	async fn start_helper(&self, verbose: bool) {
+		if let Err(e) = self.try_start_helper().await
+			&& verbose
+		{
+			println!("not using the helper, running setup commands through plain execs: {e}");
+		}
+	}
+
+	async fn try_start_helper(&self) -> Result<(), Error> {
+		let docker = self.get_docker()?;
+		let mut v = vec![];
+		let mut builder = Builder::new(&mut v);
+		let mut header = Header::new_gnu();
+		header.set_size(helper::HELPER_SCRIPT.len() as u64);
+		header.set_mode(0o755);
+		header.set_cksum();
+		builder
+			.append_data(&mut header, helper::HELPER_PATH.trim_start_matches('/'), helper::HELPER_SCRIPT.as_bytes())
+			.map_err(Error::Tar)?;
+		drop(builder);
 		docker
+			.upload_to_container(
+				&self.container_id,
+				Some(UploadToContainerOptions {
+					path: "/".into(),
+					..Default::default()
+				}),
+				bollard::body_full(v.into()),
+			)
+			.await
+			.map_err(Error::ContainerUpload)?;
+
+		let exec_id = docker
+			.create_exec(
+				&self.container_id,
+				bollard::models::ExecConfig {
+					attach_stdout: Some(true),
+					attach_stderr: Some(true),
+					attach_stdin: Some(true),
+					user: Some(self.features.user().into()),
+					tty: Some(false),
+					env: Some(self.env.to_vec()),
+					cmd: Some(vec!["/bin/sh".into(), helper::HELPER_PATH.into(), self.features.shell().into()]),
+					..Default::default()
+				},
+			)
+			.await
+			.map_err(Error::ExecCreate)?
+			.id;
+		let StartExecResults::Attached { output, input } = docker
+			.start_exec(&exec_id, None)
+			.await
+			.map_err(Error::ExecStart)?
+		else {
+			return Err(Error::ExpectedAttached);
+		};
+		let mut started = Helper {
+			input,
+			output,
+			parser: Default::default(),
+		};
+		match tokio::time::timeout(helper::READY_TIMEOUT, started.wait_ready()).await {
+			Ok(true) => {}
+			_ => return Err(Error::HelperLost),
+		}
+		*self.helper.lock().await = Some(started);
+
+		return Ok(());
+	}
+
+	/// Runs `command` as the system's user, through the helper when it is running.
+	async fn run_command(&self, command: &str) -> Result<(i64, String), Error> {
+		if let Some(started) = self.helper.lock().await.as_mut()
+			&& let Some(result) = started.request("run", command).await
+		{
+			if let Ok((status, output)) = &result {
+				self.record(command, *status, output);
+			}
+			return result;
+		}
+		let exec_id = self.create_exec(command.into(), false).await?;
+		let (status, output) = self.start_exec(&exec_id, false).await?;
+		return Ok((status, output.unwrap_or_default()));
+	}
+
+	async fn probe_features(&mut self) -> Result<(), Error> {
+		let (status, output) = self
+			.run_as_root(&features::probe_script(self.features.user()))
+			.await?;
+		if status != 0 {
+			return Err(Error::FeatureProbe(status));
+		}
+		self.features.apply_probe(&output);
+
+		return Ok(());
+	}
+
+	/// Checks the writable layer's size every `STORAGE_SAMPLE_INTERVAL` until `stop`, warning once per crossed threshold (in bytes).
+	/// The returned flag is set once any threshold was crossed.
+	fn spawn_storage_sampler(&self, thresholds: Vec<u64>, stop: CancellationToken) -> Arc<AtomicBool> {
+		let crossed = Arc::new(AtomicBool::new(false));
+		let Some(docker) = self.docker.clone() else {
+			return crossed;
+		};
+		let id = self.container_id.clone();
+		let crossed_clone = crossed.clone();
+		self.tasks.spawn(async move {
+			let mut next = 0;
+			while next < thresholds.len() {
+				tokio::select! {
+					_ = stop.cancelled() => break,
+					_ = tokio::time::sleep(STORAGE_SAMPLE_INTERVAL) => {}
+				}
+				let Ok(size) = container_size(&docker, &id).await else {
+					continue;
+				};
+				if size < thresholds[next] {
+					continue;
+				}
+				while next < thresholds.len() && size >= thresholds[next] {
+					next += 1;
+				}
+				crossed_clone.store(true, Ordering::SeqCst);
+				print_over_session(color::yellow(format!(
+					"temporary system is using {} of ephemeral storage; files outside ~/work will be lost",
+					HumanBytes(size)
+				)));
+			}
+		});
+
+		return crossed;
+	}
+
+	/// Looks at the host every `GUARD_SAMPLE_INTERVAL` until `stop` for `--guard`, warning over the session when it runs
+	/// low, and again only once it recovered in between. With `Action::Clamp`, the first warning clamps the system too.
+	fn spawn_guard(&self, thresholds: guard::Thresholds, action: guard::Action, options: &SessionOptions, stop: CancellationToken) {
+		let Some(docker) = self.docker.clone() else {
+			return;
+		};
+		if self.endpoint.as_ref().is_some_and(Endpoint::is_remote) {
+			eprintln!("{}", color::yellow("--guard watches this machine, not the one the daemon runs on"));
+		}
+		let id = self.container_id.clone();
+		let limits = (options.memory, options.nano_cpus);
+		let capabilities = self.capabilities.clone();
+		self.tasks.spawn(async move {
+			let mut alarmed = false;
+			let mut clamped = false;
+			loop {
+				tokio::select! {
+					_ = stop.cancelled() => break,
+					_ = tokio::time::sleep(GUARD_SAMPLE_INTERVAL) => {}
+				}
+				let Some(sample) = guard::sample() else {
+					break;
+				};
+				let Some(reason) = thresholds.crossed(&sample) else {
+					alarmed = false;
+					continue;
+				};
+				if alarmed {
+					continue;
+				}
+				alarmed = true;
+				print_over_session(color::yellow(format!("--guard: the host is running low, {reason}")));
+				if action == guard::Action::Clamp && !clamped {
+					clamped = true;
+					match clamp(&docker, &id, &sample, limits, &capabilities).await {
+						Ok(clamp) => print_over_session(color::yellow(format!("--guard: clamped the system to {clamp}"))),
+						Err(e) => print_over_session(color::red(format!("--guard: could not clamp the system: {e}"))),
+					}
+				}
+			}
+		});
+	}
+
+	/// Takes a snapshot every `interval` until `stop`, keeping the `keep` newest; returns the latest snapshot's reference,
+	/// once there is one. A failed snapshot is announced over the session, and the next one is tried as usual.
+	fn spawn_autosnapshot(&self, interval: Duration, keep: usize, verbose: bool, seeded: bool, stop: CancellationToken) -> Arc<Mutex<Option<String>>> {
+		let latest = Arc::new(Mutex::new(None));
+		let Some(docker) = self.docker.clone() else {
+			return latest;
+		};
+		let id = self.container_id.clone();
+		let system = self.display_name().to_string();
+		let latest_clone = latest.clone();
+		self.tasks.spawn(async move {
+			let mut taken = 0;
+			loop {
+				tokio::select! {
+					_ = stop.cancelled() => break,
+					_ = tokio::time::sleep(interval) => {}
+				}
+				let started = Instant::now();
+				taken += 1;
+				let result = tokio::select! {
+					_ = stop.cancelled() => break,
+					result = take_snapshot(&docker, &id, &system, keep, seeded.then_some(taken)) => result,
+				};
+				match result {
+					Ok(image) => {
+						if verbose {
+							print_over_session(format!("snapshot {image} took {:.1}s", started.elapsed().as_secs_f64()));
+						}
+						*latest_clone.lock().unwrap() = Some(image);
+					}
+					Err(e) => print_over_session(color::yellow(format!("could not take a snapshot: {e}"))),
+				}
+			}
+		});
+
+		return latest;
+	}
+
+	/// The digest `image` points at: what its registry says, or what was pulled last when the registry cannot be asked.
+	pub async fn current_digest(&self, image: &str) -> Option<String> {
+		let docker = self.get_docker().ok()?;
+		let credentials = registry::credentials(image, self.registry_login.as_ref()).await;
+		if let Ok(inspect) = docker.inspect_registry_image(image, credentials).await
+			&& let Some(digest) = inspect.descriptor.digest
+		{
+			return Some(digest);
+		}
+		let name = ImageRef::split(image).normalized_name();
+		let local = docker.inspect_image(image).await.ok()?;
+		return local
+			.repo_digests
+			.unwrap_or_default()
+			.iter()
+			.map(|d| ImageRef::split(d))
+			.find(|repo| repo.normalized_name() == name)
+			.and_then(|repo| repo.digest);
+	}
+
+	/// Warns when the image the project pins has moved on, and how to follow it.
+	async fn warn_pin_drift(&self, args: &Args, config: &Config) -> Result<(), Error> {
+		let Some((path, pinned)) = session::pin(config) else {
+			return Ok(());
+		};
+		if args.image.is_some() || args.tag.is_some() || args.pull == PullPolicy::Never || args.offline {
+			return Ok(());
+		}
+		let (_, image) = session::unpinned_image(args, config)?;
+		if let Some(current) = self.current_digest(&image).await
+			&& current != pinned
+		{
+			eprintln!(
+				"{}",
+				color::yellow(format!(
+					"{} pins the image to {pinned}, but {image} is at {current} now; move the pin with `tempsystem pin-image --update`",
+					path.display()
+				))
+			);
+		}
+
+		return Ok(());
+	}
+
+	/// Pulls `image` alone, for `tempsystem pull`, returning its ID and size.
+	pub async fn pull_only(&self, image: &str) -> Result<(String, u64), Error> {
+		session::validate_image(image)?;
+		self.events
+			.phase(1, 1, format!("Downloading image ({})", session::image_version(image)));
+		self.pull_image(image).await?;
+		let inspect = self
+			.get_docker()?
+			.inspect_image(image)
+			.await
+			.map_err(Error::ImageInspect)?;
+		return Ok((inspect.id.unwrap_or_default(), inspect.size.unwrap_or_default().max(0) as u64));
+	}
+
+	/// Starts a throwaway system of `image`, pulling it first as `policy` says, and probes what it provides.
+	pub async fn check_image(&mut self, image: &str, policy: PullPolicy) -> Result<ImageFeatures, Error> {
+		session::validate_image(image)?;
+		let present = self.image_present(image).await;
+		match policy {
+			PullPolicy::Always => self.pull_image(image).await?,
+			PullPolicy::Missing if !present => self.pull_image(image).await?,
+			PullPolicy::Never if !present => return Err(Error::ImageMissing(image.into())),
+			_ => {}
+		}
+		let docker = self.get_docker()?;
+		let id = docker
+			.create_container(
+				None::<bollard::query_parameters::CreateContainerOptions>,
+				bollard::models::ContainerCreateBody {
+					image: Some(image.into()),
+					// a shell waiting on its open stdin keeps the system up for the probe, whatever the image runs otherwise
+					entrypoint: Some(vec!["/bin/sh".into()]),
+					tty: Some(true),
+					open_stdin: Some(true),
+					user: Some("root".into()),
+					network_disabled: Some(true),
+					labels: Some(HashMap::from([(session::LABEL_MANAGED.to_string(), "true".to_string())])),
+					..Default::default()
+				},
+			)
+			.await
+			.map_err(Error::ContainerCreate)?
+			.id;
+		self.set_container(id);
+		let probed = async {
+			self.start_container().await?;
+			self.features = ImageFeatures::from_config(&self.image_config(image).await?);
+			self.probe_features().await?;
+			return Ok(self.features.clone());
+		}
+		.await;
+		self.delete_container().await?;
+
+		return probed;
+	}
+
+	/// The newest snapshot of `system`, or of any system.
+	pub async fn latest_snapshot(&self, system: Option<&str>) -> Result<String, Error> {
+		let label = match system {
+			Some(system) => format!("{}={system}", snapshot::LABEL_SNAPSHOT),
+			None => snapshot::LABEL_SNAPSHOT.to_string(),
+		};
+		return list_labeled_images(self.get_docker()?, &label)
+			.await
+			.map_err(Error::ImageList)?
+			.into_iter()
+			.max_by_key(|image| image.created)
+			.and_then(|image| image.repo_tags.into_iter().next())
+			.ok_or(Error::NoSnapshot);
+	}
+
+	/// Runs `BACKGROUND_UPDATE_SCRIPT` until it finishes or `stop`, announcing the result over the session.
+	fn spawn_background_update(&self, print: bool, stop: CancellationToken) -> Arc<Mutex<BackgroundUpdate>> {
+		let state = Arc::new(Mutex::new(BackgroundUpdate::Running));
+		let Some(docker) = self.docker.clone() else {
+			return state;
+		};
+		let id = self.container_id.clone();
+		let events = self.events.clone();
+		let state_clone = state.clone();
+		self.tasks.spawn(async move {
+			let result = tokio::select! {
+				_ = stop.cancelled() => return,
+				result = run_background_update(&docker, &id, &events, print) => result,
+			};
+			print_over_session(match &result {
+				Ok(()) => color::green("background system update finished"),
+				Err(e) => color::red(format!("background system update failed: {e}")),
+			});
+			*state_clone.lock().unwrap() = match result {
+				Ok(()) => BackgroundUpdate::Finished,
+				Err(e) => BackgroundUpdate::Failed(e),
+			};
+		});
+
+		return state;
+	}
+
+	/// Compares the system's filesystem changes against `policy`, reporting violations; returns whether there were any.
+	async fn verify_policy(&self, policy: &Policy, json: bool) -> Result<bool, Error> {
+		let docker = self.get_docker()?;
+		let changes = docker
+			.container_changes(&self.container_id)
+			.await
+			.map_err(Error::ContainerDiff)?
+			.unwrap_or_default();
+		let violations = policy.violations(&changes);
+		if violations.is_empty() {
+			return Ok(false);
+		}
+
+		if json {
+			println!("{}", serde_json::json!({ "event": "policy-violation", "violations": violations }));
+		} else {
+			print_error!(format!("the system changed {} path(s) its policy does not allow:", violations.len()));
+			for violation in &violations {
+				print_error!(format!("  {violation}"));
+			}
+		}
+
+		return Ok(true);
+	}
+
+	async fn command_exists(&self, command: &str, login: bool) -> Result<bool, Error> {
+		let exec_id = self
+			.create_exec_raw(
+				self.features.user(),
+				vec![
+					self.features.shell().into(),
+					if login { "-lc" } else { "-c" }.into(),
+					format!("command -v {} >/dev/null", build::shell_quote(command)),
+				],
+				false,
+				&self.env,
+			)
+			.await?;
+		let (status, _) = self.start_exec(&exec_id, false).await?;
+
+		return Ok(status == 0);
+	}
+
+	/// Fails with `CommandNotFound`, unless suggestions are on and the user chooses to install the package providing the command.
+	async fn handle_missing_command(&self, args: &Args, cur: usize, total: usize, login: bool) -> Result<(), Error> {
+		let command = &args.command[0];
+		let interactive = !args.ci && std::io::stdin().is_terminal() && std::io::stdout().is_terminal();
+		if args.no_suggest || !(args.suggest || interactive) {
+			return Err(Error::CommandNotFound(command.clone()));
+		}
+
+		let exec_id = self
+			.create_exec(suggest::LIST_COMMANDS_SCRIPT.into(), false)
+			.await?;
+		let (_, available) = self.start_exec(&exec_id, false).await?;
+		let typos = suggest::closest(command, available.as_deref().unwrap_or_default().lines(), 3);
+		let package = if self.features.pkgfile == Some(false) {
+			None
+		} else {
+			let exec_id = self
+				.create_exec(format!("pkgfile -b {}", build::shell_quote(command)), false)
+				.await?;
+			match self.start_exec(&exec_id, false).await? {
+				(0, Some(output)) => output
+					.lines()
+					.next()
+					.map(|l| l.trim().rsplit('/').next().unwrap_or(l).to_string()),
+				_ => None,
+			}
+		};
+
+		self.events.hand_off_terminal().await;
+		print_error!(format!("command `{command}` was not found in the system"));
+		if !typos.is_empty() {
+			println!("did you mean: {}", typos.join(", "));
+		}
+		let Some(package) = package else {
+			return Err(Error::CommandNotFound(command.clone()));
+		};
+		println!("`{command}` is provided by the package `{package}`");
+		if !interactive {
+			return Err(Error::CommandNotFound(command.clone()));
+		}
+		print!("install `{package}` and continue? [y/N] ");
+		let _ = std::io::stdout().flush();
+		let mut answer = String::new();
+		if std::io::stdin().read_line(&mut answer).is_err() || !answer.trim().eq_ignore_ascii_case("y") {
+			return Err(Error::CommandNotFound(command.clone()));
+		}
+
+		setup::install_packages(self, args.verbose, cur, total, &[package], 1).await?;
+		if !self.command_exists(command, login).await? {
+			return Err(Error::CommandNotFound(command.clone()));
+		}
+
+		return Ok(());
+	}
+
+	/// IDs of the systems created by `--reuse` with the given key, newest first.
+	async fn find_reusable(&self, key: &str) -> Result<Vec<String>, Error> {
+		let docker = self.get_docker()?;
+		let containers = docker
+			.list_containers(Some(
+				bollard::query_parameters::ListContainersOptionsBuilder::default()
+					.all(true)
+					.filters(&HashMap::from([("label", vec![format!("{}={key}", session::LABEL_REUSE_KEY)])]))
+					.build(),
+			))
+			.await
+			.map_err(Error::ContainerList)?;
+
+		return Ok(containers.into_iter().filter_map(|c| c.id).collect());
+	}
+
+	async fn ensure_running(&self) -> Result<(), Error> {
+		let docker = self.get_docker()?;
+		let inspect = docker
+			.inspect_container(&self.container_id, None::<bollard::query_parameters::InspectContainerOptions>)
+			.await
+			.map_err(Error::ContainerInspect)?;
+		// paused by --pause-on-detach, which counts as running
+		if inspect
+			.state
+			.as_ref()
+			.and_then(|s| s.paused)
+			.unwrap_or(false)
+		{
+			docker
+				.unpause_container(&self.container_id)
+				.await
+				.map_err(Error::ContainerUnpause)?;
+			if let Err(e) = paused::forget(&self.container_id) {
+				print_error!("could not forget that the system was paused", e);
+			}
+		}
+		if !inspect.state.and_then(|s| s.running).unwrap_or(false) {
+			self.start_container().await?;
+		}
+		self.set_lifecycle(Lifecycle::Started);
+
+		return Ok(());
+	}
+
+	async fn start_container(&self) -> Result<(), Error> {
+		let docker = self.get_docker()?;
+		match docker
 			.start_container(&self.container_id, None::<bollard::query_parameters::StartContainerOptions>)
 			.await
-			.map_err(Error::ContainerStart)?;
+		{
+			Ok(()) => {}
+			// with cgroupfs, a parent it cannot use only shows when the runtime sets the system up
+			Err(bollard::errors::Error::DockerResponseServerError { message, .. }) if self.cgroup_parent.is_some() && cgroup_parent_refused(&message) => {
+				return Err(self.cgroup_parent_error(message).await);
+			}
+			Err(e) => return Err(Error::ContainerStart(e)),
+		}
+		self.set_lifecycle(Lifecycle::Started);
 
 		return Ok(());
 	}
 }
+
+impl ImageSource for Context {
+	async fn image_config(&self, image: &str) -> Result<ImageConfig, Error> {
+		let docker = self.get_docker()?;
+		let config = docker
+			.inspect_image(image)
+			.await
+			.map_err(Error::ImageInspect)?
+			.config
+			.unwrap_or_default();
+		return Ok(ImageConfig {
+			labels: config.labels.unwrap_or_default(),
+			user: config.user.filter(|u| !u.is_empty()),
+			env: config.env.unwrap_or_default(),
+		});
+	}
+
+	async fn probe_home(&self, image: &str, user: &str) -> Result<Option<String>, Error> {
+		let docker = self.get_docker()?;
+		let id = docker
+			.create_container(
+				None::<bollard::query_parameters::CreateContainerOptions>,
+				bollard::models::ContainerCreateBody {
+					image: Some(image.into()),
+					entrypoint: Some(vec!["/bin/sh".into(), "-c".into()]),
+					cmd: Some(vec![features::HOME_PROBE_SCRIPT.into(), "sh".into(), user.into()]),
+					user: Some("root".into()),
+					network_disabled: Some(true),
+					..Default::default()
+				},
+			)
+			.await
+			.map_err(Error::ContainerCreate)?
+			.id;
+		let output = async {
+			docker
+				.start_container(&id, None::<bollard::query_parameters::StartContainerOptions>)
+				.await
+				.map_err(Error::ContainerStart)?;
+			// a failed lookup shows up as empty output, so its exit code does not matter
+			let _ = docker
+				.wait_container(&id, None::<bollard::query_parameters::WaitContainerOptions>)
+				.next()
+				.await;
+			let mut output = String::new();
+			let mut logs = docker.logs(
+				&id,
+				Some(
+					bollard::query_parameters::LogsOptionsBuilder::default()
+						.stdout(true)
+						.build(),
+				),
+			);
+			while let Some(chunk) = logs.next().await {
+				output.push_str(&chunk.map_err(Error::ContainerLogs)?.to_string());
+			}
+			return Ok::<_, Error>(output);
+		}
+		.await;
+		let _ = docker
+			.remove_container(
+				&id,
+				Some(
+					bollard::query_parameters::RemoveContainerOptionsBuilder::default()
+						.force(true)
+						.build(),
+				),
+			)
+			.await;
+
+		return Ok(features::probed_home(&output?));
+	}
+}
+
+impl Target for Context {
+	fn features(&self) -> &ImageFeatures {
+		return &self.features;
+	}
+
+	fn phase(&self, cur: usize, total: usize, msg: String) {
+		self.step(cur, total, msg);
+	}
+
+	async fn run_as_root(&self, script: &str) -> Result<(i64, String), Error> {
+		let exec_id = self
+			.create_exec_raw("root", vec!["/bin/sh".into(), "-c".into(), script.into()], false, &ExecEnv::default())
+			.await?;
+		let (status, output) = self.start_exec(&exec_id, false).await?;
+		return Ok((status, output.unwrap_or_default()));
+	}
+
+	async fn run_privileged(&self, operation: &'static str, command: &str) -> Result<(i64, String), Error> {
+		let (user, cmd) = self
+			.privilege
+			.exec(self.features.user(), operation, command)?;
+		let exec_id = self
+			.create_exec_raw(&user, cmd, false, &ExecEnv::default())
+			.await?;
+		let (status, output) = self.start_exec(&exec_id, false).await?;
+		return Ok((status, output.unwrap_or_default()));
+	}
+
+	async fn run_as_user(&self, command: &str) -> Result<(i64, String), Error> {
+		let exec_id = self.create_exec(command.into(), false).await?;
+		let (status, output) = self.start_exec(&exec_id, false).await?;
+		return Ok((status, output.unwrap_or_default()));
+	}
+
+	/// Reports the download progress and which package pacman is installing.
+	async fn run_pacman(&self, command: String, privileged: Option<&'static str>) -> Result<(i64, String), Error> {
+		// pacman only draws progress bars when it knows the terminal width
+		let env = self.env.clone().with(EnvSource::Session, "COLUMNS", "120");
+		let exec_id = match privileged {
+			Some(operation) => {
+				// sudo leaves out the environment, so pacman would not find the proxy
+				let command = match self.privilege {
+					Privilege::Sudo => format!("{}{command}", proxy::exports(&env)),
+					_ => command,
+				};
+				let (user, cmd) = self
+					.privilege
+					.exec(self.features.user(), operation, &command)?;
+				self.create_exec_raw(&user, cmd, false, &env).await?
+			}
+			None => self.create_exec_with_env(command, false, &env).await?,
+		};
+		let mut progress = DownloadProgress::default();
+		let mut installing = None;
+		let mut position = None;
+		return self
+			.start_exec_streamed(&exec_id, |chunk| {
+				progress.feed(chunk);
+				if progress.installing != installing {
+					installing = progress.installing.clone();
+					if let Some((current, total, name)) = &installing {
+						self.events.emit(Event::PackageInstalled {
+							current: *current,
+							total: *total,
+							name: name.clone(),
+						});
+					}
+				}
+				if progress.position() != position {
+					position = progress.position();
+					if let Some((downloaded, total)) = position {
+						self.events
+							.emit(Event::DownloadProgress { downloaded, total });
+					}
+				}
+			})
+			.await;
+	}
+
+	/// Same as `classified_error`, but probes the system for what architecture a binary is built for.
+	async fn classify_failure(&self, phase: Phase, output: &str, otherwise: impl FnOnce() -> Error) -> Error {
+		if let FailureKind::ArchMismatch { path } = classify::classify(phase, output) {
+			return Error::ArchMismatch(self.arch_hint(path.as_deref()).await);
+		}
+
+		return classified_error(phase, output, otherwise);
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn lifecycle(context: &Context) -> Lifecycle {
+		return *context.lifecycle.lock().unwrap();
+	}
+
+	#[test]
+	fn quotes_the_home_in_the_overlay_mount_script() {
+		let script = overlay_mount_script("/home/tempsystem", "tempsystem");
+		assert!(script.contains("\nmountpoint -q /home/tempsystem/work && exit 0\n"), "{script}");
+		assert!(
+			script.contains(
+				"mount -t overlay overlay -o lowerdir=/home/tempsystem/.work-lower,upperdir=/home/tempsystem/.work-overlay/upper,\
+				 workdir=/home/tempsystem/.work-overlay/work /home/tempsystem/work"
+			),
+			"{script}"
+		);
+
+		let script = overlay_mount_script("/home/a b;$(reboot)", "tempsystem");
+		assert!(script.contains("mkdir -p '/home/a b;$(reboot)/.work-overlay/upper' '/home/a b;$(reboot)/.work-overlay/work' '/home/a b;$(reboot)/work'"), "{script}");
+		assert!(script.contains("chown tempsystem: '/home/a b;$(reboot)/work'"), "{script}");
+		assert!(!script.lines().any(|line| line.contains(" /home/a b")), "{script}");
+	}
+
+	#[test]
+	fn quotes_the_work_dir_in_the_chown_script() {
+		let script = chown_work_script("/home/tempsystem");
+		assert!(script.contains("stat -c %u:%g /home/tempsystem/work)"), "{script}");
+		let script = chown_work_script("/home/it's here");
+		assert!(script.contains("stat -c %u:%g '/home/it'\\''s here/work')"), "{script}");
+		assert!(script.contains("find '/home/it'\\''s here/work' -xdev"), "{script}");
+	}
+
+	#[tokio::test]
+	async fn shutdown_waits_for_tasks_that_stop_on_its_token() {
+		let context = Context::default();
+		let token = context.shutdown_token();
+		context.tasks().spawn(async move { token.cancelled().await });
+		context.shutdown().await;
+		assert!(context.tasks().is_empty());
+	}
+
+	#[tokio::test]
+	async fn shutdown_gives_up_on_tasks_that_ignore_its_token() {
+		let context = Context::default();
+		context.tasks().spawn(std::future::pending::<()>());
+		let started = Instant::now();
+		context.shutdown().await;
+		assert!(started.elapsed() >= SHUTDOWN_TIMEOUT);
+		assert_eq!(context.tasks().len(), 1);
+	}
+
+	#[tokio::test]
+	async fn delete_before_create_does_nothing() {
+		// not connected, so reaching the daemon would fail
+		let context = Context::default();
+		assert_eq!(lifecycle(&context), Lifecycle::NotCreated);
+		assert!(context.delete_container().await.is_ok());
+		assert_eq!(lifecycle(&context), Lifecycle::NotCreated);
+	}
+
+	#[tokio::test]
+	async fn delete_after_delete_does_nothing() {
+		let mut context = Context::default();
+		context.set_container("0123456789ab".into());
+		context.set_lifecycle(Lifecycle::Deleted);
+		assert!(context.delete_container().await.is_ok());
+		assert!(context.delete_container().await.is_ok());
+		assert_eq!(lifecycle(&context), Lifecycle::Deleted);
+	}
+
+	#[tokio::test]
+	async fn delete_after_create_or_start_asks_the_daemon() {
+		for state in [Lifecycle::Created, Lifecycle::Started] {
+			let mut context = Context::default();
+			context.set_container("0123456789ab".into());
+			context.set_lifecycle(state);
+			assert!(matches!(context.delete_container().await, Err(Error::NotConnected)), "{state:?}");
+			// still there, so a later delete tries again
+			assert_eq!(lifecycle(&context), state);
+			assert_eq!(context.live_container.lock().unwrap().as_deref(), Some("0123456789ab"));
+		}
+	}
+
+	#[test]
+	fn setting_the_container_marks_it_created() {
+		let mut context = Context::default();
+		context.set_container("0123456789ab".into());
+		assert_eq!(lifecycle(&context), Lifecycle::Created);
+		assert_eq!(context.live_container.lock().unwrap().as_deref(), Some("0123456789ab"));
+	}
+
+	#[tokio::test]
+	async fn privileged_runs_use_the_picked_strategy() {
+		for privilege in [Privilege::Sudo, Privilege::Root] {
+			let context = Context { privilege: privilege.clone(), ..Context::default() };
+			// both go on to create an exec, which needs the daemon
+			assert!(matches!(context.run_privileged("updating the system", "pacman -Syu").await, Err(Error::NotConnected)), "{privilege:?}");
+		}
+		let missing = "`tempsystem` has sudo only for pacman and execs as root do not work";
+		let context = Context { privilege: Privilege::Unavailable(missing.into()), ..Context::default() };
+		let result = context.run_privileged("updating the system", "pacman -Syu").await;
+		assert!(matches!(result, Err(Error::Unprivileged("updating the system", m)) if m == missing));
+	}
+
+	#[test]
+	fn root_options_need_a_privilege_strategy() {
+		let features = ImageFeatures::default();
+		let unavailable = Privilege::Unavailable("`tempsystem` has no sudo and execs as root do not work".into());
+		for (flags, flag) in [
+			(&["--update-system"][..], "--update-system"),
+			(&["--extra-packages", "git"], "--extra-packages"),
+			(&["--chaotic-aur"], "--chaotic-aur"),
+		] {
+			let args = Args::from_flags(flags);
+			let options = SessionOptions::from_args(&args, &Config::default()).unwrap();
+			assert!(matches!(check_features(&features, &unavailable, &args, &options), Err(Error::Unprivileged(f, _)) if f == flag), "{flags:?}");
+			assert!(check_features(&features, &Privilege::Root, &args, &options).is_ok(), "{flags:?}");
+		}
+		let args = Args::from_flags(&[]);
+		let options = SessionOptions::from_args(&args, &Config::default()).unwrap();
+		assert!(check_features(&features, &unavailable, &args, &options).is_ok());
+	}
+
+	#[test]
+	fn keeps_the_system_across_exit_codes_and_errors() {
+		// (exit code or setup failing, --keep, --keep-on-failure, --keep-on-error) => kept
+		for (exit_code, keep, keep_on_failure, keep_on_error, kept) in [
+			(Some(0), false, false, false, false),
+			(Some(1), false, false, false, false),
+			(Some(0), true, false, false, true),
+			(Some(130), true, false, false, true),
+			(Some(0), false, true, false, false),
+			(Some(1), false, true, false, true),
+			(Some(137), false, true, false, true),
+			(Some(-1), false, true, false, true),
+			(Some(0), true, true, false, true),
+			(Some(2), true, true, false, true),
+			// --keep-on-error is about setup, not the command
+			(Some(1), false, false, true, false),
+			(Some(0), false, true, true, false),
+			(Some(1), false, true, true, true),
+			(None, false, false, false, false),
+			(None, true, false, false, false),
+			(None, false, true, false, false),
+			(None, true, true, false, false),
+			(None, false, false, true, true),
+			(None, true, true, true, true),
+		] {
+			assert_eq!(
+				keep_system(exit_code, keep, keep_on_failure, keep_on_error),
+				kept,
+				"{exit_code:?} keep={keep} keep_on_failure={keep_on_failure} keep_on_error={keep_on_error}"
+			);
+		}
+	}
+
+	#[test]
+	fn keep_flags_compose_but_not_with_wiping() {
+		let parse = Args::try_from_flags;
+		assert!(parse(&["--keep", "--keep-on-failure", "--keep-on-error"]).is_ok());
+		for flag in ["--keep", "--keep-on-failure", "--keep-on-error"] {
+			assert!(parse(&[flag, "--wipe-on-exit"]).is_err(), "{flag}");
+		}
+	}
+
+	#[test]
+	fn says_how_to_get_into_a_system_kept_on_failure() {
+		let message = kept_on_failure(3, "0123456789ab", "/usr/bin/zsh");
+		assert!(message.starts_with("the command exited with 3, so the system `0123456789ab` was kept;"), "{message}");
+		assert!(message.contains("0123456789ab /usr/bin/zsh"), "{message}");
+	}
+
+	#[test]
+	fn lists_paused_systems_with_how_to_continue_them() {
+		let system = |state: &str, paused_for| ListedSystem {
+			id: "0123456789abcdef".into(),
+			name: "quiet-otter".into(),
+			state: state.into(),
+			paused_for,
+		};
+		assert_eq!(system("running", None).to_string(), "quiet-otter (0123456789ab) running");
+		assert_eq!(system("exited", None).to_string(), "quiet-otter (0123456789ab) exited");
+		assert_eq!(
+			system("paused", Some(Duration::from_secs(3 * 3600))).to_string(),
+			"quiet-otter (0123456789ab) paused 3 hours ago, `tempsystem attach quiet-otter` continues it"
+		);
+		// paused by something other than --pause-on-detach
+		assert_eq!(system("paused", None).to_string(), "quiet-otter (0123456789ab) paused, `tempsystem attach quiet-otter` continues it");
+		assert!(system("paused", None).paused() && !system("running", None).paused());
+	}
+
+	#[test]
+	fn picks_the_session_tty_for_each_combination() {
+		// (flags, stdin readable, stdin a terminal, stdout a terminal) => tty, with `None` for an error
+		for (flags, readable, stdin_terminal, stdout_terminal, tty) in [
+			(&[][..], true, true, true, Some(true)),
+			(&[], true, false, true, Some(false)),
+			(&[], true, true, false, Some(false)),
+			(&[], true, false, false, Some(false)),
+			(&[], false, false, false, Some(false)),
+			(&["--tty"], true, true, true, Some(true)),
+			(&["--tty"], true, false, false, Some(true)),
+			(&["--tty"], false, false, true, None),
+			(&["--no-tty"], true, true, true, Some(false)),
+			(&["--no-tty"], false, false, false, Some(false)),
+			// the later one wins
+			(&["--tty", "--no-tty"], true, true, true, Some(false)),
+			(&["--no-tty", "--tty"], true, false, false, Some(true)),
+		] {
+			let args = Args::from_flags(flags);
+			let picked = pick_tty(&args, readable, stdin_terminal, stdout_terminal);
+			match tty {
+				Some(tty) => assert_eq!(picked.ok(), Some(tty), "{flags:?} {readable} {stdin_terminal} {stdout_terminal}"),
+				None => assert!(matches!(picked, Err(Error::TtyWithoutStdin)), "{flags:?}"),
+			}
+
+			let Some(tty) = tty else {
+				continue;
+			};
+			let session = exec_config("tempsystem", vec!["zsh".into()], true, tty, &ExecEnv::default());
+			assert_eq!((session.attach_stdin, session.tty), (Some(true), Some(tty)), "{flags:?}");
+			assert_eq!((session.attach_stdout, session.attach_stderr), (Some(true), Some(true)));
+			// setup execs never read our stdin nor get a tty
+			let setup = exec_config("root", vec!["true".into()], false, tty, &ExecEnv::default());
+			assert_eq!((setup.attach_stdin, setup.tty), (Some(false), Some(false)), "{flags:?}");
+			assert_eq!((setup.attach_stdout, setup.attach_stderr), (Some(true), Some(true)));
+		}
+	}
+
+	#[test]
+	fn numbers_chunks_only_when_there_are_several() {
+		assert_eq!(chunk_message("Installing packages", 0, 1), "Installing packages");
+		assert_eq!(chunk_message("Installing packages", 0, 4), "Installing packages (chunk 1/4)");
+		assert_eq!(chunk_message("Installing packages", 3, 4), "Installing packages (chunk 4/4)");
+	}
+
+	#[test]
+	fn only_a_404_means_already_deleted() {
+		let response = |status_code| bollard::errors::Error::DockerResponseServerError {
+			status_code,
+			message: String::new(),
+		};
+		assert!(already_deleted(&response(404)));
+		assert!(!already_deleted(&response(409)));
+		assert!(!already_deleted(&response(500)));
+	}
+}
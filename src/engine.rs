@@ -0,0 +1,368 @@
+use std::{
+	fmt::Display,
+	path::{Path, PathBuf},
+	process::Stdio,
+	time::Duration,
+};
+
+use bollard::{API_DEFAULT_VERSION, Docker};
+use tokio::{io::AsyncReadExt, process::Child};
+
+use crate::{backend::BackendKind, error::Error};
+
+pub const DOCKER_SOCKET: &str = "/var/run/docker.sock";
+pub const PODMAN_ROOTFUL_SOCKET: &str = "/run/podman/podman.sock";
+/// Seconds before a request to the socket gives up, same as bollard's default.
+const TIMEOUT: u64 = 120;
+/// How long `ssh` gets to log in and start forwarding the remote socket.
+const SSH_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Which engine answers on the API socket. Podman speaks docker's API, but sets some things up differently.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum Engine {
+	#[default]
+	Docker,
+	Podman {
+		rootless: bool,
+	},
+}
+
+impl Engine {
+	/// Tells docker from podman by the components the daemon reports in its version,
+	/// and rootless from rootful by its security options.
+	pub async fn detect(docker: &Docker) -> Self {
+		let podman = docker.version().await.is_ok_and(|version| {
+			version
+				.components
+				.unwrap_or_default()
+				.iter()
+				.any(|c| c.name.to_lowercase().contains("podman"))
+		});
+		if !podman {
+			return Self::Docker;
+		}
+		let rootless = docker.info().await.is_ok_and(|info| {
+			info.security_options
+				.unwrap_or_default()
+				.iter()
+				.any(|o| o.contains("rootless"))
+		});
+		return Self::Podman { rootless };
+	}
+}
+
+impl Display for Engine {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		return match self {
+			Self::Docker => write!(f, "docker"),
+			Self::Podman { rootless: true } => write!(f, "rootless podman"),
+			Self::Podman { rootless: false } => write!(f, "podman"),
+		};
+	}
+}
+
+/// The files for mutual TLS with a daemon on another machine.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TlsFiles {
+	pub ca: PathBuf,
+	pub cert: PathBuf,
+	pub key: PathBuf,
+}
+
+impl TlsFiles {
+	/// `--tls-ca`, `--tls-cert`, and `--tls-key`, which clap only lets through together, or else like the docker CLI
+	/// the `ca.pem`, `cert.pem`, and `key.pem` in `$DOCKER_CERT_PATH` (default `~/.docker`) when `$DOCKER_TLS_VERIFY` is set.
+	pub fn resolve(ca: Option<&Path>, cert: Option<&Path>, key: Option<&Path>) -> Option<Self> {
+		if let (Some(ca), Some(cert), Some(key)) = (ca, cert, key) {
+			return Some(Self {
+				ca: ca.into(),
+				cert: cert.into(),
+				key: key.into(),
+			});
+		}
+		std::env::var_os("DOCKER_TLS_VERIFY").filter(|v| !v.is_empty() && v != "0")?;
+		let dir = std::env::var_os("DOCKER_CERT_PATH")
+			.filter(|p| !p.is_empty())
+			.map(PathBuf::from)
+			.or_else(|| std::env::home_dir().map(|h| h.join(".docker")))?;
+		return Some(Self {
+			ca: dir.join("ca.pem"),
+			cert: dir.join("cert.pem"),
+			key: dir.join("key.pem"),
+		});
+	}
+}
+
+/// Where an API socket may be listening.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Endpoint {
+	/// `--host`, or else `$DOCKER_HOST`, as a `unix://`, `tcp://`, `http://`, or `ssh://` URL
+	Host(String),
+	/// a `tcp://` or `https://` host spoken to over mutual TLS
+	Tls(String, TlsFiles),
+	Socket(PathBuf),
+}
+
+impl Endpoint {
+	/// Connects to the socket, through a `Tunnel` for `ssh://` hosts; the tunnel has to live as long as the connection is used.
+	pub async fn connect(&self) -> Result<(Docker, Option<Tunnel>), Error> {
+		let path = match self {
+			Self::Socket(path) => path.clone(),
+			Self::Host(host) if host.starts_with("unix://") => host.trim_start_matches("unix://").into(),
+			Self::Host(host) if host.starts_with("tcp://") || host.starts_with("http://") => {
+				let docker = Docker::connect_with_http(host, TIMEOUT, API_DEFAULT_VERSION).map_err(Error::Connection)?;
+				return Ok((docker, None));
+			}
+			Self::Host(host) if host.starts_with("ssh://") => {
+				let tunnel = Tunnel::open(host).await?;
+				let docker = Docker::connect_with_unix(&tunnel.socket.to_string_lossy(), TIMEOUT, API_DEFAULT_VERSION).map_err(Error::Connection)?;
+				return Ok((docker, Some(tunnel)));
+			}
+			Self::Host(host) => return Err(Error::HostScheme(host.clone())),
+			Self::Tls(host, files) => return Ok((connect_tls(host, files)?, None)),
+		};
+		let docker = Docker::connect_with_unix(&path.to_string_lossy(), TIMEOUT, API_DEFAULT_VERSION).map_err(Error::Connection)?;
+		return Ok((docker, None));
+	}
+
+	/// Whether the daemon runs on another machine, so it cannot see the paths we would mount.
+	pub fn is_remote(&self) -> bool {
+		return match self {
+			Self::Host(host) => !host.starts_with("unix://"),
+			Self::Tls(..) => true,
+			Self::Socket(_) => false,
+		};
+	}
+
+	/// Why the daemon at a remote endpoint did not answer, telling a certificate either side turned down from a host
+	/// that could not be reached; `None` for local sockets, whose errors speak for themselves.
+	pub fn failure(&self, e: &bollard::errors::Error) -> Option<Error> {
+		if !self.is_remote() {
+			return None;
+		}
+		let mut reason = e.to_string();
+		let mut chain = reason.to_lowercase();
+		let mut source = std::error::Error::source(e);
+		while let Some(inner) = source {
+			reason = inner.to_string();
+			chain.push_str(&reason.to_lowercase());
+			source = inner.source();
+		}
+		if matches!(self, Self::Tls(..))
+			&& ["certificate", "handshake", "alert", "tls"]
+				.iter()
+				.any(|w| chain.contains(w))
+		{
+			return Some(Error::TlsRejected(self.to_string(), reason));
+		}
+		return Some(Error::HostUnreachable(self.to_string(), reason));
+	}
+}
+
+#[cfg(feature = "tls")]
+fn connect_tls(host: &str, files: &TlsFiles) -> Result<Docker, Error> {
+	return Docker::connect_with_ssl(host, &files.key, &files.cert, &files.ca, TIMEOUT, API_DEFAULT_VERSION).map_err(Error::Connection);
+}
+
+#[cfg(not(feature = "tls"))]
+fn connect_tls(host: &str, _: &TlsFiles) -> Result<Docker, Error> {
+	return Err(Error::TlsUnsupported(host.into()));
+}
+
+impl Display for Endpoint {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		return match self {
+			Self::Host(host) => write!(f, "{host}"),
+			Self::Tls(host, _) => write!(f, "{host} (TLS)"),
+			Self::Socket(path) => write!(f, "{}", path.display()),
+		};
+	}
+}
+
+/// An `ssh` forwarding a local socket to the remote daemon's, like the docker CLI does for `ssh://` hosts.
+/// Both go away when it is dropped.
+pub struct Tunnel {
+	_ssh: Child,
+	socket: PathBuf,
+}
+
+impl Tunnel {
+	/// Logs in to the host of `url`, `ssh://[user@]host[:port][/remote socket]`, forwarding the remote socket,
+	/// `DOCKER_SOCKET` unless the URL has a path.
+	async fn open(url: &str) -> Result<Self, Error> {
+		let rest = url.trim_start_matches("ssh://");
+		let (authority, remote) = match rest.find('/') {
+			Some(i) => (&rest[..i], &rest[i..]),
+			None => (rest, DOCKER_SOCKET),
+		};
+		let (destination, port) = match authority.rsplit_once(':') {
+			Some((destination, port)) if port.parse::<u16>().is_ok() => (destination, Some(port)),
+			_ => (authority, None),
+		};
+		if destination.is_empty() {
+			return Err(Error::HostScheme(url.into()));
+		}
+		let socket = std::env::temp_dir().join(format!("tempsystem-{}-ssh.sock", std::process::id()));
+		let _ = std::fs::remove_file(&socket);
+
+		let mut command = tokio::process::Command::new("ssh");
+		command
+			.args(["-nNT", "-o", "ExitOnForwardFailure=yes", "-L"])
+			.arg(format!("{}:{remote}", socket.display()));
+		if let Some(port) = port {
+			command.args(["-p", port]);
+		}
+		let mut ssh = command
+			.arg("--")
+			.arg(destination)
+			.stdin(Stdio::null())
+			.stdout(Stdio::null())
+			.stderr(Stdio::piped())
+			.kill_on_drop(true)
+			.spawn()
+			.map_err(|e| Error::SshTunnel(url.into(), e.to_string()))?;
+
+		let started = std::time::Instant::now();
+		while !socket.exists() {
+			if let Ok(Some(_)) = ssh.try_wait() {
+				let mut stderr = String::new();
+				if let Some(mut pipe) = ssh.stderr.take() {
+					let _ = pipe.read_to_string(&mut stderr).await;
+				}
+				return Err(Error::SshTunnel(url.into(), stderr.trim().to_string()));
+			}
+			if started.elapsed() > SSH_TIMEOUT {
+				return Err(Error::SshTunnel(url.into(), format!("no forwarded socket after {}s", SSH_TIMEOUT.as_secs())));
+			}
+			tokio::time::sleep(Duration::from_millis(100)).await;
+		}
+
+		return Ok(Self { _ssh: ssh, socket });
+	}
+}
+
+impl Drop for Tunnel {
+	fn drop(&mut self) {
+		let _ = std::fs::remove_file(&self.socket);
+	}
+}
+
+/// A docker CLI context, as `docker context create` stored it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DockerContext {
+	pub name: String,
+	pub host: String,
+	/// the certificates stored along with the context, if any
+	pub tls: Option<TlsFiles>,
+}
+
+/// `$DOCKER_CONFIG`, or `~/.docker`.
+pub fn docker_config_dir() -> Option<PathBuf> {
+	return std::env::var_os("DOCKER_CONFIG")
+		.filter(|d| !d.is_empty())
+		.map(PathBuf::from)
+		.or_else(|| std::env::home_dir().map(|h| h.join(".docker")));
+}
+
+/// Every stored context. They live in directories named by the digest of their name, which is only read back here.
+fn docker_contexts(config_dir: &Path) -> Vec<DockerContext> {
+	let Ok(entries) = std::fs::read_dir(config_dir.join("contexts/meta")) else {
+		return vec![];
+	};
+	let mut contexts: Vec<DockerContext> = entries
+		.flatten()
+		.filter_map(|entry| {
+			let meta: serde_json::Value = serde_json::from_str(&std::fs::read_to_string(entry.path().join("meta.json")).ok()?).ok()?;
+			let tls = config_dir
+				.join("contexts/tls")
+				.join(entry.file_name())
+				.join("docker");
+			return Some(DockerContext {
+				name: meta["Name"].as_str()?.into(),
+				host: meta["Endpoints"]["docker"]["Host"].as_str()?.into(),
+				tls: tls.join("ca.pem").exists().then(|| TlsFiles {
+					ca: tls.join("ca.pem"),
+					cert: tls.join("cert.pem"),
+					key: tls.join("key.pem"),
+				}),
+			});
+		})
+		.collect();
+	contexts.sort_by(|a, b| a.name.cmp(&b.name));
+	return contexts;
+}
+
+/// The context the docker CLI would use: `name`, else `$DOCKER_CONTEXT`, else the `currentContext` of its config.json.
+/// `None` for the `default` context, which points where tempsystem looks anyway.
+pub fn docker_context(name: Option<&str>) -> Result<Option<DockerContext>, Error> {
+	let Some(config_dir) = docker_config_dir() else {
+		return Ok(None);
+	};
+	let name = match name {
+		Some(name) => name.to_string(),
+		None => match std::env::var("DOCKER_CONTEXT")
+			.ok()
+			.filter(|c| !c.is_empty())
+		{
+			Some(name) => name,
+			None => {
+				let config: serde_json::Value = std::fs::read_to_string(config_dir.join("config.json"))
+					.ok()
+					.and_then(|c| serde_json::from_str(&c).ok())
+					.unwrap_or_default();
+				let Some(name) = config["currentContext"].as_str() else {
+					return Ok(None);
+				};
+				name.to_string()
+			}
+		},
+	};
+	if name == "default" {
+		return Ok(None);
+	}
+	let contexts = docker_contexts(&config_dir);
+	if let Some(context) = contexts.iter().find(|c| c.name == name) {
+		return Ok(Some(context.clone()));
+	}
+	let available = std::iter::once("default")
+		.chain(contexts.iter().map(|c| c.name.as_str()))
+		.collect::<Vec<&str>>()
+		.join(", ");
+	return Err(Error::NoSuchContext(name, available));
+}
+
+/// `$XDG_RUNTIME_DIR/podman/podman.sock`, where rootless podman listens once `podman.socket` is enabled.
+pub fn podman_user_socket() -> Option<PathBuf> {
+	return std::env::var_os("XDG_RUNTIME_DIR").map(|dir| PathBuf::from(dir).join("podman/podman.sock"));
+}
+
+/// `host` as an endpoint, spoken to over TLS when it is a `tcp://` or `https://` host and there are `tls` files.
+fn host_endpoint(host: String, tls: Option<&TlsFiles>) -> Endpoint {
+	return match tls {
+		Some(files) if host.starts_with("tcp://") || host.starts_with("https://") => Endpoint::Tls(host, files.clone()),
+		_ => Endpoint::Host(host),
+	};
+}
+
+/// The endpoints `kind` may use, in the order they are tried; only `host` when one was given.
+pub fn candidates(kind: BackendKind, host: Option<&str>, tls: Option<&TlsFiles>) -> Vec<Endpoint> {
+	if let Some(host) = host {
+		return vec![host_endpoint(host.into(), tls)];
+	}
+	let docker_host = std::env::var("DOCKER_HOST")
+		.ok()
+		.filter(|h| !h.is_empty())
+		.map(|host| host_endpoint(host, tls));
+	let docker = docker_host
+		.into_iter()
+		.chain([Endpoint::Socket(DOCKER_SOCKET.into())]);
+	let podman = podman_user_socket()
+		.into_iter()
+		.chain([PODMAN_ROOTFUL_SOCKET.into()])
+		.map(Endpoint::Socket);
+	return match kind {
+		BackendKind::Docker => docker.collect(),
+		BackendKind::Podman => podman.collect(),
+		BackendKind::Auto | BackendKind::Api | BackendKind::Cli => docker.chain(podman).collect(),
+	};
+}
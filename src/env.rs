@@ -0,0 +1,338 @@
+use std::{collections::BTreeMap, path::Path};
+
+use crate::error::Error;
+
+/// Where an exec environment variable came from.
+/// When the same key is set from several sources, the one declared later in this enum wins.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum EnvSource {
+	/// toggles tempsystem sets for the session, like `SHOW_WELCOME`
+	Session,
+
+	/// variables copied from the host by name, `DEFAULT_PASSTHROUGH` and `--env-passthrough`
+	Passthrough,
+
+	/// variables matched by `--inherit-env`
+	Inherited,
+
+	/// variables given with `--env` or `--env-file`
+	Explicit,
+}
+
+/// Never inherited: they describe the host itself, or will get dedicated options.
+const NEVER_INHERITED: &[&str] = &[
+	"HOME",
+	"USER",
+	"LOGNAME",
+	"SHELL",
+	"PATH",
+	"PWD",
+	"OLDPWD",
+	"SHLVL",
+	"HOSTNAME",
+	"_",
+	"SSH_AUTH_SOCK",
+	"SSH_AGENT_PID",
+	"DISPLAY",
+	"WAYLAND_DISPLAY",
+	"XAUTHORITY",
+	"XDG_RUNTIME_DIR",
+	"DBUS_SESSION_BUS_ADDRESS",
+];
+
+/// Copied from the host unless `--no-default-env`, so the system's terminal and locale match.
+pub const DEFAULT_PASSTHROUGH: &[&str] = &["TERM", "COLORTERM", "LANG"];
+
+/// Names that probably hold credentials; only inherited with `--inherit-env-unsafe`.
+pub const SECRET_PATTERNS: &[&str] = &["*_TOKEN", "*_SECRET", "*PASSWORD*"];
+
+/// Matches `name` against a pattern where `*` is any run of characters and `?` any single one.
+pub fn glob_match(pattern: &str, name: &str) -> bool {
+	let (pattern, name) = (pattern.as_bytes(), name.as_bytes());
+	let (mut p, mut n) = (0, 0);
+	let mut backtrack = None;
+	while n < name.len() {
+		if p < pattern.len() && (pattern[p] == b'?' || pattern[p] == name[n]) {
+			p += 1;
+			n += 1;
+		} else if p < pattern.len() && pattern[p] == b'*' {
+			backtrack = Some((p, n));
+			p += 1;
+		} else if let Some((star, matched)) = backtrack {
+			p = star + 1;
+			n = matched + 1;
+			backtrack = Some((star, matched + 1));
+		} else {
+			return false;
+		}
+	}
+
+	return pattern[p..].iter().all(|c| *c == b'*');
+}
+
+pub fn looks_secret(name: &str) -> bool {
+	let name = name.to_uppercase();
+	return SECRET_PATTERNS.iter().any(|p| glob_match(p, &name));
+}
+
+/// Parses `--env` values: `KEY=VALUE`, split at the first `=` so the value can hold more, or `KEY` alone for the
+/// host's value.
+pub fn parse_env(s: &str) -> Result<(String, Option<String>), String> {
+	let (key, value) = match s.split_once('=') {
+		Some((key, value)) => (key, Some(value.to_string())),
+		None => (s, None),
+	};
+	if key.is_empty() || key.contains(char::is_whitespace) || key.contains('\0') || value.as_deref().is_some_and(|v| v.contains('\0')) {
+		return Err(format!("`{s}` is not a variable like RUST_LOG=debug, or CARGO_HOME alone to copy it from the host"));
+	}
+
+	return Ok((key.into(), value));
+}
+
+/// The variables in the dotenv file at `path`: `KEY=VALUE` lines, optionally after `export`, with blank lines and `#`
+/// comments skipped. Double quoted values take `\n`, `\"`, and `\\` escapes, single quoted ones are taken as they are,
+/// and unquoted ones end at a ` #` comment.
+pub fn read_env_file(path: &Path) -> Result<Vec<(String, String)>, Error> {
+	let contents = std::fs::read_to_string(path).map_err(|e| Error::EnvFileRead(path.display().to_string(), e))?;
+	let mut vars = vec![];
+	for (number, line) in contents.lines().enumerate() {
+		let line = line.trim();
+		if line.is_empty() || line.starts_with('#') {
+			continue;
+		}
+		let var = parse_env_line(line).map_err(|reason| Error::EnvFile(path.display().to_string(), number + 1, reason))?;
+		vars.push(var);
+	}
+
+	return Ok(vars);
+}
+
+fn parse_env_line(line: &str) -> Result<(String, String), String> {
+	let line = line.strip_prefix("export ").map_or(line, str::trim_start);
+	let Some((key, value)) = line.split_once('=') else {
+		return Err(format!("`{line}` is not a variable like KEY=VALUE"));
+	};
+	let key = key.trim();
+	if key.is_empty() || key.starts_with(|c: char| c.is_ascii_digit()) || !key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+		return Err(format!("`{key}` is not a variable name; names are letters, digits, and `_`"));
+	}
+	let value = value.trim_start();
+	let (value, rest) = match value.chars().next() {
+		Some('"') => {
+			let mut unescaped = String::new();
+			let mut chars = value[1..].char_indices();
+			let mut end = None;
+			while let Some((at, c)) = chars.next() {
+				match c {
+					'"' => {
+						end = Some(at + 2);
+						break;
+					}
+					'\\' => match chars.next() {
+						Some((_, 'n')) => unescaped.push('\n'),
+						Some((_, c)) => unescaped.push(c),
+						None => {}
+					},
+					c => unescaped.push(c),
+				}
+			}
+			let end = end.ok_or_else(|| format!("the value of `{key}` has no closing `\"`; values cannot span lines"))?;
+			(unescaped, &value[end..])
+		}
+		Some('\'') => {
+			let Some(end) = value[1..].find('\'') else {
+				return Err(format!("the value of `{key}` has no closing `'`; values cannot span lines"));
+			};
+			(value[1..end + 1].to_string(), &value[end + 2..])
+		}
+		_ => {
+			let end = value.find(" #").unwrap_or(value.len());
+			(value[..end].trim_end().to_string(), "")
+		}
+	};
+	let rest = rest.trim_start();
+	if !rest.is_empty() && !rest.starts_with('#') {
+		return Err(format!("`{rest}` follows the quoted value of `{key}`"));
+	}
+
+	return Ok((key.into(), value));
+}
+
+/// The variables `--env-file` and `--env` set, in that order so `--env` wins.
+#[derive(Debug, Default)]
+pub struct Explicit {
+	pub vars: Vec<(String, String)>,
+	/// `--env` names without a value that are not set on the host to copy
+	pub missing: Vec<String>,
+}
+
+pub fn explicit(files: &[std::path::PathBuf], vars: &[(String, Option<String>)]) -> Result<Explicit, Error> {
+	let mut explicit = vec![];
+	for file in files {
+		explicit.extend(read_env_file(file)?);
+	}
+	let mut missing = vec![];
+	for (name, value) in vars {
+		match value.clone().or_else(|| std::env::var(name).ok()) {
+			Some(value) => explicit.push((name.clone(), value)),
+			None => missing.push(name.clone()),
+		}
+	}
+
+	return Ok(Explicit { vars: explicit, missing });
+}
+
+/// Host variables matching any of `patterns`, plus the names left out because they look like secrets.
+pub fn inherit_from_host(patterns: &[String], allow_secrets: bool) -> (Vec<(String, String)>, Vec<String>) {
+	let mut inherited = vec![];
+	let mut refused = vec![];
+	for (name, value) in std::env::vars_os().filter_map(|(k, v)| Some((k.into_string().ok()?, v.into_string().ok()?))) {
+		if NEVER_INHERITED.contains(&name.as_str()) || !patterns.iter().any(|p| glob_match(p, &name)) {
+			continue;
+		}
+		if !allow_secrets && looks_secret(&name) {
+			refused.push(name);
+		} else {
+			inherited.push((name, value));
+		}
+	}
+	inherited.sort();
+	refused.sort();
+
+	return (inherited, refused);
+}
+
+/// Environment handed to execs through `ExecConfig::env` instead of being prefixed onto the command string.
+#[derive(Debug, Clone, Default)]
+pub struct ExecEnv {
+	vars: BTreeMap<String, (EnvSource, String)>,
+}
+
+impl ExecEnv {
+	/// Sets `key`, unless it was already set by a source with higher precedence.
+	pub fn set(&mut self, source: EnvSource, key: impl Into<String>, value: impl Into<String>) -> &mut Self {
+		let key = key.into();
+		if self
+			.vars
+			.get(&key)
+			.is_none_or(|(existing, _)| *existing <= source)
+		{
+			self.vars.insert(key, (source, value.into()));
+		}
+
+		return self;
+	}
+
+	pub fn get(&self, key: &str) -> Option<&str> {
+		return self.vars.get(key).map(|(_, value)| value.as_str());
+	}
+
+	/// Changes the value of `key`, keeping where it came from.
+	pub fn replace(&mut self, key: &str, value: impl Into<String>) {
+		if let Some((_, existing)) = self.vars.get_mut(key) {
+			*existing = value.into();
+		}
+	}
+
+	/// Only the variables that came from one of `sources`.
+	pub fn only(&self, sources: &[EnvSource]) -> Self {
+		return Self {
+			vars: self
+				.vars
+				.iter()
+				.filter(|(_, (s, _))| sources.contains(s))
+				.map(|(key, value)| (key.clone(), value.clone()))
+				.collect(),
+		};
+	}
+
+	pub fn with(mut self, source: EnvSource, key: impl Into<String>, value: impl Into<String>) -> Self {
+		self.set(source, key, value);
+		return self;
+	}
+
+	/// `KEY=VALUE` pairs in the form the daemon expects.
+	pub fn to_vec(&self) -> Vec<String> {
+		return self
+			.vars
+			.iter()
+			.map(|(key, (_, value))| format!("{key}={value}"))
+			.collect();
+	}
+
+	pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+		return self
+			.vars
+			.iter()
+			.map(|(key, (_, value))| (key.as_str(), value.as_str()));
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn later_sources_win() {
+		let env = ExecEnv::default()
+			.with(EnvSource::Session, "A", "session")
+			.with(EnvSource::Passthrough, "A", "passthrough")
+			.with(EnvSource::Session, "B", "session")
+			.with(EnvSource::Explicit, "B", "explicit")
+			.with(EnvSource::Inherited, "C", "inherited")
+			.with(EnvSource::Passthrough, "C", "passthrough");
+		assert_eq!(env.get("A"), Some("passthrough"));
+		assert_eq!(env.get("B"), Some("explicit"));
+		// set first, but by the source with higher precedence
+		assert_eq!(env.get("C"), Some("inherited"));
+	}
+
+	#[test]
+	fn the_same_source_overwrites() {
+		let mut env = ExecEnv::default();
+		env.set(EnvSource::Explicit, "KEY", "first")
+			.set(EnvSource::Explicit, "KEY", "second");
+		assert_eq!(env.get("KEY"), Some("second"));
+		assert_eq!(env.to_vec(), ["KEY=second"]);
+	}
+
+	#[test]
+	fn keys_are_unique_and_sorted() {
+		let env = ExecEnv::default()
+			.with(EnvSource::Session, "SHOW_WELCOME", "true")
+			.with(EnvSource::Passthrough, "TERM", "xterm-256color")
+			.with(EnvSource::Explicit, "TERM", "dumb")
+			.with(EnvSource::Explicit, "EMPTY", "");
+		assert_eq!(env.to_vec(), ["EMPTY=", "SHOW_WELCOME=true", "TERM=dumb"]);
+		assert_eq!(env.iter().collect::<Vec<_>>(), [("EMPTY", ""), ("SHOW_WELCOME", "true"), ("TERM", "dumb")]);
+	}
+
+	#[test]
+	fn values_keep_their_equals_signs() {
+		let env = ExecEnv::default().with(EnvSource::Explicit, "FLAGS", "-C opt-level=3");
+		assert_eq!(env.to_vec(), ["FLAGS=-C opt-level=3"]);
+	}
+
+	#[test]
+	fn replace_keeps_the_source() {
+		let mut env = ExecEnv::default().with(EnvSource::Explicit, "KEY", "old");
+		env.replace("KEY", "new");
+		env.replace("MISSING", "value");
+		assert_eq!(env.get("KEY"), Some("new"));
+		assert_eq!(env.get("MISSING"), None);
+		// still explicit, so a passthrough cannot override it
+		env.set(EnvSource::Passthrough, "KEY", "host");
+		assert_eq!(env.get("KEY"), Some("new"));
+	}
+
+	#[test]
+	fn only_filters_by_source() {
+		let env = ExecEnv::default()
+			.with(EnvSource::Session, "SHOW_WELCOME", "true")
+			.with(EnvSource::Passthrough, "TERM", "xterm")
+			.with(EnvSource::Explicit, "RUST_LOG", "debug");
+		assert_eq!(env.only(&[EnvSource::Explicit]).to_vec(), ["RUST_LOG=debug"]);
+		assert_eq!(env.only(&[EnvSource::Session, EnvSource::Passthrough]).to_vec(), ["SHOW_WELCOME=true", "TERM=xterm"]);
+		assert!(env.only(&[EnvSource::Inherited]).to_vec().is_empty());
+	}
+}
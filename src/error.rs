@@ -0,0 +1,156 @@
+/// Declares `Error` along with a stable code per variant, so a variant cannot exist without one and `tempsystem errors` cannot drift from the enum.
+/// Attributes on a variant, like a `cfg`, apply to its code and message too.
+macro_rules! errors {
+	($($(#[$attr:meta])* $code:literal $variant:ident $(($($field:ident: $ty:ty),+))? => $msg:literal,)+) => {
+		#[derive(Debug)]
+		pub enum Error {
+			$($(#[$attr])* $variant $(($($ty),+))?,)+
+		}
+
+		impl Error {
+			/// Every code with its message template, in declaration order.
+			pub const CATALOG: &[(&str, &str)] = &[$($(#[$attr])* ($code, $msg)),+];
+
+			pub fn code(&self) -> &'static str {
+				return match self {
+					$($(#[$attr])* Self::$variant { .. } => $code,)+
+				};
+			}
+		}
+
+		impl std::fmt::Display for Error {
+			#[allow(unused_variables)]
+			fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+				write!(f, "[{}] ", self.code())?;
+				return match self {
+					$($(#[$attr])* Self::$variant $(($($field),+))? => write!(f, $msg),)+
+				};
+			}
+		}
+
+		impl std::error::Error for Error {}
+	};
+}
+
+errors! {
+	"E_CONNECT" Connection(e: bollard::errors::Error) => "could not connect to docker: {e}",
+	"E_HOST_SCHEME" HostScheme(host: String) => "cannot connect to `{host}`; use a unix://, tcp://, http://, or ssh:// URL",
+	#[cfg(not(feature = "tls"))]
+	"E_TLS_UNSUPPORTED" TlsUnsupported(host: String) => "cannot speak TLS to `{host}`; this tempsystem was built without the `tls` feature",
+	"E_TLS_REJECTED" TlsRejected(host: String, reason: String) => "the TLS handshake with {host} failed, check --tls-ca, --tls-cert, and --tls-key: {reason}",
+	"E_HOST_UNREACHABLE" HostUnreachable(host: String, reason: String) => "could not reach the daemon at {host}: {reason}",
+	"E_NO_SUCH_CONTEXT" NoSuchContext(name: String, available: String) => "there is no docker context `{name}`; available: {available}",
+	"E_SSH_TUNNEL" SshTunnel(host: String, reason: String) => "could not forward the daemon's socket from `{host}` over ssh: {reason}",
+	"E_NO_ENGINE" NoEngine(tried: String) => "could not reach docker or podman; tried:\n  {tried}\n  start one of them (for rootless podman: `systemctl --user enable --now podman.socket`), or use --backend cli",
+	"E_NOT_CONNECTED" NotConnected => "inner error: docker not connected",
+	"E_IMAGE_REF" InvalidImageRef(image: String, reason: &'static str) => "invalid image reference `{image}`: {reason}",
+	"E_IMAGE_DIGEST_MISMATCH" ImageDigestMismatch(expected: String, found: String) => "the pulled image is not the one {expected} names; it has {found}",
+	"E_IMAGE_INCOMPATIBLE" ImageIncompatible(image: String) => "the system cannot be entered with image `{image}`",
+	"E_IMAGE_MISSING" ImageMissing(image: String) => "image `{image}` is not available locally and --pull never forbids pulling it; pull it with `docker pull {image}` or use --pull missing",
+	"E_OFFLINE_IMAGE_MISSING" OfflineImageMissing(image: String) => "image `{image}` is not available locally and --offline does not pull it; pull it with `tempsystem pull` while online",
+	"E_OFFLINE" Offline(what: &'static str) => "{what} needs the network, so it cannot be used with --offline",
+	"E_PLATFORM_UNAVAILABLE" PlatformUnavailable(image: String, platform: String, available: String) => "image `{image}` is not available for {platform}, only for {available}",
+	"E_IMAGE_PULL" ImageCreate(image: String, reason: String) => "could not pull image `{image}`: {reason}",
+	"E_IMAGE_UNHEALTHY" ImageUnhealthy(image: String, output: String) => "image `{image}` looks broken, it is missing what it should have: {output}; pull it again with --pull always, or skip this check with --no-healthcheck",
+	"E_PIN_EXISTS" PinExists(path: String, digest: String) => "{path} already pins the image to {digest}; pass --update to move the pin",
+	"E_DIGEST_UNKNOWN" DigestUnknown(image: String) => "could not find the digest `{image}` points at; check the registry is reachable, or pull it first",
+	"E_REGISTRY_AUTH" RegistryAuth(registry: String, reason: String) => "registry `{registry}` refused the pull: {reason}",
+	"E_CONTAINER_CREATE" ContainerCreate(e: bollard::errors::Error) => "could not create container: {e}",
+	"E_CGROUP_PARENT" CgroupParent(hint: String, reason: String) => "the daemon refused --cgroup-parent: {hint}; it said: {reason}",
+	"E_STORAGE_QUOTA_UNSUPPORTED" StorageQuotaUnsupported(reason: String) => "--storage-size needs a storage driver that enforces quotas, like overlay2 on an xfs filesystem mounted with `pquota`, or btrfs or zfs; the daemon said: {reason}",
+	"E_MEMORY_SWAP" MemorySwapBelowMemory(swap: u64, memory: u64) => "--memory-swap ({swap} bytes) includes the memory, so it cannot be less than --memory ({memory} bytes)",
+	"E_MEMORY_LIMIT" MemoryLimitSet(e: bollard::errors::Error) => "could not set container memory limit: {e}",
+	"E_CONTAINER_START" ContainerStart(e: bollard::errors::Error) => "could not start container: {e}",
+	"E_CONTAINER_PAUSE" ContainerPause(e: bollard::errors::Error) => "could not pause container: {e}",
+	"E_CONTAINER_UNPAUSE" ContainerUnpause(e: bollard::errors::Error) => "could not unpause container: {e}",
+	"E_NOT_STARTED" NotStarted => "the system was not started yet",
+	"E_EXEC_CREATE" ExecCreate(e: bollard::errors::Error) => "could not create exec: {e}",
+	"E_EXEC_START" ExecStart(e: bollard::errors::Error) => "could not start exec: {e}",
+	"E_EXEC_LOST" ExpectedAttached => "exec was expected to be attached",
+	// "E_EXEC_DETACHED" ExpectedDetached => "exec was expected to be detached",
+	"E_SHARE_INVALID" ShareInvalid(reason: String) => "the share string is not one `tempsystem share` printed: {reason}",
+	"E_SHARE_TOO_NEW" ShareTooNew(version: u8, supported: u8) => "the share string is in format {version}, from a newer tempsystem; this one reads up to format {supported}, so update it",
+	"E_SHARE_SECRET" ShareSecret => "these options look like they hold a secret, so they are not shared",
+	"E_SHARE_DECLINED" ShareDeclined => "did not run the shared system",
+	"E_SHARE_UNTRUSTED" ShareUntrusted => "the shared system needs a look before it runs, but there is no terminal to ask on; pass --trust-share after reading the summary",
+	"E_SCRIPT_READ" ScriptRead(e: std::io::Error) => "could not read the script from stdin: {e}",
+	"E_EMPTY_SCRIPT" EmptyScript => "the command is `-`, but stdin had no script",
+	"E_PASSWORD_READ" PasswordRead(e: std::io::Error) => "could not read the registry password from stdin: {e}",
+	"E_BUG_REPORT" BugReport(e: std::io::Error) => "could not write the bug report bundle: {e}",
+	"E_CAPTURE_READ" CaptureRead(path: String, e: std::io::Error) => "could not read the bundle {path}: {e}",
+	"E_NO_CAPTURE" NoCapture(path: String) => "{path} holds no capture; bundles only have one when --capture-env-on-failure wrote them",
+	"E_EMPTY_PASSWORD" EmptyPassword => "--registry-password-stdin was given, but stdin had no password",
+	"E_STDIN_TAKEN" StdinTaken => "--registry-password-stdin and a script on stdin (the command `-`) cannot both read stdin",
+	"E_TTY_WITHOUT_STDIN" TtyWithoutStdin => "--tty needs a stdin to read the session's input from",
+	"E_SUSPEND" Suspend(e: std::io::Error) => "could not suspend: {e}",
+	"E_TERMINAL_SIZE" TerminalSize(e: std::io::Error) => "could not recv terminal size: {e}; --degraded-tty keeps going without it",
+	"E_EXEC_RESIZE" ExecResize(e: bollard::errors::Error) => "could not resize exec: {e}",
+	"E_EXEC_INSPECT" ExecInspect(e: bollard::errors::Error) => "could not inspect exec: {e}",
+	"E_RAW_MODE" Rawmode(e: std::io::Error) => "could not set raw mode: {e}; --degraded-tty keeps going without it",
+	"E_STDOUT_WRITE" StdoutWrite(e: std::io::Error) => "could not write to stdout: {e}",
+	"E_STDOUT_FORMAT" StdoutFmtWrite(e: std::fmt::Error) => "could not format stdout to buffer: {e}",
+	"E_STDOUT_FLUSH" StdoutFlush(e: std::io::Error) => "could flush stdout: {e}",
+	"E_CONTAINER_DELETE" ContainerDelete(e: bollard::errors::Error) => "could not delete container: {e}",
+	"E_CWD" GetCWD(e: std::io::Error) => "could not get cwd: {e}",
+	"E_PKG_DNE" PackageDNE(packages: String) => "package(s) not found: {packages}",
+	"E_PKG_CONFLICT" PackageConflict(packages: String) => "packages were requested from both the repositories and the AUR:\n  {packages}",
+	"E_TOO_MANY_PACKAGES" TooManyPackages(count: usize, max: usize) => "{count} packages were requested, but at most {max} can be installed at once",
+	"E_CLOCK_SKEW" ClockSkew(line: String) => "the system's clock looks wrong: {line}\n  the system shares the host's clock, so check it with `timedatectl`; after a suspend, restarting time sync on the host usually fixes it",
+	"E_PKG_SIGNATURE" Signature(line: String) => "a signature could not be verified: {line}\n  the keyring is probably older than the packages; --update-system refreshes it",
+	"E_MIRROR" Mirror(line: String) => "could not download from the mirrors: {line}\n  check the network; a mirror that is out of sync usually catches up within hours",
+	"E_DISK_FULL" DiskFull(line: String) => "ran out of disk space: {line}\n  free space where docker keeps its data, for example with `docker system prune`",
+	"E_DEPENDENCY_CONFLICT" DependencyConflict(line: String) => "packages conflict: {line}\n  the image may be older than the repositories; --update-system brings it up to date first",
+	"E_ARCH_MISMATCH" ArchMismatch(hint: String) => "exec format error: {hint}",
+	"E_PKG_INSTALL" PackageInstall(status: i64, output: String) => "failed to install package: {status}; {output}",
+	"E_SYSTEM_UPDATE" SystemUpdate(status: i64, output: String) => "failed to update system: {status}; {output}",
+	"E_CHAOTIC_AUR" ChaoticAUR(status: i64, output: String) => "failed to add the Chaotic-AUR: {status}; {output}",
+	"E_LANDWARE" Landware(status: i64) => "failed to add landware: {status}",
+	"E_PKGFILE" Pkgfile(status: i64) => "failed to update pkgfile database: {status}",
+	"E_TOOLCHAIN_SETUP" ToolchainSetup(command: String, status: i64, output: String) => "setting up a toolchain with `{command}` failed with {status}: {output}",
+	"E_HOME_DIR" HomeDir => "could not find user's home directory",
+	"E_HISTORY_OPEN" OpenHistory(e: std::io::Error) => "could not open ~/.zsh_history: {e}",
+	"E_TAR" Tar(e: std::io::Error) => "could not add ~/.zsh_history to tar archive: {e}",
+	"E_CONTAINER_UPLOAD" ContainerUpload(e: bollard::errors::Error) => "could not upload archive to container: {e}",
+	"E_CONTAINER_INSPECT" ContainerInspect(e: bollard::errors::Error) => "could not inspect container: {e}",
+	"E_CONFIG_READ" ConfigRead(path: String, e: std::io::Error) => "could not read config file {path}: {e}",
+	"E_ENV_FILE_READ" EnvFileRead(path: String, e: std::io::Error) => "could not read the env file {path}: {e}",
+	"E_ENV_FILE" EnvFile(path: String, line: usize, reason: String) => "invalid env file {path}, line {line}: {reason}",
+	"E_CONFIG_PARSE" ConfigParse(location: String, reason: String) => "invalid config file {location}: {reason}",
+	"E_TEMPLATE" Template(template: String, reason: String) => "invalid template `{template}`: {reason}; `tempsystem placeholders` lists the placeholders",
+	"E_IMAGE_INSPECT" ImageInspect(e: bollard::errors::Error) => "could not inspect image: {e}",
+	"E_FEATURE_PROBE" FeatureProbe(status: i64) => "could not probe the image's features: {status}",
+	"E_BOOTSTRAP_USER" BootstrapUser(status: i64, output: String) => "could not create the tempsystem user in the image: {status}; {output}",
+	"E_HELPER_LOST" HelperLost => "the in-container helper stopped responding",
+	"E_OVERLAY_MOUNT" OverlayMount(status: i64, output: String) => "could not mount the --ro-cwd-overlay overlay: {status}; {output}",
+	"E_UNPRIVILEGED" Unprivileged(operation: &'static str, missing: String) => "{operation} needs root in the system, but {missing}",
+	"E_MISSING_FEATURE" MissingFeature(feature: &'static str, needed_for: &'static str) => "the image does not provide {feature}, which is needed for {needed_for}",
+	"E_MINIMAL_FLAVOR" MinimalFlavor(feature: &'static str, needed_for: &'static str) => "the minimal flavor does not include {feature}, which is needed for {needed_for}; use --flavor full",
+	"E_CONTAINER_LIST" ContainerList(e: bollard::errors::Error) => "could not list containers: {e}",
+	"E_NO_SUCH_SYSTEM" NoSuchSystem(target: String) => "no system is named `{target}` or has an ID starting with it",
+	"E_AMBIGUOUS_SYSTEM" AmbiguousSystem(target: String, found: String) => "`{target}` could be any of: {found}",
+	"E_KILL_UNMANAGED" KillUnmanaged(name: String) => "`{name}` was not created by tempsystem; pass --force to delete it anyway",
+	"E_RESUME_UNMANAGED" ResumeUnmanaged(id: String) => "system `{id}` was not created by tempsystem and cannot be resumed",
+	"E_RESUME_MISMATCH" ResumeMismatch(id: String, differences: String) => "system `{id}` was created with different options:\n  {differences}",
+	"E_COMMAND_NOT_FOUND" CommandNotFound(command: String) => "command `{command}` was not found in the system",
+	"E_POLICY_READ" PolicyRead(path: String, e: std::io::Error) => "could not read policy file `{path}`: {e}",
+	"E_POLICY_PARSE" PolicyParse(path: String, line: usize, message: String) => "invalid policy file `{path}`, line {line}: {message}",
+	"E_CONTAINER_DIFF" ContainerDiff(e: bollard::errors::Error) => "could not list the system's filesystem changes: {e}",
+	"E_CONTAINER_LOGS" ContainerLogs(e: bollard::errors::Error) => "could not read the container's output: {e}",
+	"E_CONTAINERFILE_READ" ContainerfileRead(path: String, e: std::io::Error) => "could not read containerfile `{path}`: {e}",
+	"E_BUILD_CONTEXT" BuildContext(e: std::io::Error) => "could not create build context: {e}",
+	"E_IMAGE_BUILD" ImageBuild(error: String, output: String) => "could not build image: {error}\n{output}",
+	"E_NO_SNAPSHOT" NoSnapshot => "no snapshots found; take them with --autosnapshot",
+	"E_IMAGE_LIST" ImageList(e: bollard::errors::Error) => "could not list images: {e}",
+	"E_IMAGE_REMOVE" ImageRemove(e: bollard::errors::Error) => "could not remove image: {e}",
+	"E_CONTAINERFILE_WRITE" ContainerfileWrite(path: String, e: std::io::Error) => "could not write containerfile `{path}`: {e}",
+	"E_RUNTIME" Runtime(e: std::io::Error) => "could not start an async runtime: {e}",
+	"E_WIPE_WITH_HOST_MOUNTS" WipeWithHostMounts(mounts: String) => "--wipe-on-exit cannot wipe what the system writes to host mounts: {mounts}",
+	"E_STATE_WRITE" StateWrite(path: String, e: std::io::Error) => "could not write {path}: {e}",
+	"E_REMEMBER_INVALID" RememberInvalid(message: String) => "cannot remember these flags: {message}",
+	"E_ALLOW_HOST_RESOLVE" AllowHostResolve(host: String, e: std::io::Error) => "could not resolve --allow-host {host}: {e}",
+	"E_FIREWALL" Firewall(status: i64, output: String) => "could not set up the --allow-host rules: {status}; {output}",
+	"E_CLI_NOT_FOUND" CliNotFound => "neither `docker` nor `podman` was found in PATH",
+	"E_CLI_RUN" CliRun(program: String, e: std::io::Error) => "could not run `{program}`: {e}",
+	"E_CLI_FAILED" CliFailed(command: String, status: i64, output: String) => "`{command}` failed with {status}: {output}",
+	"E_BACKEND_UNSUPPORTED" BackendUnsupported(option: &'static str, backend: &'static str) => "{option} is not supported with the {backend} backend",
+}
@@ -0,0 +1,99 @@
+/// The key that starts an escape sequence unless `--escape-key` picks another.
+pub const DEFAULT_KEY: &str = "ctrl-]";
+
+/// The key that, after the escape key, opens a root shell.
+pub const ROOT: u8 = b'r';
+
+/// What a terminal in raw mode sends for Ctrl-Z.
+pub const SUSPEND: u8 = 0x1a;
+
+/// Parses `ctrl-<key>` into the byte a terminal sends for it.
+pub fn parse_key(s: &str) -> Result<u8, String> {
+	let key = s
+		.strip_prefix("ctrl-")
+		.filter(|key| key.len() == 1)
+		.map(|key| key.as_bytes()[0].to_ascii_uppercase())
+		.filter(|key| (b'@'..=b'_').contains(key))
+		.ok_or_else(|| format!("`{s}` is not a key like `ctrl-]`"))?;
+	return Ok(key & 0x1f);
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Input {
+	/// bytes meant for the exec
+	Forward(Vec<u8>),
+	/// the escape key followed by `ROOT`
+	Root,
+}
+
+/// Picks escape sequences out of the session's stdin. The escape key pressed twice sends it through once, and followed
+/// by anything else it is sent through along with that.
+pub struct EscapeDetector {
+	key: u8,
+	pending: bool,
+}
+
+impl EscapeDetector {
+	pub fn new(key: u8) -> Self {
+		return Self { key, pending: false };
+	}
+
+	pub fn feed(&mut self, byte: u8) -> Input {
+		if !self.pending {
+			if byte == self.key {
+				self.pending = true;
+				return Input::Forward(vec![]);
+			}
+			return Input::Forward(vec![byte]);
+		}
+
+		self.pending = false;
+		return match byte {
+			ROOT => Input::Root,
+			byte if byte == self.key => Input::Forward(vec![byte]),
+			byte => Input::Forward(vec![self.key, byte]),
+		};
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn feed_all(detector: &mut EscapeDetector, bytes: &[u8]) -> Vec<Input> {
+		return bytes.iter().map(|byte| detector.feed(*byte)).collect();
+	}
+
+	#[test]
+	fn parses_ctrl_keys() {
+		assert_eq!(parse_key(DEFAULT_KEY), Ok(0x1d));
+		assert_eq!(parse_key("ctrl-a"), Ok(0x01));
+		assert_eq!(parse_key("ctrl-A"), Ok(0x01));
+		for key in ["ctrl-", "ctrl-ab", "alt-x", "]", "ctrl-1"] {
+			assert!(parse_key(key).is_err(), "{key}");
+		}
+	}
+
+	#[test]
+	fn key_then_root_opens_a_root_shell() {
+		let mut detector = EscapeDetector::new(0x1d);
+		assert_eq!(feed_all(&mut detector, &[0x1d, ROOT]), [Input::Forward(vec![]), Input::Root]);
+		// the sequence is over, so a lone `r` is just typed
+		assert_eq!(detector.feed(ROOT), Input::Forward(vec![ROOT]));
+	}
+
+	#[test]
+	fn key_twice_sends_it_once() {
+		let mut detector = EscapeDetector::new(0x1d);
+		assert_eq!(feed_all(&mut detector, &[0x1d, 0x1d]), [Input::Forward(vec![]), Input::Forward(vec![0x1d])]);
+	}
+
+	#[test]
+	fn key_then_anything_else_sends_both() {
+		let mut detector = EscapeDetector::new(0x1d);
+		assert_eq!(
+			feed_all(&mut detector, &[b'a', 0x1d, b'x', b'b']),
+			[Input::Forward(vec![b'a']), Input::Forward(vec![]), Input::Forward(vec![0x1d, b'x']), Input::Forward(vec![b'b'])]
+		);
+	}
+}
@@ -0,0 +1,136 @@
+use serde::Serialize;
+use tokio::sync::{
+	mpsc::{UnboundedReceiver, UnboundedSender, unbounded_channel},
+	oneshot,
+};
+
+use crate::redact::redact;
+
+/// What a session reports while it runs. The CLI renders these with indicatif, or prints them as JSON lines with `--json`;
+/// other front-ends can subscribe to them instead.
+#[derive(Serialize, Debug)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event {
+	/// the system was created under `name`
+	SystemNamed {
+		id: String,
+		name: String,
+	},
+
+	/// a setup phase began, `current` of `total`
+	PhaseStarted {
+		current: usize,
+		total: usize,
+		name: String,
+	},
+
+	/// a layer of the image being pulled made progress; `status` is the daemon's, like `Downloading` or `Pull complete`
+	PullProgress {
+		layer: String,
+		current: Option<u64>,
+		total: Option<u64>,
+		status: Option<String>,
+	},
+
+	/// the image build moved on to another `Step n/m : ...` line
+	BuildStep {
+		step: String,
+	},
+
+	/// bytes of the current pacman transaction downloaded so far
+	DownloadProgress {
+		downloaded: u64,
+		total: u64,
+	},
+
+	/// pacman got to package `current` of `total` in its transaction, so the ones before it are installed
+	PackageInstalled {
+		current: usize,
+		total: usize,
+		name: String,
+	},
+
+	/// output of an exec tempsystem runs itself, not of the session; `stream` tells execs running side by side apart
+	ExecOutputChunk {
+		exec_id: String,
+		stream: String,
+		output: String,
+	},
+
+	/// progress output must get out of the way, because the session or a prompt is about to use the terminal
+	TerminalHandoff {
+		#[serde(skip)]
+		done: Option<oneshot::Sender<()>>,
+	},
+
+	/// the session ended with `exit_code`
+	Finished {
+		exit_code: i64,
+	},
+
+	Error {
+		code: &'static str,
+		message: String,
+	},
+}
+
+impl Event {
+	/// The event with every known secret in it replaced by `***`.
+	pub fn redacted(self) -> Self {
+		fn r(s: String) -> String {
+			return redact(&s).into_owned();
+		}
+		return match self {
+			Self::PhaseStarted { current, total, name } => Self::PhaseStarted { current, total, name: r(name) },
+			Self::PullProgress { layer, current, total, status } => Self::PullProgress {
+				layer,
+				current,
+				total,
+				status: status.map(r),
+			},
+			Self::BuildStep { step } => Self::BuildStep { step: r(step) },
+			Self::PackageInstalled { current, total, name } => Self::PackageInstalled { current, total, name: r(name) },
+			Self::ExecOutputChunk { exec_id, stream, output } => Self::ExecOutputChunk {
+				exec_id,
+				stream,
+				output: r(output),
+			},
+			Self::Error { code, message } => Self::Error { code, message: r(message) },
+			event @ (Self::SystemNamed { .. } | Self::DownloadProgress { .. } | Self::TerminalHandoff { .. } | Self::Finished { .. }) => event,
+		};
+	}
+}
+
+/// Where a session sends its events; without a subscriber they are dropped.
+#[derive(Clone, Debug, Default)]
+pub struct Events {
+	sender: Option<UnboundedSender<Event>>,
+}
+
+impl Events {
+	pub fn channel() -> (Self, UnboundedReceiver<Event>) {
+		let (sender, receiver) = unbounded_channel();
+		return (Self { sender: Some(sender) }, receiver);
+	}
+
+	pub fn emit(&self, event: Event) {
+		if let Some(sender) = &self.sender {
+			let _ = sender.send(event);
+		}
+	}
+
+	pub fn phase(&self, current: usize, total: usize, name: impl Into<String>) {
+		self.emit(Event::PhaseStarted {
+			current,
+			total,
+			name: name.into(),
+		});
+	}
+
+	/// Sends `TerminalHandoff` and waits for the subscriber to clear its progress output.
+	pub async fn hand_off_terminal(&self) {
+		let (done, handed_off) = oneshot::channel();
+		self.emit(Event::TerminalHandoff { done: Some(done) });
+		let _ = handed_off.await;
+	}
+}
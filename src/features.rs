@@ -0,0 +1,367 @@
+use std::{collections::HashMap, fmt::Display};
+
+use crate::error::Error;
+
+/// Image labels through which an image declares what it provides, so nothing has to be probed.
+pub const LABEL_SHELL: &str = "org.tempsystem.shell";
+pub const LABEL_USER: &str = "org.tempsystem.user";
+pub const LABEL_AUR_HELPER: &str = "org.tempsystem.aur-helper";
+/// Comma separated list of extra tools, currently `sudo` and `pkgfile`.
+pub const LABEL_FEATURES: &str = "org.tempsystem.features";
+pub const LABEL_HOME: &str = "org.tempsystem.home";
+
+pub const DEFAULT_SHELL: &str = "/usr/bin/zsh";
+pub const DEFAULT_USER: &str = "tempsystem";
+/// The user's home in our images and in the ones `BOOTSTRAP_USER_SCRIPT` adds the user to.
+pub const DEFAULT_HOME: &str = "/home/tempsystem";
+
+/// Runs as root under `/bin/sh` in a throwaway container, printing the home of the user `$1`.
+pub const HOME_PROBE_SCRIPT: &str = r#"getent passwd "$1" | cut -d: -f6"#;
+
+/// Runs as root under `/bin/sh`, printing one `key=value` line per detected feature.
+/// What `user` may use sudo for without a password is checked too, see `SudoRules`.
+pub fn probe_script(user: &str) -> String {
+	return format!(
+		r#"
+shell="$(command -v zsh || command -v bash || command -v sh)" && printf 'shell=%s\n' "$shell"
+getent passwd tempsystem >/dev/null && printf 'user=tempsystem\n'
+for helper in yay paru; do command -v "$helper" >/dev/null && printf 'aur-helper=%s\n' "$helper" && break; done
+command -v sudo >/dev/null && printf 'sudo=true\n'
+if command -v sudo >/dev/null && getent passwd {user} >/dev/null; then
+	rules="$(sudo -n -l -U {user} 2>/dev/null | grep NOPASSWD)"
+	if printf '%s\n' "$rules" | grep -Eq 'NOPASSWD: *ALL *$'; then printf 'sudo-rules=all\n'
+	elif printf '%s\n' "$rules" | grep -q pacman; then printf 'sudo-rules=pacman\n'
+	else printf 'sudo-rules=none\n'; fi
+fi
+command -v pkgfile >/dev/null && printf 'pkgfile=true\n'
+command -v pacman >/dev/null && printf 'pacman=true\n'
+true"#,
+		user = crate::build::shell_quote(user)
+	);
+}
+
+/// Runs as root under `/bin/sh` in images without a `tempsystem` user, like stock `archlinux`, creating it along with
+/// the sudo everything else relies on. Every step checks first, so running it again changes nothing.
+pub const BOOTSTRAP_USER_SCRIPT: &str = r#"
+set -e
+command -v sudo >/dev/null || pacman -Sy --needed --noconfirm sudo
+getent passwd tempsystem >/dev/null || useradd --create-home --shell "$(command -v zsh || command -v bash || command -v sh)" tempsystem
+[ -f /etc/sudoers.d/tempsystem ] || (printf 'tempsystem ALL=(ALL:ALL) NOPASSWD: ALL\n' > /etc/sudoers.d/tempsystem && chmod 440 /etc/sudoers.d/tempsystem)
+mkdir -p /home/tempsystem/work
+chown tempsystem:tempsystem /home/tempsystem"#;
+
+/// What an image's config says about who runs in it, as far as finding their home goes.
+#[derive(Debug, Clone, Default)]
+pub struct ImageConfig {
+	pub labels: HashMap<String, String>,
+	/// `USER`, which may also be `name:group` or numeric
+	pub user: Option<String>,
+	pub env: Vec<String>,
+}
+
+impl ImageConfig {
+	/// The user the image says to run as: its label, or its `USER` when that names someone other than root.
+	pub fn user(&self) -> Option<String> {
+		if let Some(user) = self.labels.get(LABEL_USER) {
+			return Some(user.clone());
+		}
+		// execs are told who to run as by name, so a numeric `USER` is no help
+		let user = self.user.as_deref()?.split(':').next()?;
+		if user.is_empty() || user == "root" || user.chars().all(|c| c.is_ascii_digit()) {
+			return None;
+		}
+		return Some(user.into());
+	}
+
+	/// The quick check, run as root right after the system starts, that the image is whole: our images have their user
+	/// and pacman, other images the user they declare. `None` when there is nothing to expect of it.
+	pub fn healthcheck_script(&self, own_image: bool) -> Option<String> {
+		if own_image {
+			return Some(format!("id {DEFAULT_USER} && pacman -V"));
+		}
+		return self
+			.user()
+			.map(|user| format!("id {}", crate::build::shell_quote(&user)));
+	}
+
+	/// The home the image declares, with its label or a `HOME` set along with a `USER`.
+	pub fn home(&self) -> Option<String> {
+		if let Some(home) = self.labels.get(LABEL_HOME) {
+			return Some(home.clone());
+		}
+		self.user()?;
+		return self
+			.env
+			.iter()
+			.find_map(|var| var.strip_prefix("HOME="))
+			.filter(|home| home.starts_with('/') && *home != "/root")
+			.map(String::from);
+	}
+}
+
+/// The home in the output of `HOME_PROBE_SCRIPT`, nothing when the user does not exist yet.
+pub fn probed_home(output: &str) -> Option<String> {
+	return Some(output.trim())
+		.filter(|home| home.starts_with('/'))
+		.map(String::from);
+}
+
+/// What the user may use sudo for without a password, going by `sudo -l`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SudoRules {
+	All,
+	/// pacman alone, which is how some images lock sudo down; enough for AUR helpers, not for setup
+	Pacman,
+	None,
+}
+
+/// How tempsystem runs what needs root in the system, picked once per session from the probes.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub enum Privilege {
+	/// through the user's sudo
+	#[default]
+	Sudo,
+	/// in an exec as root, for users whose sudo does not cover everything
+	Root,
+	/// neither works, for the reason given
+	Unavailable(String),
+}
+
+impl Privilege {
+	/// Who runs `command` and with what exec command, or what is missing for `operation`.
+	pub fn exec(&self, user: &str, operation: &'static str, command: &str) -> Result<(String, Vec<String>), Error> {
+		return match self {
+			Self::Sudo => Ok((user.into(), vec!["sudo".into(), "-n".into(), "/bin/sh".into(), "-c".into(), command.into()])),
+			Self::Root => Ok(("root".into(), vec!["/bin/sh".into(), "-c".into(), command.into()])),
+			Self::Unavailable(missing) => Err(Error::Unprivileged(operation, missing.clone())),
+		};
+	}
+}
+
+/// What the image provides. `None` means undeclared and not probed yet, `Some(None)` means known to be missing.
+#[derive(Debug, Clone, Default)]
+pub struct ImageFeatures {
+	pub shell: Option<Option<String>>,
+	pub user: Option<Option<String>>,
+	pub aur_helper: Option<Option<String>>,
+	pub sudo: Option<bool>,
+	pub pkgfile: Option<bool>,
+	/// Only ever probed, like `sudo_rules`, since an image declaring the rest is taken to have it.
+	pub pacman: Option<bool>,
+	pub sudo_rules: Option<SudoRules>,
+	pub probed: bool,
+}
+
+impl ImageFeatures {
+	/// What `config` declares, with its `USER` standing in for an undeclared user.
+	pub fn from_config(config: &ImageConfig) -> Self {
+		let mut features = Self::from_labels(&config.labels);
+		if features.user.is_none()
+			&& let Some(user) = config.user()
+		{
+			features.user = Some(Some(user));
+		}
+		return features;
+	}
+
+	pub fn from_labels(labels: &HashMap<String, String>) -> Self {
+		let features = labels.get(LABEL_FEATURES).map(|f| {
+			f.split(',')
+				.map(str::trim)
+				.map(String::from)
+				.collect::<Vec<String>>()
+		});
+		return Self {
+			shell: labels.get(LABEL_SHELL).map(|s| Some(s.clone())),
+			user: labels.get(LABEL_USER).map(|u| Some(u.clone())),
+			aur_helper: labels
+				.get(LABEL_AUR_HELPER)
+				.map(|h| Some(h.clone()).filter(|h| !h.is_empty())),
+			sudo: features.as_ref().map(|f| f.iter().any(|f| f == "sudo")),
+			pkgfile: features.as_ref().map(|f| f.iter().any(|f| f == "pkgfile")),
+			pacman: None,
+			sudo_rules: None,
+			probed: false,
+		};
+	}
+
+	pub fn fully_declared(&self) -> bool {
+		return self.shell.is_some() && self.user.is_some() && self.aur_helper.is_some() && self.sudo.is_some() && self.pkgfile.is_some();
+	}
+
+	/// Fills everything the labels left undeclared from the output of `probe_script`.
+	pub fn apply_probe(&mut self, output: &str) {
+		let found: HashMap<&str, &str> = output
+			.lines()
+			.filter_map(|l| l.trim().split_once('='))
+			.collect();
+		self.shell
+			.get_or_insert_with(|| found.get("shell").map(|s| s.to_string()));
+		self.user
+			.get_or_insert_with(|| found.get("user").map(|s| s.to_string()));
+		self.aur_helper
+			.get_or_insert_with(|| found.get("aur-helper").map(|s| s.to_string()));
+		self.sudo.get_or_insert_with(|| found.contains_key("sudo"));
+		self.pkgfile
+			.get_or_insert_with(|| found.contains_key("pkgfile"));
+		self.pacman = Some(found.contains_key("pacman"));
+		self.sudo_rules = found.get("sudo-rules").map(|v| match *v {
+			"all" => SudoRules::All,
+			"pacman" => SudoRules::Pacman,
+			_ => SudoRules::None,
+		});
+		self.probed = true;
+	}
+
+	/// Whether the user's sudo covers everything without a password, taken for granted unless probed otherwise.
+	pub fn full_sudo(&self) -> bool {
+		return self.sudo != Some(false) && self.sudo_rules.is_none_or(|r| r == SudoRules::All);
+	}
+
+	/// Whether the user may run pacman through sudo without a password, which AUR helpers do.
+	pub fn sudo_pacman(&self) -> bool {
+		return self.sudo != Some(false) && self.sudo_rules != Some(SudoRules::None);
+	}
+
+	/// How to run what needs root, given whether execs as root work.
+	pub fn privilege(&self, root_exec: bool) -> Privilege {
+		if self.full_sudo() {
+			return Privilege::Sudo;
+		}
+		if root_exec {
+			return Privilege::Root;
+		}
+		let sudo = match self.sudo_rules {
+			_ if self.sudo == Some(false) => "no sudo",
+			Some(SudoRules::Pacman) => "sudo only for pacman",
+			_ => "no passwordless sudo",
+		};
+		return Privilege::Unavailable(format!("`{}` has {sudo} and execs as root do not work", self.user()));
+	}
+
+	/// Whether the image is known to have no `tempsystem` user.
+	pub fn missing_user(&self) -> bool {
+		return matches!(self.user, Some(None));
+	}
+
+	/// Forgets everything known to be missing, so the next probe looks for it again.
+	pub fn forget_missing(&mut self) {
+		self.shell.take_if(|s| s.is_none());
+		self.user.take_if(|u| u.is_none());
+		self.aur_helper.take_if(|h| h.is_none());
+		self.sudo.take_if(|s| !*s);
+		self.pkgfile.take_if(|p| !*p);
+	}
+
+	/// The shell declared or found, zsh when the image has it, falling back to bash and then sh.
+	pub fn shell(&self) -> &str {
+		return self
+			.shell
+			.as_ref()
+			.and_then(|s| s.as_deref())
+			.unwrap_or(DEFAULT_SHELL);
+	}
+
+	pub fn user(&self) -> &str {
+		return self
+			.user
+			.as_ref()
+			.and_then(|u| u.as_deref())
+			.unwrap_or(DEFAULT_USER);
+	}
+
+	pub fn aur_helper(&self) -> Option<&str> {
+		return self.aur_helper.as_ref().and_then(|h| h.as_deref());
+	}
+}
+
+impl Display for ImageFeatures {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		fn show(value: Option<&str>) -> &str {
+			return value.unwrap_or("missing");
+		}
+		return write!(
+			f,
+			"shell={}, user={}, aur helper={}, sudo={}, pkgfile={} (from {})",
+			show(self.shell.as_ref().and_then(|s| s.as_deref())),
+			show(self.user.as_ref().and_then(|u| u.as_deref())),
+			show(self.aur_helper()),
+			self.sudo.unwrap_or(false),
+			self.pkgfile.unwrap_or(false),
+			if self.probed { "labels and probing" } else { "labels" },
+		);
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn probed(output: &str) -> ImageFeatures {
+		let mut features = ImageFeatures::default();
+		features.apply_probe(output);
+		return features;
+	}
+
+	#[test]
+	fn reads_sudo_rules_from_the_probe() {
+		let base = "shell=/usr/bin/zsh\nuser=tempsystem\nsudo=true\npacman=true\n";
+		assert_eq!(probed(&format!("{base}sudo-rules=all\n")).sudo_rules, Some(SudoRules::All));
+		assert_eq!(probed(&format!("{base}sudo-rules=pacman\n")).sudo_rules, Some(SudoRules::Pacman));
+		assert_eq!(probed(&format!("{base}sudo-rules=none\n")).sudo_rules, Some(SudoRules::None));
+		// no user to ask sudo about yet
+		assert_eq!(probed(base).sudo_rules, None);
+	}
+
+	#[test]
+	fn sudo_for_everything_uses_sudo() {
+		for features in [ImageFeatures::default(), probed("user=tempsystem\nsudo=true\nsudo-rules=all\n")] {
+			assert!(features.full_sudo() && features.sudo_pacman());
+			assert_eq!(features.privilege(false), Privilege::Sudo);
+			assert_eq!(features.privilege(true), Privilege::Sudo);
+		}
+	}
+
+	#[test]
+	fn limited_sudo_falls_back_to_root_execs() {
+		for output in ["user=tempsystem\nsudo=true\nsudo-rules=pacman\n", "user=tempsystem\nsudo=true\nsudo-rules=none\n", "user=tempsystem\n"] {
+			let features = probed(output);
+			assert!(!features.full_sudo(), "{output}");
+			assert_eq!(features.privilege(true), Privilege::Root, "{output}");
+		}
+		// AUR helpers only need pacman through sudo
+		assert!(probed("user=tempsystem\nsudo=true\nsudo-rules=pacman\n").sudo_pacman());
+		assert!(!probed("user=tempsystem\nsudo=true\nsudo-rules=none\n").sudo_pacman());
+		assert!(!probed("user=tempsystem\n").sudo_pacman());
+	}
+
+	#[test]
+	fn says_what_is_missing_without_either() {
+		for (output, missing) in [
+			("user=tempsystem\nsudo=true\nsudo-rules=pacman\n", "`tempsystem` has sudo only for pacman and execs as root do not work"),
+			("user=tempsystem\nsudo=true\nsudo-rules=none\n", "`tempsystem` has no passwordless sudo and execs as root do not work"),
+			("user=tempsystem\n", "`tempsystem` has no sudo and execs as root do not work"),
+		] {
+			assert_eq!(probed(output).privilege(false), Privilege::Unavailable(missing.into()));
+		}
+		let mut features = ImageFeatures::from_labels(&HashMap::from([(LABEL_USER.to_string(), "dev".to_string())]));
+		features.apply_probe("sudo=true\nsudo-rules=none\n");
+		assert_eq!(features.privilege(false), Privilege::Unavailable("`dev` has no passwordless sudo and execs as root do not work".into()));
+	}
+
+	#[test]
+	fn runs_each_strategy_as_its_user() {
+		assert_eq!(
+			Privilege::Sudo.exec("dev", "updating the system", "pacman -Syu").unwrap(),
+			("dev".into(), vec!["sudo".into(), "-n".into(), "/bin/sh".into(), "-c".into(), "pacman -Syu".into()])
+		);
+		assert_eq!(
+			Privilege::Root.exec("dev", "updating the system", "pacman -Syu").unwrap(),
+			("root".into(), vec!["/bin/sh".into(), "-c".into(), "pacman -Syu".into()])
+		);
+		let e = Privilege::Unavailable("`dev` has no sudo and execs as root do not work".into())
+			.exec("dev", "updating the system", "pacman -Syu")
+			.unwrap_err();
+		assert_eq!(e.to_string(), "[E_UNPRIVILEGED] updating the system needs root in the system, but `dev` has no sudo and execs as root do not work");
+	}
+}
@@ -0,0 +1,103 @@
+use std::net::{IpAddr, ToSocketAddrs};
+
+use crate::error::Error;
+
+/// The nftables table `--allow-host` keeps its rules in, inside the system's network namespace.
+pub const TABLE: &str = "tempsystem";
+
+/// Always reachable, so names can still be resolved; the same servers every system is created with.
+pub const DNS_SERVERS: &[&str] = &["1.1.1.1", "1.0.0.1"];
+
+/// Reachable when packages are installed, since the AUR helper fetches from it.
+pub const AUR_HOST: &str = "aur.archlinux.org";
+
+/// Destinations to allow, split by address family for nftables.
+#[derive(Debug, Default, PartialEq)]
+pub struct Allowed {
+	pub v4: Vec<String>,
+	pub v6: Vec<String>,
+}
+
+impl Allowed {
+	fn push(&mut self, address: String) {
+		if address.contains(':') {
+			self.v6.push(address);
+		} else {
+			self.v4.push(address);
+		}
+	}
+}
+
+/// Resolves `hosts`, each an address, a CIDR range, or a domain; domains are resolved once, here, so the rules pin
+/// whatever addresses they had at setup.
+pub fn resolve(hosts: &[String]) -> Result<Allowed, Error> {
+	let mut allowed = Allowed::default();
+	for host in hosts {
+		if host.contains('/') || host.parse::<IpAddr>().is_ok() {
+			allowed.push(host.clone());
+			continue;
+		}
+		let addresses = (host.as_str(), 0)
+			.to_socket_addrs()
+			.map_err(|e| Error::AllowHostResolve(host.clone(), e))?;
+		for address in addresses {
+			allowed.push(address.ip().to_string());
+		}
+	}
+	allowed.v4.dedup();
+	allowed.v6.dedup();
+
+	return Ok(allowed);
+}
+
+/// Runs as root, dropping every outgoing connection that is not to `allowed`, DNS, or, with `mirrors`, the package mirrors
+/// in the system's mirrorlists. nftables is installed first when the image does not have it.
+pub fn script(allowed: &Allowed, mirrors: bool, aur: bool) -> String {
+	let mut script = format!(
+		r#"set -e
+command -v nft >/dev/null || pacman -S --needed --noconfirm nftables >/dev/null
+nft delete table inet {TABLE} 2>/dev/null || true
+nft add table inet {TABLE}
+nft add chain inet {TABLE} output '{{ type filter hook output priority 0; policy accept; }}'
+nft add rule inet {TABLE} output oif lo accept
+nft add rule inet {TABLE} output ct state established,related accept
+nft add rule inet {TABLE} output ip daddr '{{ {dns} }}' meta l4proto '{{ tcp, udp }}' th dport 53 accept
+"#,
+		dns = DNS_SERVERS.join(", ")
+	);
+	for (family, addresses) in [("ip", &allowed.v4), ("ip6", &allowed.v6)] {
+		if !addresses.is_empty() {
+			script.push_str(&format!("nft add rule inet {TABLE} output {family} daddr '{{ {} }}' accept\n", addresses.join(", ")));
+		}
+	}
+	if mirrors || aur {
+		let hosts = if aur { AUR_HOST } else { "" };
+		script.push_str(&format!(
+			r#"for host in {hosts} $(sed -n 's|^[[:space:]]*Server[[:space:]]*=[[:space:]]*[a-z]*://\([^/:]*\).*|\1|p' /etc/pacman.d/*mirrorlist 2>/dev/null | sort -u); do
+	getent ahosts "$host" | while read -r address _; do
+		case "$address" in
+		*:*) nft add rule inet {TABLE} output ip6 daddr "$address" accept ;;
+		*) nft add rule inet {TABLE} output ip daddr "$address" accept ;;
+		esac
+	done
+done
+"#
+		));
+	}
+	script.push_str(&format!("nft add rule inet {TABLE} output counter drop\n"));
+
+	return script;
+}
+
+/// Runs as root, printing the rules along with how many packets the final rule dropped.
+pub const DENIED_SCRIPT: &str = "nft list chain inet tempsystem output";
+
+/// The packet count of the dropping rule in `DENIED_SCRIPT`'s output.
+pub fn denied_packets(output: &str) -> Option<u64> {
+	let line = output
+		.lines()
+		.find(|line| line.contains("counter packets") && line.trim_end().ends_with("drop"))?;
+	let mut words = line.split_whitespace();
+	words.find(|word| *word == "packets")?;
+	return words.next()?.parse().ok();
+}
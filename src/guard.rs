@@ -0,0 +1,76 @@
+use clap::ValueEnum;
+use indicatif::HumanBytes;
+
+/// What `--guard` does once the host runs low.
+#[derive(ValueEnum, Debug, Clone, Copy, Default, PartialEq)]
+pub enum Action {
+	/// print a warning over the session
+	#[default]
+	Warn,
+
+	/// warn, and the first time, lower the system's memory limit and CPU quota
+	Clamp,
+}
+
+/// When `--guard` considers the host to be running low.
+#[derive(Debug, Clone, Copy)]
+pub struct Thresholds {
+	/// share of the host's memory that has to stay available
+	pub memory_percent: u8,
+	/// one minute load average per CPU
+	pub load_per_cpu: f64,
+}
+
+/// What the host looked like at one point.
+#[derive(Debug, Clone, Copy)]
+pub struct HostSample {
+	pub available: u64,
+	pub total: u64,
+	pub load: f64,
+	pub cpus: usize,
+}
+
+/// Reads /proc/meminfo and /proc/loadavg, which is cheap enough to do every few seconds; `None` without procfs.
+pub fn sample() -> Option<HostSample> {
+	let meminfo = std::fs::read_to_string("/proc/meminfo").ok()?;
+	let field = |name: &str| {
+		return meminfo.lines().find_map(|line| {
+			let kib: u64 = line
+				.strip_prefix(name)?
+				.trim()
+				.strip_suffix("kB")?
+				.trim()
+				.parse()
+				.ok()?;
+			return Some(kib * 1024);
+		});
+	};
+	let load = std::fs::read_to_string("/proc/loadavg")
+		.ok()?
+		.split_whitespace()
+		.next()?
+		.parse()
+		.ok()?;
+
+	return Some(HostSample {
+		available: field("MemAvailable:")?,
+		total: field("MemTotal:")?,
+		load,
+		cpus: std::thread::available_parallelism().map_or(1, |n| n.get()),
+	});
+}
+
+impl Thresholds {
+	/// How the host is running low in `sample`, if it is.
+	pub fn crossed(&self, sample: &HostSample) -> Option<String> {
+		let mut reasons = vec![];
+		if sample.available * 100 < sample.total * self.memory_percent as u64 {
+			reasons.push(format!("only {} of {} memory is available", HumanBytes(sample.available), HumanBytes(sample.total)));
+		}
+		if sample.load >= self.load_per_cpu * sample.cpus as f64 {
+			reasons.push(format!("the load average is {:.1} on {} CPUs", sample.load, sample.cpus));
+		}
+
+		return (!reasons.is_empty()).then(|| reasons.join(" and "));
+	}
+}
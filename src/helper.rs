@@ -0,0 +1,120 @@
+use std::{pin::Pin, time::Duration};
+
+use bollard::container::LogOutput;
+use futures_util::{Stream, StreamExt};
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+
+use crate::error::Error;
+
+/// Where the helper is uploaded to; /tmp is writable in every image, unless the root is read only.
+pub const HELPER_PATH: &str = "/tmp/tempsystem-helper";
+
+/// How long the helper gets to announce itself before setup falls back to plain execs.
+pub const READY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Runs as the system's user for the whole session, taking the shell to run commands with as `$1`.
+/// Announces itself with `ready`, then answers requests, each a `<kind> <length>\n` line followed by `length` bytes of
+/// payload, with a `<status> <length>\n` line followed by `length` bytes of output:
+/// - `run`: runs the payload as a shell command
+/// - `list`: lists the directory the payload names
+/// - `kill`: sends SIGTERM to the process group the payload names
+pub const HELPER_SCRIPT: &str = r#"shell="$1"
+out="$(mktemp)"
+trap 'rm -f "$out"' EXIT
+echo ready
+while read -r kind len; do
+	payload="$(head -c "$len")"
+	case "$kind" in
+	run) "$shell" -c "$payload" </dev/null >"$out" 2>&1 ;;
+	list) find "$payload" -mindepth 1 -maxdepth 1 >"$out" 2>&1 ;;
+	kill) kill -TERM -- "-$payload" >"$out" 2>&1 ;;
+	*) echo "unknown request: $kind" >"$out"; false ;;
+	esac
+	status=$?
+	printf '%s %s\n' "$status" "$(wc -c <"$out")"
+	cat "$out"
+done
+"#;
+
+pub fn encode_request(kind: &str, payload: &str) -> Vec<u8> {
+	let mut request = format!("{kind} {}\n", payload.len()).into_bytes();
+	request.extend_from_slice(payload.as_bytes());
+	return request;
+}
+
+/// Splits the helper's output into the `ready` line and responses.
+#[derive(Debug, Default)]
+pub struct ResponseParser {
+	buffer: Vec<u8>,
+}
+
+impl ResponseParser {
+	pub fn feed(&mut self, bytes: &[u8]) {
+		self.buffer.extend_from_slice(bytes);
+	}
+
+	/// Takes the `ready` line once it arrived.
+	pub fn ready(&mut self) -> bool {
+		if !self.buffer.starts_with(b"ready\n") {
+			return false;
+		}
+		self.buffer.drain(.."ready\n".len());
+		return true;
+	}
+
+	/// Takes the next complete response, as `(status, output)`.
+	pub fn response(&mut self) -> Option<(i64, String)> {
+		let end = self.buffer.iter().position(|b| *b == b'\n')?;
+		let header = std::str::from_utf8(&self.buffer[..end]).ok()?;
+		let (status, len) = header.split_once(' ')?;
+		let (status, len) = (status.parse::<i64>().ok()?, len.trim().parse::<usize>().ok()?);
+		if self.buffer.len() < end + 1 + len {
+			return None;
+		}
+		let output = String::from_utf8_lossy(&self.buffer[end + 1..end + 1 + len]).to_string();
+		self.buffer.drain(..end + 1 + len);
+		return Some((status, output));
+	}
+}
+
+/// The helper's long-lived exec.
+pub struct Helper {
+	pub input: Pin<Box<dyn AsyncWrite + Send>>,
+	pub output: Pin<Box<dyn Stream<Item = Result<LogOutput, bollard::errors::Error>> + Send>>,
+	pub parser: ResponseParser,
+}
+
+impl Helper {
+	/// Waits for the helper to announce itself.
+	pub async fn wait_ready(&mut self) -> bool {
+		while !self.parser.ready() {
+			match self.output.next().await {
+				Some(Ok(output)) => self.parser.feed(&output.into_bytes()),
+				_ => return false,
+			}
+		}
+		return true;
+	}
+
+	/// Sends a request and waits for its response. `None` when the request could not be sent, so nothing ran and
+	/// it is safe to fall back to an exec; an error when the helper went away after it was.
+	pub async fn request(&mut self, kind: &str, payload: &str) -> Option<Result<(i64, String), Error>> {
+		if self
+			.input
+			.write_all(&encode_request(kind, payload))
+			.await
+			.is_err()
+		{
+			return None;
+		}
+		loop {
+			if let Some(response) = self.parser.response() {
+				return Some(Ok(response));
+			}
+			match self.output.next().await {
+				Some(Ok(output)) => self.parser.feed(&output.into_bytes()),
+				_ => return Some(Err(Error::HelperLost)),
+			}
+		}
+	}
+}
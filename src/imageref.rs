@@ -0,0 +1,410 @@
+/// Docker Hub, which references without a registry are pulled from.
+pub const DOCKER_HUB: &str = "docker.io";
+
+/// The longest name, registry included, a registry accepts.
+const MAX_NAME: usize = 255;
+
+/// An image reference, `[registry[:port]/]repository[:tag][@digest]`, split into its parts as written.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ImageRef {
+	/// the host, with its port, when the reference names one
+	pub registry: Option<String>,
+	pub repository: String,
+	pub tag: Option<String>,
+	/// like `sha256:<hex>`
+	pub digest: Option<String>,
+}
+
+impl ImageRef {
+	/// Splits `reference` without checking any of its parts, for references that are known to be fine or only looked at.
+	pub fn split(reference: &str) -> Self {
+		let (rest, digest) = match reference.split_once('@') {
+			Some((rest, digest)) => (rest, Some(digest.to_string())),
+			None => (reference, None),
+		};
+		// a colon after the last slash starts the tag; one before it is a registry port
+		let (name, tag) = match rest.rsplit_once(':') {
+			Some((name, tag)) if !tag.contains('/') => (name, Some(tag.to_string())),
+			_ => (rest, None),
+		};
+		// the first component is a registry when it looks like a host, the way the daemon decides it
+		let (registry, repository) = match name.split_once('/') {
+			Some((first, repository)) if first.contains(['.', ':']) || first == "localhost" || first.chars().any(|c| c.is_ascii_uppercase()) => {
+				(Some(first.to_string()), repository)
+			}
+			_ => (None, name),
+		};
+
+		return Self {
+			registry,
+			repository: repository.into(),
+			tag,
+			digest,
+		};
+	}
+
+	/// Splits `reference` and checks each part has the shape registries accept, so a typo fails before anything is
+	/// pulled; the error says which part is wrong.
+	pub fn parse(reference: &str) -> Result<Self, &'static str> {
+		if reference.is_empty() {
+			return Err("it is empty");
+		}
+		if reference.chars().any(|c| c.is_whitespace()) {
+			return Err("it contains whitespace");
+		}
+		if reference.matches('@').count() > 1 {
+			return Err("it has more than one `@`; the digest comes last, after a single `@`");
+		}
+		let image = Self::split(reference);
+		if let Some(registry) = &image.registry {
+			check_registry(registry)?;
+		}
+		check_repository(&image.repository)?;
+		if image.name().len() > MAX_NAME {
+			return Err("the name, registry included, is longer than 255 characters");
+		}
+		if let Some(tag) = &image.tag {
+			check_tag(tag)?;
+		}
+		if let Some(digest) = &image.digest {
+			check_digest(digest)?;
+		}
+
+		return Ok(image);
+	}
+
+	/// The registry it is pulled from, with Docker Hub's other names folded into `docker.io`.
+	pub fn registry(&self) -> &str {
+		return match self.registry.as_deref() {
+			None | Some("index.docker.io" | "registry-1.docker.io") => DOCKER_HUB,
+			Some(registry) => registry,
+		};
+	}
+
+	/// The name as written: registry and repository, without tag or digest.
+	pub fn name(&self) -> String {
+		return match &self.registry {
+			Some(registry) => format!("{registry}/{}", self.repository),
+			None => self.repository.clone(),
+		};
+	}
+
+	/// The name the daemon files it under: Docker Hub made explicit, and its official images under `library/`, so
+	/// `alpine`, `library/alpine`, and `docker.io/library/alpine` are all `docker.io/library/alpine`.
+	pub fn normalized_name(&self) -> String {
+		let registry = self.registry();
+		if registry == DOCKER_HUB && !self.repository.contains('/') {
+			return format!("{DOCKER_HUB}/library/{}", self.repository);
+		}
+		return format!("{registry}/{}", self.repository);
+	}
+
+	/// The reference the way the daemon normalizes it, with `latest` when it has neither tag nor digest.
+	pub fn normalized(&self) -> String {
+		let mut normalized = self.normalized_name();
+		match (&self.tag, &self.digest) {
+			(None, None) => normalized.push_str(":latest"),
+			(tag, digest) => {
+				if let Some(tag) = tag {
+					normalized.push_str(&format!(":{tag}"));
+				}
+				if let Some(digest) = digest {
+					normalized.push_str(&format!("@{digest}"));
+				}
+			}
+		}
+		return normalized;
+	}
+
+	/// The same name at `version`, a tag or a digest like `sha256:<hex>`, with or without the `@`, replacing both the
+	/// tag and the digest it had.
+	pub fn at(&self, version: &str) -> Self {
+		let version = version.strip_prefix('@').unwrap_or(version);
+		let is_digest = version.contains(':');
+		return Self {
+			registry: self.registry.clone(),
+			repository: self.repository.clone(),
+			tag: (!is_digest).then(|| version.into()),
+			digest: is_digest.then(|| version.into()),
+		};
+	}
+}
+
+impl std::fmt::Display for ImageRef {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		write!(f, "{}", self.name())?;
+		if let Some(tag) = &self.tag {
+			write!(f, ":{tag}")?;
+		}
+		if let Some(digest) = &self.digest {
+			write!(f, "@{digest}")?;
+		}
+		return Ok(());
+	}
+}
+
+fn check_registry(registry: &str) -> Result<(), &'static str> {
+	// an IPv6 address is in brackets, so only a colon after the `]` starts the port
+	let host_end = match registry.starts_with('[') {
+		true => registry.find(']').map_or(registry.len(), |i| i + 1),
+		false => 0,
+	};
+	let (host, port) = match registry[host_end..].rfind(':') {
+		Some(i) => (&registry[..host_end + i], Some(&registry[host_end + i + 1..])),
+		None => (registry, None),
+	};
+	if let Some(port) = port
+		&& port.parse::<u16>().is_err()
+	{
+		return Err("the registry's port must be a number, like in `localhost:5000/name`");
+	}
+	let valid_label = |label: &str| {
+		return !label.is_empty() && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-') && !label.starts_with('-') && !label.ends_with('-');
+	};
+	// an IPv6 address in brackets is a host too
+	let bracketed = host.starts_with('[') && host.ends_with(']') && host.len() > 2;
+	if !bracketed && !host.split('.').all(valid_label) {
+		return Err("the registry must be a host name like `ghcr.io` or `localhost:5000`");
+	}
+	return Ok(());
+}
+
+fn check_repository(repository: &str) -> Result<(), &'static str> {
+	let valid_component = |component: &str| {
+		if !component.starts_with(|c: char| c.is_ascii_lowercase() || c.is_ascii_digit())
+			|| !component.ends_with(|c: char| c.is_ascii_lowercase() || c.is_ascii_digit())
+		{
+			return false;
+		}
+		// between the lowercase letters and digits, separators are `.`, `_`, `__`, or any number of `-`
+		return component
+			.split(|c: char| c.is_ascii_lowercase() || c.is_ascii_digit())
+			.filter(|separator| !separator.is_empty())
+			.all(|separator| matches!(separator, "." | "_" | "__") || separator.chars().all(|c| c == '-'));
+	};
+	if !repository.split('/').all(valid_component) {
+		return Err("names are lowercase letters and digits, separated by `/`, `.`, `_`, `__`, or `-`");
+	}
+	return Ok(());
+}
+
+fn check_tag(tag: &str) -> Result<(), &'static str> {
+	if tag.is_empty()
+		|| tag.len() > 128
+		|| tag.starts_with(['.', '-'])
+		|| !tag
+			.chars()
+			.all(|c| c.is_ascii_alphanumeric() || "_.-".contains(c))
+	{
+		return Err("tags are up to 128 letters, digits, `_`, `.`, and `-`, not starting with `.` or `-`");
+	}
+	return Ok(());
+}
+
+fn check_digest(digest: &str) -> Result<(), &'static str> {
+	let Some((algorithm, hex)) = digest.split_once(':') else {
+		return Err("the digest must look like `sha256:<hex>`");
+	};
+	let valid_algorithm = !algorithm.is_empty()
+		&& algorithm.split(['+', '.', '_', '-']).all(|part| {
+			!part.is_empty()
+				&& part
+					.chars()
+					.all(|c| c.is_ascii_lowercase() || c.is_ascii_digit())
+		});
+	if !valid_algorithm
+		|| hex.len() < 32
+		|| !hex
+			.chars()
+			.all(|c| c.is_ascii_hexdigit() && !c.is_ascii_uppercase())
+	{
+		return Err("the digest must look like `sha256:<hex>`");
+	}
+	let expected = match algorithm {
+		"sha256" => Some(64),
+		"sha512" => Some(128),
+		_ => None,
+	};
+	if expected.is_some_and(|len| hex.len() != len) {
+		return Err("a sha256 digest has 64 hex digits, and a sha512 one 128");
+	}
+	return Ok(());
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	/// A reference, and the registry, repository, tag, and digest it splits into.
+	type Split = (&'static str, Option<&'static str>, &'static str, Option<&'static str>, Option<&'static str>);
+
+	const VALID: &[Split] = &[
+		("alpine", None, "alpine", None, None),
+		("alpine:3.20", None, "alpine", Some("3.20"), None),
+		("library/alpine", None, "library/alpine", None, None),
+		("user/repo:v1.0-rc_1", None, "user/repo", Some("v1.0-rc_1"), None),
+		("a/b/c/d", None, "a/b/c/d", None, None),
+		("foo__bar/baz---qux", None, "foo__bar/baz---qux", None, None),
+		("localhost/img", Some("localhost"), "img", None, None),
+		("localhost:5000/img", Some("localhost:5000"), "img", None, None),
+		("localhost:5000/img:tag", Some("localhost:5000"), "img", Some("tag"), None),
+		("Foo/bar", Some("Foo"), "bar", None, None),
+		("ghcr.io/owner/image", Some("ghcr.io"), "owner/image", None, None),
+		("registry-1.example.com:443/a/b:c", Some("registry-1.example.com:443"), "a/b", Some("c"), None),
+		("[::1]/x", Some("[::1]"), "x", None, None),
+		("[::1]:5000/x", Some("[::1]:5000"), "x", None, None),
+		("[fe80::1]:5000/x:y", Some("[fe80::1]:5000"), "x", Some("y"), None),
+		(
+			"alpine@sha256:0123456789abcdef0123456789abcdef0123456789abcdef0123456789abcdef",
+			None,
+			"alpine",
+			None,
+			Some("sha256:0123456789abcdef0123456789abcdef0123456789abcdef0123456789abcdef"),
+		),
+		(
+			"docker.io/library/alpine:3.20@sha256:0123456789abcdef0123456789abcdef0123456789abcdef0123456789abcdef",
+			Some("docker.io"),
+			"library/alpine",
+			Some("3.20"),
+			Some("sha256:0123456789abcdef0123456789abcdef0123456789abcdef0123456789abcdef"),
+		),
+		(
+			"localhost:5000/img:tag@sha512:0123456789abcdef0123456789abcdef0123456789abcdef0123456789abcdef0123456789abcdef0123456789abcdef0123456789abcdef0123456789abcdef",
+			Some("localhost:5000"),
+			"img",
+			Some("tag"),
+			Some("sha512:0123456789abcdef0123456789abcdef0123456789abcdef0123456789abcdef0123456789abcdef0123456789abcdef0123456789abcdef0123456789abcdef"),
+		),
+		(
+			"img@multihash+base58:0123456789abcdef0123456789abcdef",
+			None,
+			"img",
+			None,
+			Some("multihash+base58:0123456789abcdef0123456789abcdef"),
+		),
+	];
+
+	/// A reference `parse` rejects, and a word its reason must contain.
+	const INVALID: &[(&str, &str)] = &[
+		("", "empty"),
+		("alp ine", "whitespace"),
+		("alpine\t", "whitespace"),
+		("a@sha256:00@sha256:00", "more than one `@`"),
+		("alpine@@sha256:0123456789abcdef0123456789abcdef0123456789abcdef0123456789abcdef", "more than one `@`"),
+		("Alpine", "lowercase"),
+		("foo/Bar", "lowercase"),
+		("-alpine", "lowercase"),
+		("alpine-", "lowercase"),
+		("a..b", "lowercase"),
+		("a___b", "lowercase"),
+		("a//b", "lowercase"),
+		("a/", "lowercase"),
+		("localhost:port/img", "port"),
+		("localhost:99999/img", "port"),
+		("[::1]:x/img", "port"),
+		("exa_mple.com/img", "host name"),
+		("-example.com/img", "host name"),
+		("[::1]x/img", "host name"),
+		("alpine:", "tags"),
+		("alpine:.hidden", "tags"),
+		("alpine:-dash", "tags"),
+		("alpine:a+b", "tags"),
+		("alpine@sha256", "digest"),
+		("alpine@sha256:xyz", "digest"),
+		("alpine@:0123456789abcdef0123456789abcdef", "digest"),
+		("alpine@SHA256:0123456789abcdef0123456789abcdef0123456789abcdef0123456789abcdef", "digest"),
+		("alpine@sha256:0123456789ABCDEF0123456789abcdef0123456789abcdef0123456789abcdef", "digest"),
+		("alpine@sha256:0123456789abcdef", "digest"),
+		("alpine@sha256:0123456789abcdef0123456789abcdef0123456789abcdef0123456789abcde", "64 hex digits"),
+		("alpine@sha512:0123456789abcdef0123456789abcdef0123456789abcdef0123456789abcdef", "128"),
+	];
+
+	#[test]
+	fn splits_valid_references() {
+		for &(reference, registry, repository, tag, digest) in VALID {
+			let image = ImageRef::parse(reference).unwrap_or_else(|e| panic!("`{reference}` was rejected: {e}"));
+			assert_eq!(image.registry.as_deref(), registry, "registry of `{reference}`");
+			assert_eq!(image.repository, repository, "repository of `{reference}`");
+			assert_eq!(image.tag.as_deref(), tag, "tag of `{reference}`");
+			assert_eq!(image.digest.as_deref(), digest, "digest of `{reference}`");
+			assert_eq!(image.to_string(), reference, "`{reference}` does not display as written");
+		}
+	}
+
+	#[test]
+	fn rejects_invalid_references() {
+		for &(reference, reason) in INVALID {
+			match ImageRef::parse(reference) {
+				Ok(image) => panic!("`{reference}` was accepted as {image:?}"),
+				Err(e) => assert!(e.contains(reason), "`{reference}` was rejected with `{e}`, not for `{reason}`"),
+			}
+		}
+	}
+
+	#[test]
+	fn limits_the_name_to_255_characters() {
+		let registry = "registry.example.com/";
+		let longest = format!("{registry}{}", "a".repeat(MAX_NAME - registry.len()));
+		assert!(ImageRef::parse(&longest).is_ok());
+		assert!(ImageRef::parse(&format!("{longest}:tag")).is_ok(), "the tag does not count toward the name");
+
+		let too_long = format!("{longest}a");
+		assert_eq!(ImageRef::parse(&too_long), Err("the name, registry included, is longer than 255 characters"));
+
+		let tag = "t".repeat(128);
+		assert!(ImageRef::parse(&format!("alpine:{tag}")).is_ok());
+		assert!(ImageRef::parse(&format!("alpine:{tag}t")).is_err());
+	}
+
+	#[test]
+	fn normalizes_like_the_daemon() {
+		let cases = [
+			("alpine", "docker.io/library/alpine:latest"),
+			("alpine:3.20", "docker.io/library/alpine:3.20"),
+			("library/alpine", "docker.io/library/alpine:latest"),
+			("docker.io/alpine", "docker.io/library/alpine:latest"),
+			("docker.io/library/alpine", "docker.io/library/alpine:latest"),
+			("index.docker.io/alpine", "docker.io/library/alpine:latest"),
+			("registry-1.docker.io/user/repo:v1", "docker.io/user/repo:v1"),
+			("user/repo", "docker.io/user/repo:latest"),
+			("ghcr.io/owner/image", "ghcr.io/owner/image:latest"),
+			("localhost:5000/img", "localhost:5000/img:latest"),
+			// a registry other than Docker Hub has no `library/`
+			("localhost:5000/alpine", "localhost:5000/alpine:latest"),
+			(
+				"alpine@sha256:0123456789abcdef0123456789abcdef0123456789abcdef0123456789abcdef",
+				"docker.io/library/alpine@sha256:0123456789abcdef0123456789abcdef0123456789abcdef0123456789abcdef",
+			),
+			(
+				"alpine:3.20@sha256:0123456789abcdef0123456789abcdef0123456789abcdef0123456789abcdef",
+				"docker.io/library/alpine:3.20@sha256:0123456789abcdef0123456789abcdef0123456789abcdef0123456789abcdef",
+			),
+		];
+		for (reference, normalized) in cases {
+			assert_eq!(ImageRef::parse(reference).unwrap().normalized(), normalized, "`{reference}`");
+		}
+	}
+
+	#[test]
+	fn folds_docker_hub_names() {
+		for registry in [None, Some("docker.io"), Some("index.docker.io"), Some("registry-1.docker.io")] {
+			let reference = match registry {
+				Some(registry) => format!("{registry}/user/repo"),
+				None => "user/repo".into(),
+			};
+			let image = ImageRef::parse(&reference).unwrap();
+			assert_eq!(image.registry(), DOCKER_HUB, "`{reference}`");
+			assert_eq!(image.normalized_name(), "docker.io/user/repo", "`{reference}`");
+		}
+		assert_eq!(ImageRef::split("quay.io/user/repo").registry(), "quay.io");
+	}
+
+	#[test]
+	fn at_replaces_tag_and_digest() {
+		let digest = "sha256:0123456789abcdef0123456789abcdef0123456789abcdef0123456789abcdef";
+		let image = ImageRef::split(&format!("localhost:5000/img:old@{digest}"));
+		assert_eq!(image.at("new").to_string(), "localhost:5000/img:new");
+		assert_eq!(image.at(digest).to_string(), format!("localhost:5000/img@{digest}"));
+		assert_eq!(image.at(&format!("@{digest}")).to_string(), format!("localhost:5000/img@{digest}"));
+	}
+}
@@ -0,0 +1,94 @@
+use serde::{Deserialize, Serialize};
+
+/// Parses a number of CPUs like `1.5` into the nanocpus docker takes, so `1.5` is 1 500 000 000.
+pub fn parse_cpus(s: &str) -> Result<i64, String> {
+	let cpus: f64 = s
+		.trim()
+		.parse()
+		.map_err(|_| format!("`{s}` is not a number of CPUs like 2 or 1.5"))?;
+	if !cpus.is_finite() || cpus <= 0.0 {
+		return Err("the number of CPUs has to be more than 0".into());
+	}
+	let nanocpus = (cpus * 1e9).round();
+	if nanocpus < 1e7 || nanocpus > i64::MAX as f64 {
+		return Err(format!("{cpus} CPUs is not something docker can limit to"));
+	}
+
+	return Ok(nanocpus as i64);
+}
+
+/// Checks a cpuset like `0-3,6`: CPU numbers and ranges of them, separated by commas.
+pub fn parse_cpuset(s: &str) -> Result<String, String> {
+	let invalid = || format!("`{s}` is not a list of CPUs like 0-3 or 0,2,4");
+	for part in s.split(',') {
+		let (first, last) = part.split_once('-').unwrap_or((part, part));
+		let first: u32 = first.parse().map_err(|_| invalid())?;
+		let last: u32 = last.parse().map_err(|_| invalid())?;
+		if first > last {
+			return Err(format!("the range `{part}` in `{s}` goes backwards"));
+		}
+	}
+
+	return Ok(s.into());
+}
+
+/// The resource limits `--ulimit` can set, the ones docker knows.
+pub const ULIMITS: &[&str] = &[
+	"core",
+	"cpu",
+	"data",
+	"fsize",
+	"locks",
+	"memlock",
+	"msgqueue",
+	"nice",
+	"nofile",
+	"nproc",
+	"rss",
+	"rtprio",
+	"rttime",
+	"sigpending",
+	"stack",
+];
+
+/// One `--ulimit`, where -1 is unlimited.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Ulimit {
+	pub name: String,
+	pub soft: i64,
+	pub hard: i64,
+}
+
+impl std::fmt::Display for Ulimit {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		return write!(f, "{}={}:{}", self.name, self.soft, self.hard);
+	}
+}
+
+/// Parses a ulimit the way `docker run --ulimit` does, like `nofile=1024:4096`, or `nproc=512` for the same soft and
+/// hard limit.
+pub fn parse_ulimit(s: &str) -> Result<Ulimit, String> {
+	let Some((name, values)) = s.split_once('=') else {
+		return Err(format!("`{s}` is not a ulimit like nofile=1024:4096 or nproc=512"));
+	};
+	if !ULIMITS.contains(&name) {
+		return Err(format!("unknown ulimit `{name}`, as in nofile=1024:4096; known are {}", ULIMITS.join(", ")));
+	}
+	let value = |v: &str| {
+		return v
+			.parse::<i64>()
+			.ok()
+			.filter(|v| *v >= -1)
+			.ok_or_else(|| format!("`{v}` is not a limit like 1024, or -1 for unlimited, as in {name}=1024:4096"));
+	};
+	let (soft, hard) = match values.split_once(':') {
+		Some((soft, hard)) => (value(soft)?, value(hard)?),
+		None => (value(values)?, value(values)?),
+	};
+	// -1 is unlimited, so it is the highest
+	if hard != -1 && (soft == -1 || soft > hard) {
+		return Err(format!("the soft limit of `{s}` is above its hard limit; it goes first, as in {name}=1024:4096"));
+	}
+
+	return Ok(Ulimit { name: name.into(), soft, hard });
+}
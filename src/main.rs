@@ -1,6 +1,6 @@
 #![cfg_attr(feature = "generators", allow(unreachable_code))]
 
-use clap::{Parser, ValueEnum};
+use clap::{Parser, Subcommand, ValueEnum};
 
 #[derive(ValueEnum, Debug, Clone, Default, PartialEq)]
 enum ZshHistorySync {
@@ -15,19 +15,322 @@ enum ZshHistorySync {
 	Copy,
 }
 
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq)]
+enum UpdateSystem {
+	/// update before entering the system
+	Foreground,
+
+	/// update while the session already runs, telling the session when it is done
+	Background,
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy, Default, PartialEq)]
+enum PullPolicy {
+	/// pull even when the image is already there, to pick up a newer one
+	Always,
+
+	/// pull only when the image is not there yet
+	#[default]
+	Missing,
+
+	/// never pull, failing when the image is not there
+	Never,
+}
+
+impl Args {
+	/// Parses `argv`, noting whether the command, `tempsystem exec`'s included, came right after `--`.
+	fn parse_argv(argv: Vec<OsString>) -> Result<Self, clap::Error> {
+		let mut args = Self::try_parse_from(&argv)?;
+		let command = match &args.subcommand {
+			Some(Commands::Exec { command }) => command.len(),
+			_ => args.command.len(),
+		};
+		args.command_after_separator = command > 0 && argv.len() > command && argv[argv.len() - command - 1] == "--";
+		return Ok(args);
+	}
+
+	/// Parses `flags` as if they followed `tempsystem` on the command line, for tests.
+	#[cfg(test)]
+	fn try_from_flags(flags: &[&str]) -> Result<Self, clap::Error> {
+		return Self::parse_argv(std::iter::once("tempsystem").chain(flags.iter().copied()).map(OsString::from).collect());
+	}
+
+	/// Same as `try_from_flags`, but panics when `flags` do not parse.
+	#[cfg(test)]
+	fn from_flags(flags: &[&str]) -> Self {
+		return Self::try_from_flags(flags).unwrap();
+	}
+}
+
+#[derive(Subcommand, Debug, Clone, PartialEq)]
+enum Commands {
+	/// list every error code tempsystem can fail with, along with its message
+	Errors,
+
+	/// check config files
+	Config {
+		#[command(subcommand)]
+		action: ConfigAction,
+	},
+
+	/// list the placeholders templates, like config.toml's default-command, can use
+	Placeholders,
+
+	/// print a `ts` shell function that runs tempsystem with the configured default args, adding --reuse inside projects
+	GenerateAlias {
+		#[arg(long, value_enum)]
+		shell: alias::AliasShell,
+	},
+
+	/// print a direnv `use tempsystem` snippet that keeps a --reuse system ready and proxies commands into it
+	DirenvHook {
+		#[arg(long = "command", value_name = "NAME", default_values = ["make", "cargo"], help = "command to proxy into the system; can be repeated")]
+		commands: Vec<String>,
+	},
+
+	/// run a command in the system kept for this directory, same as --reuse
+	Exec {
+		#[arg(trailing_var_arg = true, allow_hyphen_values = true, required = true)]
+		command: Vec<String>,
+	},
+
+	/// apply these flags to every run from the current directory, until `tempsystem forget`; flags given on the command line still win
+	Remember {
+		#[arg(trailing_var_arg = true, allow_hyphen_values = true, required = true)]
+		flags: Vec<String>,
+	},
+
+	/// stop applying the flags remembered for the current directory
+	Forget,
+
+	/// start a new system from the latest --autosnapshot snapshot, taking the other options from the command line
+	RestoreSnapshot {
+		#[arg(help = "the name of the system the snapshot was taken of (default: whichever was snapshotted last)")]
+		system: Option<String>,
+	},
+
+	/// check which tempsystem features work with an image, exiting non-zero when it cannot be entered at all
+	CheckImage {
+		#[arg(help = "the image to check, pulled as --pull says")]
+		image: String,
+	},
+
+	/// print a string that stands for the system these flags would create, for a teammate to run with --from-share
+	Share,
+
+	/// compare the captures in two bundles --capture-env-on-failure wrote, like from a passing and a failing run
+	DiffCaptures {
+		#[arg(help = "the bundle to compare from")]
+		a: std::path::PathBuf,
+
+		#[arg(help = "the bundle to compare to")]
+		b: std::path::PathBuf,
+	},
+
+	/// download the image --image, --tag, --flavor, and --registry pick, so later runs start without pulling
+	Pull,
+
+	/// open a shell in a throwaway, read-only system of an image to look around, with no network and no setup
+	InspectImage {
+		#[arg(help = "the image to look into (default: the one --flavor, --tag, and --registry pick)")]
+		image: Option<String>,
+	},
+
+	/// delete systems left behind by crashes and reboots, and with --images, old versions of the tempsystem image
+	Prune {
+		#[arg(long, help = "also remove versions of the tempsystem image no tag points at anymore")]
+		images: bool,
+
+		#[arg(long, help = "also delete running and paused systems, which may still be in use")]
+		running: bool,
+
+		#[arg(
+			long,
+			value_name = "INTERVAL",
+			value_parser = snapshot::parse_interval,
+			help = "also delete systems --pause-on-detach paused longer ago than INTERVAL, like `12h`"
+		)]
+		paused_older_than: Option<std::time::Duration>,
+
+		#[arg(long, help = "only list what would be removed")]
+		dry_run: bool,
+	},
+
+	/// pin this project's image to the digest its tag points at now, in tempsystem.toml
+	PinImage {
+		#[arg(long, help = "move a pin that is already there")]
+		update: bool,
+	},
+
+	/// manage images built from --build and --containerfile, and snapshots from --autosnapshot
+	Cache {
+		#[command(subcommand)]
+		action: CacheAction,
+	},
+
+	/// list the systems tempsystem created that are still there, like kept and paused ones
+	List,
+
+	/// open a shell in a system --keep, --keep-on-error, or --pause-on-detach left behind, unpausing it first
+	Attach {
+		#[arg(help = "the system's name or ID, or a unique prefix of either")]
+		system: String,
+	},
+
+	/// delete a running system, from another terminal
+	Kill {
+		#[arg(help = "the system's name or ID, or a unique prefix of either")]
+		target: String,
+
+		#[arg(long, help = "ask the system's processes to exit with SIGTERM before deleting it")]
+		graceful: bool,
+
+		#[arg(long, help = "allow deleting containers tempsystem did not create")]
+		force: bool,
+	},
+}
+
+impl Commands {
+	/// How the subcommand is run, for messages naming it.
+	fn name(&self) -> &'static str {
+		return match self {
+			Self::Errors => "`tempsystem errors`",
+			Self::Config { .. } => "`tempsystem config`",
+			Self::Placeholders => "`tempsystem placeholders`",
+			Self::GenerateAlias { .. } => "`tempsystem generate-alias`",
+			Self::DirenvHook { .. } => "`tempsystem direnv-hook`",
+			Self::Exec { .. } => "`tempsystem exec`",
+			Self::Remember { .. } => "`tempsystem remember`",
+			Self::Forget => "`tempsystem forget`",
+			Self::RestoreSnapshot { .. } => "`tempsystem restore-snapshot`",
+			Self::CheckImage { .. } => "`tempsystem check-image`",
+			Self::Share => "`tempsystem share`",
+			Self::DiffCaptures { .. } => "`tempsystem diff-captures`",
+			Self::Pull => "`tempsystem pull`",
+			Self::InspectImage { .. } => "`tempsystem inspect-image`",
+			Self::Prune { .. } => "`tempsystem prune`",
+			Self::PinImage { .. } => "`tempsystem pin-image`",
+			Self::Cache { .. } => "`tempsystem cache`",
+			Self::List => "`tempsystem list`",
+			Self::Attach { .. } => "`tempsystem attach`",
+			Self::Kill { .. } => "`tempsystem kill`",
+		};
+	}
+}
+
+#[derive(Subcommand, Debug, Clone, PartialEq)]
+enum ConfigAction {
+	/// check a config.toml or tempsystem.toml for unknown keys and invalid values, without running anything
+	Validate {
+		#[arg(help = "the file to check (default: the config.toml tempsystem reads)")]
+		path: Option<std::path::PathBuf>,
+	},
+}
+
+#[derive(Subcommand, Debug, Clone, PartialEq)]
+enum CacheAction {
+	/// list built images and snapshots, and their sizes
+	List,
+
+	/// remove every built image and snapshot; built images are rebuilt when next needed
+	Clear,
+}
+
 #[derive(Parser, Debug)]
-#[command(name = "tempsystem", version = version::version)]
+#[command(name = "tempsystem", version = version::version, args_override_self = true)]
 #[command(about = "Create and enter a completely temporary system, whenever you want!", long_about = None)]
 struct Args {
+	#[command(subcommand)]
+	subcommand: Option<Commands>,
+
 	#[arg(long, help = "show more verbose output")]
 	verbose: bool,
 
+	#[arg(short, long, conflicts_with = "verbose", help = "do not print notes like the setup time estimate")]
+	quiet: bool,
+
+	#[arg(
+		long,
+		value_enum,
+		default_value = "auto",
+		help = "talk to the container engine through its API socket, or by running the docker/podman binary"
+	)]
+	backend: backend::BackendKind,
+
+	#[arg(
+		long,
+		value_name = "URL",
+		help = "the daemon to talk to instead of $DOCKER_HOST or the local sockets: unix://, tcp://, http://, or ssh://[user@]host[:port][/socket]"
+	)]
+	host: Option<String>,
+
+	#[arg(
+		long,
+		value_name = "NAME",
+		conflicts_with = "host",
+		help = "the docker CLI context to connect through, instead of $DOCKER_CONTEXT or the one `docker context use` picked"
+	)]
+	context: Option<String>,
+
+	#[arg(long, value_name = "FILE", requires_all = ["tls_cert", "tls_key"], help = "CA certificate of a tcp:// --host spoken to over TLS")]
+	tls_ca: Option<std::path::PathBuf>,
+
+	#[arg(long, value_name = "FILE", requires_all = ["tls_ca", "tls_key"], help = "client certificate for a tcp:// --host spoken to over TLS")]
+	tls_cert: Option<std::path::PathBuf>,
+
+	#[arg(long, value_name = "FILE", requires_all = ["tls_ca", "tls_cert"], help = "client key for a tcp:// --host spoken to over TLS")]
+	tls_key: Option<std::path::PathBuf>,
+
+	#[arg(long, help = "after the session, report how long the execs tempsystem ran took, per phase")]
+	why_slow: bool,
+
+	#[arg(long, help = "print the packages that would be installed after merging every source, then exit")]
+	dry_run: bool,
+
+	#[arg(long, help = "print errors as JSON lines with a stable `code`, for tools wrapping tempsystem")]
+	json: bool,
+
+	#[arg(
+		long,
+		help = "set up for CI runners: plain progress lines, no colors, no tty, no suggestions or prompts, and exit code 125 when setup fails \
+		        (127 when the command does not exist); flags given alongside it override what it implies"
+	)]
+	ci: bool,
+
+	#[arg(long, value_name = "PATH", help = "write a JSON summary of the run to PATH: exit code, outcome, and error")]
+	ci_report: Option<std::path::PathBuf>,
+
+	#[arg(
+		long,
+		value_enum,
+		value_name = "WHEN",
+		help = "color messages: `auto` (the default, when stdout is a terminal and NO_COLOR is unset), `always`, or `never` (default with --ci)"
+	)]
+	color: Option<color::ColorWhen>,
+
+	#[arg(
+		long,
+		value_enum,
+		value_name = "STYLE",
+		help = "how to show setup progress: `bars` (the default) or `plain` lines on stderr (default with --ci)"
+	)]
+	progress: Option<progress::Output>,
+
+	#[arg(long, help = "print the extra args the `ts` alias passes in this directory, one per line, then exit")]
+	print_effective_args: bool,
+
 	#[arg(
 		short,
 		long,
-		help = "run a system update before entering; can fix issues with package install fails (recommended with --chaotic-aur or --landware)"
+		value_enum,
+		value_name = "WHEN",
+		num_args = 0..=1,
+		require_equals = true,
+		default_missing_value = "foreground",
+		help = "run a system update before entering, or with `=background` during the session; can fix issues with package install fails \
+		        (recommended with --chaotic-aur or --landware)"
 	)]
-	update_system: bool,
+	update_system: Option<UpdateSystem>,
 
 	#[arg(
 		long,
@@ -35,17 +338,136 @@ struct Args {
 	)]
 	update_pkgfile: bool,
 
+	#[arg(
+		long,
+		value_name = "FLAVOR",
+		help = "image to use: `minimal` (pacman and a shell), `full` (the default, with zsh, yay, and pkgfile), or another tag of landsj/tempsystem"
+	)]
+	flavor: Option<String>,
+
+	#[arg(
+		long,
+		value_name = "REF",
+		conflicts_with = "flavor",
+		help = "image to base the system on instead of a flavor, like `archlinux:base-devel`; images without zsh get their default shell",
+		long_help = "image to base the system on instead of a flavor, like `archlinux:base-devel`.\n\n\
+		             tempsystem expects the image to have:\n\
+		             - a `tempsystem` user (or one named by the `org.tempsystem.user` label or USER), created on start when missing\n\
+		             - zsh, falling back to bash or sh\n\
+		             - pacman and passwordless sudo for the user, for installing packages; without sudo for everything setup runs as root,\n\
+		             and AUR helpers still need it for pacman\n\
+		             - yay or paru, for --extra-aur-packages\n\
+		             - pkgfile, for --update-pkgfile\n\n\
+		             `tempsystem check-image REF` reports which of these an image has."
+	)]
+	image: Option<String>,
+
+	#[arg(
+		long,
+		value_name = "TAG",
+		conflicts_with = "flavor",
+		visible_alias = "digest",
+		help = "pull this tag of the image, or pin it to a digest like `sha256:<hex>`, which is checked after the pull; \
+		        overrides the digest a project pins"
+	)]
+	tag: Option<String>,
+
+	#[arg(
+		long,
+		value_name = "HOST",
+		env = "TEMPSYSTEM_REGISTRY",
+		help = "pull the tempsystem image from a mirror on this registry, like `registry.corp:5000`; an --image is left alone"
+	)]
+	registry: Option<String>,
+
+	#[arg(
+		long,
+		value_enum,
+		value_name = "POLICY",
+		default_value = "missing",
+		help = "when to pull the image: `always`, `missing`, or `never`"
+	)]
+	pull: PullPolicy,
+
+	#[arg(
+		long,
+		conflicts_with = "pull",
+		help = "never touch the network for setup: use the local image without pulling it, refuse anything that installs or updates \
+		        packages, and disable the system's network unless --no-network=false"
+	)]
+	offline: bool,
+
+	#[arg(
+		long,
+		value_name = "OS/ARCH",
+		value_parser = registry::parse_platform,
+		help = "use the image's variant for this platform, like `linux/arm64`; other architectures than the host's need qemu-user-static \
+		        with binfmt support on the host"
+	)]
+	platform: Option<String>,
+
+	#[arg(
+		long,
+		value_name = "USER",
+		requires = "registry_password_stdin",
+		help = "log in to the image's registry as this user instead of with the docker CLI's credentials, for CI"
+	)]
+	registry_user: Option<String>,
+
+	#[arg(long, requires = "registry_user", help = "read the --registry-user password from stdin")]
+	registry_password_stdin: bool,
+
 	#[arg(short, long, help = "mount system root as read only (cannot be used with --extra-packages)")]
 	ro_root: bool,
 
 	#[arg(short = 'c', long, help = "mount ~/work as read only")]
 	ro_cwd: bool,
 
+	#[arg(
+		long,
+		conflicts_with_all = ["ro_cwd", "disable_cwd_mount"],
+		help = "mount the current directory read only, with a writable overlay at ~/work whose changes are discarded with the system \
+		        (lets the system mount filesystems)"
+	)]
+	ro_cwd_overlay: bool,
+
 	#[arg(short, long, help = "do not mount current directory to ~/work")]
 	disable_cwd_mount: bool,
 
-	#[arg(short, long, help = "disable network capabilities for the system (cannot be used with --extra-packages)")]
-	no_network: bool,
+	#[arg(
+		long,
+		conflicts_with_all = ["ro_cwd", "ro_cwd_overlay", "disable_cwd_mount"],
+		help = "copy the current directory into ~/work instead of mounting it, for daemons on another machine; changes stay in the system"
+	)]
+	copy_cwd: bool,
+
+	#[arg(
+		short,
+		long,
+		value_name = "BOOL",
+		num_args = 0..=1,
+		require_equals = true,
+		default_missing_value = "true",
+		help = "disable network capabilities for the system (cannot be used with --extra-packages); \
+		        `--no-network=false` keeps the network with --offline"
+	)]
+	no_network: Option<bool>,
+
+	#[arg(
+		long,
+		value_name = "HOST",
+		conflicts_with = "no_network",
+		help = "only let the system connect to this domain, address, or CIDR range, plus DNS and, when installing packages, the mirrors; \
+		        can be repeated (domains are resolved once, at setup, and root in the system can lift the restriction)"
+	)]
+	allow_host: Vec<String>,
+
+	#[arg(
+		long,
+		requires = "allow_host",
+		help = "report how many outgoing packets --allow-host dropped when the session ends"
+	)]
+	log_denied: bool,
 
 	#[arg(
 		short = 'p',
@@ -57,105 +479,1629 @@ struct Args {
 	#[arg(short = 'a', long, help = "same as --extra-packages, but fetches the packages from the AUR")]
 	extra_aur_packages: Option<String>,
 
+	#[arg(
+		long,
+		value_name = "NAME[@VERSION]",
+		value_parser = toolchain::parse,
+		help = "install and set up a language toolchain: `rust`, `go`, `python`, `node`, or `c`, like `rust@nightly` or `node@20`; \
+		        can be repeated, and --dry-run shows what it installs"
+	)]
+	toolchain: Vec<toolchain::Spec>,
+
+	#[arg(
+		long,
+		value_name = "STRING",
+		conflicts_with_all = ["image", "tag", "flavor", "extra_packages", "extra_aur_packages", "toolchain"],
+		help = "create the system a `tempsystem share` string stands for, after showing what it is like"
+	)]
+	from_share: Option<String>,
+
+	#[arg(
+		long,
+		requires = "from_share",
+		help = "run the --from-share system without asking, even if it does something risky"
+	)]
+	trust_share: bool,
+
+	#[arg(
+		long,
+		default_value_t = 100,
+		value_parser = clap::value_parser!(u64).range(1..),
+		help = "install packages in batches of this many"
+	)]
+	install_chunk_size: u64,
+
+	#[arg(
+		long,
+		help = "fail instead of creating the tempsystem user (with sudo) in images that do not have one, like stock archlinux"
+	)]
+	no_bootstrap_user: bool,
+
+	#[arg(
+		long,
+		help = "do not check that the image has its user (and pacman, for tempsystem images) right after the system starts"
+	)]
+	no_healthcheck: bool,
+
 	#[arg(long, help = "give extended privileges to the system")]
 	privileged: bool,
 
+	#[arg(
+		long,
+		help = "--privileged, plus access to every device, the host's cgroups mounted writable, and no apparmor or seccomp confinement; \
+		        anything in the system can then affect the host (see --verbose for the exact settings)"
+	)]
+	privileged_full: bool,
+
 	#[arg(long, help = "Add the Chaotic-AUR to the system")]
 	chaotic_aur: bool,
 
 	#[arg(long, help = "Restrict usable cpu cores")]
 	restrict_cpu: Option<u8>,
 
+	#[arg(
+		long,
+		value_name = "CPUS",
+		value_parser = limits::parse_cpus,
+		help = "limit the system to this much CPU time, like `1.5` for one and a half CPUs"
+	)]
+	cpus: Option<i64>,
+
+	#[arg(
+		long,
+		value_name = "LIST",
+		value_parser = limits::parse_cpuset,
+		conflicts_with = "restrict_cpu",
+		help = "only let the system run on these CPUs, like `0-3` or `0,2`"
+	)]
+	cpuset_cpus: Option<String>,
+
+	#[arg(
+		long,
+		value_name = "N",
+		value_parser = clap::value_parser!(i64).range(1..),
+		help = "limit the system to N processes at once, so a fork bomb stays inside it"
+	)]
+	pids_limit: Option<i64>,
+
+	#[arg(
+		long,
+		value_name = "NAME=SOFT[:HARD]",
+		value_parser = limits::parse_ulimit,
+		help = "set a resource limit in the system like `docker run --ulimit`, like `nofile=1024:4096` or `nproc=512`, where -1 \
+		        is unlimited; can be repeated"
+	)]
+	ulimit: Vec<limits::Ulimit>,
+
 	#[arg(long, help = "Restrict usable memory (MB)")]
 	restrict_memory: Option<usize>,
 
-	#[arg(long, help = "Add the landware repo to the system")]
-	landware: bool,
+	#[arg(
+		long,
+		value_name = "SIZE",
+		value_parser = size::parse_size,
+		conflicts_with = "restrict_memory",
+		help = "limit the memory the system can use, like `512m` or `4g`; processes over it are killed"
+	)]
+	memory: Option<u64>,
 
-	#[arg(long, help = "Sync the ZSH command history between host and system", default_value = "none")]
-	sync_zsh_history: ZshHistorySync,
+	#[arg(
+		long,
+		value_name = "SIZE",
+		value_parser = size::parse_size,
+		requires = "memory",
+		help = "limit memory and swap together to SIZE, at least --memory (default: the same as --memory, so no swap)"
+	)]
+	memory_swap: Option<u64>,
 
-	#[arg(default_value = "/usr/bin/zsh", help = "command to execute in container, then exit")]
-	command: Vec<String>,
+	#[arg(
+		long,
+		value_name = "SIZE",
+		value_parser = size::parse_size,
+		help = "limit the system's writable layer to SIZE, like `20g`; needs a storage driver with quotas, like overlay2 on xfs mounted with pquota"
+	)]
+	storage_size: Option<u64>,
 
-	#[cfg(feature = "generators")]
-	#[arg(long = "generate-man")]
-	generate_man: String,
+	#[arg(
+		long,
+		value_name = "SIZE",
+		value_parser = size::parse_size,
+		help = "size of /dev/shm in the system, like `1g`, for browsers and test suites that outgrow the default 64m"
+	)]
+	shm_size: Option<u64>,
 
-	#[cfg(feature = "generators")]
-	#[arg(value_enum, long = "generate-shell")]
-	generate_shell: clap_complete::Shell,
-}
+	#[arg(
+		long,
+		value_name = "NAME",
+		help = "create the system under this cgroup, like a systemd slice `myproject.slice`, or a path like `/myproject` with the \
+		        cgroupfs driver"
+	)]
+	cgroup_parent: Option<String>,
 
-mod docker;
-use docker::*;
-use tokio_util::sync::CancellationToken;
+	#[arg(long, help = "Add the landware repo to the system")]
+	landware: bool,
 
-#[macro_export]
-macro_rules! print_error {
-	($err:expr) => {{
-		use colorize::AnsiColor;
-		println!("{}", ($err).to_string().red());
-	}};
-	($msg:expr, $err:expr) => {
-		use colorize::AnsiColor;
-		println!("{}", format!("{}: {}", ($msg), ($err).to_string()).red());
-	};
-}
+	#[arg(
+		long,
+		value_name = "GIB",
+		help = "warn when the system's writable layer grows past GIB gibibytes; can be repeated (default: 5, 10, and 20)"
+	)]
+	storage_warn: Vec<u64>,
 
-#[tokio::main]
-async fn main() -> std::process::ExitCode {
-	let args = Args::parse();
+	#[arg(long, help = "watch this machine's available memory and load during the session, and warn when they run low")]
+	guard: bool,
 
-	#[cfg(feature = "generators")]
-	{
-		use clap::CommandFactory;
-		let man = clap_mangen::Man::new(Args::command());
-		let mut buffer: Vec<u8> = Default::default();
-		man.render(&mut buffer).unwrap();
-		std::fs::write(args.generate_man, buffer).unwrap();
+	#[arg(
+		long,
+		value_enum,
+		value_name = "ACTION",
+		default_value = "warn",
+		requires = "guard",
+		help = "what --guard does when the host runs low: `warn`, or `clamp` the system's memory and CPU as well"
+	)]
+	guard_action: guard::Action,
 
-		#[allow(unused)]
-		use clap_complete::{Generator, Shell, generate};
-		clap_complete::aot::generate(args.generate_shell, &mut Args::command(), Args::command().get_name().to_string(), &mut std::io::stdout());
+	#[arg(
+		long,
+		value_name = "PERCENT",
+		default_value_t = 10,
+		value_parser = clap::value_parser!(u8).range(1..100),
+		requires = "guard",
+		help = "the share of memory that has to stay available before --guard acts"
+	)]
+	guard_memory: u8,
 
-		return 0.into();
-	}
+	#[arg(
+		long,
+		value_name = "LOAD",
+		default_value_t = 2.0,
+		requires = "guard",
+		help = "the one minute load average per CPU at which --guard acts"
+	)]
+	guard_load: f64,
 
-	let token = CancellationToken::new();
-	let token_clone = token.clone();
-	tokio::task::spawn(async move {
-		if tokio::signal::ctrl_c().await.is_ok() {
-			token_clone.cancel();
-		}
-	});
+	#[arg(long, help = "Sync the ZSH command history between host and system", default_value = "none")]
+	sync_zsh_history: ZshHistorySync,
 
-	let mut context = Context::default();
-	if let Err(e) = context.connect() {
-		print_error!(e);
-	}
+	#[arg(long, help = "do not delete the system when setup fails, so it can be continued with --resume")]
+	keep_on_error: bool,
 
-	tokio::select! {
-		_ = token.cancelled() => {
-			if let Err(e) = context.delete_container().await {
-				print_error!("could not delete system after cancel (could be that it did not create the system yet)", e);
-			}
-		}
-		ret = context.perform_all_enter(&args) => {
-			match ret {
+	#[arg(
+		long,
+		value_name = "CONTAINER_ID",
+		help = "continue setting up a system kept by --keep-on-error instead of creating a new one"
+	)]
+	resume: Option<String>,
+
+	#[arg(long, help = "do not delete the system on exit")]
+	keep: bool,
+
+	#[arg(
+		long,
+		help = "do not delete the system when the command exits non-zero, so it can be looked into; it is deleted as usual on success, \
+		        and --keep-on-error covers setup failing"
+	)]
+	keep_on_failure: bool,
+
+	#[arg(
+		long,
+		value_name = "INTERVAL",
+		value_parser = snapshot::parse_interval,
+		help = "commit the running system to a local image every INTERVAL (like 15m or 1h, at least 60s), \
+		        so `tempsystem restore-snapshot` can start over from the latest one"
+	)]
+	autosnapshot: Option<std::time::Duration>,
+
+	#[arg(
+		long,
+		value_name = "COUNT",
+		default_value_t = snapshot::DEFAULT_KEEP,
+		value_parser = clap::value_parser!(u64).range(1..),
+		requires = "autosnapshot",
+		help = "how many --autosnapshot snapshots of the system to keep"
+	)]
+	autosnapshot_keep: u64,
+
+	#[arg(
+		long,
+		conflicts_with_all = ["keep", "keep_on_error", "keep_on_failure", "reuse", "reuse_reset", "resume"],
+		help = "before deleting the system, overwrite the files in its home directories and temporary directories, and delete its volumes; \
+		        refused with writable host mounts (whether overwritten blocks are really gone depends on the storage driver)"
+	)]
+	wipe_on_exit: bool,
+
+	#[arg(
+		long,
+		conflicts_with_all = ["ro_cwd", "ro_cwd_overlay", "disable_cwd_mount", "copy_cwd"],
+		help = "when the session ends, give files in ~/work owned by another user, like those sudo created, back to you; \
+		        reports how many it fixed"
+	)]
+	chown_work_on_exit: bool,
+
+	#[arg(
+		long,
+		conflicts_with = "wipe_on_exit",
+		help = "when the terminal hangs up, like when ssh drops, pause the system instead of deleting it; `tempsystem attach` \
+		        unpauses it, and `tempsystem prune --paused-older-than` deletes it"
+	)]
+	pause_on_detach: bool,
+
+	#[arg(
+		long,
+		help = "enter the system previously kept for this directory and options, creating it if needed (implies --keep)"
+	)]
+	reuse: bool,
+
+	#[arg(long, help = "delete the system kept for this directory by --reuse and set up a new one")]
+	reuse_reset: bool,
+
+	#[arg(
+		long,
+		value_name = "INSTRUCTION",
+		help = "containerfile instruction to build into the image before entering, e.g. 'RUN systemctl enable sshd'; can be repeated"
+	)]
+	build: Vec<String>,
+
+	#[arg(
+		long,
+		value_name = "PATH",
+		help = "containerfile to build into the image before entering, rebuilt when it changes; without a FROM it builds on the tempsystem image"
+	)]
+	containerfile: Option<std::path::PathBuf>,
+
+	#[arg(
+		long,
+		help = "rebuild the image from --build and --containerfile, without the build cache, even when it is built already"
+	)]
+	build_always: bool,
+
+	#[arg(
+		long,
+		value_name = "PATH",
+		help = "write a containerfile reproducing the system these options describe to PATH, then exit without creating it"
+	)]
+	emit_containerfile: Option<std::path::PathBuf>,
+
+	#[arg(
+		long,
+		value_name = "POLICY_FILE",
+		help = "on exit, check the paths the system changed against `allow <glob>`/`deny <glob>` rules and exit with 90 if any are not allowed"
+	)]
+	verify_exit: Option<std::path::PathBuf>,
+
+	#[arg(
+		long,
+		overrides_with = "no_login",
+		help = "run the command through a login shell, so profile scripts can set up PATH (default when no command is given)"
+	)]
+	login: bool,
+
+	#[arg(long, overrides_with = "login", help = "never run the command or default shell as a login shell")]
+	no_login: bool,
+
+	#[arg(
+		long,
+		value_name = "KEY",
+		default_value = escape::DEFAULT_KEY,
+		value_parser = escape::parse_key,
+		help = "the key that starts escape sequences in the session; followed by `r` it opens a root shell, pressed twice it is sent through"
+	)]
+	escape_key: u8,
+
+	#[arg(long, help = "do not watch the session's input for escape sequences")]
+	no_escape: bool,
+
+	#[arg(
+		long,
+		help = "do not check the system for a terminfo entry for TERM and a generated locale for LANG, falling back to xterm-256color and C.UTF-8 without them"
+	)]
+	no_term_check: bool,
+
+	#[arg(
+		long,
+		help = "make Ctrl-Z in the session suspend tempsystem on the host, instead of sending it to the program in the system"
+	)]
+	host_job_control: bool,
+
+	#[arg(
+		long,
+		help = "keep going when the terminal's size cannot be read or it cannot be put in raw mode, assuming 80x24 and passing input \
+		        line by line; on by default when stdin or stdout is not a terminal"
+	)]
+	degraded_tty: bool,
+
+	#[arg(
+		long,
+		help = "when setup fails, write a bundle of the options, timings, exec output, daemon info, and system state, \
+		        with secrets redacted, under the state directory for attaching to a bug report; nothing is uploaded"
+	)]
+	bug_report: bool,
+
+	#[arg(
+		long,
+		help = "when setup or the command fails, capture the system's environment, working directory listing, and `pacman -Q` into \
+		        a bug report bundle, for `tempsystem diff-captures` to compare with another run"
+	)]
+	capture_env_on_failure: bool,
+
+	#[arg(
+		long,
+		help = "name the system, which is also its hostname, instead of generating a name like `tempsystem-brave-otter`"
+	)]
+	name: Option<String>,
+
+	#[arg(
+		long,
+		value_name = "N",
+		help = "make tempsystem's random choices, like the generated name and hostname, and --autosnapshot tags, the same on every \
+		        run with the same N, for demos and tests"
+	)]
+	seed: Option<u64>,
+
+	#[arg(long, overrides_with = "no_tty", help = "run the session in a tty even when stdin or stdout is not a terminal")]
+	tty: bool,
+
+	#[arg(long, overrides_with = "tty", help = "run the session without a tty even when stdin and stdout are terminals")]
+	no_tty: bool,
+
+	#[arg(
+		long,
+		overrides_with = "no_suggest",
+		help = "when the command does not exist, suggest similar commands and the package providing it (default when interactive)"
+	)]
+	suggest: bool,
+
+	#[arg(long, overrides_with = "suggest", help = "fail right away when the command does not exist")]
+	no_suggest: bool,
+
+	#[arg(
+		long,
+		value_name = "GLOB",
+		help = "copy host environment variables matching GLOB into the system, e.g. 'CARGO_*'; can be repeated"
+	)]
+	inherit_env: Vec<String>,
+
+	#[arg(
+		long,
+		help = "allow --inherit-env and --env-passthrough to copy variables that look like secrets (*_TOKEN, *_SECRET, *PASSWORD*)"
+	)]
+	inherit_env_unsafe: bool,
+
+	#[arg(
+		short = 'e',
+		long = "env",
+		value_name = "KEY[=VALUE]",
+		value_parser = env::parse_env,
+		help = "set a variable in the system and every command run in it, or with KEY alone, copy it from the host; can be repeated"
+	)]
+	env: Vec<(String, Option<String>)>,
+
+	#[arg(
+		long,
+		value_name = "PATH",
+		help = "set the variables in a dotenv file, like --env does; --env wins over it, and later files over earlier ones; can be repeated"
+	)]
+	env_file: Vec<std::path::PathBuf>,
+
+	#[arg(
+		long,
+		value_name = "NAMES",
+		value_delimiter = ',',
+		help = "copy the host variables with these comma separated names into the system, globs like 'GIT_*' included; \
+		        ones not set here are skipped"
+	)]
+	env_passthrough: Vec<String>,
+
+	#[arg(long, help = "do not copy TERM, COLORTERM, and LANG from the host")]
+	no_default_env: bool,
+
+	#[arg(
+		long,
+		help = "do not copy http_proxy, https_proxy, no_proxy, and the like from the host; \
+		        otherwise a proxy on localhost is reached through host.docker.internal"
+	)]
+	no_proxy_passthrough: bool,
+
+	#[arg(
+		trailing_var_arg = true,
+		value_name = "COMMAND",
+		help = "command to execute in container, then exit, instead of an interactive shell; `-` runs the script read from stdin. \
+		        Everything from the command on, or after `--`, is passed to it untouched; a single argument before no `--` is run as a \
+		        shell command line"
+	)]
+	command: Vec<String>,
+
+	/// whether the command came after `--`, so even a single argument is a program rather than a command line
+	#[arg(skip)]
+	command_after_separator: bool,
+
+	/// the script read from stdin when the command is `-`
+	#[arg(skip)]
+	script: Option<String>,
+
+	#[cfg(feature = "generators")]
+	#[arg(long = "generate-man")]
+	generate_man: String,
+
+	#[cfg(feature = "generators")]
+	#[arg(value_enum, long = "generate-shell")]
+	generate_shell: clap_complete::Shell,
+}
+
+mod alias;
+mod backend;
+mod bugreport;
+mod build;
+mod capability;
+mod capture;
+mod ci;
+mod classify;
+mod cleanup;
+mod color;
+mod compat;
+mod config;
+mod docker;
+mod engine;
+mod env;
+mod error;
+mod escape;
+mod events;
+mod features;
+mod firewall;
+mod guard;
+mod helper;
+mod imageref;
+mod limits;
+mod names;
+mod pacman;
+mod paused;
+mod policy;
+mod progress;
+mod proxy;
+mod redact;
+mod registry;
+mod remember;
+mod rng;
+mod session;
+mod setup;
+mod share;
+mod size;
+mod snapshot;
+mod statefile;
+mod stats;
+mod streams;
+mod suggest;
+mod template;
+mod termcheck;
+mod timing;
+mod toolchain;
+mod utf8;
+use std::{
+	ffi::OsString,
+	io::{IsTerminal, Read, Write},
+	sync::{
+		Arc,
+		atomic::{AtomicI32, Ordering},
+	},
+};
+
+use backend::{BackendKind, CliBackend, ContainerBackend};
+use docker::*;
+use tokio::signal::unix::{Signal, SignalKind, signal};
+
+#[macro_export]
+macro_rules! print_error {
+	($err:expr) => {{
+		println!("{}", $crate::color::red(($err).to_string()));
+	}};
+	($msg:expr, $err:expr) => {
+		println!("{}", $crate::color::red(format!("{}: {}", ($msg), ($err).to_string())));
+	};
+}
+
+fn report_error(e: &Error, json: bool) {
+	if json {
+		let event = events::Event::Error {
+			code: e.code(),
+			message: e.to_string(),
+		}
+		.redacted();
+		println!("{}", serde_json::to_string(&event).unwrap_or_default());
+	} else {
+		print_error!(redact::redact(&e.to_string()));
+	}
+}
+
+/// Writes `--ci-report` when it was asked for; failing to only gets reported, the exit code stays the session's.
+fn write_ci_report(args: &Args, report: ci::Report) {
+	if let Some(path) = &args.ci_report
+		&& let Err(e) = ci::write_report(path, &report)
+	{
+		report_error(&e, args.json);
+	}
+}
+
+fn print_placeholders(json: bool) {
+	if json {
+		let placeholders: Vec<serde_json::Value> = template::PLACEHOLDERS
+			.iter()
+			.map(|(name, description)| serde_json::json!({ "name": name, "description": description }))
+			.collect();
+		println!("{}", serde_json::Value::Array(placeholders));
+		return;
+	}
+
+	for (name, description) in template::PLACEHOLDERS {
+		println!("{:10}  {description}", format!("{{{name}}}"));
+	}
+	println!("\nvalues are quoted for the shell; write {{{{ and }}}} for literal braces");
+}
+
+fn print_error_catalog(json: bool) {
+	if json {
+		let catalog: Vec<serde_json::Value> = Error::CATALOG
+			.iter()
+			.map(|(code, message)| serde_json::json!({ "code": code, "message": message }))
+			.collect();
+		println!("{}", serde_json::Value::Array(catalog));
+		return;
+	}
+
+	let width = Error::CATALOG
+		.iter()
+		.map(|(code, _)| code.len())
+		.max()
+		.unwrap_or(0);
+	for (code, message) in Error::CATALOG {
+		println!("{code:width$}  {}", message.replace('\n', " "));
+	}
+}
+
+fn emit_containerfile(args: &Args, config: &config::Config, path: &std::path::Path) -> Result<(), Error> {
+	let options = session::SessionOptions::from_args(args, config)?;
+	let recipe = build::Recipe::from_args(args, &options.image)?;
+	let patterns: Vec<String> = config
+		.inherit_env
+		.iter()
+		.chain(&args.inherit_env)
+		.cloned()
+		.collect();
+	let (mut inherited, refused) = env::inherit_from_host(&patterns, args.inherit_env_unsafe);
+	for name in refused {
+		print_error!(format!("not emitting `{name}` since it looks like a secret; pass --inherit-env-unsafe to allow it"));
+	}
+	for (name, value) in env::explicit(&args.env_file, &args.env)?.vars {
+		if env::looks_secret(&name) && !args.inherit_env_unsafe {
+			print_error!(format!("not emitting --env `{name}` since it looks like a secret; pass --inherit-env-unsafe to allow it"));
+		} else {
+			inherited.retain(|(n, _)| *n != name);
+			inherited.push((name, value));
+		}
+	}
+	std::fs::write(path, build::emit_containerfile(args, &options, recipe.as_ref(), &inherited))
+		.map_err(|e| Error::ContainerfileWrite(path.display().to_string(), e))?;
+	println!("wrote {}", path.display());
+
+	return Ok(());
+}
+
+async fn cache(context: &Context, action: &CacheAction) -> Result<(), Error> {
+	let images = context.derived_images().await?;
+	match action {
+		CacheAction::List => {
+			for (tag, size) in images {
+				println!("{tag}  {:.1} MiB", size as f64 / 1024.0 / 1024.0);
+			}
+		}
+		CacheAction::Clear => {
+			for (tag, _) in images {
+				context.remove_image(&tag).await?;
+				println!("removed {tag}");
+			}
+		}
+	}
+
+	return Ok(());
+}
+
+/// Turns `args` into those of `tempsystem inspect-image`: a read-only system of `image` without network, mounts, or
+/// anything to set up, entered with a shell and deleted on exit whatever the config or remembered flags say.
+fn inspect_image_args(args: &mut Args, config: &mut config::Config, image: Option<String>) {
+	if image.is_some() {
+		args.image = image;
+		args.flavor = None;
+		args.tag = None;
+	}
+	args.ro_root = true;
+	args.no_network = Some(true);
+	args.disable_cwd_mount = true;
+	args.copy_cwd = false;
+	args.ro_cwd_overlay = false;
+	args.allow_host.clear();
+	args.sync_zsh_history = ZshHistorySync::None;
+	args.update_system = None;
+	args.update_pkgfile = false;
+	args.chaotic_aur = false;
+	args.landware = false;
+	args.extra_packages = None;
+	args.extra_aur_packages = None;
+	config.packages.clear();
+	config.aur_packages.clear();
+	if let Some((_, project)) = &mut config.project {
+		project.packages.clear();
+		project.aur_packages.clear();
+	}
+	args.build.clear();
+	args.containerfile = None;
+	args.keep = false;
+	args.keep_on_error = false;
+	args.keep_on_failure = false;
+	args.reuse = false;
+	args.reuse_reset = false;
+	args.resume = None;
+	args.autosnapshot = None;
+	args.verify_exit = None;
+	args.command.clear();
+	args.script = None;
+}
+
+/// Writes the digest the project's image tag points at now to its tempsystem.toml, one in the current directory when
+/// there is no project yet.
+async fn pin_image(context: &Context, args: &Args, config: &config::Config, update: bool) -> Result<(), Error> {
+	if let Some((path, digest)) = session::pin(config)
+		&& !update
+	{
+		return Err(Error::PinExists(path.display().to_string(), digest.into()));
+	}
+	let (_, image) = session::unpinned_image(args, config)?;
+	let image = match &args.tag {
+		Some(tag) => session::with_tag(&image, tag),
+		None => image,
+	};
+	let digest = context
+		.current_digest(&image)
+		.await
+		.ok_or_else(|| Error::DigestUnknown(image.clone()))?;
+	let path = match &config.project {
+		Some((path, _)) => path.clone(),
+		None => std::env::current_dir()
+			.map_err(Error::GetCWD)?
+			.join(config::PROJECT_FILE),
+	};
+	if session::pin(config).is_some_and(|(_, pinned)| pinned == digest) {
+		println!("{} already pins {image} to {digest}", path.display());
+		return Ok(());
+	}
+	config::write_image_digest(&path, &digest)?;
+	println!("pinned {image} to {digest} in {}", path.display());
+
+	return Ok(());
+}
+
+async fn prune(context: &Context, images: bool, running: bool, paused_older_than: Option<std::time::Duration>, dry_run: bool) -> Result<(), Error> {
+	let (leftovers, skipped) = context
+		.leftovers(running, paused_older_than, images)
+		.await?;
+	let mut reclaimed = 0;
+	let mut failed = None;
+	for leftover in &leftovers {
+		if dry_run {
+			println!("would remove {leftover}, {}", indicatif::HumanBytes(leftover.size()));
+			reclaimed += leftover.size();
+			continue;
+		}
+		match context.remove_leftover(leftover).await {
+			Ok(()) => {
+				println!("removed {leftover}, {}", indicatif::HumanBytes(leftover.size()));
+				reclaimed += leftover.size();
+			}
+			Err(e) => {
+				print_error!(format!("could not remove {leftover}"), e);
+				failed = Some(e);
+			}
+		}
+	}
+	if !skipped.is_empty() {
+		println!(
+			"kept {} running or paused system(s): {}; pass --running, or --paused-older-than for paused ones, to delete them too",
+			skipped.len(),
+			skipped.join(", ")
+		);
+	}
+	if leftovers.is_empty() {
+		println!("nothing to remove");
+	} else {
+		println!("{} {}", if dry_run { "would reclaim" } else { "reclaimed" }, indicatif::HumanBytes(reclaimed));
+	}
+
+	return failed.map_or(Ok(()), Err);
+}
+
+async fn pull(context: &Context, args: &Args, config: &config::Config) -> Result<(), Error> {
+	let image = session::SessionOptions::from_args(args, config)?.image;
+	let (id, size) = context.pull_only(&image).await?;
+	context.events().hand_off_terminal().await;
+	if args.json {
+		println!("{}", serde_json::json!({ "image": image, "id": id, "size": size }));
+	} else {
+		println!("pulled {image}: {id}, {}", indicatif::HumanBytes(size));
+	}
+
+	return Ok(());
+}
+
+async fn check_image(context: &mut Context, image: &str, policy: PullPolicy, json: bool) -> Result<(), Error> {
+	let features = context.check_image(image, policy).await?;
+	context.events().hand_off_terminal().await;
+	let rows = compat::matrix(&features);
+	let compatible = compat::compatible(&rows);
+	if json {
+		let report = compat::Report {
+			image,
+			compatible,
+			features: &rows,
+		};
+		println!("{}", serde_json::to_string(&report).unwrap_or_default());
+	} else {
+		println!("{}", compat::describe(&rows));
+	}
+	if !compatible {
+		return Err(Error::ImageIncompatible(image.into()));
+	}
+
+	return Ok(());
+}
+
+async fn list(context: &Context) -> Result<(), Error> {
+	let systems = context.systems().await?;
+	if systems.is_empty() {
+		println!("no systems");
+	}
+	for system in systems {
+		if system.paused() {
+			println!("{}", color::yellow(system.to_string()));
+		} else {
+			println!("{system}");
+		}
+	}
+
+	return Ok(());
+}
+
+async fn kill(context: &mut Context, target: &str, graceful: bool, force: bool) -> Result<(), Error> {
+	let (id, name) = context.resolve_container(target, force).await?;
+	context.kill_container(&id, graceful).await?;
+	println!("deleted system `{name}` ({})", &id[..id.len().min(12)]);
+
+	return Ok(());
+}
+
+/// Checks that `flags` parse as flags alone before remembering them for the current directory.
+fn remember_flags(flags: Vec<String>) -> Result<(), Error> {
+	let parsed = Args::try_parse_from(std::iter::once("tempsystem".to_string()).chain(flags.iter().cloned())).map_err(|e| {
+		let message = e.to_string();
+		Error::RememberInvalid(
+			message
+				.lines()
+				.next()
+				.unwrap_or_default()
+				.trim_start_matches("error: ")
+				.into(),
+		)
+	})?;
+	if parsed.subcommand.is_some() || !docker::is_default_command(&parsed) {
+		return Err(Error::RememberInvalid("only flags can be remembered, not a command".into()));
+	}
+	let dir = remember::current_dir()?;
+	remember::remember(&dir, flags.clone())?;
+	println!("remembered {} for {}", flags.join(" "), dir.display());
+
+	return Ok(());
+}
+
+/// Shows what the --from-share system is like, and unless --trust-share was given, asks before running one that does
+/// something risky.
+fn confirm_share(args: &Args, config: &config::Config) -> Result<(), Error> {
+	let options = session::SessionOptions::from_args(args, config)?;
+	let dangers = share::dangers(&options);
+	if !args.quiet || !dangers.is_empty() {
+		println!("the shared system:\n{}", share::describe(&options));
+	}
+	if dangers.is_empty() || args.trust_share {
+		return Ok(());
+	}
+	for danger in &dangers {
+		eprintln!("{}", color::yellow(format!("note: {danger}")));
+	}
+	if args.ci || !std::io::stdin().is_terminal() || !std::io::stdout().is_terminal() {
+		return Err(Error::ShareUntrusted);
+	}
+	print!("run it? [y/N] ");
+	let _ = std::io::stdout().flush();
+	let mut answer = String::new();
+	if std::io::stdin().read_line(&mut answer).is_err() || !answer.trim().eq_ignore_ascii_case("y") {
+		return Err(Error::ShareDeclined);
+	}
+
+	return Ok(());
+}
+
+/// Reads the script `-` stands for, all of stdin, before anything else could read from it.
+fn read_script() -> Result<String, Error> {
+	let mut script = String::new();
+	std::io::stdin()
+		.read_to_string(&mut script)
+		.map_err(Error::ScriptRead)?;
+	if script.trim().is_empty() {
+		return Err(Error::EmptyScript);
+	}
+	return Ok(script);
+}
+
+/// Reads the --registry-password-stdin password, all of stdin without the final newline, like `docker login` does.
+fn read_password() -> Result<String, Error> {
+	let mut password = String::new();
+	std::io::stdin()
+		.read_to_string(&mut password)
+		.map_err(Error::PasswordRead)?;
+	let password = password.trim_end_matches(['\n', '\r']);
+	if password.is_empty() {
+		return Err(Error::EmptyPassword);
+	}
+	return Ok(password.into());
+}
+
+/// Connects to an API socket, or picks the CLI backend when asked to or when `auto` cannot reach any socket.
+async fn connect(context: &mut Context, args: &Args) -> Result<Option<CliBackend>, Error> {
+	// like the docker CLI, $DOCKER_HOST wins over the current context, but not over one picked with --context
+	let docker_context = if args.host.is_none() && (args.context.is_some() || std::env::var_os("DOCKER_HOST").is_none()) {
+		engine::docker_context(args.context.as_deref())?
+	} else {
+		None
+	};
+	let kind = args.backend;
+	let host = args
+		.host
+		.as_deref()
+		.or(docker_context.as_ref().map(|c| c.host.as_str()));
+	let tls = engine::TlsFiles::resolve(args.tls_ca.as_deref(), args.tls_cert.as_deref(), args.tls_key.as_deref())
+		.or(docker_context.as_ref().and_then(|c| c.tls.clone()));
+	if args.verbose
+		&& let Some(context) = &docker_context
+	{
+		println!("using docker context `{}`", context.name);
+	}
+	let find_cli = || CliBackend::find().map(|cli| cli.with_host(host));
+	if kind == BackendKind::Cli {
+		return find_cli().map(Some).ok_or(Error::CliNotFound);
+	}
+	if let Err(e) = context.connect(kind, host, tls.as_ref()).await {
+		if kind == BackendKind::Auto {
+			return find_cli().map(Some).ok_or(e);
+		}
+		return Err(e);
+	}
+
+	return Ok(None);
+}
+
+/// SIGINT, SIGTERM, and SIGHUP, all of which should tear the system down.
+struct TerminatingSignals {
+	interrupt: Signal,
+	terminate: Signal,
+	hangup: Signal,
+}
+
+impl TerminatingSignals {
+	fn new() -> std::io::Result<Self> {
+		return Ok(Self {
+			interrupt: signal(SignalKind::interrupt())?,
+			terminate: signal(SignalKind::terminate())?,
+			hangup: signal(SignalKind::hangup())?,
+		});
+	}
+
+	/// Resolves with the number of the next signal received.
+	async fn recv(&mut self) -> i32 {
+		let kind = tokio::select! {
+			_ = self.interrupt.recv() => SignalKind::interrupt(),
+			_ = self.terminate.recv() => SignalKind::terminate(),
+			_ = self.hangup.recv() => SignalKind::hangup(),
+		};
+
+		return kind.as_raw_value();
+	}
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum SignalState {
+	/// no signal received yet
+	Running,
+
+	/// one signal received, the system is being deleted
+	CleaningUp,
+
+	/// another signal arrived during clean up, exit without waiting for it
+	ForceQuit,
+}
+
+/// What to do about a signal, decided by `SignalState::receive`.
+#[derive(Debug, PartialEq)]
+enum SignalAction {
+	/// cancel the session, which deletes the system
+	CleanUp,
+
+	/// exit with this code now, without waiting for the clean up
+	ForceQuit(i32),
+}
+
+impl SignalState {
+	/// The state after `signal` arrives, and what to do about it.
+	fn receive(self, signal: i32) -> (Self, SignalAction) {
+		return match self {
+			Self::Running => (Self::CleaningUp, SignalAction::CleanUp),
+			Self::CleaningUp | Self::ForceQuit => (Self::ForceQuit, SignalAction::ForceQuit(128 + signal)),
+		};
+	}
+}
+
+#[tokio::main]
+async fn main() -> std::process::ExitCode {
+	// remembered flags go first, so the command line overrides them
+	let remembered = remember::current_dir()
+		.map(|dir| remember::flags_for(&dir))
+		.unwrap_or_default();
+	let mut args = Args::parse_argv(remember::apply(std::env::args_os().collect(), &remembered)).unwrap_or_else(|e| e.exit());
+	if let Some(Commands::Exec { command }) = args
+		.subcommand
+		.take_if(|c| matches!(c, Commands::Exec { .. }))
+	{
+		args.command = command;
+		args.reuse = true;
+	}
+	if args.ci {
+		ci::apply_preset(&mut args);
+	}
+	color::init(args.color.unwrap_or(color::ColorWhen::Auto));
+
+	#[cfg(feature = "generators")]
+	{
+		use clap::CommandFactory;
+		let man = clap_mangen::Man::new(Args::command());
+		let mut buffer: Vec<u8> = Default::default();
+		man.render(&mut buffer).unwrap();
+		std::fs::write(args.generate_man, buffer).unwrap();
+
+		#[allow(unused)]
+		use clap_complete::{Generator, Shell, generate};
+		clap_complete::aot::generate(args.generate_shell, &mut Args::command(), Args::command().get_name().to_string(), &mut std::io::stdout());
+
+		return 0.into();
+	}
+
+	match args.subcommand {
+		Some(Commands::Errors) => {
+			print_error_catalog(args.json);
+			return 0.into();
+		}
+		Some(Commands::Config {
+			action: ConfigAction::Validate { path },
+		}) => {
+			let path = path.or_else(|| config::config_dir().map(|d| d.join("config.toml")));
+			return match path.as_deref().map(config::validate) {
+				Some(Ok(())) => {
+					println!("{} is valid", path.unwrap_or_default().display());
+					0.into()
+				}
+				Some(Err(e)) => {
+					report_error(&e, args.json);
+					1.into()
+				}
+				None => {
+					report_error(&Error::HomeDir, args.json);
+					1.into()
+				}
+			};
+		}
+		Some(Commands::Placeholders) => {
+			print_placeholders(args.json);
+			return 0.into();
+		}
+		Some(Commands::GenerateAlias { shell }) => {
+			print!("{}", alias::generate(shell));
+			return 0.into();
+		}
+		Some(Commands::DirenvHook { commands }) => {
+			print!("{}", alias::direnv_hook(&commands));
+			return 0.into();
+		}
+		Some(Commands::Remember { flags }) => {
+			return match remember_flags(flags) {
+				Ok(()) => 0.into(),
+				Err(e) => {
+					report_error(&e, args.json);
+					1.into()
+				}
+			};
+		}
+		Some(Commands::DiffCaptures { a, b }) => {
+			return match capture::diff(&a, &b) {
+				Ok(diff) => {
+					print!("{diff}");
+					0.into()
+				}
 				Err(e) => {
-					print_error!(e);
+					report_error(&e, args.json);
+					1.into()
+				}
+			};
+		}
+		Some(Commands::Forget) => {
+			return match remember::current_dir().and_then(|dir| remember::forget(&dir)) {
+				Ok(Some(flags)) => {
+					println!("forgot {} for this directory", flags.join(" "));
+					0.into()
+				}
+				Ok(None) => {
+					println!("nothing is remembered for this directory");
+					0.into()
+				}
+				Err(e) => {
+					report_error(&e, args.json);
+					1.into()
+				}
+			};
+		}
+		Some(
+			Commands::Cache { .. }
+			| Commands::CheckImage { .. }
+			| Commands::Exec { .. }
+			| Commands::InspectImage { .. }
+			| Commands::Kill { .. }
+			| Commands::List
+			| Commands::Attach { .. }
+			| Commands::PinImage { .. }
+			| Commands::Prune { .. }
+			| Commands::Pull
+			| Commands::RestoreSnapshot { .. }
+			| Commands::Share,
+		)
+		| None => {}
+	}
+	if !remembered.is_empty() && !args.quiet && !args.json {
+		println!("using flags remembered for this directory: {} (`tempsystem forget` drops them)", remembered.join(" "));
+	}
+	if !args.ci && !args.quiet && !args.json && ci::detected() {
+		println!("note: this looks like a CI job; --ci sets tempsystem up for one");
+	}
+
+	let mut config = match config::Config::load() {
+		Ok(config) => config,
+		Err(e) => {
+			report_error(&e, args.json);
+			return 1.into();
+		}
+	};
+	redact::init(&config.secret_patterns);
+	if let Some(warning) = session::overridden_pin(&args, &config) {
+		eprintln!("{}", color::yellow(warning));
+	}
+
+	if let Some(path) = &args.emit_containerfile {
+		return match emit_containerfile(&args, &config, path) {
+			Ok(()) => 0.into(),
+			Err(e) => {
+				report_error(&e, args.json);
+				1.into()
+			}
+		};
+	}
+
+	if let Some(Commands::Share) = args.subcommand {
+		return match session::SessionOptions::from_args(&args, &config).and_then(|options| share::encode(&options)) {
+			Ok(share) => {
+				println!("{share}");
+				0.into()
+			}
+			Err(e) => {
+				report_error(&e, args.json);
+				1.into()
+			}
+		};
+	}
+
+	if args.dry_run {
+		match session::SessionOptions::from_args(&args, &config) {
+			Ok(options) => {
+				for note in &options.notes {
+					eprintln!("{}", color::yellow(note));
+				}
+				println!("{}", options.describe_packages());
+				return 0.into();
+			}
+			Err(e) => {
+				report_error(&e, args.json);
+				return 1.into();
+			}
+		}
+	}
+
+	if args.print_effective_args {
+		for arg in alias::effective_args(&config) {
+			println!("{arg}");
+		}
+		return 0.into();
+	}
+
+	if let Some(Commands::InspectImage { image }) = args
+		.subcommand
+		.take_if(|c| matches!(c, Commands::InspectImage { .. }))
+	{
+		inspect_image_args(&mut args, &mut config, image);
+	}
+
+	if args.from_share.is_some()
+		&& args.subcommand.is_none()
+		&& let Err(e) = confirm_share(&args, &config)
+	{
+		report_error(&e, args.json);
+		return 1.into();
+	}
+
+	if args.command == ["-"] && args.registry_password_stdin {
+		report_error(&Error::StdinTaken, args.json);
+		return 1.into();
+	}
+	let registry_login = match args.registry_user.clone() {
+		Some(user) if args.registry_password_stdin => match read_password() {
+			Ok(password) => Some(registry::Login { user, password }),
+			Err(e) => {
+				report_error(&e, args.json);
+				return 1.into();
+			}
+		},
+		_ => None,
+	};
+
+	if args.command == ["-"] {
+		match read_script() {
+			Ok(script) => args.script = Some(script),
+			Err(e) => {
+				report_error(&e, args.json);
+				return 1.into();
+			}
+		}
+	}
+
+	let started = std::time::Instant::now();
+	let mut outcome = ci::Outcome::Completed;
+	let mut failure: Option<ci::ReportedError> = None;
+	let mut context = Context::default();
+	context.set_registry_login(registry_login);
+	context.set_platform(args.platform.clone());
+	let token = context.token();
+	let token_clone = token.clone();
+	let received_signal = Arc::new(AtomicI32::new(0));
+	let received_signal_clone = received_signal.clone();
+	let shutdown_token = context.shutdown_token();
+	let live_container = context.live_container();
+	let events = context.subscribe();
+	context
+		.tasks()
+		.spawn(progress::render(events, args.json, args.progress.unwrap_or_default(), args.verbose, context.shutdown_token()));
+	context.tasks().spawn(async move {
+		let mut signals = match TerminatingSignals::new() {
+			Ok(signals) => signals,
+			Err(e) => {
+				print_error!("could not listen for signals", e);
+				return;
+			}
+		};
+		let mut state = SignalState::Running;
+		loop {
+			let signal = tokio::select! {
+				_ = shutdown_token.cancelled() => break,
+				signal = signals.recv() => signal,
+			};
+			let action;
+			(state, action) = state.receive(signal);
+			match action {
+				SignalAction::CleanUp => {
+					received_signal_clone.store(signal, Ordering::SeqCst);
+					token_clone.cancel();
+					println!("cleaning up, press Ctrl-C again to force quit");
+				}
+				SignalAction::ForceQuit(code) => {
+					if let Some(id) = live_container.lock().unwrap().as_ref() {
+						print_error!(format!("force quit; system `{id}` was left behind, remove it with `docker rm -f {id}`"));
+					}
+					std::process::exit(code);
+				}
+			}
+		}
+	});
+
+	let cli = match connect(&mut context, &args).await {
+		Ok(cli) => cli,
+		Err(e) => {
+			report_error(&e, args.json);
+			context.shutdown().await;
+			return 1.into();
+		}
+	};
+	cleanup::install_panic_hook(context.live_container(), cli.as_ref().map(CliBackend::program), context.endpoint().cloned(), args.keep_on_error);
+	// progress output is cleared before an error is printed under it
+	let events = context.events();
+	if args.verbose
+		&& let Some(endpoint) = context.endpoint()
+	{
+		println!("using {} at {endpoint}", context.engine());
+	}
+	if let Some(cli) = cli {
+		if args.verbose {
+			println!("using `{}` instead of an API socket", cli.program());
+		}
+		let code = if let Some(subcommand) = &args.subcommand {
+			report_error(&Error::BackendUnsupported(subcommand.name(), "cli"), args.json);
+			1
+		} else {
+			let live_container = context.live_container();
+			tokio::select! {
+				_ = token.cancelled() => {
+					events.hand_off_terminal().await;
+					outcome = ci::Outcome::Cancelled;
+					let id = live_container.lock().unwrap().take();
+					if let Some(id) = id
+						&& let Err(e) = cli.remove(&id).await
+					{
+						print_error!("could not delete system after cancel", e);
+					}
+					128 + received_signal.load(Ordering::SeqCst) as u8
+				}
+				ret = backend::enter(&cli, &args, &config, &events, live_container.clone()) => {
+					match ret {
+						Err(e) => {
+							events.hand_off_terminal().await;
+							report_error(&e, args.json);
+							print_error!("note: running with --verbose can help in determining error cause");
+							let id = live_container.lock().unwrap().take();
+							if let Some(id) = id
+								&& let Err(e) = cli.remove(&id).await
+							{
+								print_error!("could not delete system after error", e);
+							}
+							outcome = ci::Outcome::SetupFailed;
+							failure = Some((&e).into());
+							ci::setup_failed_exit_code(&e, args.ci)
+						}
+						Ok(code) => code as u8,
+					}
+				}
+			}
+		};
+		context.shutdown().await;
+		write_ci_report(
+			&args,
+			ci::Report {
+				exit_code: code,
+				outcome,
+				error: failure,
+				system: None,
+				duration_secs: started.elapsed().as_secs_f64(),
+			},
+		);
+		return code.into();
+	}
+
+	if let Some(Commands::RestoreSnapshot { system }) = args
+		.subcommand
+		.take_if(|c| matches!(c, Commands::RestoreSnapshot { .. }))
+	{
+		match context.latest_snapshot(system.as_deref()).await {
+			Ok(image) => {
+				args.image = Some(image);
+				args.flavor = None;
+				args.tag = None;
+				args.pull = PullPolicy::Never;
+			}
+			Err(e) => {
+				report_error(&e, args.json);
+				context.shutdown().await;
+				return 1.into();
+			}
+		}
+	}
+
+	if let Some(Commands::Cache { action }) = &args.subcommand {
+		let code = match cache(&context, action).await {
+			Ok(()) => 0,
+			Err(e) => {
+				report_error(&e, args.json);
+				1
+			}
+		};
+		context.shutdown().await;
+		return code.into();
+	}
+
+	if let Some(Commands::CheckImage { image }) = &args.subcommand {
+		let code = match check_image(&mut context, image, args.pull, args.json).await {
+			Ok(()) => 0,
+			Err(e) => {
+				events.hand_off_terminal().await;
+				report_error(&e, args.json);
+				1
+			}
+		};
+		context.shutdown().await;
+		return code.into();
+	}
+
+	if let Some(Commands::PinImage { update }) = &args.subcommand {
+		let code = match pin_image(&context, &args, &config, *update).await {
+			Ok(()) => 0,
+			Err(e) => {
+				report_error(&e, args.json);
+				1
+			}
+		};
+		context.shutdown().await;
+		return code.into();
+	}
+
+	if let Some(Commands::Prune {
+		images,
+		running,
+		paused_older_than,
+		dry_run,
+	}) = &args.subcommand
+	{
+		let code = match prune(&context, *images, *running, *paused_older_than, *dry_run).await {
+			Ok(()) => 0,
+			Err(e) => {
+				report_error(&e, args.json);
+				1
+			}
+		};
+		context.shutdown().await;
+		return code.into();
+	}
+
+	if let Some(Commands::Pull) = &args.subcommand {
+		let code = match pull(&context, &args, &config).await {
+			Ok(()) => 0,
+			Err(e) => {
+				events.hand_off_terminal().await;
+				report_error(&e, args.json);
+				1
+			}
+		};
+		context.shutdown().await;
+		return code.into();
+	}
+
+	if let Some(Commands::Kill { target, graceful, force }) = &args.subcommand {
+		let code = match kill(&mut context, target, *graceful, *force).await {
+			Ok(()) => 0,
+			Err(e) => {
+				report_error(&e, args.json);
+				1
+			}
+		};
+		context.shutdown().await;
+		return code.into();
+	}
+
+	if let Some(Commands::List) = &args.subcommand {
+		let code = match list(&context).await {
+			Ok(()) => 0,
+			Err(e) => {
+				report_error(&e, args.json);
+				1
+			}
+		};
+		context.shutdown().await;
+		return code.into();
+	}
+
+	if let Some(Commands::Attach { system }) = &args.subcommand {
+		// the system was there before and stays, whatever ends the session
+		let code = tokio::select! {
+			_ = token.cancelled() => {
+				events.hand_off_terminal().await;
+				128 + received_signal.load(Ordering::SeqCst) as u8
+			}
+			ret = context.attach(system, &args, &config) => match ret {
+				Ok(code) => code as u8,
+				Err(e) => {
+					events.hand_off_terminal().await;
+					report_error(&e, args.json);
+					1
+				}
+			}
+		};
+		context.shutdown().await;
+		return code.into();
+	}
+
+	let code = tokio::select! {
+		_ = token.cancelled() => {
+			events.hand_off_terminal().await;
+			outcome = ci::Outcome::Cancelled;
+			let hung_up = received_signal.load(Ordering::SeqCst) == SignalKind::hangup().as_raw_value();
+			let paused = if args.pause_on_detach && hung_up {
+				match context.pause_detached().await {
+					Ok(()) => {
+						// the terminal is gone, but a log may still catch this
+						eprintln!(
+							"paused system `{}`; continue it with `tempsystem attach {}`",
+							context.display_name(),
+							context.display_name()
+						);
+						true
+					}
+					Err(e) => {
+						print_error!("could not pause the system after the terminal hung up, deleting it", e);
+						false
+					}
+				}
+			} else {
+				false
+			};
+			if !paused
+				&& !context.reused()
+				&& let Err(e) = context.delete_container().await
+			{
+				print_error!("could not delete system after cancel", e);
+			}
+			128 + received_signal.load(Ordering::SeqCst) as u8
+		}
+		ret = context.perform_all_enter(&args, &config) => {
+			match ret {
+				Err(e) => {
+					events.hand_off_terminal().await;
+					report_error(&e, args.json);
 					print_error!("note: running with --verbose can help in determining error cause");
-					if let Err(e) = context.delete_container().await {
+					let captured = if args.capture_env_on_failure && capture::exec_possible(&e) {
+						context.capture_env().await
+					} else {
+						None
+					};
+					if args.bug_report || captured.is_some() {
+						let failure = serde_json::to_string_pretty(&ci::ReportedError::from(&e)).unwrap_or_default();
+						match context.bug_report(&args, &config, failure, captured).await {
+							Ok(path) => print_error!(format!("note: wrote a bug report bundle to {}", path.display())),
+							Err(e) => report_error(&e, args.json),
+						}
+					}
+					if docker::keep_system(None, args.keep, args.keep_on_failure, args.keep_on_error) && !context.container_id().is_empty() {
+						print_error!(format!(
+							"note: kept system `{}`; continue with `tempsystem --resume {}` and the same options",
+							context.display_name(),
+							context.container_id()
+						));
+					} else if !context.reused()
+						&& let Err(e) = context.delete_container().await
+					{
 						print_error!("could not delete system after error", e);
 					}
+					outcome = ci::Outcome::SetupFailed;
+					failure = Some((&e).into());
+					ci::setup_failed_exit_code(&e, args.ci)
 				}
-				Ok(code) => {
-					return (code as u8).into();
-				}
+				Ok(code) => code as u8,
 			}
 		}
+	};
+
+	let system = (!context.container_id().is_empty()).then(|| context.display_name().to_string());
+	context.shutdown().await;
+	write_ci_report(
+		&args,
+		ci::Report {
+			exit_code: code,
+			outcome,
+			error: failure,
+			system,
+			duration_secs: started.elapsed().as_secs_f64(),
+		},
+	);
+	return code.into();
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn passes_the_command_flags_through() {
+		let args = Args::from_flags(&["ls", "-la", "--color=always"]);
+		assert_eq!(args.command, ["ls", "-la", "--color=always"]);
+		// `--keep` and `--verbose` belong to the command once it started
+		let args = Args::from_flags(&["--keep", "cargo", "test", "--verbose", "--keep"]);
+		assert!(args.keep && !args.verbose);
+		assert_eq!(args.command, ["cargo", "test", "--verbose", "--keep"]);
 	}
 
-	return 0.into();
+	#[test]
+	fn passes_everything_after_double_dash_through() {
+		let args = Args::from_flags(&["--keep", "--", "--verbose", "-p", "git"]);
+		assert!(args.keep && !args.verbose && args.extra_packages.is_none());
+		assert_eq!(args.command, ["--verbose", "-p", "git"]);
+		let args = Args::from_flags(&["-p", "git", "--", "ls", "--", "-la"]);
+		assert_eq!(args.extra_packages.as_deref(), Some("git"));
+		assert_eq!(args.command, ["ls", "--", "-la"]);
+	}
+
+	#[test]
+	fn nothing_after_double_dash_is_the_shell() {
+		for argv in [&[][..], &["--"], &["--keep", "--"]] {
+			let args = Args::from_flags(argv);
+			assert!(args.command.is_empty(), "{argv:?}");
+			assert!(docker::is_default_command(&args), "{argv:?}");
+		}
+		assert!(!docker::is_default_command(&Args::from_flags(&["--", "zsh"])));
+	}
+
+	#[test]
+	fn names_subcommands_as_they_are_typed() {
+		for argv in [
+			&["cache", "list"][..],
+			&["check-image", "archlinux"],
+			&["restore-snapshot"],
+			&["pin-image"],
+			&["prune"],
+			&["pull"],
+			&["list"],
+			&["attach", "quiet-otter"],
+			&["kill", "quiet-otter"],
+		] {
+			let subcommand = Args::from_flags(argv).subcommand.unwrap();
+			assert_eq!(subcommand.name(), format!("`tempsystem {}`", argv[0]));
+		}
+	}
+
+	#[test]
+	fn quotes_the_command_for_the_shell() {
+		let shell = |argv: &[&str]| docker::session_command(&Args::from_flags(argv), "/usr/bin/zsh", &crate::env::ExecEnv::default(), None).0;
+		assert_eq!(shell(&["--", "echo", "a b", "it's", "$HOME"]), ["/usr/bin/zsh", "-c", "echo 'a b' 'it'\\''s' '$HOME'"]);
+		// a single argument is a command line of its own, unless it came after `--`
+		assert_eq!(shell(&["make | tee log"]), ["/usr/bin/zsh", "-c", "make | tee log"]);
+		assert_eq!(shell(&["--", "my script.sh"]), ["/usr/bin/zsh", "-c", "'my script.sh'"]);
+		assert_eq!(shell(&["--keep", "--", "$HOME"]), ["/usr/bin/zsh", "-c", "'$HOME'"]);
+		assert!(Args::from_flags(&["exec", "--", "my script.sh"]).command_after_separator);
+		assert!(!Args::from_flags(&["exec", "make | tee log"]).command_after_separator);
+		assert_eq!(shell(&[]), ["/usr/bin/zsh", "-l"]);
+	}
+
+	const SIGINT: i32 = 2;
+	const SIGTERM: i32 = 15;
+
+	#[test]
+	fn first_signal_cleans_up() {
+		assert_eq!(SignalState::Running.receive(SIGINT), (SignalState::CleaningUp, SignalAction::CleanUp));
+		assert_eq!(SignalState::Running.receive(SIGTERM), (SignalState::CleaningUp, SignalAction::CleanUp));
+	}
+
+	#[test]
+	fn second_signal_force_quits_with_its_code() {
+		assert_eq!(SignalState::CleaningUp.receive(SIGINT), (SignalState::ForceQuit, SignalAction::ForceQuit(130)));
+		assert_eq!(SignalState::CleaningUp.receive(SIGTERM), (SignalState::ForceQuit, SignalAction::ForceQuit(143)));
+	}
+
+	#[test]
+	fn later_signals_keep_force_quitting() {
+		let mut state = SignalState::Running;
+		let mut actions = Vec::new();
+		for _ in 0..4 {
+			let action;
+			(state, action) = state.receive(SIGINT);
+			actions.push(action);
+		}
+		assert_eq!(state, SignalState::ForceQuit);
+		assert_eq!(
+			actions,
+			[SignalAction::CleanUp, SignalAction::ForceQuit(130), SignalAction::ForceQuit(130), SignalAction::ForceQuit(130)]
+		);
+	}
 }
@@ -0,0 +1,162 @@
+use crate::{error::Error, rng::Rng};
+
+/// Every generated name starts with this, so they are easy to tell apart from other containers.
+pub const PREFIX: &str = "tempsystem";
+
+/// How many generated names are tried before a name conflict is given up on.
+pub const MAX_ATTEMPTS: usize = 5;
+
+const ADJECTIVES: &[&str] = &[
+	"brave", "calm", "clever", "cosy", "curious", "daring", "eager", "fancy", "gentle", "happy", "humble", "jolly", "keen", "lively", "lucky", "mellow",
+	"merry", "nimble", "plucky", "polite", "proud", "quick", "quiet", "rapid", "shiny", "silly", "sleepy", "snappy", "steady", "swift", "tidy", "witty",
+];
+
+const ANIMALS: &[&str] = &[
+	"badger", "beaver", "bison", "camel", "crane", "dingo", "falcon", "ferret", "gecko", "heron", "ibis", "koala", "lemur", "lynx", "marmot", "moose", "newt",
+	"ocelot", "otter", "panda", "puffin", "quokka", "raven", "seal", "sloth", "stoat", "tapir", "toucan", "walrus", "wombat", "yak", "zebra",
+];
+
+/// A name like `tempsystem-brave-otter`.
+pub fn generate(rng: &mut Rng) -> String {
+	let adjective = rng.pick(ADJECTIVES);
+	let animal = rng.pick(ANIMALS);
+	return format!("{PREFIX}-{adjective}-{animal}");
+}
+
+/// Whether `e` is the engine refusing a name another container already has.
+pub fn is_conflict(e: &Error) -> bool {
+	return match e {
+		Error::ContainerCreate(bollard::errors::Error::DockerResponseServerError { status_code, .. }) => *status_code == 409,
+		Error::CliFailed(_, _, output) => output.contains("is already in use"),
+		_ => false,
+	};
+}
+
+/// Creates a system named `requested`, or else with names from `rng` until one is free, returning `(id, name)`.
+pub async fn create_with_retry<F, Fut>(requested: Option<&str>, rng: &mut Rng, mut create: F) -> Result<(String, String), Error>
+where
+	F: FnMut(String) -> Fut,
+	Fut: Future<Output = Result<String, Error>>,
+{
+	if let Some(name) = requested {
+		return Ok((create(name.into()).await?, name.into()));
+	}
+
+	let mut attempt = 1;
+	loop {
+		let name = generate(rng);
+		match create(name.clone()).await {
+			Err(e) if is_conflict(&e) && attempt < MAX_ATTEMPTS => attempt += 1,
+			result => return result.map(|id| (id, name)),
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn the_same_seed_generates_the_same_names() {
+		let run = |seed| {
+			let mut rng = Rng::new(Some(seed));
+			return (0..8).map(|_| generate(&mut rng)).collect::<Vec<String>>();
+		};
+		assert_eq!(run(42), run(42));
+		assert_ne!(run(42), run(43));
+	}
+
+	fn conflict() -> Error {
+		return Error::ContainerCreate(bollard::errors::Error::DockerResponseServerError {
+			status_code: 409,
+			message: "Conflict. The container name is already in use".into(),
+		});
+	}
+
+	/// Runs `create_with_retry` against a fake daemon that refuses the first `taken` names, returning the result and
+	/// every name it was asked to create.
+	async fn create(requested: Option<&str>, taken: usize, refuse: fn() -> Error) -> (Result<(String, String), Error>, Vec<String>) {
+		let mut tried = vec![];
+		let result = create_with_retry(requested, &mut Rng::new(Some(7)), |name| {
+			tried.push(name.clone());
+			let result = if tried.len() <= taken { Err(refuse()) } else { Ok(format!("id-of-{name}")) };
+			return async move { result };
+		})
+		.await;
+		return (result, tried);
+	}
+
+	#[test]
+	fn generates_prefixed_names() {
+		let mut rng = Rng::new(Some(1));
+		for _ in 0..32 {
+			let name = generate(&mut rng);
+			let words: Vec<&str> = name.split('-').collect();
+			assert_eq!(words.len(), 3, "{name}");
+			assert_eq!(words[0], PREFIX);
+			assert!(ADJECTIVES.contains(&words[1]), "{name}");
+			assert!(ANIMALS.contains(&words[2]), "{name}");
+		}
+	}
+
+	#[test]
+	fn recognizes_conflicts() {
+		assert!(is_conflict(&conflict()));
+		assert!(is_conflict(&Error::CliFailed(
+			"docker create".into(),
+			125,
+			"Error response from daemon: Conflict. The container name \"/x\" is already in use".into()
+		)));
+		assert!(!is_conflict(&Error::ContainerCreate(bollard::errors::Error::DockerResponseServerError {
+			status_code: 500,
+			message: String::new(),
+		})));
+		assert!(!is_conflict(&Error::CliFailed("docker create".into(), 125, "no such image".into())));
+		assert!(!is_conflict(&Error::NotConnected));
+	}
+
+	#[tokio::test]
+	async fn uses_the_first_free_name() {
+		let (result, tried) = create(None, 0, conflict).await;
+		assert_eq!(tried.len(), 1);
+		assert_eq!(result.unwrap(), (format!("id-of-{}", tried[0]), tried[0].clone()));
+	}
+
+	#[tokio::test]
+	async fn retries_with_new_names_on_conflicts() {
+		let (result, tried) = create(None, 2, conflict).await;
+		assert_eq!(tried.len(), 3);
+		assert_eq!(result.unwrap().1, tried[2]);
+		// the same seed tries the same names
+		assert_eq!(create(None, 2, conflict).await.1, tried);
+	}
+
+	#[tokio::test]
+	async fn gives_up_after_max_attempts() {
+		let (result, tried) = create(None, MAX_ATTEMPTS, conflict).await;
+		assert_eq!(tried.len(), MAX_ATTEMPTS);
+		assert!(result.is_err_and(|e| is_conflict(&e)));
+
+		let (result, tried) = create(None, MAX_ATTEMPTS - 1, conflict).await;
+		assert_eq!(tried.len(), MAX_ATTEMPTS);
+		assert!(result.is_ok());
+	}
+
+	#[tokio::test]
+	async fn does_not_retry_other_errors() {
+		let (result, tried) = create(None, 1, || Error::NotConnected).await;
+		assert_eq!(tried.len(), 1);
+		assert!(matches!(result, Err(Error::NotConnected)));
+	}
+
+	#[tokio::test]
+	async fn never_renames_a_requested_name() {
+		let (result, tried) = create(Some("mine"), 0, conflict).await;
+		assert_eq!(tried, ["mine"]);
+		assert_eq!(result.unwrap(), ("id-of-mine".into(), "mine".into()));
+
+		let (result, tried) = create(Some("mine"), 1, conflict).await;
+		assert_eq!(tried, ["mine"]);
+		assert!(result.is_err_and(|e| is_conflict(&e)));
+	}
+}
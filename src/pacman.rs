@@ -0,0 +1,300 @@
+use std::collections::HashMap;
+
+/// Something pacman (or an AUR helper wrapping it) printed that says how far a download is.
+#[derive(Debug, Clone, PartialEq)]
+pub enum DownloadLine {
+	/// `Total Download Size:   123.45 MiB` from the transaction summary
+	TotalSize(u64),
+
+	/// A progress bar line, `name  size  [rate  eta]  [####---]  42%`.
+	/// Pacman 6 adds an overall line named like `Total ( 3/12)`, pacman 5 only prints one per package.
+	Progress { name: String, size: u64, percent: u8 },
+}
+
+fn unit_size(unit: &str) -> Option<f64> {
+	return Some(match unit {
+		"B" => 1.0,
+		"KiB" => 1024.0,
+		"MiB" => 1024.0 * 1024.0,
+		"GiB" => 1024.0 * 1024.0 * 1024.0,
+		_ => return None,
+	});
+}
+
+/// Parses sizes the way pacman prints them, like `12.5 MiB`.
+pub fn parse_size(s: &str) -> Option<u64> {
+	let (number, unit) = s.trim().split_once(char::is_whitespace)?;
+	return Some((number.parse::<f64>().ok()? * unit_size(unit.trim())?) as u64);
+}
+
+pub fn parse_line(line: &str) -> Option<DownloadLine> {
+	let line = line.trim();
+	if let Some(size) = line.strip_prefix("Total Download Size:") {
+		return Some(DownloadLine::TotalSize(parse_size(size)?));
+	}
+
+	let (info, bar) = line.split_once('[')?;
+	let percent = bar
+		.rsplit_once(']')?
+		.1
+		.trim()
+		.strip_suffix('%')?
+		.trim()
+		.parse::<u8>()
+		.ok()?;
+	let words: Vec<&str> = info.split_whitespace().collect();
+	let at = (1..words.len().saturating_sub(1)).find(|&i| words[i].parse::<f64>().is_ok() && unit_size(words[i + 1]).is_some())?;
+
+	return Some(DownloadLine::Progress {
+		name: words[..at].join(" "),
+		size: parse_size(&format!("{} {}", words[at], words[at + 1]))?,
+		percent: percent.min(100),
+	});
+}
+
+/// Parses pacman's `(3/12) installing name` lines (also upgrading, reinstalling, and downgrading)
+/// into the package's position in the transaction and its name.
+pub fn parse_install_line(line: &str) -> Option<(usize, usize, String)> {
+	let (position, rest) = line.trim().strip_prefix('(')?.split_once(')')?;
+	let (current, total) = position.split_once('/')?;
+	let rest = rest.trim_start();
+	let name = ["installing ", "upgrading ", "reinstalling ", "downgrading "]
+		.iter()
+		.find_map(|action| rest.strip_prefix(action))?
+		.split_whitespace()
+		.next()?
+		.trim_end_matches('.');
+
+	return Some((current.trim().parse().ok()?, total.trim().parse().ok()?, name.into()));
+}
+
+/// Names `pacman -S` refused with `error: target not found: name`.
+pub fn targets_not_found(output: &str) -> Vec<String> {
+	return output
+		.lines()
+		.filter_map(|l| l.trim().strip_prefix("error: target not found: "))
+		.map(|name| name.trim().to_string())
+		.collect();
+}
+
+/// Names from the `Name : value` lines of `-Si` style package info.
+pub fn info_names(output: &str) -> Vec<String> {
+	return output
+		.lines()
+		.filter_map(|l| l.split_once(':'))
+		.filter(|(key, _)| key.trim() == "Name")
+		.map(|(_, name)| name.trim().to_string())
+		.collect();
+}
+
+/// Follows a pacman exec's output and works out how many bytes of the transaction are downloaded,
+/// and which package is being installed.
+#[derive(Debug, Default)]
+pub struct DownloadProgress {
+	/// `(current, total, name)` of the package being installed, once installing started
+	pub installing: Option<(usize, usize, String)>,
+	buffer: String,
+	total: Option<u64>,
+	overall_percent: Option<u8>,
+	packages: HashMap<String, u64>,
+}
+
+impl DownloadProgress {
+	/// Feeds a chunk of output. Progress bars redraw with `\r`, so both `\r` and `\n` end a line.
+	pub fn feed(&mut self, chunk: &str) {
+		self.buffer.push_str(chunk);
+		while let Some(end) = self.buffer.find(['\r', '\n']) {
+			let line: String = self.buffer.drain(..=end).collect();
+			match parse_line(&line) {
+				Some(DownloadLine::TotalSize(size)) => {
+					self.total = Some(size);
+					self.overall_percent = None;
+					self.packages.clear();
+				}
+				Some(DownloadLine::Progress { name, percent, .. }) if name.starts_with("Total (") => {
+					self.overall_percent = Some(percent);
+				}
+				Some(DownloadLine::Progress { name, size, percent }) => {
+					self.packages.insert(name, size * percent as u64 / 100);
+				}
+				None => {
+					if let Some(installing) = parse_install_line(&line) {
+						self.installing = Some(installing);
+					}
+				}
+			}
+		}
+	}
+
+	/// `(downloaded, total)` in bytes, once the output had enough to tell.
+	pub fn position(&self) -> Option<(u64, u64)> {
+		let total = self.total?;
+		if let Some(percent) = self.overall_percent {
+			return Some((total * percent as u64 / 100, total));
+		}
+		if self.packages.is_empty() {
+			return None;
+		}
+
+		return Some((self.packages.values().sum::<u64>().min(total), total));
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	const MIB: u64 = 1024 * 1024;
+
+	/// Pacman 5, one progress bar per package.
+	const PACMAN_5: &str = include_str!("../testdata/pacman/download-v5.txt");
+
+	/// Pacman 6, parallel downloads with an overall `Total` bar.
+	const PACMAN_6: &str = include_str!("../testdata/pacman/download-v6.txt");
+
+	/// The position after each line of `output`, fed one at a time.
+	fn positions(output: &str) -> Vec<Option<(u64, u64)>> {
+		let mut progress = DownloadProgress::default();
+		return output
+			.split_inclusive(['\r', '\n'])
+			.map(|line| {
+				progress.feed(line);
+				return progress.position();
+			})
+			.collect();
+	}
+
+	#[test]
+	fn parses_sizes() {
+		assert_eq!(parse_size("12 B"), Some(12));
+		assert_eq!(parse_size("1.5 KiB"), Some(1536));
+		assert_eq!(parse_size(" 110.00 MiB "), Some(110 * MIB));
+		assert_eq!(parse_size("2 GiB"), Some(2048 * MIB));
+		assert_eq!(parse_size("12 MB"), None);
+		assert_eq!(parse_size("MiB"), None);
+		assert_eq!(parse_size("x MiB"), None);
+	}
+
+	#[test]
+	fn parses_pacman_5_lines() {
+		assert_eq!(parse_line("Total Download Size:    110.00 MiB"), Some(DownloadLine::TotalSize(110 * MIB)));
+		assert_eq!(
+			parse_line(" linux-5.12.arch1-1-x86_64  100.0 MiB  9.80 MiB/s 00:05 [#####-----------------]  25%"),
+			Some(DownloadLine::Progress {
+				name: "linux-5.12.arch1-1-x86_64".into(),
+				size: 100 * MIB,
+				percent: 25,
+			})
+		);
+		// the rate is `0.00   B/s` before anything arrived
+		assert_eq!(
+			parse_line(" glibc-2.33-5-x86_64         10.0 MiB  0.00   B/s 00:00 [----------------------]   0%"),
+			Some(DownloadLine::Progress {
+				name: "glibc-2.33-5-x86_64".into(),
+				size: 10 * MIB,
+				percent: 0,
+			})
+		);
+	}
+
+	#[test]
+	fn parses_pacman_6_lines() {
+		assert_eq!(
+			parse_line("Total ( 1/3)                12.5 MiB  2.50 MiB/s 00:03 [##########------------]  48%"),
+			Some(DownloadLine::Progress {
+				name: "Total ( 1/3)".into(),
+				size: 12 * MIB + MIB / 2,
+				percent: 48,
+			})
+		);
+		assert_eq!(parse_line(" git-2.45.2-1-x86_64 downloading..."), None);
+	}
+
+	#[test]
+	fn ignores_other_lines() {
+		for line in [
+			"",
+			":: Proceed with installation? [Y/n] ",
+			"(2/2) checking keys in keyring                     [######################] 100%",
+			"(1/2) upgrading glibc                              [######################] 100%",
+			"Total Installed Size:   180.50 MiB",
+			"[broken] 50",
+		] {
+			assert_eq!(parse_line(line), None, "{line:?}");
+		}
+	}
+
+	#[test]
+	fn parses_install_lines() {
+		assert_eq!(parse_install_line("(1/2) upgrading glibc    [###] 100%"), Some((1, 2, "glibc".into())));
+		assert_eq!(parse_install_line("( 3/12) installing git..."), Some((3, 12, "git".into())));
+		assert_eq!(parse_install_line("(3/3) reinstalling vim"), Some((3, 3, "vim".into())));
+		assert_eq!(parse_install_line("(1/1) downgrading zsh"), Some((1, 1, "zsh".into())));
+		assert_eq!(parse_install_line("(2/2) checking keys in keyring"), None);
+		assert_eq!(parse_install_line("installing git"), None);
+	}
+
+	#[test]
+	fn follows_pacman_5_downloads() {
+		let positions: Vec<(u64, u64)> = positions(PACMAN_5).into_iter().flatten().collect();
+		let total = 110 * MIB;
+		assert_eq!(positions.first(), Some(&(0, total)));
+		assert!(positions.contains(&(5 * MIB, total)), "glibc at 50%");
+		assert!(positions.contains(&(35 * MIB, total)), "glibc done and linux at 25%");
+		assert_eq!(positions.last(), Some(&(total, total)));
+		assert!(positions.windows(2).all(|w| w[0].0 <= w[1].0), "went backwards: {positions:?}");
+	}
+
+	#[test]
+	fn follows_pacman_6_downloads() {
+		let positions: Vec<(u64, u64)> = positions(PACMAN_6).into_iter().flatten().collect();
+		let total = 12 * MIB + MIB / 2;
+		// before the first `Total` bar, the packages are summed
+		assert_eq!(positions.first(), Some(&(2 * MIB * 40 / 100, total)));
+		assert!(positions.contains(&(total * 20 / 100, total)));
+		assert!(positions.contains(&(total * 48 / 100, total)));
+		assert_eq!(positions.last(), Some(&(total, total)));
+	}
+
+	#[test]
+	fn tracks_the_package_being_installed() {
+		let mut progress = DownloadProgress::default();
+		progress.feed(PACMAN_6);
+		assert_eq!(progress.installing, Some((3, 3, "vim".into())));
+		progress.feed(PACMAN_5);
+		assert_eq!(progress.installing, Some((2, 2, "linux".into())));
+	}
+
+	#[test]
+	fn chunk_boundaries_do_not_matter() {
+		for output in [PACMAN_5, PACMAN_6] {
+			let mut whole = DownloadProgress::default();
+			whole.feed(output);
+			for size in 1..=17 {
+				let mut chunked = DownloadProgress::default();
+				let chars: Vec<char> = output.chars().collect();
+				for chunk in chars.chunks(size) {
+					chunked.feed(&chunk.iter().collect::<String>());
+				}
+				assert_eq!(chunked.position(), whole.position(), "chunks of {size}");
+				assert_eq!(chunked.installing, whole.installing, "chunks of {size}");
+			}
+		}
+	}
+
+	#[test]
+	fn a_new_transaction_starts_over() {
+		let mut progress = DownloadProgress::default();
+		progress.feed(PACMAN_5);
+		progress.feed("Total Download Size:    1.00 MiB\n");
+		assert_eq!(progress.position(), None);
+	}
+
+	#[test]
+	fn finds_missing_targets_and_info_names() {
+		let output = "error: target not found: nope\nerror: target not found: gone \nresolving dependencies...\n";
+		assert_eq!(targets_not_found(output), ["nope", "gone"]);
+		let info = "Repository      : extra\nName            : git\nVersion         : 2.45.2-1\n\nName            : vim\n";
+		assert_eq!(info_names(info), ["git", "vim"]);
+	}
+}
@@ -0,0 +1,71 @@
+use std::{
+	collections::BTreeMap,
+	path::{Path, PathBuf},
+	time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use crate::{color, config, error::Error, statefile};
+
+/// `$XDG_STATE_HOME/tempsystem/paused.json`, mapping the ids of systems `--pause-on-detach` paused to when, in seconds
+/// since the epoch.
+fn path() -> Option<PathBuf> {
+	return config::state_dir().map(|d| d.join("paused.json"));
+}
+
+fn parse(path: &Path, contents: &str) -> BTreeMap<String, u64> {
+	if contents.trim().is_empty() {
+		return BTreeMap::new();
+	}
+	return serde_json::from_str(contents).unwrap_or_else(|e| {
+		eprintln!("{}", color::yellow(format!("ignoring unreadable {}: {e}", path.display())));
+		BTreeMap::new()
+	});
+}
+
+fn now() -> u64 {
+	return SystemTime::now()
+		.duration_since(UNIX_EPOCH)
+		.unwrap_or_default()
+		.as_secs();
+}
+
+/// How long each recorded system has been paused, by id.
+pub fn load() -> BTreeMap<String, Duration> {
+	let Some(path) = path() else {
+		return BTreeMap::new();
+	};
+	let now = now();
+	return std::fs::read_to_string(&path)
+		.map(|contents| parse(&path, &contents))
+		.unwrap_or_default()
+		.into_iter()
+		.map(|(id, since)| (id, Duration::from_secs(now.saturating_sub(since))))
+		.collect();
+}
+
+fn update(change: impl FnOnce(&mut BTreeMap<String, u64>)) -> Result<(), Error> {
+	let path = path().ok_or(Error::HomeDir)?;
+	return statefile::update(&path, |contents| {
+		let mut paused = parse(&path, contents);
+		change(&mut paused);
+		return Ok((serde_json::to_vec_pretty(&paused).map_err(std::io::Error::other)?, ()));
+	})
+	.map_err(|e| Error::StateWrite(path.display().to_string(), e));
+}
+
+/// Notes that the system `id` was paused just now.
+pub fn record(id: &str) -> Result<(), Error> {
+	return update(|paused| {
+		paused.insert(id.into(), now());
+	});
+}
+
+/// Drops `id`, once it is running again or deleted.
+pub fn forget(id: &str) -> Result<(), Error> {
+	if !load().contains_key(id) {
+		return Ok(());
+	}
+	return update(|paused| {
+		paused.remove(id);
+	});
+}
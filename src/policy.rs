@@ -0,0 +1,260 @@
+use std::path::Path;
+
+use bollard::models::{ChangeType, FilesystemChange};
+use serde::Serialize;
+
+use crate::error::Error;
+
+/// Exit code when the system changed paths its `--verify-exit` policy does not allow, whatever the command exited with.
+pub const EXIT_POLICY_VIOLATION: i64 = 90;
+
+/// Matches a path against a pattern where `**` matches anything, including `/`,
+/// `*` matches within one path segment, and `?` matches a single character other than `/`.
+/// A pattern ending in `/**` also matches the directory itself.
+pub fn path_match(pattern: &str, path: &str) -> bool {
+	if let Some(dir) = pattern.strip_suffix("/**")
+		&& dir == path
+	{
+		return true;
+	}
+
+	fn matches(p: &[u8], s: &[u8]) -> bool {
+		return match p {
+			[] => s.is_empty(),
+			[b'*', b'*', rest @ ..] => (0..=s.len()).any(|i| matches(rest, &s[i..])),
+			[b'*', rest @ ..] => (0..=s.len())
+				.take_while(|&i| i == 0 || s[i - 1] != b'/')
+				.any(|i| matches(rest, &s[i..])),
+			[b'?', rest @ ..] => s.first().is_some_and(|c| *c != b'/') && matches(rest, &s[1..]),
+			[c, rest @ ..] => s.first() == Some(c) && matches(rest, &s[1..]),
+		};
+	}
+
+	return matches(pattern.as_bytes(), path.as_bytes());
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Rule {
+	pub allow: bool,
+	pub pattern: String,
+}
+
+impl std::fmt::Display for Rule {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		return write!(f, "{} {}", if self.allow { "allow" } else { "deny" }, self.pattern);
+	}
+}
+
+/// Which paths a system may change, one `allow <glob>` or `deny <glob>` rule per line.
+/// The first rule matching a path decides; paths matching no rule are violations.
+#[derive(Debug, Clone, Default)]
+pub struct Policy {
+	pub rules: Vec<Rule>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Violation {
+	pub path: String,
+	pub kind: &'static str,
+	/// the deny rule that matched, or `None` when no rule did
+	pub rule: Option<String>,
+}
+
+impl std::fmt::Display for Violation {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		return match &self.rule {
+			Some(rule) => write!(f, "{} {} (denied by `{rule}`)", self.kind, self.path),
+			None => write!(f, "{} {} (not allowed by any rule)", self.kind, self.path),
+		};
+	}
+}
+
+impl Policy {
+	/// Parses a policy, or returns the line number and problem of the first bad line.
+	pub fn parse(text: &str) -> Result<Self, (usize, String)> {
+		let mut rules = vec![];
+		for (i, line) in text.lines().enumerate() {
+			let line = line.trim();
+			if line.is_empty() || line.starts_with('#') {
+				continue;
+			}
+			let (action, pattern) = line
+				.split_once(char::is_whitespace)
+				.ok_or((i + 1, format!("expected `allow <glob>` or `deny <glob>`, found `{line}`")))?;
+			let allow = match action {
+				"allow" => true,
+				"deny" => false,
+				_ => return Err((i + 1, format!("unknown action `{action}`, expected `allow` or `deny`"))),
+			};
+			let pattern = pattern.trim();
+			if !pattern.starts_with('/') {
+				return Err((i + 1, format!("pattern `{pattern}` must be an absolute path")));
+			}
+			rules.push(Rule {
+				allow,
+				pattern: pattern.into(),
+			});
+		}
+
+		return Ok(Self { rules });
+	}
+
+	pub fn load(path: &Path) -> Result<Self, Error> {
+		let text = std::fs::read_to_string(path).map_err(|e| Error::PolicyRead(path.display().to_string(), e))?;
+		return Self::parse(&text).map_err(|(line, message)| Error::PolicyParse(path.display().to_string(), line, message));
+	}
+
+	/// `Ok` when `path` may be changed, otherwise the deny rule that matched, if any.
+	pub fn check(&self, path: &str) -> Result<(), Option<&Rule>> {
+		return match self.rules.iter().find(|r| path_match(&r.pattern, path)) {
+			Some(rule) if rule.allow => Ok(()),
+			rule => Err(rule),
+		};
+	}
+
+	/// The changes this policy does not allow. Directories only marked as changed because something below them was are skipped.
+	pub fn violations(&self, changes: &[FilesystemChange]) -> Vec<Violation> {
+		return changes
+			.iter()
+			.filter(|c| {
+				c.kind != ChangeType::_0
+					|| !changes
+						.iter()
+						.any(|other| other.path.starts_with(&format!("{}/", c.path)))
+			})
+			.filter_map(|c| {
+				let rule = self.check(&c.path).err()?;
+				return Some(Violation {
+					path: c.path.clone(),
+					kind: match c.kind {
+						ChangeType::_0 => "changed",
+						ChangeType::_1 => "added",
+						ChangeType::_2 => "deleted",
+					},
+					rule: rule.map(Rule::to_string),
+				});
+			})
+			.collect();
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn change(path: &str, kind: ChangeType) -> FilesystemChange {
+		return FilesystemChange { path: path.into(), kind };
+	}
+
+	#[test]
+	fn matches_globs() {
+		for (pattern, path, matched) in [
+			("/etc/hosts", "/etc/hosts", true),
+			("/etc/hosts", "/etc/hostname", false),
+			("/home/tempsystem/**", "/home/tempsystem", true),
+			("/home/tempsystem/**", "/home/tempsystem/.zsh_history", true),
+			("/home/tempsystem/**", "/home/tempsystem/work/a/b/c", true),
+			("/home/tempsystem/**", "/home/tempsystemx", false),
+			("/home/tempsystem/**", "/home", false),
+			("/tmp/*", "/tmp/file", true),
+			("/tmp/*", "/tmp/dir/file", false),
+			("/tmp/*", "/tmp", false),
+			("/tmp/*.log", "/tmp/build.log", true),
+			("/tmp/*.log", "/tmp/build.log.1", false),
+			("/var/**/*.db", "/var/lib/pacman/sync/core.db", true),
+			("/var/**/*.db", "/var/core.db", false),
+			("/var/**.db", "/var/core.db", true),
+			("/dev/tty?", "/dev/tty1", true),
+			("/dev/tty?", "/dev/tty10", false),
+			("/a?b", "/a/b", false),
+			("/**", "/anything/at/all", true),
+			("**", "", true),
+		] {
+			assert_eq!(path_match(pattern, path), matched, "{pattern} against {path}");
+		}
+	}
+
+	#[test]
+	fn parses_rules_skipping_blanks_and_comments() {
+		let policy = Policy::parse("# scratch space only\n\nallow /home/tempsystem/**\n  deny   /etc/**  \nallow /tmp/*\n").unwrap();
+		assert_eq!(
+			policy.rules,
+			[
+				Rule { allow: true, pattern: "/home/tempsystem/**".into() },
+				Rule { allow: false, pattern: "/etc/**".into() },
+				Rule { allow: true, pattern: "/tmp/*".into() },
+			]
+		);
+		assert_eq!(policy.rules[1].to_string(), "deny /etc/**");
+		assert!(Policy::parse("").unwrap().rules.is_empty());
+	}
+
+	#[test]
+	fn reports_the_line_of_a_bad_rule() {
+		assert_eq!(
+			Policy::parse("allow /tmp/*\n\nallow").unwrap_err(),
+			(3, "expected `allow <glob>` or `deny <glob>`, found `allow`".into())
+		);
+		assert_eq!(
+			Policy::parse("# comment\npermit /tmp").unwrap_err(),
+			(2, "unknown action `permit`, expected `allow` or `deny`".into())
+		);
+		assert_eq!(
+			Policy::parse("deny home/**").unwrap_err(),
+			(1, "pattern `home/**` must be an absolute path".into())
+		);
+	}
+
+	#[test]
+	fn the_first_matching_rule_decides() {
+		let policy = Policy::parse("deny /home/tempsystem/.ssh/**\nallow /home/tempsystem/**\ndeny /**").unwrap();
+		assert_eq!(policy.check("/home/tempsystem/work/out"), Ok(()));
+		assert_eq!(policy.check("/home/tempsystem/.ssh/authorized_keys"), Err(Some(&policy.rules[0])));
+		assert_eq!(policy.check("/etc/passwd"), Err(Some(&policy.rules[2])));
+		// nothing matches, which is a violation too
+		assert_eq!(Policy::parse("allow /tmp/**").unwrap().check("/etc/passwd"), Err(None));
+	}
+
+	#[test]
+	fn lists_violations_without_their_parents() {
+		let policy = Policy::parse("allow /home/tempsystem/**\ndeny /etc/**").unwrap();
+		let changes = [
+			change("/etc", ChangeType::_0),
+			change("/etc/hosts", ChangeType::_0),
+			change("/home", ChangeType::_0),
+			change("/home/tempsystem", ChangeType::_0),
+			change("/home/tempsystem/file", ChangeType::_1),
+			change("/usr/bin/evil", ChangeType::_1),
+			change("/var/cache/gone", ChangeType::_2),
+			// changed itself, with nothing below it
+			change("/opt", ChangeType::_0),
+		];
+		let report: Vec<String> = policy
+			.violations(&changes)
+			.iter()
+			.map(Violation::to_string)
+			.collect();
+		assert_eq!(
+			report,
+			[
+				"changed /etc/hosts (denied by `deny /etc/**`)",
+				"added /usr/bin/evil (not allowed by any rule)",
+				"deleted /var/cache/gone (not allowed by any rule)",
+				"changed /opt (not allowed by any rule)",
+			]
+		);
+	}
+
+	#[test]
+	fn serializes_violations_for_json() {
+		let policy = Policy::parse("deny /etc/**").unwrap();
+		let violations = policy.violations(&[change("/etc/hosts", ChangeType::_1), change("/root/x", ChangeType::_2)]);
+		assert_eq!(
+			serde_json::to_value(&violations).unwrap(),
+			serde_json::json!([
+				{ "path": "/etc/hosts", "kind": "added", "rule": "deny /etc/**" },
+				{ "path": "/root/x", "kind": "deleted", "rule": null },
+			])
+		);
+	}
+}
@@ -0,0 +1,291 @@
+use std::{borrow::Cow, collections::HashMap, ops::Deref, time::Duration};
+
+use clap::ValueEnum;
+use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
+use tokio::sync::mpsc::UnboundedReceiver;
+use tokio_util::sync::CancellationToken;
+
+use crate::events::Event;
+
+#[derive(ValueEnum, Debug, Clone, Copy, Default, PartialEq)]
+pub enum Output {
+	/// spinners and bars that redraw in place
+	#[default]
+	Bars,
+
+	/// one line on stderr per phase, for logs that keep every line
+	Plain,
+}
+
+/// A bar on a `MultiProgress` that finishes, clears, and removes itself when dropped,
+/// so an error or cancellation in the middle of a phase cannot leave a frozen line behind.
+pub struct PhaseSpinner {
+	multi: MultiProgress,
+	bar: ProgressBar,
+}
+
+impl PhaseSpinner {
+	/// The `[x/y] ⠋ message...` spinner used for setup phases.
+	pub fn new(m: &MultiProgress) -> Self {
+		let spinner = Self::add(
+			m,
+			ProgressBar::new_spinner().with_style(ProgressStyle::with_template("{prefix:.bold.dim} {spinner:.blue} {msg}...").unwrap()),
+		);
+		spinner.enable_steady_tick(Duration::from_millis(50));
+		return spinner;
+	}
+
+	pub fn add(m: &MultiProgress, bar: ProgressBar) -> Self {
+		return Self {
+			bar: m.add(bar),
+			multi: m.clone(),
+		};
+	}
+
+	pub fn step(&self, cur: usize, total: usize, msg: impl Into<Cow<'static, str>>) {
+		self.bar.set_prefix(format!("[{cur}/{total}]"));
+		self.bar.set_message(msg);
+	}
+}
+
+impl Deref for PhaseSpinner {
+	type Target = ProgressBar;
+
+	fn deref(&self) -> &Self::Target {
+		return &self.bar;
+	}
+}
+
+impl Drop for PhaseSpinner {
+	fn drop(&mut self) {
+		self.bar.finish_and_clear();
+		self.multi.remove(&self.bar);
+	}
+}
+
+/// How far one image layer got, for the bar over all of them.
+#[derive(Default)]
+struct LayerProgress {
+	downloaded: u64,
+	size: u64,
+	done: bool,
+}
+
+/// The CLI's progress output: a `[x/y]` spinner per phase with bars for the image pull and package downloads under it.
+#[derive(Default)]
+struct Renderer {
+	multi: MultiProgress,
+	spinner: Option<PhaseSpinner>,
+	/// the bar over every layer of the image being pulled
+	pull: Option<PhaseSpinner>,
+	pulled: HashMap<String, LayerProgress>,
+	/// a bar per layer, with --verbose
+	layers: HashMap<String, PhaseSpinner>,
+	build_step: Option<PhaseSpinner>,
+	download: Option<PhaseSpinner>,
+	/// the system's name, shown before the phase counter once it is known
+	system: Option<String>,
+	plain: bool,
+	verbose: bool,
+}
+
+impl Renderer {
+	fn spinner(&mut self) -> &PhaseSpinner {
+		return self
+			.spinner
+			.get_or_insert_with(|| PhaseSpinner::new(&self.multi));
+	}
+
+	fn clear(&mut self) {
+		self.pull = None;
+		self.pulled.clear();
+		self.layers.clear();
+		self.build_step = None;
+		self.download = None;
+		self.spinner = None;
+	}
+
+	/// Sums the bytes of every layer seen so far into one bar. Layers that already exist never report a size, so they
+	/// only count towards the layers done.
+	fn pull_total(&mut self, layer: &str, current: Option<u64>, total: Option<u64>, status: Option<&str>) {
+		let progress = self.pulled.entry(layer.into()).or_default();
+		match status {
+			Some("Downloading") => {
+				if let Some(total) = total {
+					progress.size = total;
+				}
+				if let Some(current) = current {
+					progress.downloaded = current;
+				}
+			}
+			// what comes after, like extracting, reports progress of its own that is not about bytes downloaded
+			Some("Download complete" | "Verifying Checksum" | "Extracting") => progress.downloaded = progress.size,
+			Some("Pull complete" | "Already exists") => {
+				progress.downloaded = progress.size;
+				progress.done = true;
+			}
+			_ => {}
+		}
+		let (downloaded, size) = self
+			.pulled
+			.values()
+			.fold((0, 0), |(downloaded, size), p| (downloaded + p.downloaded, size + p.size));
+		let done = self.pulled.values().filter(|p| p.done).count();
+		let layers = self.pulled.len();
+
+		let multi = self.multi.clone();
+		let bar = self.pull.get_or_insert_with(|| {
+			PhaseSpinner::add(
+				&multi,
+				ProgressBar::new(0).with_style(
+					ProgressStyle::with_template("      {bar:40.cyan/blue} {bytes:>10}/{total_bytes:10} eta {eta:<4} {msg}")
+						.unwrap()
+						.progress_chars("##-"),
+				),
+			)
+		});
+		bar.set_length(size);
+		bar.set_position(downloaded);
+		bar.set_message(format!("{done}/{layers} layers"));
+	}
+
+	fn pull_progress(&mut self, layer: String, current: Option<u64>, total: Option<u64>, status: Option<String>) {
+		self.pull_total(&layer, current, total, status.as_deref());
+		if !self.verbose {
+			return;
+		}
+		let bar = self.layers.entry(layer.clone()).or_insert_with(|| {
+			PhaseSpinner::add(
+				&self.multi,
+				ProgressBar::no_length().with_style(
+					ProgressStyle::with_template("[{elapsed_precise}] {bar:40.cyan/blue} {bytes:>15}/{total_bytes:15} {msg}")
+						.unwrap()
+						.progress_chars("##-"),
+				),
+			)
+		});
+		if let Some(current) = current
+			&& let Some(total) = total
+		{
+			bar.set_length(total);
+			bar.set_position(current);
+		}
+		match status {
+			Some(status) if status == "Pull complete" => {
+				self.layers.remove(&layer);
+			}
+			Some(status) if status.ends_with(" complete") => {
+				if let Some(max) = bar.length() {
+					bar.set_position(max);
+				}
+			}
+			Some(status) => bar.set_message(status),
+			None => {}
+		}
+	}
+
+	/// `handle` for `Output::Plain`, which skips anything only a redrawn bar would show.
+	fn handle_plain(&mut self, event: Event) {
+		match event {
+			Event::PhaseStarted { current, total, name } => match &self.system {
+				Some(system) => eprintln!("{system} [{current}/{total}] {name}"),
+				None => eprintln!("[{current}/{total}] {name}"),
+			},
+			Event::BuildStep { step } => eprintln!("      {step}"),
+			Event::PackageInstalled { current, total, name } => eprintln!("      installing {name} ({current}/{total})"),
+			Event::TerminalHandoff { done: Some(done) } => {
+				let _ = done.send(());
+			}
+			Event::SystemNamed { name, .. } => self.system = Some(name),
+			_ => {}
+		}
+	}
+
+	fn handle(&mut self, event: Event) {
+		if self.plain {
+			return self.handle_plain(event);
+		}
+		match event {
+			Event::PhaseStarted { current, total, name } => {
+				self.pull = None;
+				self.pulled.clear();
+				self.layers.clear();
+				self.build_step = None;
+				self.download = None;
+				self.spinner().step(current, total, name);
+				if let Some(system) = &self.system {
+					let prefix = format!("{system} [{current}/{total}]");
+					self.spinner().set_prefix(prefix);
+				}
+			}
+			Event::PullProgress { layer, current, total, status } => self.pull_progress(layer, current, total, status),
+			Event::BuildStep { step } => {
+				let multi = self.multi.clone();
+				self.build_step
+					.get_or_insert_with(|| {
+						PhaseSpinner::add(&multi, ProgressBar::new_spinner().with_style(ProgressStyle::with_template("      {msg}").unwrap()))
+					})
+					.set_message(step);
+			}
+			Event::DownloadProgress { downloaded, total } => {
+				let multi = self.multi.clone();
+				let bar = self.download.get_or_insert_with(|| {
+					PhaseSpinner::add(
+						&multi,
+						ProgressBar::new(total).with_style(
+							ProgressStyle::with_template("      {bar:40.cyan/blue} {bytes:>10}/{total_bytes:10} {percent:>3}%")
+								.unwrap()
+								.progress_chars("##-"),
+						),
+					)
+				});
+				bar.set_length(total);
+				bar.set_position(downloaded);
+			}
+			Event::PackageInstalled { current, total, name } => {
+				self.spinner()
+					.set_message(format!("Installing {name} ({current}/{total})"));
+			}
+			Event::TerminalHandoff { done } => {
+				self.clear();
+				if let Some(done) = done {
+					let _ = done.send(());
+				}
+			}
+			Event::SystemNamed { name, .. } => self.system = Some(name),
+			Event::Finished { .. } => self.clear(),
+			Event::ExecOutputChunk { .. } | Event::Error { .. } => {}
+		}
+	}
+}
+
+/// Consumes a session's events until `stop`, drawing them as `output` says, or printing them as JSON lines when `json`.
+/// Layers get a bar each only when `verbose`.
+pub async fn render(mut events: UnboundedReceiver<Event>, json: bool, output: Output, verbose: bool, stop: CancellationToken) {
+	let mut renderer = Renderer {
+		plain: output == Output::Plain,
+		verbose,
+		..Default::default()
+	};
+	loop {
+		let event = tokio::select! {
+			_ = stop.cancelled() => break,
+			event = events.recv() => event,
+		};
+		let Some(event) = event else {
+			break;
+		};
+		if !json {
+			renderer.handle(event);
+			continue;
+		}
+		let event = event.redacted();
+		if let Ok(line) = serde_json::to_string(&event) {
+			println!("{line}");
+		}
+		if let Event::TerminalHandoff { done: Some(done) } = event {
+			let _ = done.send(());
+		}
+	}
+	renderer.clear();
+}
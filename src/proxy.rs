@@ -0,0 +1,89 @@
+use crate::{build::shell_quote, env::ExecEnv};
+
+/// The proxy variables passed on, in both cases since tools disagree on which they read; curl, which pacman uses, only
+/// reads `http_proxy` in lower case.
+pub const VARS: &[&str] = &[
+	"http_proxy",
+	"https_proxy",
+	"ftp_proxy",
+	"all_proxy",
+	"no_proxy",
+	"HTTP_PROXY",
+	"HTTPS_PROXY",
+	"FTP_PROXY",
+	"ALL_PROXY",
+	"NO_PROXY",
+];
+
+/// What a proxy on the host's loopback is reached under from the system.
+pub const HOST_ALIAS: &str = "host.docker.internal";
+
+/// The extra host entry that points `HOST_ALIAS` at the host, through the gateway of the system's network.
+pub const HOST_ALIAS_ENTRY: &str = "host.docker.internal:host-gateway";
+
+/// The proxy variables set on the host, with proxies on its loopback moved to `HOST_ALIAS`, since inside the system
+/// `127.0.0.1` is the system itself.
+pub fn from_host() -> Vec<(String, String)> {
+	return VARS
+		.iter()
+		.filter_map(|name| {
+			let value = std::env::var(name).ok().filter(|v| !v.is_empty())?;
+			// no_proxy lists hosts to reach directly, not a proxy to reach
+			let value = if name.eq_ignore_ascii_case("no_proxy") {
+				value
+			} else {
+				rewrite(&value).unwrap_or(value)
+			};
+			Some((name.to_string(), value))
+		})
+		.collect();
+}
+
+/// `url` with its host replaced by `HOST_ALIAS`, when it is a loopback address; `None` when it is not.
+pub fn rewrite(url: &str) -> Option<String> {
+	let authority_start = url.find("://").map_or(0, |i| i + 3);
+	let authority_end = url[authority_start..]
+		.find('/')
+		.map_or(url.len(), |i| authority_start + i);
+	let host_start = url[authority_start..authority_end]
+		.rfind('@')
+		.map_or(authority_start, |i| authority_start + i + 1);
+	let host_end = if url[host_start..].starts_with('[') {
+		url[host_start..authority_end].find(']')? + host_start + 1
+	} else {
+		url[host_start..authority_end]
+			.find(':')
+			.map_or(authority_end, |i| host_start + i)
+	};
+	if !is_loopback(&url[host_start..host_end]) {
+		return None;
+	}
+
+	return Some(format!("{}{HOST_ALIAS}{}", &url[..host_start], &url[host_end..]));
+}
+
+fn is_loopback(host: &str) -> bool {
+	let host = host.trim_start_matches('[').trim_end_matches(']');
+	if host.eq_ignore_ascii_case("localhost") || host == "0.0.0.0" {
+		return true;
+	}
+	return host
+		.parse::<std::net::IpAddr>()
+		.is_ok_and(|ip| ip.is_loopback());
+}
+
+/// Whether `env` points a proxy at `HOST_ALIAS`, so the system needs `HOST_ALIAS_ENTRY`.
+pub fn needs_host_alias(env: &ExecEnv) -> bool {
+	return VARS.iter().any(|name| {
+		env.get(name)
+			.is_some_and(|value| value.contains(HOST_ALIAS))
+	});
+}
+
+/// `export` statements for the proxy variables in `env`, for commands run through sudo, which drops them.
+pub fn exports(env: &ExecEnv) -> String {
+	return VARS
+		.iter()
+		.filter_map(|name| Some(format!("export {name}={}; ", shell_quote(env.get(name)?))))
+		.collect();
+}
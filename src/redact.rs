@@ -0,0 +1,39 @@
+use std::{borrow::Cow, sync::OnceLock};
+
+use crate::env::{glob_match, looks_secret};
+
+pub const REDACTED: &str = "***";
+
+/// Shorter values are left alone, they would match all over the place.
+const MIN_SECRET_LEN: usize = 4;
+
+static SECRETS: OnceLock<Vec<String>> = OnceLock::new();
+
+/// Collects the values of host environment variables whose names look like secrets, or match one of `extra_patterns`,
+/// so `redact` can hide them. They still reach the system intact; only what tempsystem itself prints is redacted.
+pub fn init(extra_patterns: &[String]) {
+	let mut secrets: Vec<String> = std::env::vars_os()
+		.filter_map(|(k, v)| Some((k.into_string().ok()?, v.into_string().ok()?)))
+		.filter(|(name, _)| looks_secret(name) || extra_patterns.iter().any(|p| glob_match(p, name)))
+		.map(|(_, value)| value)
+		.filter(|value| value.len() >= MIN_SECRET_LEN)
+		.collect();
+	// longest first, so a secret containing another is hidden whole
+	secrets.sort_by_key(|s| std::cmp::Reverse(s.len()));
+	secrets.dedup();
+	let _ = SECRETS.set(secrets);
+}
+
+/// `s` with every known secret replaced by `***`.
+pub fn redact(s: &str) -> Cow<'_, str> {
+	let secrets = SECRETS.get().map(Vec::as_slice).unwrap_or_default();
+	if !secrets.iter().any(|secret| s.contains(secret.as_str())) {
+		return Cow::Borrowed(s);
+	}
+
+	let mut redacted = s.to_string();
+	for secret in secrets {
+		redacted = redacted.replace(secret.as_str(), REDACTED);
+	}
+	return Cow::Owned(redacted);
+}
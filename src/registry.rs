@@ -0,0 +1,184 @@
+use std::process::Stdio;
+
+use base64::Engine as _;
+use bollard::auth::DockerCredentials;
+use tokio::{io::AsyncWriteExt, process::Command};
+
+use crate::{color, engine, imageref::ImageRef};
+
+/// What config.json keys Docker Hub under, and what credential helpers expect for it.
+const DOCKER_HUB: &str = "https://index.docker.io/v1/";
+
+/// Credentials given with --registry-user and --registry-password-stdin.
+#[derive(Debug, Clone)]
+pub struct Login {
+	pub user: String,
+	pub password: String,
+}
+
+/// The registry `image` is pulled from: its first component when that looks like a host, else Docker Hub.
+pub fn registry_of(image: &str) -> String {
+	return ImageRef::split(image).registry().into();
+}
+
+/// A key of config.json's `auths` or `credHelpers` as a bare host, with Docker Hub's many names folded into `docker.io`.
+fn normalize(key: &str) -> &str {
+	let host = key
+		.trim_start_matches("https://")
+		.trim_start_matches("http://");
+	let host = host.split('/').next().unwrap_or(host);
+	return match host {
+		"index.docker.io" | "registry-1.docker.io" | "registry.hub.docker.com" => "docker.io",
+		host => host,
+	};
+}
+
+/// The credentials for pulling `image`: `login`, else what the credential helper config.json names for its registry
+/// gives, else what config.json stores itself. `None` pulls anonymously.
+pub async fn credentials(image: &str, login: Option<&Login>) -> Option<DockerCredentials> {
+	let registry = registry_of(image);
+	let serveraddress = if registry == "docker.io" { DOCKER_HUB.to_string() } else { registry.clone() };
+	if let Some(login) = login {
+		return Some(DockerCredentials {
+			username: Some(login.user.clone()),
+			password: Some(login.password.clone()),
+			serveraddress: Some(serveraddress),
+			..Default::default()
+		});
+	}
+
+	let config: serde_json::Value = engine::docker_config_dir()
+		.and_then(|dir| std::fs::read_to_string(dir.join("config.json")).ok())
+		.and_then(|c| serde_json::from_str(&c).ok())
+		.unwrap_or_default();
+	let find = |section: &str| {
+		return config[section]
+			.as_object()?
+			.iter()
+			.find(|(key, _)| normalize(key) == registry)
+			.map(|(_, value)| value.clone());
+	};
+	let helper = find("credHelpers")
+		.and_then(|h| h.as_str().map(String::from))
+		.or_else(|| config["credsStore"].as_str().map(String::from));
+	if let Some(helper) = helper
+		&& let Some(credentials) = from_helper(&helper, &serveraddress).await
+	{
+		return Some(credentials);
+	}
+	return from_config(&find("auths")?, serveraddress);
+}
+
+/// What an `auths` entry holds: `auth` is base64 of `user:password`, and an `identitytoken` replaces the password.
+fn from_config(entry: &serde_json::Value, serveraddress: String) -> Option<DockerCredentials> {
+	let decoded = entry["auth"]
+		.as_str()
+		.and_then(|auth| base64::engine::general_purpose::STANDARD.decode(auth).ok())
+		.and_then(|auth| String::from_utf8(auth).ok())
+		.unwrap_or_default();
+	let (user, password) = decoded.split_once(':').unwrap_or((&decoded, ""));
+	if let Some(token) = entry["identitytoken"].as_str() {
+		return Some(DockerCredentials {
+			username: (!user.is_empty()).then(|| user.into()),
+			identitytoken: Some(token.into()),
+			serveraddress: Some(serveraddress),
+			..Default::default()
+		});
+	}
+	if user.is_empty() {
+		return None;
+	}
+	return Some(DockerCredentials {
+		username: Some(user.into()),
+		password: Some(password.into()),
+		serveraddress: Some(serveraddress),
+		..Default::default()
+	});
+}
+
+/// Asks `docker-credential-<helper>` for the credentials of `serveraddress`, warning when it fails for any reason
+/// but not having any.
+async fn from_helper(helper: &str, serveraddress: &str) -> Option<DockerCredentials> {
+	let program = format!("docker-credential-{helper}");
+	let result = async {
+		let mut child = Command::new(&program)
+			.arg("get")
+			.stdin(Stdio::piped())
+			.stdout(Stdio::piped())
+			.stderr(Stdio::piped())
+			.spawn()?;
+		if let Some(mut stdin) = child.stdin.take() {
+			stdin.write_all(serveraddress.as_bytes()).await?;
+		}
+		return child.wait_with_output().await;
+	}
+	.await;
+	let output = match result {
+		Ok(output) => output,
+		Err(e) => {
+			eprintln!("{}", color::yellow(format!("could not run `{program}`, pulling without its credentials: {e}")));
+			return None;
+		}
+	};
+	let stdout = String::from_utf8_lossy(&output.stdout);
+	if !output.status.success() {
+		if !stdout.contains("credentials not found") {
+			let stderr = String::from_utf8_lossy(&output.stderr);
+			let reason = if stderr.trim().is_empty() { stdout.trim() } else { stderr.trim() };
+			eprintln!("{}", color::yellow(format!("`{program} get` failed, pulling without its credentials: {reason}")));
+		}
+		return None;
+	}
+	let found: serde_json::Value = serde_json::from_str(&stdout).ok()?;
+	let user = found["Username"].as_str()?;
+	let secret = found["Secret"].as_str()?;
+	// helpers store identity tokens under this made-up user
+	if user == "<token>" {
+		return Some(DockerCredentials {
+			identitytoken: Some(secret.into()),
+			serveraddress: Some(serveraddress.into()),
+			..Default::default()
+		});
+	}
+	return Some(DockerCredentials {
+		username: Some(user.into()),
+		password: Some(secret.into()),
+		serveraddress: Some(serveraddress.into()),
+		..Default::default()
+	});
+}
+
+/// Parses platforms like `linux/amd64` or `linux/arm/v7`.
+pub fn parse_platform(s: &str) -> Result<String, String> {
+	let parts: Vec<&str> = s.split('/').collect();
+	if !(2..=3).contains(&parts.len()) || parts.iter().any(|p| p.is_empty()) {
+		return Err(format!("`{s}` is not a platform like linux/amd64 or linux/arm64"));
+	}
+	return Ok(s.to_lowercase());
+}
+
+/// `os/architecture[/variant]`, the way --platform takes it.
+pub fn platform_name(os: Option<&str>, architecture: Option<&str>, variant: Option<&str>) -> String {
+	let mut name = format!("{}/{}", os.unwrap_or("unknown"), architecture.unwrap_or("unknown"));
+	if let Some(variant) = variant.filter(|v| !v.is_empty()) {
+		name = format!("{name}/{variant}");
+	}
+	return name;
+}
+
+/// Whether `available` is the platform `requested` asks for; a variant only counts when `requested` names one.
+pub fn platform_matches(requested: &str, available: &str) -> bool {
+	let available: Vec<&str> = available.split('/').collect();
+	let requested: Vec<&str> = requested.split('/').collect();
+	return requested.len() <= available.len() && requested.iter().zip(&available).all(|(r, a)| r == a);
+}
+
+/// Whether the registry turned a pull down for missing or wrong credentials.
+pub fn is_auth_failure(e: &bollard::errors::Error) -> bool {
+	return match e {
+		bollard::errors::Error::DockerResponseServerError { status_code, message } => {
+			*status_code == 401 || message.contains("unauthorized") || message.contains("authentication required")
+		}
+		_ => false,
+	};
+}
@@ -0,0 +1,79 @@
+use std::{
+	collections::BTreeMap,
+	ffi::OsString,
+	path::{Path, PathBuf},
+};
+
+use crate::{color, config, error::Error, statefile};
+
+/// `$XDG_STATE_HOME/tempsystem/remembered.json`, mapping canonical directories to the flags remembered for them.
+pub fn path() -> Option<PathBuf> {
+	return config::state_dir().map(|d| d.join("remembered.json"));
+}
+
+/// What `contents` remembers; empty, with a warning, when it cannot be read.
+fn parse(path: &Path, contents: &str) -> BTreeMap<String, Vec<String>> {
+	if contents.trim().is_empty() {
+		return BTreeMap::new();
+	}
+	return serde_json::from_str(contents).unwrap_or_else(|e| {
+		eprintln!("{}", color::yellow(format!("ignoring unreadable {}: {e}", path.display())));
+		BTreeMap::new()
+	});
+}
+
+/// Everything remembered; empty when nothing is, or the file cannot be read.
+fn load() -> BTreeMap<String, Vec<String>> {
+	let Some(path) = path() else {
+		return BTreeMap::new();
+	};
+	return std::fs::read_to_string(&path)
+		.map(|contents| parse(&path, &contents))
+		.unwrap_or_default();
+}
+
+/// Changes what is remembered with `change` while holding the lock, so an update from another tempsystem is not lost.
+fn update<T>(change: impl FnOnce(&mut BTreeMap<String, Vec<String>>) -> T) -> Result<T, Error> {
+	let path = path().ok_or(Error::HomeDir)?;
+	return statefile::update(&path, |contents| {
+		let mut remembered = parse(&path, contents);
+		let result = change(&mut remembered);
+		return Ok((serde_json::to_vec_pretty(&remembered).map_err(std::io::Error::other)?, result));
+	})
+	.map_err(|e| Error::StateWrite(path.display().to_string(), e));
+}
+
+/// The current directory, canonicalized, which is what flags are remembered for.
+pub fn current_dir() -> Result<PathBuf, Error> {
+	return std::env::current_dir()
+		.and_then(|d| d.canonicalize())
+		.map_err(Error::GetCWD);
+}
+
+pub fn flags_for(dir: &Path) -> Vec<String> {
+	return load()
+		.remove(&dir.display().to_string())
+		.unwrap_or_default();
+}
+
+/// Replaces the flags remembered for `dir`.
+pub fn remember(dir: &Path, flags: Vec<String>) -> Result<(), Error> {
+	return update(|remembered| {
+		remembered.insert(dir.display().to_string(), flags);
+	});
+}
+
+/// Drops the flags remembered for `dir`, returning them.
+pub fn forget(dir: &Path) -> Result<Option<Vec<String>>, Error> {
+	if !load().contains_key(&dir.display().to_string()) {
+		return Ok(None);
+	}
+	return update(|remembered| remembered.remove(&dir.display().to_string()));
+}
+
+/// The command line with `flags` put right after the program name, so any flag given on the command line comes later and wins.
+pub fn apply(mut argv: Vec<OsString>, flags: &[String]) -> Vec<OsString> {
+	let at = argv.len().min(1);
+	argv.splice(at..at, flags.iter().map(OsString::from));
+	return argv;
+}
@@ -0,0 +1,79 @@
+use std::hash::{BuildHasher, RandomState};
+
+/// A small splitmix64 generator for the random choices tempsystem makes, like generated names. `--seed` seeds it, so
+/// the same seed makes the same choices on every run.
+#[derive(Debug, Clone)]
+pub struct Rng(u64);
+
+impl Rng {
+	/// A generator seeded with `seed`, or else randomly.
+	pub fn new(seed: Option<u64>) -> Self {
+		// every `RandomState` is seeded differently, which is all the randomness tempsystem needs
+		return Self(seed.unwrap_or_else(|| RandomState::new().hash_one(std::time::SystemTime::now())));
+	}
+
+	pub fn next_u64(&mut self) -> u64 {
+		self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+		let mut z = self.0;
+		z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+		z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+		return z ^ (z >> 31);
+	}
+
+	/// One of `items`, which must not be empty.
+	pub fn pick<'a, T>(&mut self, items: &'a [T]) -> &'a T {
+		return &items[(self.next_u64() % items.len() as u64) as usize];
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn draws(rng: &mut Rng) -> Vec<u64> {
+		return (0..16).map(|_| rng.next_u64()).collect();
+	}
+
+	#[test]
+	fn the_same_seed_makes_the_same_choices() {
+		assert_eq!(draws(&mut Rng::new(Some(42))), draws(&mut Rng::new(Some(42))));
+		assert_eq!(draws(&mut Rng::new(Some(0))), draws(&mut Rng::new(Some(0))));
+
+		let items = ["a", "b", "c", "d", "e"];
+		let picks = |seed| {
+			let mut rng = Rng::new(Some(seed));
+			return (0..32).map(|_| *rng.pick(&items)).collect::<Vec<&str>>();
+		};
+		assert_eq!(picks(7), picks(7));
+	}
+
+	#[test]
+	fn different_seeds_make_different_choices() {
+		assert_ne!(draws(&mut Rng::new(Some(1))), draws(&mut Rng::new(Some(2))));
+	}
+
+	#[test]
+	fn is_splitmix64() {
+		// the reference sequence for seed 1234567
+		let mut rng = Rng::new(Some(1234567));
+		assert_eq!(rng.next_u64(), 6457827717110365317);
+		assert_eq!(rng.next_u64(), 3203168211198807973);
+		assert_eq!(rng.next_u64(), 9817491932198370423);
+	}
+
+	#[test]
+	fn picks_every_item_eventually() {
+		let items = [0, 1, 2];
+		let mut rng = Rng::new(Some(3));
+		let mut seen = [false; 3];
+		for _ in 0..100 {
+			seen[*rng.pick(&items)] = true;
+		}
+		assert_eq!(seen, [true; 3]);
+	}
+
+	#[test]
+	fn unseeded_generators_differ() {
+		assert_ne!(draws(&mut Rng::new(None)), draws(&mut Rng::new(None)));
+	}
+}
@@ -0,0 +1,595 @@
+use std::{collections::HashMap, path::Path};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{Args, ZshHistorySync, config::Config, error::Error, features, imageref::ImageRef, limits, share, toolchain};
+
+pub const LABEL_MANAGED: &str = "dev.tempsystem.managed";
+pub const LABEL_OPTIONS: &str = "dev.tempsystem.options";
+pub const LABEL_OPTIONS_HASH: &str = "dev.tempsystem.options-hash";
+pub const LABEL_REUSE_KEY: &str = "dev.tempsystem.reuse-key";
+pub const LABEL_FLAVOR: &str = "dev.tempsystem.flavor";
+
+pub const IMAGE_REPOSITORY: &str = "landsj/tempsystem";
+
+/// Which tag of `IMAGE_REPOSITORY` to use.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub enum Flavor {
+	/// pacman and a shell, nothing else
+	Minimal,
+
+	/// zsh, yay, pkgfile, and the usual tools
+	#[default]
+	Full,
+
+	/// any other tag
+	Tag(String),
+
+	/// an image from anywhere, from `--image`
+	Image(String),
+}
+
+impl Flavor {
+	pub fn image(&self) -> String {
+		let tag = match self {
+			Self::Minimal => "minimal",
+			Self::Full => "latest",
+			Self::Tag(tag) => tag,
+			Self::Image(image) => return image.clone(),
+		};
+		return format!("{IMAGE_REPOSITORY}:{tag}");
+	}
+}
+
+impl From<&str> for Flavor {
+	fn from(s: &str) -> Self {
+		return match s {
+			"minimal" => Self::Minimal,
+			"full" => Self::Full,
+			tag => Self::Tag(tag.into()),
+		};
+	}
+}
+
+impl std::fmt::Display for Flavor {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		return match self {
+			Self::Minimal => write!(f, "minimal"),
+			Self::Full => write!(f, "full"),
+			Self::Tag(tag) => write!(f, "{tag}"),
+			Self::Image(image) => write!(f, "{image}"),
+		};
+	}
+}
+
+/// `reference` with its tag and digest replaced by `tag`, which is either a tag or a digest like `sha256:<hex>`,
+/// with or without the `@`.
+pub fn with_tag(reference: &str, tag: &str) -> String {
+	return ImageRef::split(reference).at(tag).to_string();
+}
+
+pub fn image_digest(reference: &str) -> Option<String> {
+	return ImageRef::split(reference).digest;
+}
+
+/// The tag of `reference`, or its digest cut to 12 digits, for progress messages.
+pub fn image_version(reference: &str) -> String {
+	let image = ImageRef::split(reference);
+	return match (image.tag, image.digest) {
+		(_, Some(digest)) => digest.get(.."sha256:".len() + 12).unwrap_or(&digest).into(),
+		(Some(tag), None) => tag,
+		// what docker pulls without a tag
+		(None, None) => "latest".into(),
+	};
+}
+
+/// Whether an image with these `RepoDigests` (`name@sha256:...`) is the one `digest` names.
+pub fn digest_matches(digest: &str, repo_digests: &[String]) -> bool {
+	return repo_digests
+		.iter()
+		.any(|d| ImageRef::split(d).digest.as_deref() == Some(digest));
+}
+
+/// The image `args` pick before any --tag or pin: --image, or the flavor's, mirrored on --registry.
+pub fn unpinned_image(args: &Args, config: &Config) -> Result<(Flavor, String), Error> {
+	let flavor = match &args.image {
+		Some(image) => {
+			validate_image(image)?;
+			Flavor::Image(image.clone())
+		}
+		None => args
+			.flavor
+			.as_deref()
+			.or(config.flavor.as_deref())
+			.map(Flavor::from)
+			.unwrap_or_default(),
+	};
+	let mut image = flavor.image();
+	if !matches!(flavor, Flavor::Image(_))
+		&& let Some(registry) = &args.registry
+	{
+		image = mirrored(&image, registry);
+		validate_image(&image)?;
+	}
+	return Ok((flavor, image));
+}
+
+/// The project file and the digest it pins the image to, if it does.
+pub fn pin(config: &Config) -> Option<(&Path, &str)> {
+	let (path, project) = config.project.as_ref()?;
+	return Some((path, project.image_digest.as_deref()?));
+}
+
+/// The warning for a pin that --image or --tag overrides, if there is one.
+pub fn overridden_pin(args: &Args, config: &Config) -> Option<String> {
+	let (path, digest) = pin(config)?;
+	let flag = if args.image.is_some() {
+		"--image"
+	} else {
+		args.tag.as_ref().map(|_| "--tag")?
+	};
+	return Some(format!("{flag} overrides the image digest {digest} pinned in {}", path.display()));
+}
+
+/// `reference` pulled from `registry` instead, keeping its path, for images mirrored under the same name.
+pub fn mirrored(reference: &str, registry: &str) -> String {
+	return format!("{}/{reference}", registry.trim_end_matches('/'));
+}
+
+/// Whether `reference` is one of our images, whose user and home are known without looking; mirrors count too.
+pub fn is_own_image(reference: &str) -> bool {
+	let repository = ImageRef::split(reference).repository;
+	return repository == IMAGE_REPOSITORY || repository.ends_with(&format!("/{IMAGE_REPOSITORY}"));
+}
+
+/// Checks `reference` is a valid `[registry[:port]/]name[:tag][@digest]`, so a typo fails before anything is pulled.
+pub fn validate_image(reference: &str) -> Result<(), Error> {
+	return ImageRef::parse(reference)
+		.map(|_| ())
+		.map_err(|reason| Error::InvalidImageRef(reference.into(), reason));
+}
+
+/// More packages than this across `--extra-packages` and `--extra-aur-packages` is refused up front.
+pub const MAX_PACKAGES: usize = 1000;
+
+/// Everything that decides what a created system looks like.
+/// Stored on the container as labels so a later run can tell whether an existing system matches what it was asked for.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct SessionOptions {
+	pub image: String,
+	/// already part of `image`, kept for messages and its own label
+	#[serde(skip)]
+	pub flavor: Flavor,
+	/// the home of the user in the system, which the mounts go under; worked out from the image before creating it
+	#[serde(skip)]
+	pub home: String,
+	pub network: bool,
+	#[serde(default, skip_serializing_if = "Vec::is_empty")]
+	pub allow_host: Vec<String>,
+	pub privileged: bool,
+	/// left out of the label when off, so systems from before it existed keep their hash
+	#[serde(default, skip_serializing_if = "std::ops::Not::not")]
+	pub privileged_full: bool,
+	pub ro_root: bool,
+	pub ro_cwd: bool,
+	#[serde(default, skip_serializing_if = "std::ops::Not::not")]
+	pub ro_cwd_overlay: bool,
+	pub mount_cwd: bool,
+	/// the current directory is uploaded to ~/work instead of mounted
+	#[serde(default, skip_serializing_if = "std::ops::Not::not")]
+	pub copy_cwd: bool,
+	pub mount_history: bool,
+	pub restrict_cpu: Option<u8>,
+	pub restrict_memory: Option<usize>,
+	/// nanocpus, from --cpus
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub nano_cpus: Option<i64>,
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub cpuset_cpus: Option<String>,
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub pids_limit: Option<i64>,
+	#[serde(default, skip_serializing_if = "Vec::is_empty")]
+	pub ulimits: Vec<limits::Ulimit>,
+	/// bytes, from --memory
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub memory: Option<u64>,
+	/// bytes of memory and swap together, from --memory-swap or else --memory
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub memory_swap: Option<u64>,
+	/// bytes the writable layer may grow to, from --storage-size
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub storage_size: Option<u64>,
+	/// bytes of /dev/shm, from --shm-size or config.toml
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub shm_size: Option<u64>,
+	/// from --cgroup-parent or config.toml
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub cgroup_parent: Option<String>,
+	pub chaotic_aur: bool,
+	pub landware: bool,
+	pub packages: Vec<String>,
+	pub aur_packages: Vec<String>,
+	/// commands finishing --toolchain's toolchains once the packages are in
+	#[serde(default, skip_serializing_if = "Vec::is_empty")]
+	pub toolchain_setup: Vec<String>,
+	/// what could not be had the way it was asked for, to warn about
+	#[serde(skip)]
+	pub notes: Vec<String>,
+}
+
+impl SessionOptions {
+	/// Refuses, with --offline, anything setup would need the network for.
+	pub fn check_offline(&self, args: &Args) -> Result<(), Error> {
+		if !args.offline {
+			return Ok(());
+		}
+		let needs_network = [
+			("installing packages", !self.packages.is_empty()),
+			("installing AUR packages", !self.aur_packages.is_empty()),
+			("--update-system", args.update_system.is_some()),
+			("--update-pkgfile", args.update_pkgfile),
+			("--chaotic-aur", self.chaotic_aur),
+			("--landware", self.landware),
+			("--build-always", args.build_always),
+		];
+		if let Some((what, _)) = needs_network.into_iter().find(|(_, used)| *used) {
+			return Err(Error::Offline(what));
+		}
+		return Ok(());
+	}
+
+	/// Options for `args`, with the packages from the config and project file merged in.
+	pub fn from_args(args: &Args, config: &Config) -> Result<Self, Error> {
+		if let Some(shared) = &args.from_share {
+			return share::decode(shared);
+		}
+		let mut lists = vec![
+			PackageList::new("config.toml packages", false, config.packages.iter().cloned()),
+			PackageList::new("config.toml aur-packages", true, config.aur_packages.iter().cloned()),
+		];
+		if let Some((_, project)) = &config.project {
+			lists.push(PackageList::new("tempsystem.toml packages", false, project.packages.iter().cloned()));
+			lists.push(PackageList::new("tempsystem.toml aur-packages", true, project.aur_packages.iter().cloned()));
+		}
+		let toolchains = toolchain::expand(&args.toolchain);
+		lists.push(PackageList::new("--toolchain", false, toolchains.packages));
+		lists.push(PackageList::new("--toolchain", true, toolchains.aur_packages));
+		lists.push(PackageList::new("--extra-packages", false, split_packages(args.extra_packages.as_deref())));
+		lists.push(PackageList::new("--extra-aur-packages", true, split_packages(args.extra_aur_packages.as_deref())));
+		let (packages, aur_packages) = resolve_packages(&lists)?;
+		if let Some(memory) = args.memory
+			&& let Some(swap) = args.memory_swap
+			&& swap < memory
+		{
+			return Err(Error::MemorySwapBelowMemory(swap, memory));
+		}
+		let (flavor, mut image) = unpinned_image(args, config)?;
+		if let Some((_, digest)) = pin(config)
+			&& args.image.is_none()
+			&& args.tag.is_none()
+		{
+			image = with_tag(&image, digest);
+			validate_image(&image)?;
+		}
+		let flavor = match &args.tag {
+			Some(tag) => {
+				image = with_tag(&image, tag);
+				validate_image(&image)?;
+				Flavor::Image(image.clone())
+			}
+			None => flavor,
+		};
+
+		return Ok(Self {
+			image,
+			flavor,
+			home: features::DEFAULT_HOME.into(),
+			network: !args.no_network.unwrap_or(args.offline),
+			allow_host: args.allow_host.clone(),
+			privileged: args.privileged || args.privileged_full,
+			privileged_full: args.privileged_full,
+			ro_root: args.ro_root,
+			ro_cwd: args.ro_cwd,
+			ro_cwd_overlay: args.ro_cwd_overlay,
+			mount_cwd: !args.disable_cwd_mount && !args.copy_cwd,
+			copy_cwd: args.copy_cwd,
+			mount_history: args.sync_zsh_history == ZshHistorySync::Mount,
+			restrict_cpu: args.restrict_cpu,
+			restrict_memory: args.restrict_memory,
+			nano_cpus: args.cpus,
+			cpuset_cpus: args.cpuset_cpus.clone(),
+			pids_limit: args.pids_limit,
+			ulimits: args.ulimit.clone(),
+			memory: args.memory,
+			memory_swap: args.memory_swap.or(args.memory),
+			storage_size: args.storage_size,
+			shm_size: args.shm_size.or(config.shm_size),
+			cgroup_parent: args.cgroup_parent.clone().or(config.cgroup_parent.clone()),
+			chaotic_aur: args.chaotic_aur,
+			landware: args.landware,
+			packages,
+			aur_packages,
+			toolchain_setup: toolchains.setup,
+			notes: toolchains.notes,
+		});
+	}
+
+	/// Turns off the mounts of host paths, which a daemon on another machine cannot see, returning what was turned off.
+	pub fn drop_host_mounts(&mut self) -> Vec<&'static str> {
+		let mut dropped = vec![];
+		if self.mount_cwd || self.ro_cwd_overlay {
+			dropped.push("the current directory (use --copy-cwd to copy it in instead)");
+		}
+		if self.mount_history {
+			dropped.push("the zsh history");
+		}
+		self.mount_cwd = false;
+		self.ro_cwd_overlay = false;
+		self.mount_history = false;
+		return dropped;
+	}
+
+	/// Refuses more than `MAX_PACKAGES` packages, counted after duplicates are dropped.
+	pub fn check_package_count(&self) -> Result<(), Error> {
+		let requested = self.packages.len() + self.aur_packages.len();
+		if requested > MAX_PACKAGES {
+			return Err(Error::TooManyPackages(requested, MAX_PACKAGES));
+		}
+
+		return Ok(());
+	}
+
+	/// How many install steps the packages take in chunks of `chunk_size`, repository and AUR packages chunked
+	/// separately.
+	pub fn install_steps(&self, chunk_size: usize) -> usize {
+		return self.packages.len().div_ceil(chunk_size) + self.aur_packages.len().div_ceil(chunk_size);
+	}
+
+	/// The resolved package lists, one per line.
+	pub fn describe_packages(&self) -> String {
+		fn list(packages: &[String]) -> String {
+			return if packages.is_empty() { "none".into() } else { packages.join(" ") };
+		}
+		let mut description = format!("packages: {}\naur packages: {}", list(&self.packages), list(&self.aur_packages));
+		if !self.toolchain_setup.is_empty() {
+			description.push_str(&format!("\ntoolchain setup: {}", self.toolchain_setup.join("; ")));
+		}
+		return description;
+	}
+
+	pub fn to_label(&self) -> String {
+		return serde_json::to_string(self).unwrap_or_default();
+	}
+
+	pub fn from_label(label: &str) -> Option<Self> {
+		return serde_json::from_str(label).ok();
+	}
+
+	/// FNV-1a over the serialized options; stable across builds, unlike `DefaultHasher`.
+	pub fn hash(&self) -> String {
+		return format!("{:016x}", fnv1a(self.to_label().as_bytes()));
+	}
+
+	/// Identifies the `--reuse` system for a directory; changing any option gives a fresh system.
+	pub fn reuse_key(&self, cwd: &Path) -> String {
+		return format!("{:016x}", fnv1a(format!("{}\n{}", cwd.display(), self.hash()).as_bytes()));
+	}
+
+	/// Human readable list of the fields that differ, formatted as `field: was X, now Y`.
+	pub fn differences(&self, other: &Self) -> Vec<String> {
+		let (Ok(serde_json::Value::Object(old)), Ok(serde_json::Value::Object(new))) = (serde_json::to_value(self), serde_json::to_value(other)) else {
+			return vec![];
+		};
+		return old
+			.iter()
+			.filter_map(|(key, was)| {
+				let now = new.get(key).unwrap_or(&serde_json::Value::Null);
+				(was != now).then(|| format!("{key}: was {was}, now {now}"))
+			})
+			.collect();
+	}
+}
+
+pub fn fnv1a(bytes: &[u8]) -> u64 {
+	return bytes
+		.iter()
+		.fold(0xcbf29ce484222325, |hash, b| (hash ^ *b as u64).wrapping_mul(0x100000001b3));
+}
+
+fn split_packages(packages: Option<&str>) -> Vec<String> {
+	return packages
+		.unwrap_or_default()
+		.split_whitespace()
+		.map(String::from)
+		.collect();
+}
+
+/// Packages asked for by one source, like `--extra-packages` or the project file.
+pub struct PackageList {
+	source: &'static str,
+	aur: bool,
+	names: Vec<String>,
+}
+
+impl PackageList {
+	pub fn new(source: &'static str, aur: bool, names: impl IntoIterator<Item = String>) -> Self {
+		return Self {
+			source,
+			aur,
+			names: names.into_iter().collect(),
+		};
+	}
+}
+
+/// Merges `lists` into repository and AUR packages, each in order of first appearance without repeats.
+/// Package names are case insensitive, so they are lowercased. A name requested from both the repositories
+/// and the AUR is an error, reported together with every other such name and where each was requested.
+pub fn resolve_packages(lists: &[PackageList]) -> Result<(Vec<String>, Vec<String>), Error> {
+	let mut order: Vec<(String, bool)> = vec![];
+	let mut sources: HashMap<String, Vec<(bool, &'static str)>> = HashMap::new();
+	for list in lists {
+		for name in &list.names {
+			let name = name.trim().to_lowercase();
+			if name.is_empty() {
+				continue;
+			}
+			let seen = sources.entry(name.clone()).or_default();
+			if seen.is_empty() {
+				order.push((name, list.aur));
+			}
+			if !seen.contains(&(list.aur, list.source)) {
+				seen.push((list.aur, list.source));
+			}
+		}
+	}
+
+	let conflicts: Vec<String> = order
+		.iter()
+		.filter(|(name, _)| {
+			let seen = &sources[name];
+			return seen.iter().any(|(aur, _)| *aur) && seen.iter().any(|(aur, _)| !*aur);
+		})
+		.map(|(name, _)| {
+			let from = sources[name]
+				.iter()
+				.map(|(_, source)| *source)
+				.collect::<Vec<&str>>()
+				.join(", ");
+			return format!("{name} (from {from})");
+		})
+		.collect();
+	if !conflicts.is_empty() {
+		return Err(Error::PackageConflict(conflicts.join("\n  ")));
+	}
+
+	let (mut repo, mut aur) = (vec![], vec![]);
+	for (name, is_aur) in order {
+		if is_aur {
+			aur.push(name);
+		} else {
+			repo.push(name);
+		}
+	}
+
+	return Ok((repo, aur));
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn options(flags: &[&str]) -> Result<SessionOptions, Error> {
+		let args = Args::from_flags(flags);
+		return SessionOptions::from_args(&args, &Config::default());
+	}
+
+	/// `count` distinct package names, space separated like `--extra-packages` takes them.
+	fn packages(prefix: &str, count: usize) -> String {
+		return (0..count)
+			.map(|i| format!("{prefix}{i}"))
+			.collect::<Vec<String>>()
+			.join(" ");
+	}
+
+	#[test]
+	fn chunks_at_the_boundaries() {
+		for (count, steps) in [(0, 0), (1, 1), (99, 1), (100, 1), (101, 2), (200, 2), (201, 3)] {
+			let options = options(&["-p", &packages("pkg", count)]).unwrap();
+			assert_eq!(options.install_steps(100), steps, "{count} packages");
+			let chunks: Vec<&[String]> = options.packages.chunks(100).collect();
+			assert_eq!(chunks.len(), steps, "{count} packages");
+			assert!(chunks.iter().all(|c| !c.is_empty() && c.len() <= 100));
+			assert_eq!(chunks.concat(), options.packages, "chunks lost or reordered packages");
+		}
+	}
+
+	#[test]
+	fn chunks_repository_and_aur_packages_separately() {
+		let options = options(&["-p", &packages("pkg", 3), "-a", &packages("aur", 3)]).unwrap();
+		assert_eq!(options.install_steps(2), 4);
+		assert_eq!(options.install_steps(3), 2);
+		assert_eq!(options.install_steps(1), 6);
+	}
+
+	#[test]
+	fn duplicates_do_not_take_a_chunk() {
+		// 100 distinct names, each requested twice and in both cases
+		let twice = format!("{} {}", packages("pkg", 100), packages("PKG", 100));
+		let options = options(&["-p", &twice]).unwrap();
+		assert_eq!(options.packages.len(), 100);
+		assert_eq!(options.install_steps(100), 1);
+	}
+
+	#[test]
+	fn caps_the_packages_after_dropping_duplicates() {
+		let at_cap = options(&["-p", &packages("pkg", MAX_PACKAGES - 1), "-a", "aur"]).unwrap();
+		assert!(at_cap.check_package_count().is_ok());
+
+		let over = options(&["-p", &packages("pkg", MAX_PACKAGES), "-a", "aur"]).unwrap();
+		assert!(matches!(over.check_package_count(), Err(Error::TooManyPackages(1001, MAX_PACKAGES))));
+
+		let repeated = format!("{} {}", packages("pkg", MAX_PACKAGES), packages("pkg", MAX_PACKAGES));
+		assert!(options(&["-p", &repeated]).unwrap().check_package_count().is_ok());
+	}
+
+	#[test]
+	fn refuses_a_package_from_both_sources() {
+		assert!(matches!(options(&["-p", "git vim", "-a", "VIM"]), Err(Error::PackageConflict(_))));
+	}
+
+	#[test]
+	fn merges_sources_in_order_of_first_appearance() {
+		let lists = [
+			PackageList::new("config.toml packages", false, ["git".into(), "vim".into()]),
+			PackageList::new("config.toml aur-packages", true, ["yay-bin".into()]),
+			PackageList::new("tempsystem.toml packages", false, ["Rustup".into(), "git".into()]),
+			PackageList::new("--extra-packages", false, ["  ".into(), "ripgrep".into(), "VIM".into(), "rustup".into()]),
+			PackageList::new("--extra-aur-packages", true, ["paru".into(), "YAY-BIN".into()]),
+		];
+		let (repo, aur) = resolve_packages(&lists).unwrap();
+		assert_eq!(repo, ["git", "vim", "rustup", "ripgrep"]);
+		assert_eq!(aur, ["yay-bin", "paru"]);
+		assert_eq!(resolve_packages(&[]).unwrap(), (vec![], vec![]));
+	}
+
+	#[test]
+	fn merges_the_config_and_project_before_the_command_line() {
+		let mut config = Config {
+			packages: vec!["git".into(), "base-devel".into()],
+			aur_packages: vec!["yay-bin".into()],
+			..Config::default()
+		};
+		config.project = Some((
+			"/work/tempsystem.toml".into(),
+			crate::config::ProjectConfig {
+				packages: vec!["cmake".into(), "git".into()],
+				..Default::default()
+			},
+		));
+		let args = Args::from_flags(&["-p", "ninja Git", "-a", "yay-bin"]);
+		let options = SessionOptions::from_args(&args, &config).unwrap();
+		assert_eq!(options.packages, ["git", "base-devel", "cmake", "ninja"]);
+		assert_eq!(options.aur_packages, ["yay-bin"]);
+		assert_eq!(options.describe_packages(), "packages: git base-devel cmake ninja\naur packages: yay-bin");
+		assert_eq!(SessionOptions::from_args(&Args::from_flags(&[]), &Config::default()).unwrap().describe_packages(), "packages: none\naur packages: none");
+	}
+
+	#[test]
+	fn reports_every_conflict_with_its_sources() {
+		let lists = [
+			PackageList::new("config.toml packages", false, ["neovim".into(), "git".into()]),
+			PackageList::new("tempsystem.toml aur-packages", true, ["git".into(), "Neovim".into()]),
+			PackageList::new("--extra-packages", false, ["neovim".into(), "ripgrep".into()]),
+			PackageList::new("--extra-aur-packages", true, ["git".into(), "paru".into()]),
+		];
+		let Err(Error::PackageConflict(conflicts)) = resolve_packages(&lists) else {
+			panic!("expected a conflict");
+		};
+		// each source once per name, in the order the name was first requested in
+		assert_eq!(
+			conflicts,
+			"neovim (from config.toml packages, tempsystem.toml aur-packages, --extra-packages)\n  \
+			 git (from config.toml packages, tempsystem.toml aur-packages, --extra-aur-packages)"
+		);
+	}
+}
@@ -0,0 +1,294 @@
+use crate::{
+	Args, UpdateSystem,
+	classify::{self, Phase},
+	docker::{self, CHAOTIC_AUR_SETUP, LANDWARE_SETUP, chunk_message, classified_error, get_error_from_either, get_error_from_pacman, quote_all},
+	error::Error,
+	features::{self, ImageConfig, ImageFeatures},
+	pacman, print_error,
+	redact::redact,
+	session::{self, SessionOptions},
+};
+
+/// Where image configuration comes from, for working out the user's home before a system exists.
+pub trait ImageSource {
+	async fn image_config(&self, image: &str) -> Result<ImageConfig, Error>;
+
+	/// Runs `features::HOME_PROBE_SCRIPT` for `user` in a throwaway system, returning the home it found.
+	async fn probe_home(&self, image: &str, user: &str) -> Result<Option<String>, Error>;
+}
+
+/// A started system the setup phases run in, so they run the same whichever backend it is on.
+pub trait Target {
+	fn features(&self) -> &ImageFeatures;
+
+	/// Starts the next of `total` steps.
+	fn phase(&self, cur: usize, total: usize, msg: String);
+
+	async fn run_as_root(&self, script: &str) -> Result<(i64, String), Error>;
+
+	/// Runs `command` as root the way the system's `Privilege` says, failing with what is missing for `operation` when
+	/// neither works.
+	async fn run_privileged(&self, operation: &'static str, command: &str) -> Result<(i64, String), Error>;
+
+	/// Runs `command` through the image's shell as its user.
+	async fn run_as_user(&self, command: &str) -> Result<(i64, String), Error>;
+
+	/// Runs a pacman command, as root for `privileged`, the operation it names, reporting its progress where the backend can.
+	async fn run_pacman(&self, command: String, privileged: Option<&'static str>) -> Result<(i64, String), Error>;
+
+	/// The error for output a phase failed with.
+	async fn classify_failure(&self, phase: Phase, output: &str, otherwise: impl FnOnce() -> Error) -> Error {
+		return classified_error(phase, output, otherwise);
+	}
+}
+
+/// Where the user of `image` has their home: what the image declares, else what `features::HOME_PROBE_SCRIPT` finds in a
+/// throwaway system, else `features::DEFAULT_HOME`, where `features::BOOTSTRAP_USER_SCRIPT` would create it.
+pub async fn resolve_home(images: &impl ImageSource, image: &str, verbose: bool) -> String {
+	if session::is_own_image(image) {
+		return features::DEFAULT_HOME.into();
+	}
+	let config = match images.image_config(image).await {
+		Ok(config) => config,
+		Err(e) => {
+			if verbose {
+				print_error!("could not inspect the image for its user's home", e);
+			}
+			return features::DEFAULT_HOME.into();
+		}
+	};
+	if let Some(home) = config.home() {
+		return home;
+	}
+	let user = config.user().unwrap_or(features::DEFAULT_USER.into());
+	return match images.probe_home(image, &user).await {
+		Ok(home) => home.unwrap_or(features::DEFAULT_HOME.into()),
+		Err(e) => {
+			if verbose {
+				print_error!("could not look up the user's home", e);
+			}
+			features::DEFAULT_HOME.into()
+		}
+	};
+}
+
+/// Runs the repository, update, and package phases, returning the step counter after them.
+pub async fn run(target: &impl Target, args: &Args, options: &SessionOptions, mut cur: usize, total: usize) -> Result<usize, Error> {
+	// the clock is the host's, but a mismatched timezone still makes timestamps look off
+	if !args.ro_root
+		&& let Some(zone) = docker::host_timezone()
+	{
+		target.run_as_root(&docker::timezone_script(&zone)).await?;
+	}
+	if args.chaotic_aur {
+		target.phase(cur, total, "Adding Chaotic-AUR".into());
+		let (status, output) = target
+			.run_privileged("adding Chaotic-AUR", CHAOTIC_AUR_SETUP)
+			.await?;
+		if args.verbose {
+			println!("{}", redact(&output));
+		}
+		if status != 0 {
+			return Err(classified_error(Phase::Packages, &output, || Error::ChaoticAUR(status, get_error_from_either(&output))));
+		}
+		cur += 1;
+	}
+	if args.landware {
+		target.phase(cur, total, "Adding landware".into());
+		let (status, output) = target
+			.run_privileged("adding landware", LANDWARE_SETUP)
+			.await?;
+		if args.verbose {
+			println!("{}", redact(&output));
+		}
+		if status != 0 {
+			return Err(classified_error(Phase::Packages, &output, || Error::Landware(status)));
+		}
+		cur += 1;
+	}
+	if args.update_system == Some(UpdateSystem::Foreground) {
+		target.phase(cur, total, "Updating system".into());
+		update_system(target, args.verbose).await?;
+		cur += 1;
+	}
+	if args.update_pkgfile {
+		target.phase(cur, total, "Updating pkgfile database".into());
+		let (status, output) = target
+			.run_privileged("updating the pkgfile database", "pkgfile -u")
+			.await?;
+		if args.verbose {
+			println!("{}", redact(&output));
+		}
+		if status != 0 {
+			return Err(classified_error(Phase::Setup, &output, || Error::Pkgfile(status)));
+		}
+		cur += 1;
+	}
+	let chunk_size = args.install_chunk_size as usize;
+	cur += install_packages(target, args.verbose, cur, total, &options.packages, chunk_size).await?;
+	cur += install_aur_packages(target, args.verbose, cur, total, &options.aur_packages, chunk_size).await?;
+	if !options.toolchain_setup.is_empty() {
+		target.phase(cur, total, "Setting up toolchains".into());
+		for command in &options.toolchain_setup {
+			let (status, output) = target.run_as_user(command).await?;
+			if args.verbose {
+				println!("{}", redact(&output));
+			}
+			if status != 0 {
+				return Err(Error::ToolchainSetup(command.clone(), status, output.trim().to_string()));
+			}
+		}
+		cur += 1;
+	}
+
+	return Ok(cur);
+}
+
+/// Checks all of `packages` exist in the repositories with a single exec, which pacman
+/// answers with one `target not found` error per unknown name.
+async fn check_packages_exist(target: &impl Target, verbose: bool, packages: &[String]) -> Result<(), Error> {
+	let (status, output) = target
+		.run_as_user(&format!("/bin/pacman -Sp --print-format %n {}", quote_all(packages)))
+		.await?;
+	if verbose {
+		println!("{}", redact(&output));
+	}
+	let missing = pacman::targets_not_found(&output);
+	if !missing.is_empty() {
+		return Err(Error::PackageDNE(missing.join(", ")));
+	}
+	if status != 0 {
+		return Err(classified_error(Phase::Packages, &output, || Error::PackageInstall(status, get_error_from_pacman(&output))));
+	}
+
+	return Ok(());
+}
+
+/// Same as `check_packages_exist`, but for the AUR, asking the helper for every package's info at once.
+async fn check_aur_packages_exist(target: &impl Target, verbose: bool, helper: &str, packages: &[String]) -> Result<(), Error> {
+	let (_, output) = target
+		.run_as_user(&format!("{helper} --aur -Si {}", quote_all(packages)))
+		.await?;
+	if verbose {
+		println!("{}", redact(&output));
+	}
+	let found = pacman::info_names(&output);
+	let missing: Vec<&str> = packages
+		.iter()
+		.filter(|p| !found.contains(p))
+		.map(String::as_str)
+		.collect();
+	if !missing.is_empty() {
+		return Err(Error::PackageDNE(missing.join(", ")));
+	}
+
+	return Ok(());
+}
+
+/// Checks every package exists with one exec, then installs them in chunks of `chunk_size` with one exec
+/// and step each; returns the number of steps taken.
+pub async fn install_packages(
+	target: &impl Target,
+	verbose: bool,
+	current_task: usize,
+	total_tasks: usize,
+	packages: &[String],
+	chunk_size: usize,
+) -> Result<usize, Error> {
+	if packages.is_empty() {
+		return Ok(0);
+	}
+	let chunks: Vec<&[String]> = packages.chunks(chunk_size).collect();
+	target.phase(current_task, total_tasks, chunk_message("Installing packages", 0, chunks.len()));
+	check_packages_exist(target, verbose, packages).await?;
+	for (i, chunk) in chunks.iter().enumerate() {
+		target.phase(i + current_task, total_tasks, chunk_message("Installing packages", i, chunks.len()));
+		let (status, output) = run_transaction(
+			target,
+			format!("/bin/pacman -S --needed --noconfirm {}", quote_all(chunk)),
+			Some("installing packages"),
+			verbose,
+		)
+		.await?;
+		if status != 0 {
+			return Err(target
+				.classify_failure(Phase::Packages, &output, || Error::PackageInstall(status, get_error_from_pacman(&output)))
+				.await);
+		}
+	}
+
+	return Ok(chunks.len());
+}
+
+async fn install_aur_packages(
+	target: &impl Target,
+	verbose: bool,
+	current_task: usize,
+	total_tasks: usize,
+	packages: &[String],
+	chunk_size: usize,
+) -> Result<usize, Error> {
+	if packages.is_empty() {
+		return Ok(0);
+	}
+	let helper = target
+		.features()
+		.aur_helper()
+		.ok_or(Error::MissingFeature("an AUR helper (yay or paru)", "--extra-aur-packages"))?;
+	let chunks: Vec<&[String]> = packages.chunks(chunk_size).collect();
+	target.phase(current_task, total_tasks, chunk_message("Installing packages from AUR", 0, chunks.len()));
+	check_aur_packages_exist(target, verbose, helper, packages).await?;
+	for (i, chunk) in chunks.iter().enumerate() {
+		target.phase(i + current_task, total_tasks, chunk_message("Installing packages from AUR", i, chunks.len()));
+		let (status, output) = run_transaction(target, format!("{helper} --sync --needed --noconfirm --noprogressbar {}", quote_all(chunk)), None, verbose).await?;
+		if status != 0 {
+			return Err(target
+				.classify_failure(Phase::Packages, &output, || Error::PackageInstall(status, get_error_from_pacman(&output)))
+				.await);
+		}
+	}
+
+	return Ok(chunks.len());
+}
+
+async fn update_system(target: &impl Target, verbose: bool) -> Result<(), Error> {
+	let (status, output) = run_transaction(target, "/bin/pacman -Syu --noconfirm".into(), Some("updating the system"), verbose).await?;
+	if status != 0 {
+		return Err(target
+			.classify_failure(Phase::Packages, &output, || Error::SystemUpdate(status, get_error_from_pacman(&output)))
+			.await);
+	}
+
+	return Ok(());
+}
+
+/// Runs a pacman transaction, and once more after `FailureKind::retry_script` when it failed in a way that
+/// usually goes away with it, like a keyring older than the packages. `privileged` names the operation when it needs root.
+async fn run_transaction(target: &impl Target, command: String, privileged: Option<&'static str>, verbose: bool) -> Result<(i64, String), Error> {
+	let (status, output) = target.run_pacman(command.clone(), privileged).await?;
+	if verbose {
+		println!("{}", redact(&output));
+	}
+	if status == 0 {
+		return Ok((status, output));
+	}
+	let Some(fix) = classify::classify(Phase::Packages, &output).retry_script() else {
+		return Ok((status, output));
+	};
+	if verbose {
+		println!("retrying after `{fix}`");
+	}
+	let (fix_status, fix_output) = target.run_as_root(fix).await?;
+	if verbose {
+		println!("{}", redact(&fix_output));
+	}
+	if fix_status != 0 {
+		return Ok((status, output));
+	}
+	let (status, output) = target.run_pacman(command, privileged).await?;
+	if verbose {
+		println!("{}", redact(&output));
+	}
+
+	return Ok((status, output));
+}
@@ -0,0 +1,213 @@
+use std::io::{Read, Write};
+
+use base64::Engine as _;
+use flate2::{Compression, read::DeflateDecoder, write::DeflateEncoder};
+
+use crate::{
+	error::Error,
+	redact::redact,
+	session::{Flavor, SessionOptions},
+};
+
+/// What `tempsystem share` strings start with; `--from-share` also takes them without it.
+pub const PREFIX: &str = "tempsystem://share/";
+
+/// Bumped whenever a string made by this version could mean something else to an older one, which then refuses it.
+const FORMAT_VERSION: u8 = 1;
+
+/// `options` as a `tempsystem://share/` string: the format version, then the options as deflated JSON, in URL-safe
+/// base64. The options hold no host paths, since the home and the mounts' sources are worked out where they run;
+/// options that look like they hold a secret are refused.
+pub fn encode(options: &SessionOptions) -> Result<String, Error> {
+	let json = options.to_label();
+	if redact(&json) != json {
+		return Err(Error::ShareSecret);
+	}
+	let mut encoder = DeflateEncoder::new(vec![FORMAT_VERSION], Compression::best());
+	let payload = encoder
+		.write_all(json.as_bytes())
+		.and_then(|()| encoder.finish())
+		.map_err(|e| Error::ShareInvalid(e.to_string()))?;
+
+	return Ok(format!("{PREFIX}{}", base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(payload)));
+}
+
+/// The options a `tempsystem share` string stands for.
+pub fn decode(share: &str) -> Result<SessionOptions, Error> {
+	let share = share.trim();
+	let payload = base64::engine::general_purpose::URL_SAFE_NO_PAD
+		.decode(share.strip_prefix(PREFIX).unwrap_or(share))
+		.map_err(|e| Error::ShareInvalid(e.to_string()))?;
+	let Some((&version, deflated)) = payload.split_first() else {
+		return Err(Error::ShareInvalid("it is empty".into()));
+	};
+	if version > FORMAT_VERSION {
+		return Err(Error::ShareTooNew(version, FORMAT_VERSION));
+	}
+	let mut json = String::new();
+	DeflateDecoder::new(deflated)
+		.read_to_string(&mut json)
+		.map_err(|e| Error::ShareInvalid(e.to_string()))?;
+	let mut options: SessionOptions = serde_json::from_str(&json).map_err(|e| Error::ShareInvalid(e.to_string()))?;
+	options.flavor = Flavor::Image(options.image.clone());
+	options.home = crate::features::DEFAULT_HOME.into();
+
+	return Ok(options);
+}
+
+/// What a shared system would be like, one setting per line.
+pub fn describe(options: &SessionOptions) -> String {
+	let mut lines = vec![format!("image: {}", options.image)];
+	lines.push(format!(
+		"network: {}",
+		match (options.network, options.allow_host.is_empty()) {
+			(false, _) => "off".to_string(),
+			(true, true) => "on".to_string(),
+			(true, false) => format!("only {}", options.allow_host.join(", ")),
+		}
+	));
+	lines.push(options.describe_packages());
+	let mounts: Vec<&str> = [
+		("the current directory", options.mount_cwd && !options.ro_cwd),
+		("the current directory, read-only", options.mount_cwd && options.ro_cwd),
+		("a copy of the current directory", options.copy_cwd),
+		("the current directory under an overlay", options.ro_cwd_overlay),
+		("the zsh history", options.mount_history),
+	]
+	.into_iter()
+	.filter_map(|(mount, on)| on.then_some(mount))
+	.collect();
+	lines.push(format!("mounts: {}", if mounts.is_empty() { "none".into() } else { mounts.join(", ") }));
+
+	return lines.join("\n");
+}
+
+/// What a shared system would do that deserves a look before running it.
+pub fn dangers(options: &SessionOptions) -> Vec<String> {
+	let mut dangers = vec![];
+	if options.privileged_full {
+		dangers.push("it runs with --privileged-full, which gives it the host's devices".into());
+	} else if options.privileged {
+		dangers.push("it runs --privileged".into());
+	}
+	if options.mount_history {
+		dangers.push("it mounts the zsh history of this machine, and can write to it".into());
+	}
+	if !options.aur_packages.is_empty() {
+		dangers.push(format!("it builds AUR packages, which run their own scripts: {}", options.aur_packages.join(" ")));
+	}
+	for (repo, used) in [("chaotic-aur", options.chaotic_aur), ("landware", options.landware)] {
+		if used {
+			dangers.push(format!("it adds the {repo} repository"));
+		}
+	}
+	if !options.toolchain_setup.is_empty() {
+		dangers.push(format!("it runs these commands: {}", options.toolchain_setup.join("; ")));
+	}
+	if !crate::session::is_own_image(&options.image) {
+		dangers.push(format!("it uses the image `{}`, which is not a tempsystem image", options.image));
+	}
+
+	return dangers;
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::{Args, config::Config, limits::Ulimit, rng::Rng};
+
+	const WORDS: &[&str] = &["git", "base-devel", "rust", "yay-bin", "0,1", "example.com", "make -j4", "x y", "ä", ""];
+
+	fn word(rng: &mut Rng) -> String {
+		return rng.pick(WORDS).to_string();
+	}
+
+	fn words(rng: &mut Rng) -> Vec<String> {
+		return (0..rng.next_u64() % 4).map(|_| word(rng)).collect();
+	}
+
+	fn maybe<T>(rng: &mut Rng, value: impl FnOnce(&mut Rng) -> T) -> Option<T> {
+		return (rng.next_u64().is_multiple_of(2)).then(|| value(rng));
+	}
+
+	/// Options with every shared setting picked by `rng`, as `decode` gives them back.
+	fn random_options(rng: &mut Rng) -> SessionOptions {
+		let mut flag = || rng.next_u64().is_multiple_of(2);
+		let flags: [bool; 11] = std::array::from_fn(|_| flag());
+		let mut options = SessionOptions::from_args(&Args::from_flags(&[]), &Config::default()).unwrap();
+		options.image = format!("{}:{}", rng.pick(&["archlinux", "ghcr.io/owner/image", "localhost:5000/a/b"]), rng.next_u64() % 100);
+		options.flavor = Flavor::Image(options.image.clone());
+		options.home = crate::features::DEFAULT_HOME.into();
+		[
+			options.network,
+			options.privileged,
+			options.privileged_full,
+			options.ro_root,
+			options.ro_cwd,
+			options.ro_cwd_overlay,
+			options.mount_cwd,
+			options.copy_cwd,
+			options.mount_history,
+			options.chaotic_aur,
+			options.landware,
+		] = flags;
+		options.allow_host = words(rng);
+		options.restrict_cpu = maybe(rng, |rng| rng.next_u64() as u8);
+		options.restrict_memory = maybe(rng, |rng| rng.next_u64() as usize);
+		options.nano_cpus = maybe(rng, |rng| rng.next_u64() as i64);
+		options.cpuset_cpus = maybe(rng, word);
+		options.pids_limit = maybe(rng, |rng| rng.next_u64() as i64);
+		options.ulimits = (0..rng.next_u64() % 3)
+			.map(|_| Ulimit {
+				name: rng.pick(&["nofile", "nproc", "core"]).to_string(),
+				soft: rng.next_u64() as i64,
+				hard: -1,
+			})
+			.collect();
+		options.memory = maybe(rng, Rng::next_u64);
+		options.memory_swap = maybe(rng, Rng::next_u64);
+		options.storage_size = maybe(rng, Rng::next_u64);
+		options.shm_size = maybe(rng, Rng::next_u64);
+		options.cgroup_parent = maybe(rng, word);
+		options.packages = words(rng);
+		options.aur_packages = words(rng);
+		options.toolchain_setup = words(rng);
+		return options;
+	}
+
+	#[test]
+	fn round_trips_random_options() {
+		for seed in 0..200 {
+			let options = random_options(&mut Rng::new(Some(seed)));
+			let share = encode(&options).unwrap();
+			assert!(share.starts_with(PREFIX), "{share}");
+			assert_eq!(decode(&share).unwrap(), options, "seed {seed}");
+			// without the prefix, and pasted with whitespace around it
+			assert_eq!(decode(&format!(" {}\n", &share[PREFIX.len()..])).unwrap(), options, "seed {seed}");
+		}
+	}
+
+	/// A share string of `json` in format `version`.
+	fn share(version: u8, json: &str) -> String {
+		let mut encoder = DeflateEncoder::new(vec![version], Compression::best());
+		encoder.write_all(json.as_bytes()).unwrap();
+		return format!("{PREFIX}{}", base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(encoder.finish().unwrap()));
+	}
+
+	#[test]
+	fn refuses_a_newer_format() {
+		let json = random_options(&mut Rng::new(Some(0))).to_label();
+		assert!(decode(&share(FORMAT_VERSION, &json)).is_ok());
+		let version = FORMAT_VERSION + 1;
+		assert!(matches!(decode(&share(version, &json)), Err(Error::ShareTooNew(v, FORMAT_VERSION)) if v == version));
+		// refused before the rest is even read
+		assert!(matches!(decode(&share(version, "not json")), Err(Error::ShareTooNew(..))));
+	}
+
+	#[test]
+	fn refuses_what_share_did_not_print() {
+		for garbage in [PREFIX.to_string(), format!("{PREFIX}not base64!"), share(FORMAT_VERSION, "{}"), share(FORMAT_VERSION, "")] {
+			assert!(matches!(decode(&garbage), Err(Error::ShareInvalid(_))), "{garbage}");
+		}
+	}
+}
@@ -0,0 +1,103 @@
+use serde::{Deserialize, Deserializer, de::Error as _};
+
+/// Parses sizes like `512m`, `4g`, or `1.5GiB` in binary units, the way docker does; a bare number is bytes.
+pub fn parse_size(s: &str) -> Result<u64, String> {
+	let lower = s.trim().to_ascii_lowercase();
+	let (number, unit) = lower.split_at(
+		lower
+			.find(|c: char| !c.is_ascii_digit() && c != '.')
+			.unwrap_or(lower.len()),
+	);
+	let number: f64 = number
+		.parse()
+		.map_err(|_| format!("`{s}` is not a size like 512m or 4g"))?;
+	let shift = match unit.trim_end_matches("ib").trim_end_matches('b') {
+		"" => 0,
+		"k" => 10,
+		"m" => 20,
+		"g" => 30,
+		"t" => 40,
+		_ => return Err(format!("unknown unit `{unit}`; use b, k, m, g, or t")),
+	};
+	let bytes = number * (1u64 << shift) as f64;
+	if !bytes.is_finite() || bytes < 1.0 || bytes >= u64::MAX as f64 {
+		return Err(format!("`{s}` is not a size tempsystem can use"));
+	}
+
+	return Ok(bytes as u64);
+}
+
+/// Deserializes an optional size written like on the command line, as in `shm-size = "1g"`.
+pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<u64>, D::Error> {
+	return Option::<String>::deserialize(deserializer)?
+		.map(|s| parse_size(&s).map_err(D::Error::custom))
+		.transpose();
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	const KIB: u64 = 1024;
+	const MIB: u64 = 1024 * KIB;
+	const GIB: u64 = 1024 * MIB;
+
+	#[test]
+	fn parses_binary_units() {
+		for (s, bytes) in [
+			("1", 1),
+			("4096", 4096),
+			("1b", 1),
+			("1k", KIB),
+			("1kb", KIB),
+			("1KiB", KIB),
+			("512m", 512 * MIB),
+			("512M", 512 * MIB),
+			("512mb", 512 * MIB),
+			("4g", 4 * GIB),
+			("4GiB", 4 * GIB),
+			("1.5GiB", 3 * GIB / 2),
+			(".5k", 512),
+			("2t", 2048 * GIB),
+			(" 64m ", 64 * MIB),
+		] {
+			assert_eq!(parse_size(s), Ok(bytes), "{s}");
+		}
+	}
+
+	#[test]
+	fn rounds_fractions_down_to_bytes() {
+		assert_eq!(parse_size("1.5"), Ok(1));
+		assert_eq!(parse_size("0.3k"), Ok(307));
+	}
+
+	#[test]
+	fn rejects_what_is_not_a_size() {
+		for s in ["", "m", "g4", "1.2.3m", "-1m", "abc"] {
+			assert_eq!(parse_size(s), Err(format!("`{s}` is not a size like 512m or 4g")), "{s}");
+		}
+		assert_eq!(parse_size("4x"), Err("unknown unit `x`; use b, k, m, g, or t".into()));
+		assert_eq!(parse_size("4 g"), Err("unknown unit ` g`; use b, k, m, g, or t".into()));
+		assert_eq!(parse_size("1p"), Err("unknown unit `p`; use b, k, m, g, or t".into()));
+	}
+
+	#[test]
+	fn rejects_sizes_that_cannot_be_used() {
+		for s in ["0", "0m", "0.5", "99999999t"] {
+			assert_eq!(parse_size(s), Err(format!("`{s}` is not a size tempsystem can use")), "{s}");
+		}
+	}
+
+	#[test]
+	fn deserializes_from_config() {
+		#[derive(Deserialize)]
+		struct Config {
+			#[serde(default, deserialize_with = "deserialize")]
+			size: Option<u64>,
+		}
+		assert_eq!(toml::from_str::<Config>("size = \"1g\"").unwrap().size, Some(GIB));
+		assert_eq!(toml::from_str::<Config>("").unwrap().size, None);
+		let e = toml::from_str::<Config>("size = \"lots\"").err().unwrap();
+		assert!(e.to_string().contains("`lots` is not a size"), "{e}");
+	}
+}
@@ -0,0 +1,66 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Repository `--autosnapshot` commits to; tags are `<system name>-<unix time>`.
+pub const REPOSITORY: &str = "tempsystem-snapshot";
+/// Set on snapshot images, holding the name of the system they were taken from.
+pub const LABEL_SNAPSHOT: &str = "dev.tempsystem.snapshot";
+
+pub const DEFAULT_KEEP: u64 = 2;
+/// Committing copies the whole writable layer, so shorter intervals would keep the daemon busy most of the session.
+pub const MIN_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Parses intervals like `90s`, `15m`, or `1h`; a bare number is minutes.
+pub fn parse_interval(s: &str) -> Result<Duration, String> {
+	let s = s.trim();
+	let (number, unit) = match s.find(|c: char| !c.is_ascii_digit()) {
+		Some(at) => s.split_at(at),
+		None => (s, "m"),
+	};
+	let number: u64 = number
+		.parse()
+		.map_err(|_| format!("`{s}` is not an interval like 90s, 15m, or 1h"))?;
+	let seconds = match unit {
+		"s" => number,
+		"m" => number * 60,
+		"h" => number * 60 * 60,
+		_ => return Err(format!("unknown unit `{unit}`; use s, m, or h")),
+	};
+	let interval = Duration::from_secs(seconds);
+	if interval < MIN_INTERVAL {
+		return Err(format!("snapshots can be taken at most once every {}s", MIN_INTERVAL.as_secs()));
+	}
+
+	return Ok(interval);
+}
+
+/// The tag for a snapshot of `system` taken now, which sorts after the ones taken before it. With `--seed`, the
+/// `sequence` number of the snapshot in the session stands in for the time, so tags repeat between runs.
+pub fn tag(system: &str, sequence: Option<u64>) -> String {
+	let now = sequence.unwrap_or_else(|| {
+		SystemTime::now()
+			.duration_since(UNIX_EPOCH)
+			.unwrap_or_default()
+			.as_secs()
+	});
+	return format!("{system}-{now}");
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn seeded_tags_repeat_between_runs() {
+		let run = || (1..=3).map(|sequence| tag("tempsystem-brave-otter", Some(sequence))).collect::<Vec<String>>();
+		assert_eq!(run(), ["tempsystem-brave-otter-1", "tempsystem-brave-otter-2", "tempsystem-brave-otter-3"]);
+		assert_eq!(run(), run());
+	}
+
+	#[test]
+	fn unseeded_tags_use_the_time() {
+		let before = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
+		let tag = tag("system", None);
+		let time: u64 = tag.strip_prefix("system-").unwrap().parse().unwrap();
+		assert!(time >= before, "{tag}");
+	}
+}
@@ -0,0 +1,112 @@
+use std::{
+	fs::File,
+	io::Write,
+	path::{Path, PathBuf},
+};
+
+use crate::color;
+
+/// The file locked while `path` is read and rewritten, next to it so it never has to be renamed over.
+fn lock_path(path: &Path) -> PathBuf {
+	let mut name = path.file_name().unwrap_or_default().to_os_string();
+	name.push(".lock");
+	return path.with_file_name(name);
+}
+
+/// Holds the lock on `path` until dropped, so tempsystems running side by side take turns updating it.
+pub fn lock(path: &Path) -> std::io::Result<File> {
+	if let Some(parent) = path.parent() {
+		std::fs::create_dir_all(parent)?;
+	}
+	let file = File::options()
+		.create(true)
+		.truncate(false)
+		.write(true)
+		.open(lock_path(path))?;
+	file.lock()?;
+	return Ok(file);
+}
+
+/// Replaces `path` with `contents` by writing a temporary file next to it and renaming that over it,
+/// so readers see either the old contents or the new, never half of them.
+pub fn write_atomic(path: &Path, contents: &[u8]) -> std::io::Result<()> {
+	if let Some(parent) = path.parent() {
+		std::fs::create_dir_all(parent)?;
+	}
+	let mut name = path.file_name().unwrap_or_default().to_os_string();
+	name.push(format!(".{}.tmp", std::process::id()));
+	let temp = path.with_file_name(name);
+	let written = (|| -> std::io::Result<()> {
+		let mut file = File::create(&temp)?;
+		file.write_all(contents)?;
+		file.sync_all()?;
+		return std::fs::rename(&temp, path);
+	})();
+	if written.is_err() {
+		let _ = std::fs::remove_file(&temp);
+	}
+	return written;
+}
+
+/// Reads `path` and writes back what `update` makes of its contents, holding the lock in between so no one else's update is lost.
+/// The contents are empty when there is no file yet. Returns what `update` returns.
+pub fn update<T>(path: &Path, update: impl FnOnce(&str) -> std::io::Result<(Vec<u8>, T)>) -> std::io::Result<T> {
+	let _lock = lock(path)?;
+	let contents = match std::fs::read_to_string(path) {
+		Ok(contents) => contents,
+		Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+		Err(e) => return Err(e),
+	};
+	let (contents, result) = update(&contents)?;
+	write_atomic(path, &contents)?;
+	return Ok(result);
+}
+
+/// Warns that `skipped` records of `path` could not be read and were left out.
+pub fn warn_skipped(path: &Path, skipped: usize) {
+	if skipped > 0 {
+		eprintln!(
+			"{}",
+			color::yellow(format!("skipped {skipped} unreadable record{} in {}", if skipped == 1 { "" } else { "s" }, path.display()))
+		);
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn concurrent_updates_keep_every_record() {
+		const WRITERS: usize = 8;
+		const UPDATES: usize = 25;
+		let dir = std::env::temp_dir().join(format!("tempsystem-statefile-{}", std::process::id()));
+		let _ = std::fs::remove_dir_all(&dir);
+		let path = dir.join("history.jsonl");
+
+		std::thread::scope(|scope| {
+			for writer in 0..WRITERS {
+				let path = &path;
+				scope.spawn(move || {
+					for i in 0..UPDATES {
+						update(path, |contents| Ok((format!("{contents}{writer}-{i}\n").into_bytes(), ()))).unwrap();
+					}
+				});
+			}
+		});
+
+		let contents = std::fs::read_to_string(&path).unwrap();
+		let mut records: Vec<&str> = contents.lines().collect();
+		assert_eq!(records.len(), WRITERS * UPDATES);
+		records.sort();
+		records.dedup();
+		assert_eq!(records.len(), WRITERS * UPDATES);
+		let leftovers: Vec<_> = std::fs::read_dir(&dir)
+			.unwrap()
+			.map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+			.filter(|name| name.ends_with(".tmp"))
+			.collect();
+		assert!(leftovers.is_empty(), "{leftovers:?}");
+		std::fs::remove_dir_all(&dir).unwrap();
+	}
+}
@@ -0,0 +1,174 @@
+use std::{path::PathBuf, time::Duration};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{config, statefile};
+
+/// How many setups are remembered; older ones are dropped as new ones are recorded.
+pub const HISTORY_LIMIT: usize = 200;
+
+/// How many similar setups an estimate needs before it is shown.
+pub const MIN_SIMILAR: usize = 3;
+
+/// How long one setup took, with what decides its length the most.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct SetupRecord {
+	/// whether the image had to be downloaded first
+	pub pulled: bool,
+	pub packages: usize,
+	pub aur_packages: usize,
+	pub seconds: f64,
+}
+
+/// `$XDG_STATE_HOME/tempsystem/setup-history.jsonl`, one `SetupRecord` per line.
+pub fn history_path() -> Option<PathBuf> {
+	return config::state_dir().map(|d| d.join("setup-history.jsonl"));
+}
+
+/// The records in `contents`, with how many lines could not be read, like one cut short.
+fn parse(contents: &str) -> (Vec<SetupRecord>, usize) {
+	let mut skipped = 0;
+	let records = contents
+		.lines()
+		.filter(|line| !line.trim().is_empty())
+		.filter_map(|line| {
+			serde_json::from_str(line)
+				.inspect_err(|_| skipped += 1)
+				.ok()
+		})
+		.collect();
+	return (records, skipped);
+}
+
+/// Every recorded setup, skipping lines that cannot be read with a warning; empty when there is no history.
+pub fn load() -> Vec<SetupRecord> {
+	let Some(path) = history_path() else {
+		return vec![];
+	};
+	let Ok(contents) = std::fs::read_to_string(&path) else {
+		return vec![];
+	};
+	let (history, skipped) = parse(&contents);
+	statefile::warn_skipped(&path, skipped);
+	return history;
+}
+
+/// Adds `record` to the history, dropping the oldest records past `HISTORY_LIMIT` along with any that cannot be read.
+pub fn record(record: SetupRecord) -> std::io::Result<()> {
+	let Some(path) = history_path() else {
+		return Ok(());
+	};
+	return statefile::update(&path, |contents| {
+		let (mut history, _) = parse(contents);
+		history.push(record);
+		let start = history.len().saturating_sub(HISTORY_LIMIT);
+		let mut lines = String::new();
+		for record in &history[start..] {
+			lines.push_str(&serde_json::to_string(record).map_err(std::io::Error::other)?);
+			lines.push('\n');
+		}
+		return Ok((lines.into_bytes(), ()));
+	});
+}
+
+/// Package counts are compared in ranges, since one package more or less barely changes how long a setup takes.
+fn bucket(count: usize) -> u8 {
+	return match count {
+		0 => 0,
+		1..=5 => 1,
+		6..=20 => 2,
+		_ => 3,
+	};
+}
+
+/// The median duration of past setups like this one, with how many there were; `None` with fewer than `MIN_SIMILAR`.
+pub fn estimate(history: &[SetupRecord], pulled: bool, packages: usize, aur_packages: usize) -> Option<(Duration, usize)> {
+	let mut similar: Vec<f64> = history
+		.iter()
+		.filter(|r| r.pulled == pulled && bucket(r.packages) == bucket(packages) && bucket(r.aur_packages) == bucket(aur_packages))
+		.map(|r| r.seconds)
+		.collect();
+	if similar.len() < MIN_SIMILAR {
+		return None;
+	}
+	similar.sort_by(f64::total_cmp);
+
+	return Some((Duration::from_secs_f64(similar[similar.len() / 2]), similar.len()));
+}
+
+/// `estimated setup: ~2m 10s based on 7 similar runs`
+pub fn describe_estimate(duration: Duration, runs: usize) -> String {
+	let seconds = duration.as_secs();
+	let rounded = if seconds >= 60 {
+		format!("{}m {}s", seconds / 60, seconds % 60)
+	} else {
+		format!("{seconds}s")
+	};
+	return format!("estimated setup: ~{rounded} based on {runs} similar runs");
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn run(pulled: bool, packages: usize, aur_packages: usize, seconds: f64) -> SetupRecord {
+		return SetupRecord { pulled, packages, aur_packages, seconds };
+	}
+
+	/// Cached images with a handful of packages, pulled images with none, and a couple of outliers in other buckets.
+	fn history() -> Vec<SetupRecord> {
+		return vec![
+			run(false, 3, 0, 40.0),
+			run(true, 0, 0, 95.0),
+			run(false, 5, 0, 30.0),
+			run(false, 30, 2, 600.0),
+			run(true, 0, 0, 80.0),
+			run(false, 1, 0, 50.0),
+			run(true, 0, 0, 110.0),
+			run(false, 4, 0, 35.0),
+			run(false, 2, 1, 300.0),
+		];
+	}
+
+	#[test]
+	fn takes_the_median_of_similar_setups() {
+		// 30, 35, 40, 50: the upper of the two middle ones
+		assert_eq!(estimate(&history(), false, 2, 0), Some((Duration::from_secs(40), 4)));
+		assert_eq!(estimate(&history(), true, 0, 0), Some((Duration::from_secs(95), 3)));
+	}
+
+	#[test]
+	fn compares_package_counts_in_buckets() {
+		assert_eq!(bucket(0), 0);
+		assert_eq!(bucket(5), 1);
+		assert_eq!(bucket(6), 2);
+		assert_eq!(bucket(21), 3);
+		// 4 and 5 packages count as similar, 6 does not
+		assert_eq!(estimate(&history(), false, 5, 0).map(|(_, runs)| runs), Some(4));
+		assert_eq!(estimate(&history(), false, 6, 0), None);
+	}
+
+	#[test]
+	fn needs_enough_similar_setups() {
+		assert_eq!(estimate(&[], false, 0, 0), None);
+		// only one pulled setup with AUR packages, and only one without any packages at all
+		assert_eq!(estimate(&history(), false, 30, 2), None);
+		assert_eq!(estimate(&history(), false, 0, 0), None);
+		let mut history = history();
+		history.extend([run(false, 25, 3, 500.0), run(false, 40, 1, 700.0)]);
+		assert_eq!(estimate(&history, false, 30, 2), Some((Duration::from_secs(600), 3)));
+	}
+
+	#[test]
+	fn describes_estimates() {
+		assert_eq!(describe_estimate(Duration::from_secs(130), 7), "estimated setup: ~2m 10s based on 7 similar runs");
+		assert_eq!(describe_estimate(Duration::from_secs_f64(42.9), 3), "estimated setup: ~42s based on 3 similar runs");
+		assert_eq!(describe_estimate(Duration::from_secs(60), 3), "estimated setup: ~1m 0s based on 3 similar runs");
+	}
+
+	#[test]
+	fn skips_unreadable_history_lines() {
+		let contents = "{\"pulled\":true,\"packages\":0,\"aur_packages\":0,\"seconds\":12.5}\n\n{\"pulled\":false,\"pack\n";
+		assert_eq!(parse(contents), (vec![run(true, 0, 0, 12.5)], 1));
+	}
+}
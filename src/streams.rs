@@ -0,0 +1,187 @@
+use crate::color;
+
+/// Cuts one stream's chunks into lines, holding back a partial line until the rest of it arrives. A lone `\r` ends
+/// a line as well, since that is how pacman and curl redraw their progress, and is kept so the redraw still works.
+#[derive(Debug, Default)]
+pub struct LineAssembler {
+	partial: String,
+}
+
+impl LineAssembler {
+	/// The lines `chunk` completes, each with its `\n`, `\r\n`, or `\r`.
+	pub fn feed(&mut self, chunk: &str) -> Vec<String> {
+		self.partial.push_str(chunk);
+		let mut lines = vec![];
+		let mut start = 0;
+		let bytes = self.partial.as_bytes();
+		let mut i = 0;
+		while i < bytes.len() {
+			let end = match bytes[i] {
+				b'\n' => i + 1,
+				// a `\r` at the very end might be the first half of `\r\n`
+				b'\r' if i + 1 == bytes.len() => break,
+				b'\r' if bytes[i + 1] == b'\n' => i + 2,
+				b'\r' => i + 1,
+				_ => {
+					i += 1;
+					continue;
+				}
+			};
+			lines.push(self.partial[start..end].to_string());
+			start = end;
+			i = end;
+		}
+		self.partial.drain(..start);
+		return lines;
+	}
+
+	/// Whatever partial line is left, once the stream ended.
+	pub fn flush(&mut self) -> Option<String> {
+		return (!self.partial.is_empty()).then(|| std::mem::take(&mut self.partial));
+	}
+}
+
+/// Labels one stream's lines with a colored `[name]`, so output of execs running side by side can be told apart.
+#[derive(Debug)]
+pub struct LabeledStream {
+	name: String,
+	lines: LineAssembler,
+}
+
+impl LabeledStream {
+	pub fn new(name: impl Into<String>) -> Self {
+		return Self {
+			name: name.into(),
+			lines: LineAssembler::default(),
+		};
+	}
+
+	pub fn name(&self) -> &str {
+		return &self.name;
+	}
+
+	/// The complete lines of `chunk`, each prefixed. Lines end in `\r\n`, so they come out right in raw mode too.
+	pub fn feed(&mut self, chunk: &str) -> String {
+		return self
+			.lines
+			.feed(chunk)
+			.iter()
+			.map(|line| self.prefixed(line))
+			.collect();
+	}
+
+	/// The prefixed partial line left at the end of the stream, if any.
+	pub fn flush(&mut self) -> String {
+		return self
+			.lines
+			.flush()
+			.map(|line| self.prefixed(&line))
+			.unwrap_or_default();
+	}
+
+	fn prefixed(&self, line: &str) -> String {
+		let text = line.trim_end_matches(['\r', '\n']);
+		// progress redraws go back to the start of the line, where the prefix has to be drawn again
+		let end = if line.ends_with('\n') || !line.ends_with('\r') { "\r\n" } else { "\r" };
+		return format!("{} {text}{end}", self.tag());
+	}
+
+	/// `[name]`, colored by the kind of stream before any `:` or `#`, so every `hook:*` looks alike.
+	fn tag(&self) -> String {
+		let tag = format!("[{}]", self.name);
+		return match self.name.split([':', '#']).next().unwrap_or_default() {
+			"update" => color::yellow(tag),
+			"setup" => color::green(tag),
+			_ => tag,
+		};
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	/// Everything `feed` returns for `chunks`, then the flushed rest.
+	fn assemble(chunks: &[&str]) -> (Vec<String>, Option<String>) {
+		let mut lines = LineAssembler::default();
+		let fed = chunks.iter().flat_map(|chunk| lines.feed(chunk)).collect();
+		return (fed, lines.flush());
+	}
+
+	#[test]
+	fn holds_partial_lines() {
+		assert_eq!(assemble(&["hel", "lo\nwor", "ld\n"]), (vec!["hello\n".into(), "world\n".into()], None));
+		assert_eq!(assemble(&["no newline"]), (vec![], Some("no newline".into())));
+	}
+
+	#[test]
+	fn keeps_each_line_ending() {
+		assert_eq!(
+			assemble(&["unix\ndos\r\n 10%\r 20%\r 30%\n"]),
+			(vec!["unix\n".into(), "dos\r\n".into(), " 10%\r".into(), " 20%\r".into(), " 30%\n".into()], None)
+		);
+	}
+
+	#[test]
+	fn a_crlf_split_across_chunks_is_one_ending() {
+		assert_eq!(assemble(&["dos\r", "\nnext\n"]), (vec!["dos\r\n".into(), "next\n".into()], None));
+		// the `\r` is held until the next chunk tells which it is
+		assert_eq!(assemble(&["50%\r", "60%\r", "done\n"]), (vec!["50%\r".into(), "60%\r".into(), "done\n".into()], None));
+		assert_eq!(assemble(&["last\r"]), (vec![], Some("last\r".into())));
+	}
+
+	#[test]
+	fn every_chunking_gives_the_same_lines() {
+		let output = "resolving...\r\n git  [##--]  40%\r git  [####] 100%\n(1/1) installing git\r\nend";
+		let (whole, rest) = assemble(&[output]);
+		for size in 1..output.len() {
+			let chunks: Vec<&str> = output
+				.as_bytes()
+				.chunks(size)
+				.map(|c| std::str::from_utf8(c).unwrap())
+				.collect();
+			assert_eq!(assemble(&chunks), (whole.clone(), rest.clone()), "chunks of {size}");
+		}
+	}
+
+	#[test]
+	fn prefixes_every_line_and_redraw() {
+		let mut stream = LabeledStream::new("hook:sync");
+		assert_eq!(stream.feed("one\ntw"), "[hook:sync] one\r\n");
+		assert_eq!(stream.feed("o\r\n 50%\r100%\r"), "[hook:sync] two\r\n[hook:sync]  50%\r");
+		assert_eq!(stream.feed("\n"), "[hook:sync] 100%\r\n");
+		assert_eq!(stream.flush(), "");
+		stream.feed("unterminated");
+		assert_eq!(stream.flush(), "[hook:sync] unterminated\r\n");
+	}
+
+	#[test]
+	fn interleaved_streams_never_prefix_mid_line() {
+		let mut update = LabeledStream::new("watch#1");
+		let mut hook = LabeledStream::new("hook:sync");
+		let update_chunks = [":: Synchronizing", " package databases...\n core  10%\r core", " 100%\n"];
+		let hook_chunks = ["rsync: sen", "ding\r\n", "done"];
+		let mut terminal = String::new();
+		for (u, h) in update_chunks.iter().zip(hook_chunks) {
+			terminal.push_str(&update.feed(u));
+			terminal.push_str(&hook.feed(h));
+		}
+		terminal.push_str(&update.flush());
+		terminal.push_str(&hook.flush());
+		assert_eq!(
+			terminal,
+			"[watch#1] :: Synchronizing package databases...\r\n\
+			 [watch#1]  core  10%\r\
+			 [hook:sync] rsync: sending\r\n\
+			 [watch#1]  core 100%\r\n\
+			 [hook:sync] done\r\n"
+		);
+	}
+
+	#[test]
+	fn tags_by_the_kind_of_stream() {
+		assert_eq!(LabeledStream::new("watch#3").tag(), "[watch#3]");
+		assert!(LabeledStream::new("update").tag().contains("[update]"));
+		assert!(LabeledStream::new("setup:chaotic-aur").tag().contains("[setup:chaotic-aur]"));
+	}
+}
@@ -0,0 +1,40 @@
+/// Lists every executable name on the user's PATH, one per line.
+pub const LIST_COMMANDS_SCRIPT: &str = r#"IFS=:; for dir in $PATH; do ls -1 "$dir" 2>/dev/null; done"#;
+
+/// Levenshtein distance, counting inserted, removed, and substituted characters.
+pub fn edit_distance(a: &str, b: &str) -> usize {
+	let b: Vec<char> = b.chars().collect();
+	let mut row: Vec<usize> = (0..=b.len()).collect();
+	for (i, ca) in a.chars().enumerate() {
+		let mut diagonal = row[0];
+		row[0] = i + 1;
+		for (j, cb) in b.iter().enumerate() {
+			let above = row[j + 1];
+			row[j + 1] = (above + 1)
+				.min(row[j] + 1)
+				.min(diagonal + (ca != *cb) as usize);
+			diagonal = above;
+		}
+	}
+
+	return row[b.len()];
+}
+
+/// Up to `max` of `candidates` closest to `name`, allowing one typo per three characters.
+pub fn closest<'a>(name: &str, candidates: impl IntoIterator<Item = &'a str>, max: usize) -> Vec<String> {
+	let allowed = (name.chars().count() / 3).max(1);
+	let mut found: Vec<(usize, &str)> = candidates
+		.into_iter()
+		.filter(|c| *c != name)
+		.map(|c| (edit_distance(name, c), c))
+		.filter(|(distance, _)| *distance <= allowed)
+		.collect();
+	found.sort();
+	found.dedup();
+
+	return found
+		.into_iter()
+		.take(max)
+		.map(|(_, c)| c.to_string())
+		.collect();
+}
@@ -0,0 +1,158 @@
+use crate::build::shell_quote;
+
+/// Placeholders a template can use, with what each stands for.
+pub const PLACEHOLDERS: &[(&str, &str)] = &[
+	("project", "name of the current directory"),
+	("hostname", "hostname of the system, which is also its name"),
+	("user", "user the session runs as"),
+	("home", "home directory of that user in the system"),
+];
+
+/// What the placeholders stand for in one session.
+pub struct Values<'a> {
+	pub project: String,
+	pub hostname: &'a str,
+	pub user: &'a str,
+	pub home: &'a str,
+}
+
+impl Values<'_> {
+	fn get(&self, name: &str) -> &str {
+		return match name {
+			"project" => &self.project,
+			"hostname" => self.hostname,
+			"user" => self.user,
+			"home" => self.home,
+			_ => "",
+		};
+	}
+}
+
+/// The name of the current directory, for `{project}`.
+pub fn project_name() -> String {
+	return std::env::current_dir()
+		.ok()
+		.and_then(|cwd| cwd.file_name().map(|n| n.to_string_lossy().into_owned()))
+		.unwrap_or_default();
+}
+
+/// Checks that every brace in `template` pairs up and every placeholder is known, without expanding it.
+pub fn validate(template: &str) -> Result<(), String> {
+	return render(template, |_| String::new()).map(|_| ());
+}
+
+/// `template` with each `{name}` replaced by its value, quoted for the shell, since templates are command lines.
+/// `{{` and `}}` stand for literal braces.
+pub fn expand(template: &str, values: &Values) -> Result<String, String> {
+	return render(template, |name| shell_quote(values.get(name)));
+}
+
+fn render(template: &str, value: impl Fn(&str) -> String) -> Result<String, String> {
+	let mut rendered = String::new();
+	let mut chars = template.char_indices().peekable();
+	while let Some((at, c)) = chars.next() {
+		match c {
+			'{' if chars.next_if(|(_, c)| *c == '{').is_some() => rendered.push('{'),
+			'}' if chars.next_if(|(_, c)| *c == '}').is_some() => rendered.push('}'),
+			'{' => {
+				let rest = &template[at + 1..];
+				let Some(end) = rest.find('}') else {
+					return Err(format!("the `{{` at {at} is never closed; write `{{{{` for a literal brace"));
+				};
+				let name = &rest[..end];
+				if !PLACEHOLDERS.iter().any(|(known, _)| *known == name) {
+					return Err(format!("unknown placeholder `{{{name}}}`"));
+				}
+				rendered.push_str(&value(name));
+				for _ in 0..name.chars().count() + 1 {
+					chars.next();
+				}
+			}
+			'}' => return Err(format!("the `}}` at {at} closes nothing; write `}}}}` for a literal brace")),
+			c => rendered.push(c),
+		}
+	}
+
+	return Ok(rendered);
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn values() -> Values<'static> {
+		return Values {
+			project: "my project".into(),
+			hostname: "tempsystem-brave-otter",
+			user: "tempsystem",
+			home: "/home/tempsystem",
+		};
+	}
+
+	#[test]
+	fn expands_placeholders() {
+		assert_eq!(
+			expand("tmux new -A -s {project}", &values()).unwrap(),
+			"tmux new -A -s 'my project'"
+		);
+		assert_eq!(
+			expand("{user}@{hostname}:{home}", &values()).unwrap(),
+			"tempsystem@tempsystem-brave-otter:/home/tempsystem"
+		);
+		assert_eq!(expand("zsh", &values()).unwrap(), "zsh");
+		assert_eq!(expand("", &values()).unwrap(), "");
+	}
+
+	#[test]
+	fn quotes_values_for_the_shell() {
+		let values = Values {
+			project: "it's $(rm -rf ~)".into(),
+			..values()
+		};
+		assert_eq!(expand("cd {project}", &values).unwrap(), r"cd 'it'\''s $(rm -rf ~)'");
+		let empty = Values { project: String::new(), ..values };
+		assert_eq!(expand("echo {project}", &empty).unwrap(), "echo ''");
+	}
+
+	#[test]
+	fn doubled_braces_are_literal() {
+		assert_eq!(expand("{{project}}", &values()).unwrap(), "{project}");
+		assert_eq!(expand("awk '{{print $1}}'", &values()).unwrap(), "awk '{print $1}'");
+		assert_eq!(expand("{{{project}}}", &values()).unwrap(), "{'my project'}");
+		assert_eq!(expand("a {{ b }} c", &values()).unwrap(), "a { b } c");
+	}
+
+	#[test]
+	fn expands_after_multibyte_text() {
+		assert_eq!(expand("échо {user} ✓", &values()).unwrap(), "échо tempsystem ✓");
+	}
+
+	#[test]
+	fn rejects_unknown_placeholders() {
+		for template in ["{cwd}", "tmux -s {Project}", "{}", "{ project }"] {
+			let e = validate(template).unwrap_err();
+			assert!(e.starts_with("unknown placeholder"), "{template}: {e}");
+		}
+		assert_eq!(validate("{nope}").unwrap_err(), "unknown placeholder `{nope}`");
+	}
+
+	#[test]
+	fn rejects_unpaired_braces() {
+		assert_eq!(
+			validate("echo {project").unwrap_err(),
+			"the `{` at 5 is never closed; write `{{` for a literal brace"
+		);
+		assert_eq!(
+			validate("echo }").unwrap_err(),
+			"the `}` at 5 closes nothing; write `}}` for a literal brace"
+		);
+		assert!(validate("{project}}").is_err());
+	}
+
+	#[test]
+	fn validates_without_values() {
+		for (name, _) in PLACEHOLDERS {
+			assert!(validate(&format!("run {{{name}}}")).is_ok(), "{name}");
+		}
+	}
+}
@@ -0,0 +1,48 @@
+use crate::env::ExecEnv;
+
+/// Used instead of a `TERM` the system has no terminfo entry for.
+pub const FALLBACK_TERM: &str = "xterm-256color";
+
+/// Used instead of a `LANG` whose locale the system has not generated.
+pub const FALLBACK_LANG: &str = "C.UTF-8";
+
+/// Prints `term` when the system has no terminfo entry for `$TERM`, and `lang` when `$LANG` names a locale that is not generated.
+/// Only looks at files and `locale -a`, so it stays quick even in images without ncurses.
+pub const TERM_CHECK_SCRIPT: &str = r#"
+known_term() {
+	for dir in "$HOME/.terminfo" /etc/terminfo /usr/share/terminfo /usr/lib/terminfo; do
+		[ -e "$dir/$(printf %.1s "$TERM")/$TERM" ] && return 0
+	done
+	return 1
+}
+normalized() {
+	printf '%s\n' "$1" | tr 'A-Z' 'a-z' | sed 's/utf-8/utf8/'
+}
+[ -n "$TERM" ] && ! known_term && echo term
+case "$LANG" in
+""|C|POSIX) ;;
+*) command -v locale >/dev/null && ! locale -a 2>/dev/null | tr 'A-Z' 'a-z' | grep -qx "$(normalized "$LANG")" && echo lang ;;
+esac
+true
+"#;
+
+/// Swaps the variables `TERM_CHECK_SCRIPT` reported in `output` for their fallbacks, returning a notice saying so.
+pub fn apply(output: &str, env: &mut ExecEnv) -> Option<String> {
+	let mut replaced = vec![];
+	for (report, key, fallback) in [("term", "TERM", FALLBACK_TERM), ("lang", "LANG", FALLBACK_LANG)] {
+		if output.lines().any(|line| line.trim() == report)
+			&& let Some(value) = env.get(key).map(str::to_string)
+		{
+			env.replace(key, fallback);
+			replaced.push(format!("{key} `{value}` with {fallback}"));
+		}
+	}
+	if replaced.is_empty() {
+		return None;
+	}
+	return Some(format!(
+		"note: the system does not support {}, replaced {}; pass --no-term-check to keep it",
+		if replaced.len() == 1 { "this setting" } else { "these settings" },
+		replaced.join(" and ")
+	));
+}
@@ -0,0 +1,108 @@
+use std::{
+	collections::HashMap,
+	time::{Duration, Instant},
+};
+
+use indicatif::HumanDuration;
+
+/// Median exec latency above which the report suggests looking at the daemon.
+pub const SLOW_EXEC_THRESHOLD: Duration = Duration::from_millis(500);
+
+/// Upper bounds of the histogram buckets; the last bucket holds everything slower.
+const BUCKETS: &[Duration] = &[
+	Duration::from_millis(50),
+	Duration::from_millis(100),
+	Duration::from_millis(250),
+	Duration::from_millis(500),
+	Duration::from_secs(1),
+	Duration::from_secs(2),
+];
+
+/// Wall time of every non-interactive exec, from creation until its output ended, grouped by setup phase.
+#[derive(Debug, Default)]
+pub struct ExecTimings {
+	phase: String,
+	started: HashMap<String, (Instant, String)>,
+	finished: Vec<(String, Duration)>,
+}
+
+impl ExecTimings {
+	/// Names the phase execs created from now on belong to.
+	pub fn set_phase(&mut self, phase: impl Into<String>) {
+		self.phase = phase.into();
+	}
+
+	pub fn created(&mut self, exec_id: &str) {
+		self.started
+			.insert(exec_id.into(), (Instant::now(), self.phase.clone()));
+	}
+
+	pub fn finished(&mut self, exec_id: &str) {
+		if let Some((start, phase)) = self.started.remove(exec_id) {
+			self.finished.push((phase, start.elapsed()));
+		}
+	}
+
+	fn sorted(&self) -> Vec<Duration> {
+		let mut durations: Vec<Duration> = self.finished.iter().map(|(_, d)| *d).collect();
+		durations.sort();
+		return durations;
+	}
+
+	pub fn median(&self) -> Option<Duration> {
+		let durations = self.sorted();
+		return durations.get(durations.len() / 2).copied();
+	}
+
+	/// Summary, histogram, and per-phase counts, or `None` when no exec finished.
+	pub fn report(&self) -> Option<String> {
+		let durations = self.sorted();
+		let median = self.median()?;
+		let total: Duration = durations.iter().sum();
+		let mut report = format!(
+			"{} exec(s) took {} in total; median {:?}, p90 {:?}, max {:?}\n",
+			durations.len(),
+			HumanDuration(total),
+			median,
+			durations[durations.len() * 9 / 10],
+			durations[durations.len() - 1],
+		);
+
+		let mut lower = Duration::ZERO;
+		for upper in BUCKETS.iter().map(Some).chain([None]) {
+			let count = durations
+				.iter()
+				.filter(|d| **d >= lower && upper.is_none_or(|u| *d < u))
+				.count();
+			let label = match upper {
+				Some(upper) => format!("< {upper:?}"),
+				None => format!(">= {lower:?}"),
+			};
+			report.push_str(&format!("  {label:>10} {count:>4} {}\n", "#".repeat(count.min(50))));
+			lower = upper.copied().unwrap_or(lower);
+		}
+
+		let mut phases: Vec<(&str, usize, Duration)> = vec![];
+		for (phase, duration) in &self.finished {
+			match phases.iter_mut().find(|(p, ..)| p == phase) {
+				Some((_, count, time)) => {
+					*count += 1;
+					*time += *duration;
+				}
+				None => phases.push((phase, 1, *duration)),
+			}
+		}
+		report.push_str("execs per phase:\n");
+		for (phase, count, time) in phases {
+			report.push_str(&format!("  {count:>4} in {}, {}\n", if phase.is_empty() { "preparing" } else { phase }, HumanDuration(time)));
+		}
+
+		if median > SLOW_EXEC_THRESHOLD {
+			report.push_str(&format!(
+				"hint: a median of {median:?} per exec is slow; daemon authorization or auditing plugins and userns-remap add overhead to every exec\n"
+			));
+		}
+
+		return Some(report);
+	}
+}
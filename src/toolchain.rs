@@ -0,0 +1,143 @@
+/// Where versions other than the current one come from.
+#[derive(Debug)]
+enum Versions {
+	/// any version, handed to the setup as `{version}`; this one when none is asked for
+	Setup(&'static str),
+	/// versions the repositories package under a name of their own, replacing the first package
+	Repo(&'static [(&'static str, &'static str)]),
+	/// versions only the AUR has, as the first package with the version's digits appended, like `python311`
+	Aur,
+	/// only the current version
+	Current,
+}
+
+/// A language toolchain `--toolchain` sets up.
+#[derive(Debug)]
+pub struct Toolchain {
+	pub name: &'static str,
+	packages: &'static [&'static str],
+	/// run as the user once the packages are in, with `{version}` replaced
+	setup: &'static [&'static str],
+	versions: Versions,
+}
+
+pub const TOOLCHAINS: &[Toolchain] = &[
+	Toolchain {
+		name: "rust",
+		packages: &["rustup"],
+		setup: &["rustup default {version}"],
+		versions: Versions::Setup("stable"),
+	},
+	Toolchain {
+		name: "go",
+		packages: &["go"],
+		setup: &[],
+		versions: Versions::Current,
+	},
+	Toolchain {
+		name: "python",
+		packages: &["python", "python-pip"],
+		setup: &[],
+		versions: Versions::Aur,
+	},
+	Toolchain {
+		name: "node",
+		packages: &["nodejs", "npm"],
+		setup: &[],
+		versions: Versions::Repo(&[("18", "nodejs-lts-hydrogen"), ("20", "nodejs-lts-iron"), ("22", "nodejs-lts-jod")]),
+	},
+	Toolchain {
+		name: "c",
+		packages: &["base-devel", "gcc", "clang", "cmake", "gdb"],
+		setup: &[],
+		versions: Versions::Current,
+	},
+];
+
+/// A toolchain from `--toolchain`, with the version after its `@`.
+#[derive(Debug, Clone)]
+pub struct Spec {
+	toolchain: &'static Toolchain,
+	version: Option<String>,
+}
+
+/// Parses toolchains like `rust` or `node@20`.
+pub fn parse(s: &str) -> Result<Spec, String> {
+	let (name, version) = match s.split_once('@') {
+		Some((name, version)) => (name, Some(version.to_string())),
+		None => (s, None),
+	};
+	let Some(toolchain) = TOOLCHAINS
+		.iter()
+		.find(|t| t.name.eq_ignore_ascii_case(name))
+	else {
+		let known: Vec<&str> = TOOLCHAINS.iter().map(|t| t.name).collect();
+		return Err(format!("unknown toolchain `{name}`; known are {}", known.join(", ")));
+	};
+	// versions end up in setup commands
+	if let Some(version) = &version
+		&& (version.is_empty()
+			|| !version
+				.chars()
+				.all(|c| c.is_ascii_alphanumeric() || "._-".contains(c)))
+	{
+		return Err(format!("`{version}` is not a version like 20 or 3.11"));
+	}
+
+	return Ok(Spec { toolchain, version });
+}
+
+/// What some toolchains come down to: packages, the setup to run after them, and notes on versions that could not be
+/// had as asked.
+#[derive(Debug, Default)]
+pub struct Expansion {
+	pub packages: Vec<String>,
+	pub aur_packages: Vec<String>,
+	pub setup: Vec<String>,
+	pub notes: Vec<String>,
+}
+
+pub fn expand(specs: &[Spec]) -> Expansion {
+	let mut expansion = Expansion::default();
+	for Spec { toolchain, version } in specs {
+		let mut packages: Vec<String> = toolchain.packages.iter().map(|p| p.to_string()).collect();
+		let mut setup_version = None;
+		match (&toolchain.versions, version.as_deref()) {
+			(Versions::Setup(default), version) => setup_version = Some(version.unwrap_or(default)),
+			(_, None) => {}
+			(Versions::Repo(versions), Some(version)) => match versions.iter().find(|(v, _)| *v == version) {
+				Some((_, package)) => packages[0] = package.to_string(),
+				None => {
+					let have: Vec<&str> = versions.iter().map(|(v, _)| *v).collect();
+					expansion.notes.push(format!(
+						"{}@{version} is not in the repositories, which have {} and the current version; installing the current one",
+						toolchain.name,
+						have.join(", ")
+					));
+				}
+			},
+			(Versions::Aur, Some(version)) => {
+				let package = format!("{}{}", packages.remove(0), version.replace('.', ""));
+				expansion.notes.push(format!(
+					"{}@{version} is only in the AUR; installing `{package}` from there next to the current version",
+					toolchain.name
+				));
+				expansion.aur_packages.push(package);
+			}
+			(Versions::Current, Some(version)) => expansion.notes.push(format!(
+				"the repositories only have the current {}, not {version}; older versions are in the Arch Linux Archive, which \
+				 tempsystem does not install from; installing the current one",
+				toolchain.name
+			)),
+		}
+		expansion.packages.extend(packages);
+		expansion.setup.extend(
+			toolchain
+				.setup
+				.iter()
+				.map(|command| command.replace("{version}", setup_version.unwrap_or_default())),
+		);
+	}
+
+	return expansion;
+}
@@ -0,0 +1,142 @@
+use std::time::Duration;
+
+/// How long an incomplete UTF-8 sequence is held back waiting for the rest of it; output may just as well be binary.
+pub const HOLDBACK_TIMEOUT: Duration = Duration::from_millis(50);
+
+/// Length of the UTF-8 sequence `lead` starts, or 1 for bytes that cannot start one.
+fn sequence_len(lead: u8) -> usize {
+	return match lead {
+		0xc0..=0xdf => 2,
+		0xe0..=0xef => 3,
+		0xf0..=0xf7 => 4,
+		_ => 1,
+	};
+}
+
+/// How many bytes at the end of `bytes` are the start of a sequence whose continuation bytes have not arrived yet.
+fn incomplete_tail(bytes: &[u8]) -> usize {
+	let Some(lead) = (1..=bytes.len().min(3))
+		.map(|back| bytes.len() - back)
+		.find(|&i| bytes[i] & 0xc0 != 0x80)
+	else {
+		return 0;
+	};
+	let tail = bytes.len() - lead;
+	if sequence_len(bytes[lead]) > tail {
+		return tail;
+	}
+
+	return 0;
+}
+
+/// Splits a byte stream at UTF-8 sequence boundaries, so a multi-byte character cut in two by the
+/// stream's framing is written to the terminal in one piece instead of two flushes.
+#[derive(Debug, Default)]
+pub struct Utf8Holdback {
+	pending: Vec<u8>,
+}
+
+impl Utf8Holdback {
+	/// The bytes of `chunk`, after whatever was held back, that can be written now.
+	pub fn feed(&mut self, chunk: &[u8]) -> Vec<u8> {
+		self.pending.extend_from_slice(chunk);
+		let keep = incomplete_tail(&self.pending);
+		let rest = self.pending.split_off(self.pending.len() - keep);
+		return std::mem::replace(&mut self.pending, rest);
+	}
+
+	/// Everything held back, for when the rest did not arrive in time or the stream ended.
+	pub fn flush(&mut self) -> Vec<u8> {
+		return std::mem::take(&mut self.pending);
+	}
+
+	pub fn is_empty(&self) -> bool {
+		return self.pending.is_empty();
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	/// One to four byte sequences, box drawing like TUIs use included.
+	const TEXT: &str = "a é ─┼─ 你好 🦀 end";
+
+	#[test]
+	fn passes_ascii_through() {
+		let mut holdback = Utf8Holdback::default();
+		assert_eq!(holdback.feed(b"plain text\n"), b"plain text\n");
+		assert!(holdback.is_empty());
+	}
+
+	#[test]
+	fn holds_back_a_split_sequence_until_it_is_complete() {
+		let crab = "🦀".as_bytes();
+		let mut holdback = Utf8Holdback::default();
+		assert_eq!(holdback.feed(&[b'x', crab[0]]), b"x");
+		assert_eq!(holdback.feed(&crab[1..3]), b"");
+		assert!(!holdback.is_empty());
+		assert_eq!(holdback.feed(&[crab[3], b'y']), "🦀y".as_bytes());
+		assert!(holdback.is_empty());
+	}
+
+	#[test]
+	fn every_split_in_two_writes_whole_characters() {
+		let bytes = TEXT.as_bytes();
+		for at in 0..=bytes.len() {
+			let mut holdback = Utf8Holdback::default();
+			let first = holdback.feed(&bytes[..at]);
+			let second = holdback.feed(&bytes[at..]);
+			assert!(std::str::from_utf8(&first).is_ok(), "split at {at}: {first:?}");
+			assert_eq!([first, second].concat(), bytes, "split at {at}");
+			assert!(holdback.is_empty(), "split at {at}");
+		}
+	}
+
+	#[test]
+	fn byte_at_a_time_writes_whole_characters() {
+		let mut holdback = Utf8Holdback::default();
+		let mut written = String::new();
+		for byte in TEXT.as_bytes() {
+			let out = holdback.feed(&[*byte]);
+			written.push_str(std::str::from_utf8(&out).unwrap());
+		}
+		assert_eq!(written, TEXT);
+		assert!(holdback.is_empty());
+	}
+
+	#[test]
+	fn every_split_in_three_keeps_the_bytes() {
+		let bytes = "─🦀─".as_bytes();
+		for first in 0..=bytes.len() {
+			for second in first..=bytes.len() {
+				let mut holdback = Utf8Holdback::default();
+				let mut out = holdback.feed(&bytes[..first]);
+				out.extend(holdback.feed(&bytes[first..second]));
+				out.extend(holdback.feed(&bytes[second..]));
+				assert_eq!(out, bytes, "split at {first} and {second}");
+			}
+		}
+	}
+
+	#[test]
+	fn flush_gives_up_on_an_incomplete_sequence() {
+		let mut holdback = Utf8Holdback::default();
+		assert_eq!(holdback.feed(&[b'a', 0xe2, 0x94]), b"a");
+		assert_eq!(holdback.flush(), [0xe2, 0x94]);
+		assert!(holdback.is_empty());
+		assert_eq!(holdback.flush(), b"");
+	}
+
+	#[test]
+	fn passes_binary_through() {
+		let mut holdback = Utf8Holdback::default();
+		// stray continuation bytes and bytes that never start a sequence are not held
+		assert_eq!(holdback.feed(&[0x00, 0x80, 0xbf]), [0x00, 0x80, 0xbf]);
+		assert_eq!(holdback.feed(&[0xff, 0xfe]), [0xff, 0xfe]);
+		assert!(holdback.is_empty());
+		// a lead byte followed by more than it needs is not waiting for anything
+		assert_eq!(holdback.feed(&[0xc3, 0xa9, 0x80]), [0xc3, 0xa9, 0x80]);
+		assert!(holdback.is_empty());
+	}
+}
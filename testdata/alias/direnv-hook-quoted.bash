@@ -0,0 +1,23 @@
+# add to .envrc, or put the function in ~/.config/direnv/direnvrc and keep only the last line
+use_tempsystem() {
+	local root=$PWD
+	local bin=$root/.direnv/tempsystem/bin
+	mkdir -p "$bin"
+	local cmd
+	for cmd in "$@"; do
+		printf '#!/usr/bin/env bash\ncd %q && exec tempsystem exec -- %q "$@"\n' "$root" "$cmd" > "$bin/$cmd"
+		chmod +x "$bin/$cmd"
+	done
+	PATH_add "$bin"
+
+	local lock=$root/.direnv/tempsystem/creating
+	if mkdir "$lock" 2>/dev/null; then
+		(
+			if tempsystem exec -- true; then
+				command -v notify-send && notify-send tempsystem "system for $root is ready"
+			fi
+			rmdir "$lock"
+		) </dev/null >/dev/null 2>&1 &
+	fi
+}
+use tempsystem 'npm run' 'it'\''s' ./gradlew
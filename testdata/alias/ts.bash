@@ -0,0 +1,7 @@
+ts() {
+	local out
+	out=$(command tempsystem --print-effective-args) || return
+	local -a extra=()
+	[[ -n $out ]] && mapfile -t extra <<< "$out"
+	command tempsystem "${extra[@]}" "$@"
+}
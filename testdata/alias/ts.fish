@@ -0,0 +1,4 @@
+function ts --wraps tempsystem
+	set -l extra (command tempsystem --print-effective-args); or return
+	command tempsystem $extra $argv
+end
@@ -0,0 +1,199 @@
+//! The create, install, enter, and delete flow of `--backend cli`, against a fake `docker` that records how it was
+//! called and answers like a daemon with the tempsystem image would.
+
+use std::{
+	os::unix::fs::PermissionsExt,
+	path::PathBuf,
+	process::{Command, Output, Stdio},
+};
+
+/// Appends its arguments to `$FAKE_DOCKER_LOG`, one call per line. An exec whose arguments contain `$FAKE_DOCKER_FAIL`
+/// fails like pacman, and one running `exit <n>` exits with `n`.
+const FAKE_DOCKER: &str = r#"#!/bin/sh
+printf '%s\n' "$*" >> "$FAKE_DOCKER_LOG"
+case "$1" in
+image)
+	case "$*" in
+	*'{{json .Config}}'*)
+		printf '%s\n' '{"User":"tempsystem","Env":[],"Labels":{"org.tempsystem.shell":"/usr/bin/zsh","org.tempsystem.user":"tempsystem","org.tempsystem.aur-helper":"yay","org.tempsystem.features":"sudo,pkgfile"}}'
+		;;
+	*) printf 'sha256:0123456789ab\n' ;;
+	esac
+	;;
+create) printf 'c0ffee\n' ;;
+exec)
+	if [ -n "$FAKE_DOCKER_FAIL" ]; then
+		case "$*" in
+		*"$FAKE_DOCKER_FAIL"*)
+			printf 'error: failed to prepare transaction (could not satisfy dependencies)\n'
+			exit 1
+			;;
+		esac
+	fi
+	eval "last=\${$#}"
+	case "$last" in
+	'exit '*) exit "${last#exit }" ;;
+	esac
+	;;
+esac
+exit 0
+"#;
+
+struct Sandbox {
+	dir: PathBuf,
+}
+
+impl Sandbox {
+	fn new(name: &str) -> Self {
+		let dir = std::env::temp_dir().join(format!("tempsystem-cli-{}-{name}", std::process::id()));
+		let _ = std::fs::remove_dir_all(&dir);
+		for sub in ["bin", "home", "config", "state", "cache", "work"] {
+			std::fs::create_dir_all(dir.join(sub)).unwrap();
+		}
+		let docker = dir.join("bin/docker");
+		std::fs::write(&docker, FAKE_DOCKER).unwrap();
+		std::fs::set_permissions(&docker, std::fs::Permissions::from_mode(0o755)).unwrap();
+		return Self { dir };
+	}
+
+	/// Runs tempsystem with `args`, with `fail` passed on as `$FAKE_DOCKER_FAIL`.
+	fn run(&self, args: &[&str], fail: Option<&str>) -> Output {
+		return self.run_with_env(args, fail, &[]);
+	}
+
+	/// Same as `run`, with `env` set on top of the sandbox's variables.
+	fn run_with_env(&self, args: &[&str], fail: Option<&str>, env: &[(&str, &str)]) -> Output {
+		let mut command = Command::new(env!("CARGO_BIN_EXE_tempsystem"));
+		command
+			.args(["--backend", "cli", "--no-term-check"])
+			.args(args)
+			.current_dir(self.dir.join("work"))
+			.env_clear()
+			.env("PATH", format!("{}:/usr/bin:/bin", self.dir.join("bin").display()))
+			.env("HOME", self.dir.join("home"))
+			.env("XDG_CONFIG_HOME", self.dir.join("config"))
+			.env("XDG_STATE_HOME", self.dir.join("state"))
+			.env("XDG_CACHE_HOME", self.dir.join("cache"))
+			.env("FAKE_DOCKER_LOG", self.log_path())
+			.envs(env.iter().copied())
+			.stdin(Stdio::null());
+		if let Some(fail) = fail {
+			command.env("FAKE_DOCKER_FAIL", fail);
+		}
+		return command.output().unwrap();
+	}
+
+	fn log_path(&self) -> PathBuf {
+		return self.dir.join("docker.log");
+	}
+
+	/// Every call made to the fake `docker`, in order.
+	fn calls(&self) -> Vec<String> {
+		return std::fs::read_to_string(self.log_path())
+			.unwrap_or_default()
+			.lines()
+			.map(String::from)
+			.collect();
+	}
+}
+
+impl Drop for Sandbox {
+	fn drop(&mut self) {
+		let _ = std::fs::remove_dir_all(&self.dir);
+	}
+}
+
+fn position(calls: &[String], prefix: &str) -> usize {
+	return calls
+		.iter()
+		.position(|call| call.starts_with(prefix))
+		.unwrap_or_else(|| panic!("no `{prefix}` call in {calls:#?}"));
+}
+
+fn describe(output: &Output) -> String {
+	return format!(
+		"exit {:?}\nstdout:\n{}\nstderr:\n{}",
+		output.status.code(),
+		String::from_utf8_lossy(&output.stdout),
+		String::from_utf8_lossy(&output.stderr)
+	);
+}
+
+#[test]
+fn creates_installs_enters_and_deletes() {
+	let sandbox = Sandbox::new("flow");
+	let output = sandbox.run(&["-p", "git ripgrep", "--", "true"], None);
+	assert_eq!(output.status.code(), Some(0), "{}", describe(&output));
+
+	let calls = sandbox.calls();
+	let create = position(&calls, "create ");
+	let start = position(&calls, "start c0ffee");
+	let check = position(&calls, "exec --user tempsystem c0ffee /usr/bin/zsh -c /bin/pacman -Sp --print-format %n git ripgrep");
+	let install = position(&calls, "exec --user tempsystem c0ffee sudo");
+	let session = position(&calls, "exec --interactive --user tempsystem");
+	let remove = position(&calls, "rm --force c0ffee");
+	assert!(create < start && start < check && check < install && install < session && session < remove, "{calls:#?}");
+	assert!(calls[install].contains("sudo -n /bin/sh -c /bin/pacman -S --needed --noconfirm git ripgrep"), "{}", calls[install]);
+	assert!(calls[session].ends_with("c0ffee /usr/bin/zsh -c true"), "{}", calls[session]);
+	assert_eq!(calls.last().unwrap(), "rm --force c0ffee");
+}
+
+#[test]
+fn passes_the_proxy_through_sudo() {
+	let sandbox = Sandbox::new("proxy");
+	let output = sandbox.run_with_env(&["-p", "git", "--", "true"], None, &[("https_proxy", "http://proxy.example:3128")]);
+	assert_eq!(output.status.code(), Some(0), "{}", describe(&output));
+
+	let calls = sandbox.calls();
+	let install = &calls[position(&calls, "exec --user tempsystem --env https_proxy c0ffee sudo")];
+	assert!(
+		install.contains("sudo -n /bin/sh -c export https_proxy=http://proxy.example:3128; /bin/pacman -S --needed --noconfirm git"),
+		"{install}"
+	);
+}
+
+#[test]
+fn exits_with_the_command() {
+	let sandbox = Sandbox::new("exit");
+	let output = sandbox.run(&["--", "exit", "3"], None);
+	assert_eq!(output.status.code(), Some(3), "{}", describe(&output));
+	assert_eq!(sandbox.calls().last().unwrap(), "rm --force c0ffee");
+}
+
+#[test]
+fn keeps_the_system_when_asked() {
+	let sandbox = Sandbox::new("keep");
+	let output = sandbox.run(&["--keep", "--", "true"], None);
+	assert_eq!(output.status.code(), Some(0), "{}", describe(&output));
+	assert!(String::from_utf8_lossy(&output.stdout).contains("kept system"), "{}", describe(&output));
+	assert!(!sandbox.calls().iter().any(|call| call.starts_with("rm ")));
+
+	let sandbox = Sandbox::new("keep-on-failure");
+	let output = sandbox.run(&["--keep-on-failure", "--", "exit", "2"], None);
+	assert_eq!(output.status.code(), Some(2), "{}", describe(&output));
+	assert!(!sandbox.calls().iter().any(|call| call.starts_with("rm ")));
+}
+
+#[test]
+fn deletes_the_system_when_setup_fails() {
+	for (flags, code) in [(&[][..], 1), (&["--ci"], 125)] {
+		let sandbox = Sandbox::new(if flags.is_empty() { "fail" } else { "fail-ci" });
+		let mut args = flags.to_vec();
+		args.extend(["-p", "nonsense", "--", "true"]);
+		let output = sandbox.run(&args, Some("pacman -S"));
+		assert_eq!(output.status.code(), Some(code), "{flags:?}\n{}", describe(&output));
+
+		let calls = sandbox.calls();
+		assert!(!calls.iter().any(|call| call.starts_with("exec --interactive")), "{calls:#?}");
+		assert_eq!(calls.last().unwrap(), "rm --force c0ffee");
+	}
+}
+
+#[test]
+fn refuses_what_the_cli_cannot_do_before_creating_anything() {
+	let sandbox = Sandbox::new("unsupported");
+	let output = sandbox.run(&["--wipe-on-exit", "--", "true"], None);
+	assert_eq!(output.status.code(), Some(1), "{}", describe(&output));
+	assert!(String::from_utf8_lossy(&output.stdout).contains("[E_BACKEND_UNSUPPORTED] --wipe-on-exit"), "{}", describe(&output));
+	assert!(!sandbox.calls().iter().any(|call| call.starts_with("create ")));
+}